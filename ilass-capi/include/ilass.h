@@ -0,0 +1,48 @@
+/* C declarations of ilass-capi (see src/lib.rs for the full documentation). */
+
+#ifndef ILASS_H
+#define ILASS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define ILASS_OK 0
+#define ILASS_ERROR_NULL_POINTER (-1)
+#define ILASS_ERROR_INVALID_SPAN (-2)
+#define ILASS_ERROR_INTERNAL (-3)
+#define ILASS_ERROR_RATING_OVERFLOW (-4)
+
+/* A subtitle line or voice segment (in ticks, e.g. milliseconds). */
+typedef struct IlassSpan {
+    int64_t start;
+    int64_t end;
+} IlassSpan;
+
+/* The offset (in ticks) to add to one incorrect span. */
+typedef struct IlassOffset {
+    int64_t delta;
+} IlassOffset;
+
+typedef struct IlassOptions {
+    /* between 0 and 1000, where 1000 applies a single offset to all spans */
+    double split_penalty;
+    /* a negative value disables the speed optimization */
+    double speed_optimization;
+} IlassOptions;
+
+/* Aligns `incorrect` to `reference`. On success returns ILASS_OK and one offset per incorrect
+ * span in `*out_offsets`, which has to be released with `ilass_free(*out_offsets, *out_len)`. */
+int ilass_align(const IlassSpan *reference, size_t reference_len, const IlassSpan *incorrect, size_t incorrect_len,
+                const IlassOptions *opts, IlassOffset **out_offsets, size_t *out_len);
+
+void ilass_free(IlassOffset *offsets, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,282 @@
+#![deny(
+    missing_debug_implementations,
+    trivial_casts,
+    unstable_features,
+    unused_import_braces
+)]
+
+//! A minimal C ABI for the `ilass` aligner, so tools written in other languages can align
+//! subtitles without going through the command line tool.
+//!
+//! The declarations for C callers are in `include/ilass.h`. All times are integer ticks of any
+//! resolution (e.g. milliseconds), as long as the reference and the incorrect spans use the same
+//! one.
+
+use ilass::{AlignError, AlignOptions, NoProgressHandler, TimeDelta, TimePoint, TimeSpan};
+use std::os::raw::c_int;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::ptr;
+
+/// The alignment succeeded.
+pub const ILASS_OK: c_int = 0;
+
+/// A required pointer was null (span pointers may only be null if their length is zero).
+pub const ILASS_ERROR_NULL_POINTER: c_int = -1;
+
+/// A span ends before it starts.
+pub const ILASS_ERROR_INVALID_SPAN: c_int = -2;
+
+/// The aligner failed unexpectedly (a bug in `ilass`).
+pub const ILASS_ERROR_INTERNAL: c_int = -3;
+
+/// The rating of an offset does not fit into the rating type of `ilass` (see
+/// `ilass::AlignError::RatingOverflow`).
+pub const ILASS_ERROR_RATING_OVERFLOW: c_int = -4;
+
+/// A subtitle line or voice segment from `start` to `end` (in ticks).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IlassSpan {
+    pub start: i64,
+    pub end: i64,
+}
+
+/// The offset (in ticks) that has to be added to one incorrect span to align it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IlassOffset {
+    pub delta: i64,
+}
+
+/// Options of `ilass_align` (see `ilass::align` for their meaning).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IlassOptions {
+    /// Between 0 and 1000, where 1000 only applies a single offset to all spans.
+    pub split_penalty: f64,
+
+    /// A negative value disables the speed optimization.
+    pub speed_optimization: f64,
+}
+
+unsafe fn read_spans(spans: *const IlassSpan, len: usize) -> Result<Vec<TimeSpan>, c_int> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    if spans.is_null() {
+        return Err(ILASS_ERROR_NULL_POINTER);
+    }
+
+    // SAFETY: the caller guarantees that `spans` points to `len` valid spans
+    let spans = unsafe { std::slice::from_raw_parts(spans, len) };
+    spans
+        .iter()
+        .map(|span| {
+            if span.start <= span.end {
+                Ok(TimeSpan::new(TimePoint::from(span.start), TimePoint::from(span.end)))
+            } else {
+                Err(ILASS_ERROR_INVALID_SPAN)
+            }
+        })
+        .collect()
+}
+
+fn error_code(error: AlignError) -> c_int {
+    match error {
+        AlignError::RatingOverflow { .. } => ILASS_ERROR_RATING_OVERFLOW,
+        // there is no cancel flag
+        AlignError::Cancelled => ILASS_ERROR_INTERNAL,
+    }
+}
+
+/// Aligns the `incorrect_len` spans at `incorrect` to the `reference_len` spans at `reference`
+/// with `ilass::align_with_options` and the standard scoring.
+///
+/// On success, `ILASS_OK` is returned and `*out_offsets` points to `*out_len` offsets (one for
+/// every incorrect span, in the same order). That memory belongs to the caller, who has to release
+/// it with `ilass_free` (and not with `free`). On failure, a negative error code is returned and
+/// `*out_offsets` is null.
+///
+/// # Safety
+///
+/// `reference` and `incorrect` have to point to as many readable spans as their length says (or
+/// be null if the length is zero). `opts`, `out_offsets` and `out_len` have to be valid pointers.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ilass_align(
+    reference: *const IlassSpan,
+    reference_len: usize,
+    incorrect: *const IlassSpan,
+    incorrect_len: usize,
+    opts: *const IlassOptions,
+    out_offsets: *mut *mut IlassOffset,
+    out_len: *mut usize,
+) -> c_int {
+    if opts.is_null() || out_offsets.is_null() || out_len.is_null() {
+        return ILASS_ERROR_NULL_POINTER;
+    }
+    // SAFETY: checked for null above, the caller guarantees that the pointers are valid
+    unsafe {
+        *out_offsets = ptr::null_mut();
+        *out_len = 0;
+    }
+
+    // SAFETY: the caller guarantees that the span pointers are valid
+    let reference = match unsafe { read_spans(reference, reference_len) } {
+        Ok(spans) => spans,
+        Err(code) => return code,
+    };
+    let incorrect = match unsafe { read_spans(incorrect, incorrect_len) } {
+        Ok(spans) => spans,
+        Err(code) => return code,
+    };
+    // SAFETY: checked for null above
+    let opts = unsafe { *opts };
+
+    let speed_optimization = if opts.speed_optimization < 0. {
+        None
+    } else {
+        Some(opts.speed_optimization)
+    };
+
+    // a panic must not unwind into the C caller
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        ilass::align_with_options(
+            &reference,
+            &incorrect,
+            opts.split_penalty,
+            ilass::standard_scoring,
+            AlignOptions::new(speed_optimization, NoProgressHandler),
+        )
+    }));
+    let deltas: Vec<TimeDelta> = match result {
+        Ok(Ok((deltas, _))) => deltas,
+        Ok(Err(error)) => return error_code(error),
+        Err(_) => return ILASS_ERROR_INTERNAL,
+    };
+
+    let offsets: Box<[IlassOffset]> = deltas
+        .into_iter()
+        .map(|delta| IlassOffset { delta: delta.as_i64() })
+        .collect();
+    // SAFETY: checked for null above
+    unsafe {
+        *out_len = offsets.len();
+        *out_offsets = Box::into_raw(offsets).cast::<IlassOffset>();
+    }
+
+    ILASS_OK
+}
+
+/// Releases the `len` offsets returned by `ilass_align`. Passing null does nothing.
+///
+/// # Safety
+///
+/// `offsets` and `len` have to be exactly what `ilass_align` returned, and the offsets must not be
+/// used or released again afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ilass_free(offsets: *mut IlassOffset, len: usize) {
+    if offsets.is_null() {
+        return;
+    }
+
+    // SAFETY: the caller guarantees that this is the boxed slice that `ilass_align` leaked
+    drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(offsets, len)) });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> IlassOptions {
+        IlassOptions {
+            split_penalty: 7.,
+            speed_optimization: -1.,
+        }
+    }
+
+    /// Calls `ilass_align` like a C caller would and copies the returned offsets.
+    fn align_like_c(reference: &[IlassSpan], incorrect: &[IlassSpan], opts: &IlassOptions) -> Result<Vec<i64>, c_int> {
+        let mut offsets: *mut IlassOffset = ptr::null_mut();
+        let mut len: usize = 0;
+        let code = unsafe {
+            ilass_align(
+                reference.as_ptr(),
+                reference.len(),
+                incorrect.as_ptr(),
+                incorrect.len(),
+                opts,
+                &mut offsets,
+                &mut len,
+            )
+        };
+        if code != ILASS_OK {
+            assert!(offsets.is_null());
+            return Err(code);
+        }
+
+        let deltas = unsafe { std::slice::from_raw_parts(offsets, len) }
+            .iter()
+            .map(|offset| offset.delta)
+            .collect();
+        unsafe { ilass_free(offsets, len) };
+        Ok(deltas)
+    }
+
+    fn test_spans(count: i64) -> Vec<IlassSpan> {
+        ilass::test_util::test_spans(count)
+            .into_iter()
+            .map(|span| IlassSpan {
+                start: span.start().as_i64(),
+                end: span.end().as_i64(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn align_constant_shift() {
+        // the incorrect spans are 1000 too late
+        let reference = test_spans(20);
+        let incorrect: Vec<IlassSpan> = reference
+            .iter()
+            .map(|span| IlassSpan {
+                start: span.start + 1000,
+                end: span.end + 1000,
+            })
+            .collect();
+
+        assert_eq!(align_like_c(&reference, &incorrect, &options()), Ok(vec![-1000; 20]));
+    }
+
+    #[test]
+    fn empty_input() {
+        let reference = [IlassSpan { start: 0, end: 1000 }];
+        assert_eq!(align_like_c(&reference, &[], &options()), Ok(vec![]));
+
+        unsafe { ilass_free(ptr::null_mut(), 0) };
+    }
+
+    #[test]
+    fn error_codes() {
+        let span = IlassSpan { start: 0, end: 1000 };
+        let reversed = IlassSpan { start: 1000, end: 0 };
+        assert_eq!(
+            align_like_c(&[span], &[reversed], &options()),
+            Err(ILASS_ERROR_INVALID_SPAN)
+        );
+
+        let mut offsets: *mut IlassOffset = ptr::null_mut();
+        let mut len: usize = 0;
+        let code = unsafe { ilass_align(ptr::null(), 1, &span, 1, &options(), &mut offsets, &mut len) };
+        assert_eq!(code, ILASS_ERROR_NULL_POINTER);
+        let code = unsafe { ilass_align(&span, 1, &span, 1, ptr::null(), &mut offsets, &mut len) };
+        assert_eq!(code, ILASS_ERROR_NULL_POINTER);
+    }
+
+    #[test]
+    fn rating_overflow_has_its_own_error_code() {
+        let overflow = AlignError::RatingOverflow {
+            offset: TimeDelta::from_i64(10),
+        };
+        assert_eq!(error_code(overflow), ILASS_ERROR_RATING_OVERFLOW);
+    }
+}
@@ -871,6 +871,9 @@ fn perform_vad(movie: &database::Movie, cache: TCache) -> Result<Vec<Span>, TopL
             let video_file_handler: VideoFileHandler = VideoFileHandler::open_video_file(
                 movie.path.as_path(),
                 None,
+                DEFAULT_VAD_FRAME_MS,
+                None,
+                None,
                 NoProgressInfo {},
                 /*ProgressInfo::new(
                     500,
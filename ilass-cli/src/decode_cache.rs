@@ -0,0 +1,236 @@
+//! A cache on disk for the voice segments that were extracted from a video file, so aligning
+//! against the same video again does not have to decode its audio again.
+//!
+//! The cache stores the voice segments and not the rating buffers of the aligner: those depend on
+//! the incorrect subtitle too, while the voice segments only depend on the video and the decoding
+//! options.
+
+use crate::errors::InputVideoError;
+use crate::{VideoFileHandler, video_decoder};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use subparse::timetypes::{TimePoint, TimeSpan};
+
+/// Changes whenever the layout of the cache entries changes, so old entries are not misread.
+static CACHE_FORMAT_VERSION: u32 = 1;
+
+/// Everything the extracted voice segments depend on. A cache entry is only used if all of it is
+/// the same, so modifying the video, decoding it with different options or with another version of
+/// ilass (whose decoder or voice detection might have changed) invalidates the entry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DecodeCacheKey {
+    ilass_version: String,
+    path: PathBuf,
+    modified_secs: u64,
+    modified_nanos: u32,
+    size: u64,
+    audio_index: Option<usize>,
+    vad_frame_ms: u32,
+    max_analysis_seconds: Option<f64>,
+}
+
+impl DecodeCacheKey {
+    /// Reads the modification time and size of the file at `path`. Fails if the file does not
+    /// exist or the platform does not provide a modification time.
+    pub fn new(
+        path: &Path,
+        audio_index: Option<usize>,
+        vad_frame_ms: u32,
+        max_analysis_seconds: Option<f64>,
+    ) -> std::io::Result<DecodeCacheKey> {
+        let path = fs::canonicalize(path)?;
+        let metadata = fs::metadata(&path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| std::io::Error::other("modification time is before 1970"))?;
+
+        Ok(DecodeCacheKey {
+            ilass_version: env!("CARGO_PKG_VERSION").to_string(),
+            path,
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            size: metadata.len(),
+            audio_index,
+            vad_frame_ms,
+            max_analysis_seconds,
+        })
+    }
+
+    fn file_name(&self) -> String {
+        // the whole key is stored in the entry too, so a hash collision only causes a cache miss
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(self).unwrap_or_default().hash(&mut hasher);
+        format!("{:016x}.json", hasher.finish())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    version: u32,
+    key: DecodeCacheKey,
+    analysis_truncated: bool,
+    spans_ms: Vec<(i64, i64)>,
+}
+
+/// A directory with one file of voice segments for every decoded video.
+///
+/// The cache only speeds up repeated runs: entries that can not be read are treated as missing,
+/// and failing to store an entry is not an error. An entry is only written after the audio was
+/// decoded completely and is renamed into place in one step, so an interrupted run never leaves a
+/// partial entry behind.
+///
+/// ```
+/// use ilass_cli::VideoFileHandler;
+/// use ilass_cli::decode_cache::{DecodeCache, DecodeCacheKey};
+/// use subparse::timetypes::{TimePoint, TimeSpan};
+///
+/// let dir = std::env::temp_dir().join(format!("ilass-decode-cache-doctest-{}", std::process::id()));
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let video_path = dir.join("video.mkv");
+/// std::fs::write(&video_path, b"not really a video").unwrap();
+///
+/// let cache = DecodeCache::new(dir.join("cache"));
+/// let key = DecodeCacheKey::new(&video_path, None, 10, None).unwrap();
+///
+/// let mut decode_calls = 0;
+/// for _ in 0..2 {
+///     let video_file = cache
+///         .get_or_decode(&key, || -> Result<VideoFileHandler, ()> {
+///             decode_calls += 1;
+///             let span = TimeSpan::new(TimePoint::from_msecs(1000), TimePoint::from_msecs(2500));
+///             Ok(VideoFileHandler::from_cache(vec![span]))
+///         })
+///         .unwrap();
+///     assert_eq!(video_file.timespans().len(), 1);
+/// }
+///
+/// // the second alignment was served from the cache
+/// assert_eq!(decode_calls, 1);
+///
+/// // decoding with other options misses the cache
+/// let other_key = DecodeCacheKey::new(&video_path, Some(1), 10, None).unwrap();
+/// assert!(cache.get(&other_key).is_none());
+///
+/// std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct DecodeCache {
+    dir: PathBuf,
+}
+
+impl DecodeCache {
+    pub fn new(dir: PathBuf) -> DecodeCache {
+        DecodeCache { dir }
+    }
+
+    /// `$XDG_CACHE_HOME/ilass`, `$HOME/.cache/ilass` or `%LOCALAPPDATA%\ilass` (whichever is set
+    /// first), or `None` if none of these variables is set.
+    pub fn default_dir() -> Option<PathBuf> {
+        let non_empty = |name: &str| {
+            std::env::var_os(name)
+                .filter(|value| !value.is_empty())
+                .map(PathBuf::from)
+        };
+
+        non_empty("XDG_CACHE_HOME")
+            .or_else(|| non_empty("HOME").map(|home| home.join(".cache")))
+            .or_else(|| non_empty("LOCALAPPDATA"))
+            .map(|dir| dir.join("ilass"))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the cached voice segments for `key`, or `None` if there is no usable entry.
+    pub fn get(&self, key: &DecodeCacheKey) -> Option<VideoFileHandler> {
+        let data = fs::read(self.dir.join(key.file_name())).ok()?;
+        let entry: CacheEntry = serde_json::from_slice(&data).ok()?;
+        if entry.version != CACHE_FORMAT_VERSION || entry.key != *key {
+            return None;
+        }
+
+        let subparse_timespans = entry
+            .spans_ms
+            .into_iter()
+            .map(|(start, end)| TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(end)))
+            .collect();
+
+        Some(VideoFileHandler {
+            subparse_timespans,
+            // the limit is part of the key, so it is the point at which the cached decoding stopped
+            truncated_at_seconds: key.max_analysis_seconds.filter(|_| entry.analysis_truncated),
+        })
+    }
+
+    /// Stores the voice segments of `video_file` for `key`, replacing an older entry.
+    pub fn put(&self, key: &DecodeCacheKey, video_file: &VideoFileHandler) -> std::io::Result<()> {
+        let entry = CacheEntry {
+            version: CACHE_FORMAT_VERSION,
+            key: key.clone(),
+            analysis_truncated: video_file.truncated_at_seconds.is_some(),
+            spans_ms: video_file
+                .subparse_timespans
+                .iter()
+                .map(|span| (span.start.msecs(), span.end.msecs()))
+                .collect(),
+        };
+        let data = serde_json::to_vec(&entry).map_err(std::io::Error::other)?;
+
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(key.file_name());
+        let temporary_path = path.with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&temporary_path, data)?;
+        fs::rename(&temporary_path, &path).inspect_err(|_| {
+            let _ = fs::remove_file(&temporary_path);
+        })
+    }
+
+    /// Returns the cached voice segments for `key` or calls `decode` and caches its result.
+    pub fn get_or_decode<E>(
+        &self,
+        key: &DecodeCacheKey,
+        decode: impl FnOnce() -> Result<VideoFileHandler, E>,
+    ) -> Result<VideoFileHandler, E> {
+        if let Some(video_file) = self.get(key) {
+            return Ok(video_file);
+        }
+
+        let video_file = decode()?;
+        let _ = self.put(key, &video_file);
+        Ok(video_file)
+    }
+
+    /// Like `VideoFileHandler::open_video_file`, but only decodes the audio if the cache has no
+    /// entry for this file and these options.
+    pub fn open_video_file(
+        &self,
+        file_path: &Path,
+        audio_index: Option<usize>,
+        vad_frame_ms: u32,
+        max_analysis_seconds: Option<f64>,
+        decode_rate_limit: Option<u64>,
+        video_decode_progress: impl video_decoder::ProgressHandler,
+    ) -> Result<VideoFileHandler, InputVideoError> {
+        let decode = || {
+            VideoFileHandler::open_video_file(
+                file_path,
+                audio_index,
+                vad_frame_ms,
+                max_analysis_seconds,
+                decode_rate_limit,
+                video_decode_progress,
+            )
+        };
+
+        match DecodeCacheKey::new(file_path, audio_index, vad_frame_ms, max_analysis_seconds) {
+            Ok(key) => self.get_or_decode(&key, decode),
+            // without a key the entry could not be invalidated, so the file is not cached
+            Err(_) => decode(),
+        }
+    }
+}
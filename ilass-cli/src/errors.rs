@@ -71,6 +71,7 @@ define_error!(InputFileError, InputFileErrorKind);
 pub enum InputFileErrorKind {
     VideoFile(PathBuf),
     SubtitleFile(PathBuf),
+    IntervalFile(PathBuf),
 }
 
 impl fmt::Display for InputFileErrorKind {
@@ -78,6 +79,7 @@ impl fmt::Display for InputFileErrorKind {
         match self {
             InputFileErrorKind::VideoFile(p) => write!(f, "processing video file '{}' failed", p.display()),
             InputFileErrorKind::SubtitleFile(p) => write!(f, "processing subtitle file '{}' failed", p.display()),
+            InputFileErrorKind::IntervalFile(p) => write!(f, "processing interval file '{}' failed", p.display()),
         }
     }
 }
@@ -89,6 +91,7 @@ pub enum FileOperationErrorKind {
     FileOpen { path: PathBuf },
     FileRead { path: PathBuf },
     FileWrite { path: PathBuf },
+    BackupExists { path: PathBuf },
 }
 
 impl fmt::Display for FileOperationErrorKind {
@@ -97,6 +100,11 @@ impl fmt::Display for FileOperationErrorKind {
             FileOperationErrorKind::FileOpen { path } => write!(f, "failed to open file '{}'", path.display()),
             FileOperationErrorKind::FileRead { path } => write!(f, "failed to read file '{}'", path.display()),
             FileOperationErrorKind::FileWrite { path } => write!(f, "failed to read file '{}'", path.display()),
+            FileOperationErrorKind::BackupExists { path } => write!(
+                f,
+                "backup file '{}' already exists (pass '--force' to overwrite it)",
+                path.display()
+            ),
         }
     }
 }
@@ -107,6 +115,7 @@ define_error!(InputVideoError, InputVideoErrorKind);
 pub enum InputVideoErrorKind {
     FailedToDecode { path: PathBuf },
     VadAnalysisFailed,
+    UnsupportedVadFrameLength { frame_ms: u32 },
 }
 
 impl fmt::Display for InputVideoErrorKind {
@@ -116,6 +125,35 @@ impl fmt::Display for InputVideoErrorKind {
                 write!(f, "failed to extract voice segments from file '{}'", path.display())
             }
             InputVideoErrorKind::VadAnalysisFailed => write!(f, "failed to analyse audio segment for voice activity"),
+            InputVideoErrorKind::UnsupportedVadFrameLength { frame_ms } => write!(
+                f,
+                "voice activity detection frames of {}ms are not supported (expected 10, 20 or 30)",
+                frame_ms
+            ),
+        }
+    }
+}
+
+define_error!(InputIntervalsError, InputIntervalsErrorKind);
+
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum InputIntervalsErrorKind {
+    ReadingIntervalFileFailed(PathBuf),
+    InvalidLine { path: PathBuf, line_number: usize },
+}
+
+impl fmt::Display for InputIntervalsErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputIntervalsErrorKind::ReadingIntervalFileFailed(path) => {
+                write!(f, "reading interval file '{}' failed", path.display())
+            }
+            InputIntervalsErrorKind::InvalidLine { path, line_number } => write!(
+                f,
+                "line {} of interval file '{}' is not a pair of start and end seconds",
+                line_number,
+                path.display()
+            ),
         }
     }
 }
@@ -128,6 +166,7 @@ pub enum InputSubtitleErrorKind {
     UnknownSubtitleFormat(PathBuf),
     ParsingSubtitleFailed(PathBuf),
     RetrievingSubtitleLinesFailed(PathBuf),
+    ExtractingEmbeddedSubtitleFailed(PathBuf),
 }
 
 impl fmt::Display for InputSubtitleErrorKind {
@@ -145,6 +184,13 @@ impl fmt::Display for InputSubtitleErrorKind {
             InputSubtitleErrorKind::RetrievingSubtitleLinesFailed(path) => {
                 write!(f, "retreiving subtitle file '{}' failed", path.display())
             }
+            InputSubtitleErrorKind::ExtractingEmbeddedSubtitleFailed(path) => {
+                write!(
+                    f,
+                    "extracting the embedded subtitles of file '{}' failed",
+                    path.display()
+                )
+            }
         }
     }
 }
@@ -169,8 +215,17 @@ pub enum InputArgumentsErrorKind {
     #[fail(display = "expected non-negative number for '{}', found '{}'", argument_name, value)]
     ExpectedNonNegativeNumber { argument_name: String, value: f64 },
 
+    #[fail(
+        display = "expected positive number of seconds for '{}', found '{}'",
+        argument_name, value
+    )]
+    ExpectedPositiveSeconds { argument_name: String, value: f64 },
+
     #[fail(display = "argument '{}' with value '{}' could not be parsed", argument_name, value)]
     ArgumentParseError { argument_name: String, value: String },
+
+    #[fail(display = "'{}' is only supported with the ffmpeg-binary backend", argument_name)]
+    RequiresFfmpegBinary { argument_name: String },
 }
 
 define_error!(TopLevelError, TopLevelErrorKind);
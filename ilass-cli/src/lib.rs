@@ -1,20 +1,26 @@
 use encoding_rs::Encoding;
 use failure::ResultExt;
+use ilass::segments::RatingBuffer;
 use ilass::{TimeDelta as AlgTimeDelta, TimePoint as AlgTimePoint, TimeSpan as AlgTimeSpan};
 use pbr::ProgressBar;
+use serde::Serialize;
+use std::cell::RefCell;
 use std::cmp::{max, min};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::result::Result;
 
+use decode_cache::DecodeCache;
 use errors::*;
 
+pub mod decode_cache;
 pub mod errors;
 pub mod video_decoder;
 
 use subparse::timetypes::*;
-use subparse::{SubtitleFile, get_subtitle_format_err, parse_bytes};
+use subparse::{SubtitleEntry, SubtitleFile, get_subtitle_format_err, parse_bytes};
 
 pub struct NoProgressInfo {}
 
@@ -30,11 +36,92 @@ impl video_decoder::ProgressHandler for NoProgressInfo {
     fn finish(&mut self) {}
 }
 
+/// One line of the newline-delimited JSON stream written by `--json-events`.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JsonEvent<'a> {
+    PhaseStart {
+        phase: &'a str,
+        steps: i64,
+    },
+    Progress {
+        phase: &'a str,
+        step: i64,
+        steps: i64,
+    },
+    PhaseEnd {
+        phase: &'a str,
+    },
+    Result {
+        fps_scaling_factor: f64,
+        shifts: &'a [JsonShiftGroup],
+    },
+}
+
+/// A block of consecutive subtitle lines which were shifted by the same offset.
+#[derive(Serialize)]
+pub struct JsonShiftGroup {
+    pub lines: usize,
+    pub first_start_ms: i64,
+    pub last_start_ms: i64,
+    pub offset_ms: i64,
+}
+
+/// The correction of the reference media that `--direction media` writes instead of a corrected
+/// subtitle file.
+#[derive(Serialize)]
+pub struct JsonMediaCorrection {
+    pub fps_scaling_factor: f64,
+    pub shifts: Vec<JsonMediaShift>,
+    /// The ffmpeg input option that applies the correction (only if it is a single constant shift).
+    pub ffmpeg_input_args: Option<String>,
+}
+
+/// From `start_ms` on (in the time of the reference media), the media has to be shifted by
+/// `offset_ms` to match the subtitles.
+#[derive(Serialize)]
+pub struct JsonMediaShift {
+    pub start_ms: i64,
+    pub offset_ms: i64,
+}
+
+pub struct JsonEventWriter {
+    output: Box<dyn Write>,
+}
+
+pub type SharedJsonEventWriter = Rc<RefCell<JsonEventWriter>>;
+
+impl JsonEventWriter {
+    /// Opens the event stream; the path `-` writes the events to stderr.
+    pub fn open(path: &Path) -> Result<JsonEventWriter, FileOperationError> {
+        let output: Box<dyn Write> = if path == Path::new("-") {
+            Box::new(std::io::stderr())
+        } else {
+            Box::new(File::create(path).with_context(|_| FileOperationErrorKind::FileOpen {
+                path: path.to_path_buf(),
+            })?)
+        };
+        Ok(JsonEventWriter { output })
+    }
+
+    pub fn emit(&mut self, event: &JsonEvent) {
+        // the events are purely informational, so a reader that went away should not abort the synchronization
+        if let Ok(line) = serde_json::to_string(event) {
+            let _ = writeln!(self.output, "{}", line);
+            let _ = self.output.flush();
+        }
+    }
+}
+
 pub struct ProgressInfo {
     init_msg: Option<String>,
     prescaler: i64,
     counter: i64,
     progress_bar: Option<ProgressBar<std::io::Stdout>>,
+    json_events: Option<(SharedJsonEventWriter, &'static str)>,
+    steps: i64,
+    step: i64,
+    reported_percent: i64,
 }
 
 impl ProgressInfo {
@@ -44,28 +131,81 @@ impl ProgressInfo {
             prescaler,
             counter: 0,
             progress_bar: None,
+            json_events: None,
+            steps: 0,
+            step: 0,
+            reported_percent: 0,
         }
     }
+
+    /// Additionally reports the progress as JSON events for the given phase (does nothing for `None`).
+    pub fn with_json_events(
+        mut self,
+        json_events: Option<&SharedJsonEventWriter>,
+        phase: &'static str,
+    ) -> ProgressInfo {
+        self.json_events = json_events.map(|json_events| (json_events.clone(), phase));
+        self
+    }
+
+    fn emit_json_event(&self, event: JsonEvent) {
+        if let Some((json_events, _)) = &self.json_events {
+            json_events.borrow_mut().emit(&event);
+        }
+    }
+
+    fn phase(&self) -> &'static str {
+        self.json_events.as_ref().map_or("", |(_, phase)| *phase)
+    }
 }
 
 impl ProgressInfo {
     fn init(&mut self, steps: i64) {
-        self.progress_bar = Some(ProgressBar::new((steps / self.prescaler) as u64));
+        // a progress bar without any steps can not display a fraction
+        self.progress_bar = if steps / self.prescaler > 0 {
+            Some(ProgressBar::new((steps / self.prescaler) as u64))
+        } else {
+            None
+        };
         if let Some(init_msg) = &self.init_msg {
             println!("{}", init_msg);
         }
+
+        self.steps = steps;
+        self.step = 0;
+        self.reported_percent = 0;
+        self.emit_json_event(JsonEvent::PhaseStart {
+            phase: self.phase(),
+            steps,
+        });
     }
 
     fn inc(&mut self) {
         self.counter += 1;
         if self.counter == self.prescaler {
-            self.progress_bar.as_mut().unwrap().inc();
+            if let Some(progress_bar) = &mut self.progress_bar {
+                progress_bar.inc();
+            }
             self.counter = 0;
         }
+
+        // only report whole percents, so the stream stays small for long files
+        self.step += 1;
+        if self.steps > 0 && self.step * 100 / self.steps > self.reported_percent {
+            self.reported_percent = self.step * 100 / self.steps;
+            self.emit_json_event(JsonEvent::Progress {
+                phase: self.phase(),
+                step: self.step,
+                steps: self.steps,
+            });
+        }
     }
 
     fn finish(&mut self) {
-        self.progress_bar.as_mut().unwrap().finish_println("\n");
+        if let Some(progress_bar) = &mut self.progress_bar {
+            progress_bar.finish_println("\n");
+        }
+        self.emit_json_event(JsonEvent::PhaseEnd { phase: self.phase() });
     }
 }
 
@@ -115,6 +255,155 @@ pub fn write_data_to_file(path: &Path, d: Vec<u8>) -> Result<(), FileOperationEr
     Ok(())
 }
 
+/// Summary of one synchronization, printed as a single line with `--stats`.
+///
+/// ```
+/// use subparse::timetypes::TimeDelta;
+///
+/// let summary = ilass_cli::RunSummary {
+///     input_path: "in.srt".into(),
+///     output_path: "out.srt".into(),
+///     shift: Some(TimeDelta::from_msecs(-2500)),
+///     score: 12.345,
+///     offset_segments: 1,
+///     lines_changed: 120,
+///     elapsed: std::time::Duration::from_millis(1500),
+///     warnings: vec!["low-overlap"],
+/// };
+/// assert_eq!(
+///     summary.to_string(),
+///     format!(
+///         "input='in.srt' output='out.srt' shift={} score=12.35 offset-segments=1 lines-changed=120 elapsed=1.50s warnings=low-overlap",
+///         TimeDelta::from_msecs(-2500)
+///     )
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct RunSummary {
+    pub input_path: PathBuf,
+    pub output_path: PathBuf,
+
+    /// the offset all lines were shifted by, or `None` if the lines got different offsets
+    pub shift: Option<TimeDelta>,
+
+    /// the score of the alignment
+    pub score: f64,
+
+    /// number of blocks of consecutive lines with the same offset
+    pub offset_segments: usize,
+
+    /// number of lines whose timing was changed
+    pub lines_changed: usize,
+
+    pub elapsed: std::time::Duration,
+
+    /// short names of the warnings that were printed during the run
+    pub warnings: Vec<&'static str>,
+}
+
+impl std::fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "input='{}' output='{}' shift=",
+            self.input_path.display(),
+            self.output_path.display()
+        )?;
+        match self.shift {
+            Some(shift) => write!(f, "{}", shift)?,
+            None => write!(f, "time-varying")?,
+        }
+        write!(
+            f,
+            " score={:.2} offset-segments={} lines-changed={} elapsed={:.2}s warnings={}",
+            self.score,
+            self.offset_segments,
+            self.lines_changed,
+            self.elapsed.as_secs_f64(),
+            if self.warnings.is_empty() {
+                "none".to_string()
+            } else {
+                self.warnings.join(",")
+            }
+        )
+    }
+}
+
+/// Returns the `shift`, `offset_segments` and `lines_changed` of the `RunSummary` for lines that
+/// were moved by the deltas of the `shift_groups` (see `get_subtitle_delta_groups`) and scaled by
+/// `fps_scaling_factor`. A framerate correction changes the timing of every line.
+pub fn summarize_shift_groups(
+    shift_groups: &[(TimeDelta, Vec<TimeSpan>)],
+    fps_scaling_factor: f64,
+) -> (Option<TimeDelta>, usize, usize) {
+    // the groups are blocks of consecutive lines, so all lines have the same delta in a single group
+    let shift = match shift_groups {
+        [(delta, _)] => Some(*delta),
+        _ => None,
+    };
+    let lines_changed = shift_groups
+        .iter()
+        .filter(|(delta, _)| fps_scaling_factor != 1. || delta.msecs() != 0)
+        .map(|(_, lines)| lines.len())
+        .sum();
+    (shift, shift_groups.len(), lines_changed)
+}
+
+/// Returns the path of the numbered output file for an alignment candidate: `output.srt` becomes
+/// `output.<number>.srt`, which keeps the extension that determines the subtitle format.
+pub fn candidate_file_path(path: &Path, number: usize) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_owned();
+    file_name.push(format!(".{}", number));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+/// Copies the file at `path` to `<path>.bak` and returns the path of the backup, or `None` if
+/// there is no file at `path`. An existing backup is only overwritten if `force` is set.
+pub fn backup_file(path: &Path, force: bool) -> Result<Option<PathBuf>, FileOperationError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let mut backup_path = path.as_os_str().to_owned();
+    backup_path.push(".bak");
+    let backup_path = PathBuf::from(backup_path);
+
+    if backup_path.exists() && !force {
+        return Err(FileOperationErrorKind::BackupExists { path: backup_path }.into());
+    }
+
+    std::fs::copy(path, &backup_path).with_context(|_| FileOperationErrorKind::FileWrite {
+        path: backup_path.clone(),
+    })?;
+
+    Ok(Some(backup_path))
+}
+
+/// Sets the modification time of `path` to the one of `source_path`.
+pub fn copy_modification_time(source_path: &Path, path: &Path) -> Result<(), FileOperationError> {
+    let modified = std::fs::metadata(source_path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|_| FileOperationErrorKind::FileRead {
+            path: source_path.to_path_buf(),
+        })?;
+
+    let file = File::options()
+        .write(true)
+        .open(path)
+        .with_context(|_| FileOperationErrorKind::FileOpen {
+            path: path.to_path_buf(),
+        })?;
+    file.set_times(std::fs::FileTimes::new().set_modified(modified))
+        .with_context(|_| FileOperationErrorKind::FileWrite {
+            path: path.to_path_buf(),
+        })?;
+    Ok(())
+}
+
 pub fn timing_to_alg_timepoint(t: TimePoint, interval: i64) -> AlgTimePoint {
     assert!(interval > 0);
     AlgTimePoint::from(t.msecs() / interval)
@@ -138,15 +427,136 @@ pub fn timings_to_alg_timespans(v: &[TimeSpan], interval: i64) -> Vec<AlgTimeSpa
         .collect()
 }
 
+pub fn alg_timepoint_to_timing(t: AlgTimePoint, interval: i64) -> TimePoint {
+    assert!(interval > 0);
+    TimePoint::from_msecs(t.as_i64() * interval)
+}
+
+/// Converts parsed subtitle lines into timespans of the alignment algorithm, where one algorithm
+/// time unit is `interval` milliseconds. Lines with `end < start` are flipped.
+///
+/// Timestamps that are multiples of `interval` survive the round trip through
+/// `alg_timespans_to_subtitle_entries`:
+///
+/// ```
+/// use ilass_cli::{alg_timespans_to_subtitle_entries, subtitle_entries_to_alg_timespans};
+/// use subparse::SubtitleEntry;
+/// use subparse::timetypes::{TimePoint, TimeSpan};
+///
+/// let span = |start, end| TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(end));
+/// let entries = vec![SubtitleEntry::from(span(1000, 2500)), SubtitleEntry::from(span(4000, 7000))];
+///
+/// let alg_timespans = subtitle_entries_to_alg_timespans(&entries, 10);
+/// assert_eq!((alg_timespans[0].start().as_i64(), alg_timespans[0].end().as_i64()), (100, 250));
+///
+/// let restored = alg_timespans_to_subtitle_entries(&alg_timespans, 10);
+/// assert_eq!(restored.len(), entries.len());
+/// for (entry, restored_entry) in entries.iter().zip(&restored) {
+///     assert_eq!(restored_entry.timespan, entry.timespan);
+///     assert_eq!(restored_entry.line, None);
+/// }
+/// ```
+pub fn subtitle_entries_to_alg_timespans(entries: &[SubtitleEntry], interval: i64) -> Vec<AlgTimeSpan> {
+    let timespans: Vec<TimeSpan> = entries.iter().map(|entry| entry.timespan).collect();
+    timings_to_alg_timespans(&timespans, interval)
+}
+
+/// Inverse of `subtitle_entries_to_alg_timespans` (the lines of the returned entries are empty).
+pub fn alg_timespans_to_subtitle_entries(v: &[AlgTimeSpan], interval: i64) -> Vec<SubtitleEntry> {
+    v.iter()
+        .map(|timespan| {
+            SubtitleEntry::from(TimeSpan::new(
+                alg_timepoint_to_timing(timespan.start(), interval),
+                alg_timepoint_to_timing(timespan.end(), interval),
+            ))
+        })
+        .collect()
+}
+
 pub fn alg_deltas_to_timing_deltas(v: &[AlgTimeDelta], interval: i64) -> Vec<TimeDelta> {
     v.iter().cloned().map(|x| alg_delta_to_delta(x, interval)).collect()
 }
 
+/// Checks whether the incorrect spans still cover enough of the reference time range once they are
+/// moved by the aligned `alg_deltas` (see `ilass::check_overlap`).
+pub fn check_aligned_overlap(
+    ref_spans: &[AlgTimeSpan],
+    inc_spans: &[AlgTimeSpan],
+    alg_deltas: &[AlgTimeDelta],
+) -> Option<ilass::AlignWarning> {
+    let aligned_spans: Vec<AlgTimeSpan> = inc_spans
+        .iter()
+        .zip(alg_deltas)
+        .map(|(&span, &delta)| span + delta)
+        .collect();
+    ilass::check_overlap(ref_spans, &aligned_spans)
+}
+
+/// The rating curve of the reference in reference time: one at the points that one of the
+/// `ref_spans` covers and zero everywhere else between the first and the last span (see
+/// `RatingBuffer::from_speech_intervals`). `None` if there is no span with a positive length.
+pub fn reference_rating(ref_spans: &[AlgTimeSpan]) -> Option<RatingBuffer> {
+    let zero = AlgTimePoint::from(0);
+    let intervals: Vec<(AlgTimeDelta, AlgTimeDelta)> = ref_spans
+        .iter()
+        .filter(|span| !span.is_empty())
+        .map(|span| (span.start() - zero, span.end() - zero))
+        .collect();
+
+    let start = intervals.iter().map(|&(start, _)| start).min()?;
+    let end = intervals.iter().map(|&(_, end)| end).max()?;
+    Some(RatingBuffer::from_speech_intervals(start, end, &intervals))
+}
+
+/// Writes the `rating` as CSV with one row of the time (in milliseconds) and the rating (see
+/// `RatingBuffer::sample_readable`) every `step_ms` milliseconds, e.g. to plot the reference rating
+/// against the waveform of the audio.
+pub fn write_rating_csv(
+    mut writer: impl Write,
+    rating: &RatingBuffer,
+    step_ms: i64,
+    interval: i64,
+) -> std::io::Result<()> {
+    writeln!(writer, "time_ms,rating")?;
+    let step = AlgTimeDelta::from_i64(max(step_ms / interval, 1));
+    for (point, rating) in rating.sample_readable(step) {
+        writeln!(writer, "{},{}", alg_delta_to_delta(point, interval).msecs(), rating)?;
+    }
+    writer.flush()
+}
+
+/// Rounds `delta` to the nearest multiple of `quantum_ms` milliseconds (halfway cases are rounded away
+/// from zero).
+pub fn quantize_delta(delta: TimeDelta, quantum_ms: i64) -> TimeDelta {
+    assert!(quantum_ms > 0);
+    let msecs = delta.msecs();
+    let rounded = (msecs.abs() + quantum_ms / 2) / quantum_ms * quantum_ms;
+    TimeDelta::from_msecs(rounded * msecs.signum())
+}
+
+/// Converts the aligned `alg_deltas` to the deltas that are applied to the lines, which are rounded
+/// to multiples of `offset_quantum` milliseconds if it is given (see `quantize_delta`).
+pub fn alg_deltas_to_applied_deltas(
+    alg_deltas: &[AlgTimeDelta],
+    interval: i64,
+    offset_quantum: Option<i64>,
+) -> Vec<TimeDelta> {
+    let deltas = alg_deltas_to_timing_deltas(alg_deltas, interval);
+    match offset_quantum {
+        // every line is shifted by a constant offset, so there is no slope which would need quantizing
+        Some(offset_quantum) => deltas
+            .into_iter()
+            .map(|delta| quantize_delta(delta, offset_quantum))
+            .collect(),
+        None => deltas,
+    }
+}
+
 /// Groups consecutive timespans with the same delta together.
-pub fn get_subtitle_delta_groups(mut v: Vec<(AlgTimeDelta, TimeSpan)>) -> Vec<(AlgTimeDelta, Vec<TimeSpan>)> {
+pub fn get_subtitle_delta_groups<D: PartialEq>(mut v: Vec<(D, TimeSpan)>) -> Vec<(D, Vec<TimeSpan>)> {
     v.sort_by_key(|t| min((t.1).start, (t.1).end));
 
-    let mut result: Vec<(AlgTimeDelta, Vec<TimeSpan>)> = Vec::new();
+    let mut result: Vec<(D, Vec<TimeSpan>)> = Vec::new();
 
     for (delta, original_timespan) in v {
         let mut new_block = false;
@@ -169,9 +579,106 @@ pub fn get_subtitle_delta_groups(mut v: Vec<(AlgTimeDelta, TimeSpan)>) -> Vec<(A
     result
 }
 
+/// Rewrites the timestamps in the timing lines of a SubRip file to the strict `HH:MM:SS,mmm` form,
+/// so that files with a `.` as decimal separator or with fewer/more than three digits of fractional
+/// seconds can be parsed. All other bytes are kept as they are.
+pub fn normalize_srt_timestamps(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    for line in data.split_inclusive(|&byte| byte == b'\n') {
+        if line.windows(3).any(|w| w == b"-->") {
+            normalize_srt_timing_line(line, &mut result);
+        } else {
+            result.extend_from_slice(line);
+        }
+    }
+    result
+}
+
+fn normalize_srt_timing_line(line: &[u8], result: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < line.len() {
+        let after_digit = i > 0 && line[i - 1].is_ascii_digit();
+        match parse_srt_timestamp(&line[i..]) {
+            Some(timestamp) if !after_digit => {
+                for (group_idx, group) in timestamp.hms.iter().enumerate() {
+                    if group_idx > 0 {
+                        result.push(b':');
+                    }
+                    // "5" is "05", longer groups (like 100 hours) are kept
+                    if group.len() < 2 {
+                        result.push(b'0');
+                    }
+                    result.extend_from_slice(group);
+                }
+                result.push(b',');
+                // fractional seconds: "5" is 500ms, "0123" is 12ms
+                for digit_idx in 0..3 {
+                    result.push(timestamp.fraction.get(digit_idx).copied().unwrap_or(b'0'));
+                }
+                i += timestamp.len;
+            }
+            _ => {
+                result.push(line[i]);
+                i += 1;
+            }
+        }
+    }
+}
+
+/// A timestamp found by `parse_srt_timestamp`.
+#[derive(Debug, PartialEq, Eq)]
+struct SrtTimestamp<'a> {
+    /// the digits of the hours, minutes and seconds
+    hms: [&'a [u8]; 3],
+    /// the digits of the fractional seconds (can be empty)
+    fraction: &'a [u8],
+    /// the number of bytes of the whole timestamp
+    len: usize,
+}
+
+/// Parses `H:M:S` with an optional `,`/`.` and fractional seconds at the start of `s`.
+fn parse_srt_timestamp(s: &[u8]) -> Option<SrtTimestamp<'_>> {
+    let count_digits = |from: usize| s[from..].iter().take_while(|byte| byte.is_ascii_digit()).count();
+
+    let mut hms: [&[u8]; 3] = [&[]; 3];
+    let mut pos = 0;
+    for (group_idx, group) in hms.iter_mut().enumerate() {
+        let digits = count_digits(pos);
+        if digits == 0 {
+            return None;
+        }
+        *group = &s[pos..pos + digits];
+        pos += digits;
+        if group_idx < 2 {
+            if s.get(pos) != Some(&b':') {
+                return None;
+            }
+            pos += 1;
+        }
+    }
+
+    if let Some(b',') | Some(b'.') = s.get(pos) {
+        let digits = count_digits(pos + 1);
+        if digits > 0 {
+            return Some(SrtTimestamp {
+                hms,
+                fraction: &s[pos + 1..pos + 1 + digits],
+                len: pos + 1 + digits,
+            });
+        }
+    }
+
+    Some(SrtTimestamp {
+        hms,
+        fraction: &[],
+        len: pos,
+    })
+}
+
 pub enum InputFileHandler {
     Subtitle(SubtitleFileHandler),
     Video(VideoFileHandler),
+    Intervals(IntervalFileHandler),
 }
 
 pub struct SubtitleFileHandler {
@@ -192,7 +699,46 @@ impl SubtitleFileHandler {
         let file_format = get_subtitle_format_err(file_path.extension(), &sub_data)
             .with_context(|_| InputSubtitleErrorKind::UnknownSubtitleFormat(file_path.to_path_buf()))?;
 
-        let parsed_subtitle_data: SubtitleFile = parse_bytes(file_format, &sub_data, sub_encoding, sub_fps)
+        // SubRip files in the wild often have slightly malformed timestamps which the parser rejects
+        let sub_data = if file_format == subparse::SubtitleFormat::SubRip {
+            normalize_srt_timestamps(&sub_data)
+        } else {
+            sub_data
+        };
+
+        Self::parse_sub_data(file_path, file_format, &sub_data, sub_encoding, sub_fps)
+    }
+
+    /// Extracts the embedded text subtitle stream `subtitle_index` (or the subtitle stream selected
+    /// by `policy`) of a video file with ffmpeg and parses it like a SubRip file.
+    #[cfg(feature = "ffmpeg-binary")]
+    pub fn open_embedded_sub(
+        file_path: &Path,
+        subtitle_index: Option<usize>,
+        policy: video_decoder::SubtitleTrackPolicy,
+        sub_fps: f64,
+    ) -> Result<SubtitleFileHandler, InputSubtitleError> {
+        let sub_data = video_decoder::VideoDecoder::extract_subtitle_stream(file_path, subtitle_index, policy)
+            .with_context(|_| InputSubtitleErrorKind::ExtractingEmbeddedSubtitleFailed(file_path.to_path_buf()))?;
+
+        // ffmpeg always writes UTF-8
+        Self::parse_sub_data(
+            file_path,
+            subparse::SubtitleFormat::SubRip,
+            &sub_data,
+            Some(encoding_rs::UTF_8),
+            sub_fps,
+        )
+    }
+
+    fn parse_sub_data(
+        file_path: &Path,
+        file_format: subparse::SubtitleFormat,
+        sub_data: &[u8],
+        sub_encoding: Option<&'static Encoding>,
+        sub_fps: f64,
+    ) -> Result<SubtitleFileHandler, InputSubtitleError> {
+        let parsed_subtitle_data: SubtitleFile = parse_bytes(file_format, sub_data, sub_encoding, sub_fps)
             .with_context(|_| InputSubtitleErrorKind::ParsingSubtitleFailed(file_path.to_path_buf()))?;
 
         let subparse_timespans: Vec<TimeSpan> = parsed_subtitle_data
@@ -225,29 +771,144 @@ impl SubtitleFileHandler {
     }
 }
 
+/// Sample rate of the audio the voice activity detection runs on.
+pub static VAD_SAMPLE_RATE: u32 = 8000;
+
+/// Frame length of the voice activity detection that is used if nothing else is configured.
+pub static DEFAULT_VAD_FRAME_MS: u32 = 10;
+
+/// The share of the progress of `VideoFileHandler::open_video_file` that decoding the audio,
+/// combining the voice frames into segments and converting the segments into time spans take.
+pub static VIDEO_PROGRESS_WEIGHTS: [u32; 3] = [60, 30, 10];
+
+/// Returns the number of samples in a voice activity detection frame of `frame_ms` milliseconds.
+///
+/// The WebRTC voice activity detection only accepts frames of 10, 20 or 30 milliseconds.
+///
+/// ```
+/// assert_eq!(ilass_cli::vad_frame_samples(20).unwrap(), 160);
+/// assert!(ilass_cli::vad_frame_samples(0).is_err());
+/// ```
+pub fn vad_frame_samples(frame_ms: u32) -> Result<usize, InputVideoError> {
+    if ![10, 20, 30].contains(&frame_ms) {
+        return Err(InputVideoErrorKind::UnsupportedVadFrameLength { frame_ms }.into());
+    }
+    Ok((VAD_SAMPLE_RATE * frame_ms / 1000) as usize)
+}
+
+/// A preset for the parameters that trade the speed of the alignment for its accuracy (see
+/// `QualityOptions::with_quality`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    Fast,
+    Balanced,
+    Precise,
+}
+
+/// The parameters that trade the speed of the alignment for its accuracy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualityOptions {
+    /// The smallest recognized time interval in milliseconds.
+    pub interval_ms: i64,
+
+    /// See `ilass::align` (`None` disables the speed optimization).
+    pub speed_optimization: Option<f64>,
+
+    /// Frame length of the voice activity detection (see `vad_frame_samples`).
+    pub vad_frame_ms: u32,
+}
+
+impl QualityOptions {
+    /// Returns the parameters of a preset:
+    ///
+    /// | quality    | interval | speed optimization | VAD frame length |
+    /// |------------|----------|--------------------|------------------|
+    /// | `Fast`     | 10ms     | 3                  | 30ms             |
+    /// | `Balanced` | 1ms      | 1                  | 10ms             |
+    /// | `Precise`  | 1ms      | disabled           | 10ms             |
+    ///
+    /// `Balanced` are the defaults of the command line tool.
+    ///
+    /// ```
+    /// use ilass_cli::{QualityOptions, Quality};
+    ///
+    /// let fast = QualityOptions::with_quality(Quality::Fast);
+    /// assert_eq!((fast.interval_ms, fast.speed_optimization, fast.vad_frame_ms), (10, Some(3.), 30));
+    ///
+    /// let balanced = QualityOptions::with_quality(Quality::Balanced);
+    /// assert_eq!((balanced.interval_ms, balanced.speed_optimization, balanced.vad_frame_ms), (1, Some(1.), 10));
+    ///
+    /// let precise = QualityOptions::with_quality(Quality::Precise);
+    /// assert_eq!((precise.interval_ms, precise.speed_optimization, precise.vad_frame_ms), (1, None, 10));
+    /// ```
+    pub fn with_quality(quality: Quality) -> QualityOptions {
+        match quality {
+            Quality::Fast => QualityOptions {
+                interval_ms: 10,
+                speed_optimization: Some(3.),
+                vad_frame_ms: 30,
+            },
+            Quality::Balanced => QualityOptions {
+                interval_ms: 1,
+                speed_optimization: Some(1.),
+                vad_frame_ms: DEFAULT_VAD_FRAME_MS,
+            },
+            Quality::Precise => QualityOptions {
+                interval_ms: 1,
+                speed_optimization: None,
+                vad_frame_ms: DEFAULT_VAD_FRAME_MS,
+            },
+        }
+    }
+}
+
+/// Returns `true` if the decoding of `vad_frames` frames of `vad_frame_ms` milliseconds stopped at
+/// the `max_analysis_seconds` limit.
+fn is_analysis_truncated(vad_frames: usize, vad_frame_ms: u32, max_analysis_seconds: Option<f64>) -> bool {
+    // the audio was cut off if the decoder stopped within the last frame before the limit
+    max_analysis_seconds.is_some_and(|max_analysis_seconds| {
+        (vad_frames + 1) as f64 * f64::from(vad_frame_ms) >= max_analysis_seconds * 1000.
+    })
+}
+
 pub struct VideoFileHandler {
     //video_file_format: VideoFileFormat,
     subparse_timespans: Vec<TimeSpan>,
     //aligner_timespans: Vec<ilass::TimeSpan>,
+    /// the `max_analysis_seconds` limit at which the decoding stopped
+    truncated_at_seconds: Option<f64>,
 }
 
 impl VideoFileHandler {
     pub fn from_cache(timespans: Vec<TimeSpan>) -> VideoFileHandler {
         VideoFileHandler {
             subparse_timespans: timespans,
+            truncated_at_seconds: None,
         }
     }
 
+    /// Extracts the voice segments from the audio of a video file. Every frame of `vad_frame_ms`
+    /// milliseconds is classified separately (see `vad_frame_samples`).
+    ///
+    /// Only the first `max_analysis_seconds` seconds of the audio are analyzed if a limit is given
+    /// (regardless of the duration the file claims to have), and at most `decode_rate_limit`
+    /// samples are decoded per second (see `video_decoder::RateLimiter`).
     pub fn open_video_file(
         file_path: &Path,
         audio_index: Option<usize>,
+        vad_frame_ms: u32,
+        max_analysis_seconds: Option<f64>,
+        decode_rate_limit: Option<u64>,
         video_decode_progress: impl video_decoder::ProgressHandler,
     ) -> Result<VideoFileHandler, InputVideoError> {
         //video_decoder::VideoDecoder::decode(file_path, );
         use webrtc_vad::*;
 
+        let frame_samples = vad_frame_samples(vad_frame_ms)?;
+
         struct WebRtcFvad {
             fvad: Vad,
+            frame_samples: usize,
             vad_buffer: Vec<bool>,
         }
 
@@ -256,8 +917,8 @@ impl VideoFileHandler {
             type Error = InputVideoError;
 
             fn push_samples(&mut self, samples: &[i16]) -> Result<(), InputVideoError> {
-                // the chunked audio receiver should only provide 10ms of 8000kHz -> 80 samples
-                assert!(samples.len() == 80);
+                // the chunked audio receiver should only provide exactly one frame
+                assert!(samples.len() == self.frame_samples);
 
                 let is_voice = self
                     .fvad
@@ -276,16 +937,28 @@ impl VideoFileHandler {
 
         let vad_processor = WebRtcFvad {
             fvad: Vad::new_with_rate(SampleRate::Rate8kHz),
+            frame_samples,
             vad_buffer: Vec::new(),
         };
 
-        let chunk_processor = video_decoder::ChunkedAudioReceiver::new(80, vad_processor);
+        let chunk_processor = video_decoder::ChunkedAudioReceiver::new(frame_samples, vad_processor);
+
+        let mut progress = video_decoder::PhasedProgressHandler::new(video_decode_progress, &VIDEO_PROGRESS_WEIGHTS);
+
+        let vad_buffer = video_decoder::VideoDecoder::decode(
+            file_path,
+            audio_index,
+            max_analysis_seconds,
+            decode_rate_limit,
+            chunk_processor,
+            progress.next_phase(),
+        )
+        .with_context(|_| InputVideoErrorKind::FailedToDecode {
+            path: PathBuf::from(file_path),
+        })?;
 
-        let vad_buffer =
-            video_decoder::VideoDecoder::decode(file_path, audio_index, chunk_processor, video_decode_progress)
-                .with_context(|_| InputVideoErrorKind::FailedToDecode {
-                    path: PathBuf::from(file_path),
-                })?;
+        let truncated_at_seconds = max_analysis_seconds
+            .filter(|_| is_analysis_truncated(vad_buffer.len(), vad_frame_ms, max_analysis_seconds));
 
         let mut voice_segments: Vec<(i64, i64)> = Vec::new();
         let mut voice_segment_start = 0;
@@ -295,7 +968,10 @@ impl VideoFileHandler {
         let mut last_segment_end = 0;
         let mut already_saved_span = true;
 
+        let mut combine_progress = progress.next_phase();
+        combine_progress.init(vad_buffer.len() as i64 + 1);
         for (i, is_voice_segment) in vad_buffer.into_iter().chain(std::iter::once(false)).enumerate() {
+            combine_progress.inc();
             let i = i as i64;
 
             if is_voice_segment {
@@ -313,17 +989,38 @@ impl VideoFileHandler {
             }
         }
 
+        combine_progress.finish();
+
+        let mut convert_progress = progress.next_phase();
+        convert_progress.init(voice_segments.len() as i64);
         let subparse_timespans: Vec<TimeSpan> = voice_segments
             .into_iter()
-            .map(|(start, end)| TimeSpan::new(TimePoint::from_msecs(start * 10), TimePoint::from_msecs(end * 10)))
+            .map(|(start, end)| {
+                convert_progress.inc();
+                let frame_ms = i64::from(vad_frame_ms);
+                TimeSpan::new(
+                    TimePoint::from_msecs(start * frame_ms),
+                    TimePoint::from_msecs(end * frame_ms),
+                )
+            })
             .collect();
+        convert_progress.finish();
+        progress.finish();
 
         Ok(VideoFileHandler {
             //video_file_format: VideoFileFormat::NotImplemented,
             subparse_timespans,
+            truncated_at_seconds,
         })
     }
 
+    /// Returns `AlignWarning::AnalysisTruncated` if the audio was longer than the `max_analysis_seconds`
+    /// limit, so only its beginning was analyzed.
+    pub fn analysis_warning(&self) -> Option<ilass::AlignWarning> {
+        self.truncated_at_seconds
+            .map(|analyzed_seconds| ilass::AlignWarning::AnalysisTruncated { analyzed_seconds })
+    }
+
     pub fn filter_with_min_span_length_ms(&mut self, min_vad_span_length_ms: i64) {
         self.subparse_timespans = self
             .subparse_timespans
@@ -338,12 +1035,113 @@ impl VideoFileHandler {
     }
 }
 
+/// Parses a list of intervals which should be used in place of subtitle lines, e.g. the times in
+/// which OCR found hardcoded subtitles on screen.
+///
+/// Every line holds the start and the end of one interval in seconds, separated by whitespace, a
+/// comma or a semicolon. Empty lines and lines starting with `#` are ignored. Returns the (1-based)
+/// number of the first invalid line as error.
+///
+/// ```
+/// use subparse::timetypes::{TimePoint, TimeSpan};
+///
+/// let intervals = ilass_cli::parse_intervals("# appear, disappear\n1.5, 3.25\n\n10 12.5\n").unwrap();
+/// assert_eq!(
+///     intervals,
+///     vec![
+///         TimeSpan::new(TimePoint::from_msecs(1500), TimePoint::from_msecs(3250)),
+///         TimeSpan::new(TimePoint::from_msecs(10000), TimePoint::from_msecs(12500)),
+///     ]
+/// );
+///
+/// assert_eq!(ilass_cli::parse_intervals("1.5, 3.25\n10\n"), Err(2));
+/// ```
+pub fn parse_intervals(text: &str) -> Result<Vec<TimeSpan>, usize> {
+    let mut intervals = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let seconds: Vec<f64> = line
+            .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+            .filter(|field| !field.is_empty())
+            .map(|field| field.parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| i + 1)?;
+        if seconds.len() != 2 || !seconds.iter().all(|s| s.is_finite()) {
+            return Err(i + 1);
+        }
+
+        let start = TimePoint::from_msecs((seconds[0] * 1000.).round() as i64);
+        let end = TimePoint::from_msecs((seconds[1] * 1000.).round() as i64);
+        intervals.push(TimeSpan::new(min(start, end), max(start, end)));
+    }
+    Ok(intervals)
+}
+
+pub struct IntervalFileHandler {
+    subparse_timespans: Vec<TimeSpan>,
+}
+
+impl IntervalFileHandler {
+    /// Reads an interval file (see `parse_intervals`).
+    pub fn open_interval_file(file_path: &Path) -> Result<IntervalFileHandler, InputIntervalsError> {
+        let text = std::fs::read_to_string(file_path)
+            .with_context(|_| InputIntervalsErrorKind::ReadingIntervalFileFailed(file_path.to_path_buf()))?;
+
+        let mut subparse_timespans =
+            parse_intervals(&text).map_err(|line_number| InputIntervalsErrorKind::InvalidLine {
+                path: file_path.to_path_buf(),
+                line_number,
+            })?;
+        subparse_timespans.sort_by_key(|timespan| timespan.start);
+
+        Ok(IntervalFileHandler { subparse_timespans })
+    }
+
+    pub fn timespans(&self) -> &[TimeSpan] {
+        self.subparse_timespans.as_slice()
+    }
+}
+
 impl InputFileHandler {
+    /// Uses an embedded text subtitle stream of a video file instead of its audio (see
+    /// `SubtitleFileHandler::open_embedded_sub`).
+    #[cfg(feature = "ffmpeg-binary")]
+    pub fn open_embedded_subtitle(
+        file_path: &Path,
+        subtitle_index: Option<usize>,
+        policy: video_decoder::SubtitleTrackPolicy,
+        sub_fps: f64,
+    ) -> Result<InputFileHandler, InputFileError> {
+        Ok(
+            SubtitleFileHandler::open_embedded_sub(file_path, subtitle_index, policy, sub_fps)
+                .map(InputFileHandler::Subtitle)
+                .with_context(|_| InputFileErrorKind::VideoFile(file_path.to_path_buf()))?,
+        )
+    }
+
+    /// Opens a file with intervals instead of a subtitle or video file (see `parse_intervals`).
+    pub fn open_intervals(file_path: &Path) -> Result<InputFileHandler, InputFileError> {
+        Ok(IntervalFileHandler::open_interval_file(file_path)
+            .map(InputFileHandler::Intervals)
+            .with_context(|_| InputFileErrorKind::IntervalFile(file_path.to_path_buf()))?)
+    }
+
+    /// Opens a subtitle file or extracts the voice segments from a video file. The voice segments
+    /// are looked up in (and stored to) `decode_cache` if one is given.
+    #[allow(clippy::too_many_arguments)]
     pub fn open(
         file_path: &Path,
         audio_index: Option<usize>,
+        vad_frame_ms: u32,
+        max_analysis_seconds: Option<f64>,
+        decode_rate_limit: Option<u64>,
         sub_encoding: Option<&'static Encoding>,
         sub_fps: f64,
+        decode_cache: Option<&DecodeCache>,
         video_decode_progress: impl video_decoder::ProgressHandler,
     ) -> Result<InputFileHandler, InputFileError> {
         if let Some(extension) = file_path.extension().map(|os_str| os_str.to_string_lossy()) {
@@ -356,16 +1154,32 @@ impl InputFileHandler {
         }
 
         // Did not match any subtitle extensions we support, assume it's a video file.
-        Ok(
-            VideoFileHandler::open_video_file(file_path, audio_index, video_decode_progress)
-                .map(InputFileHandler::Video)
-                .with_context(|_| InputFileErrorKind::VideoFile(file_path.to_path_buf()))?,
-        )
+        let video_file = match decode_cache {
+            Some(decode_cache) => decode_cache.open_video_file(
+                file_path,
+                audio_index,
+                vad_frame_ms,
+                max_analysis_seconds,
+                decode_rate_limit,
+                video_decode_progress,
+            ),
+            None => VideoFileHandler::open_video_file(
+                file_path,
+                audio_index,
+                vad_frame_ms,
+                max_analysis_seconds,
+                decode_rate_limit,
+                video_decode_progress,
+            ),
+        };
+        Ok(video_file
+            .map(InputFileHandler::Video)
+            .with_context(|_| InputFileErrorKind::VideoFile(file_path.to_path_buf()))?)
     }
 
     pub fn into_subtitle_file(self) -> Option<SubtitleFile> {
         match self {
-            InputFileHandler::Video(_) => None,
+            InputFileHandler::Video(_) | InputFileHandler::Intervals(_) => None,
             InputFileHandler::Subtitle(sub_handler) => Some(sub_handler.subtitle_file),
         }
     }
@@ -374,6 +1188,16 @@ impl InputFileHandler {
         match self {
             InputFileHandler::Video(video_handler) => video_handler.timespans(),
             InputFileHandler::Subtitle(sub_handler) => sub_handler.timespans(),
+            InputFileHandler::Intervals(interval_handler) => interval_handler.timespans(),
+        }
+    }
+
+    /// Returns `AlignWarning::AnalysisTruncated` for a video whose audio was only analyzed up to the
+    /// `max_analysis_seconds` limit (see `VideoFileHandler::analysis_warning`).
+    pub fn analysis_warning(&self) -> Option<ilass::AlignWarning> {
+        match self {
+            InputFileHandler::Video(video_handler) => video_handler.analysis_warning(),
+            InputFileHandler::Subtitle(_) | InputFileHandler::Intervals(_) => None,
         }
     }
 
@@ -447,3 +1271,400 @@ pub fn print_error_chain(error: failure::Error) {
         println!("not: run with environment variable 'RUST_BACKTRACE=1' for detailed stack traces");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A path in the temporary directory which is unique to this test process.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ilass-test-{}-{}", std::process::id(), name))
+    }
+
+    /// The start and end (in milliseconds) of `count` non-overlapping lines.
+    fn test_lines_ms(count: i64) -> Vec<(i64, i64)> {
+        ilass::test_util::test_spans(count)
+            .into_iter()
+            .map(|span| (span.start().as_i64(), span.end().as_i64()))
+            .collect()
+    }
+
+    #[test]
+    fn interval_file_reference_recovers_a_known_shift() {
+        let lines = test_lines_ms(60);
+
+        let path = temp_path("reference.txt");
+        let intervals: String = lines
+            .iter()
+            .map(|&(start, end)| format!("{:.3}, {:.3}\n", start as f64 / 1000., end as f64 / 1000.))
+            .collect();
+        std::fs::write(&path, format!("# appear, disappear\n{}", intervals)).unwrap();
+        let reference = InputFileHandler::open_intervals(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // the incorrect subtitles are 4.2 seconds late
+        let incorrect: Vec<TimeSpan> = lines
+            .iter()
+            .map(|&(start, end)| TimeSpan::new(TimePoint::from_msecs(start + 4200), TimePoint::from_msecs(end + 4200)))
+            .collect();
+
+        let (alg_deltas, _) = ilass::align(
+            &timings_to_alg_timespans(reference.timespans(), 1),
+            &timings_to_alg_timespans(&incorrect, 1),
+            7.,
+            Some(1.),
+            ilass::standard_scoring,
+            ilass::NoProgressHandler,
+        );
+        let deltas = alg_deltas_to_timing_deltas(&alg_deltas, 1);
+        assert_eq!(deltas, vec![TimeDelta::from_msecs(-4200); lines.len()]);
+    }
+
+    #[test]
+    fn json_events_are_written_one_object_per_line() {
+        let path = temp_path("events.jsonl");
+        {
+            let mut writer = JsonEventWriter::open(&path).unwrap();
+            writer.emit(&JsonEvent::PhaseStart {
+                phase: "align",
+                steps: 2,
+            });
+            writer.emit(&JsonEvent::Progress {
+                phase: "align",
+                step: 1,
+                steps: 2,
+            });
+            writer.emit(&JsonEvent::PhaseEnd { phase: "align" });
+        }
+        let output = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(output.ends_with('\n'));
+        let events: Vec<serde_json::Value> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(
+            events,
+            vec![
+                serde_json::json!({"event": "phase_start", "phase": "align", "steps": 2}),
+                serde_json::json!({"event": "progress", "phase": "align", "step": 1, "steps": 2}),
+                serde_json::json!({"event": "phase_end", "phase": "align"}),
+            ]
+        );
+    }
+
+    #[test]
+    fn modification_time_is_copied() {
+        let source_path = temp_path("mtime-source.srt");
+        let path = temp_path("mtime-target.srt");
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_500_000_000);
+        File::create(&source_path)
+            .unwrap()
+            .set_times(std::fs::FileTimes::new().set_modified(modified))
+            .unwrap();
+        File::create(&path).unwrap();
+
+        copy_modification_time(&source_path, &path).unwrap();
+        let copied = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::remove_file(&source_path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(copied, modified);
+    }
+
+    #[test]
+    fn backup_file_does_not_overwrite_an_existing_backup() {
+        let path = temp_path("backup.srt");
+        assert!(backup_file(&path, false).unwrap().is_none());
+
+        std::fs::write(&path, "first").unwrap();
+        let backup_path = backup_file(&path, false).unwrap().unwrap();
+        assert_eq!(std::fs::read_to_string(&backup_path).unwrap(), "first");
+
+        std::fs::write(&path, "second").unwrap();
+        let error = backup_file(&path, false).unwrap_err();
+        assert!(matches!(error.kind(), FileOperationErrorKind::BackupExists { path } if *path == backup_path));
+        assert_eq!(std::fs::read_to_string(&backup_path).unwrap(), "first");
+
+        // `force` overwrites the backup
+        assert_eq!(backup_file(&path, true).unwrap(), Some(backup_path.clone()));
+        assert_eq!(std::fs::read_to_string(&backup_path).unwrap(), "second");
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&backup_path).unwrap();
+    }
+
+    #[test]
+    fn overlap_is_checked_after_applying_the_deltas() {
+        let span = |start: i64, end: i64| AlgTimeSpan::new(AlgTimePoint::from(start), AlgTimePoint::from(end));
+        let reference = vec![span(0, 1000), span(5000, 10000)];
+        // the same content, but 20000 too late
+        let incorrect = vec![span(20200, 21200), span(25200, 29000)];
+
+        let aligned_deltas = vec![AlgTimeDelta::from_i64(-20000); 2];
+        assert_eq!(check_aligned_overlap(&reference, &incorrect, &aligned_deltas), None);
+
+        let unmoved_deltas = vec![AlgTimeDelta::zero(); 2];
+        assert_eq!(
+            check_aligned_overlap(&reference, &incorrect, &unmoved_deltas),
+            Some(ilass::AlignWarning::LowOverlap { fraction: 0. })
+        );
+    }
+
+    #[test]
+    fn fast_quality_does_less_work_than_precise() {
+        let lines = test_lines_ms(150);
+        let spans = |shift_ms: i64| -> Vec<TimeSpan> {
+            lines
+                .iter()
+                .map(|&(start, end)| {
+                    TimeSpan::new(
+                        TimePoint::from_msecs(start + shift_ms),
+                        TimePoint::from_msecs(end + shift_ms),
+                    )
+                })
+                .collect()
+        };
+        let (reference, incorrect) = (spans(0), spans(2500));
+
+        // the aligner rates every offset between the spans (before the speed optimization merges
+        // them), and the voice activity detection classifies every frame of the audio
+        let align = |quality: Quality| {
+            let options = QualityOptions::with_quality(quality);
+            let ref_spans = timings_to_alg_timespans(&reference, options.interval_ms);
+            let inc_spans = timings_to_alg_timespans(&incorrect, options.interval_ms);
+            let (alg_deltas, _) = ilass::align(
+                &ref_spans,
+                &inc_spans,
+                7.,
+                options.speed_optimization,
+                ilass::standard_scoring,
+                ilass::NoProgressHandler,
+            );
+
+            let offsets = ((ref_spans.last().unwrap().end() - inc_spans.first().unwrap().start())
+                - (ref_spans.first().unwrap().start() - inc_spans.last().unwrap().end()))
+            .as_i64();
+            let vad_frames_per_minute =
+                60 * VAD_SAMPLE_RATE as usize / vad_frame_samples(options.vad_frame_ms).unwrap();
+            (
+                alg_deltas_to_timing_deltas(&alg_deltas, options.interval_ms),
+                offsets,
+                vad_frames_per_minute,
+            )
+        };
+
+        let (fast_deltas, fast_offsets, fast_vad_frames) = align(Quality::Fast);
+        let (precise_deltas, precise_offsets, precise_vad_frames) = align(Quality::Precise);
+
+        assert_eq!(fast_deltas, vec![TimeDelta::from_msecs(-2500); lines.len()]);
+        assert_eq!(fast_deltas, precise_deltas);
+
+        assert!(
+            fast_offsets * 9 < precise_offsets,
+            "{} vs {} offsets",
+            fast_offsets,
+            precise_offsets
+        );
+        assert_eq!((fast_vad_frames, precise_vad_frames), (2000, 6000));
+    }
+
+    #[test]
+    fn dumped_reference_rating_matches_the_rating_buffer() {
+        let interval = 10;
+        let span = |start: i64, end: i64| AlgTimeSpan::new(AlgTimePoint::from(start), AlgTimePoint::from(end));
+        let ref_spans = [span(300, 420), span(0, 100), span(150, 150)];
+        let rating = reference_rating(&ref_spans).unwrap();
+        assert_eq!(
+            (rating.start(), rating.end()),
+            (AlgTimeDelta::zero(), Some(AlgTimeDelta::from_i64(420)))
+        );
+
+        let mut csv = Vec::new();
+        write_rating_csv(&mut csv, &rating, 50, interval).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut rows = csv.lines();
+        assert_eq!(rows.next(), Some("time_ms,rating"));
+
+        // a rating of one is the maximum, so it can be compared without knowing the precision
+        let (max_rating, _) = rating.maximum();
+        let rows: Vec<(i64, f64)> = rows
+            .map(|row| {
+                let (time_ms, value) = row.split_once(',').unwrap();
+                (time_ms.parse().unwrap(), value.parse().unwrap())
+            })
+            .collect();
+        assert_eq!(rows.len(), 420 / 5 + 1);
+        for &(time_ms, value) in &rows {
+            let point = AlgTimeDelta::from_i64(time_ms / interval);
+            assert_eq!(value, rating.get_rating_at(point) as f64 / max_rating as f64);
+        }
+        assert_eq!(rows[1], (50, 1.));
+        assert_eq!(rows[25], (1250, 0.));
+        assert_eq!(rows.last(), Some(&(4190, 1.)));
+
+        assert!(reference_rating(&[span(5, 5)]).is_none());
+        assert!(reference_rating(&[]).is_none());
+    }
+
+    #[test]
+    fn analysis_is_truncated_at_the_limit() {
+        // 60 seconds of 10ms frames, the last frame might be incomplete
+        assert!(is_analysis_truncated(6000, 10, Some(60.)));
+        assert!(is_analysis_truncated(5999, 10, Some(60.)));
+
+        // the audio ended before the limit
+        assert!(!is_analysis_truncated(5998, 10, Some(60.)));
+        assert!(!is_analysis_truncated(10, 30, Some(60.)));
+
+        assert!(!is_analysis_truncated(1_000_000, 10, None));
+    }
+
+    #[test]
+    fn truncated_video_reports_analysis_warning() {
+        let truncated = InputFileHandler::Video(VideoFileHandler {
+            subparse_timespans: Vec::new(),
+            truncated_at_seconds: Some(600.),
+        });
+        assert_eq!(
+            truncated.analysis_warning(),
+            Some(ilass::AlignWarning::AnalysisTruncated { analyzed_seconds: 600. })
+        );
+
+        let complete = InputFileHandler::Video(VideoFileHandler::from_cache(Vec::new()));
+        assert_eq!(complete.analysis_warning(), None);
+    }
+
+    #[test]
+    fn quantize_delta_rounds_to_the_nearest_multiple() {
+        let quantized = |msecs: i64, quantum_ms: i64| quantize_delta(TimeDelta::from_msecs(msecs), quantum_ms).msecs();
+
+        assert_eq!(quantized(1234, 100), 1200);
+        assert_eq!(quantized(1250, 100), 1300);
+        assert_eq!(quantized(-1249, 100), -1200);
+        assert_eq!(quantized(-1250, 100), -1300);
+        assert_eq!(quantized(49, 100), 0);
+        assert_eq!(quantized(0, 100), 0);
+        assert_eq!(quantized(777, 1), 777);
+    }
+
+    #[test]
+    fn srt_timestamps_are_normalized() {
+        let normalized = |data: &str| String::from_utf8(normalize_srt_timestamps(data.as_bytes())).unwrap();
+
+        // both decimal separators
+        assert_eq!(
+            normalized("00:00:01,500 --> 00:00:02.250\n"),
+            "00:00:01,500 --> 00:00:02,250\n"
+        );
+        // single-digit fields and a missing or short fraction
+        assert_eq!(normalized("0:1:2.5 --> 0:1:3\n"), "00:01:02,500 --> 00:01:03,000\n");
+        // more than three digits of the fraction are cut off
+        assert_eq!(
+            normalized("00:00:01.0123 --> 100:00:02,9999\n"),
+            "00:00:01,012 --> 100:00:02,999\n"
+        );
+        // only the timing lines are changed
+        assert_eq!(
+            normalized("1\n0:0:1.5 --> 0:0:2.5\nat 1:2:3.4\n"),
+            "1\n00:00:01,500 --> 00:00:02,500\nat 1:2:3.4\n"
+        );
+    }
+
+    #[test]
+    fn malformed_srt_timestamps_are_rejected() {
+        assert_eq!(parse_srt_timestamp(b"00:01,500"), None);
+        assert_eq!(parse_srt_timestamp(b"00::01,500"), None);
+        assert_eq!(parse_srt_timestamp(b":00:01"), None);
+        assert_eq!(parse_srt_timestamp(b"1:2:"), None);
+
+        // a separator without digits does not belong to the timestamp
+        let timestamp = parse_srt_timestamp(b"1:2:3. -->").unwrap();
+        assert_eq!(timestamp.hms, [&b"1"[..], b"2", b"3"]);
+        assert_eq!((timestamp.fraction, timestamp.len), (&b""[..], 5));
+
+        // a rejected timestamp is kept as it is
+        let data = b"00:01,5 --> 00:02,5\n";
+        assert_eq!(normalize_srt_timestamps(data), data.to_vec());
+    }
+
+    #[test]
+    fn aligned_shifts_are_multiples_of_a_frame() {
+        // the second half of the lines is shifted by a different offset, both are no multiples of
+        // the 40ms frames of a 25fps video
+        let lines = test_lines_ms(80);
+        let reference: Vec<TimeSpan> = lines
+            .iter()
+            .map(|&(start, end)| TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(end)))
+            .collect();
+        let incorrect: Vec<TimeSpan> = reference
+            .iter()
+            .enumerate()
+            .map(|(i, &span)| span + TimeDelta::from_msecs(if i < 40 { 1257 } else { 3333 }))
+            .collect();
+
+        let interval = 1;
+        let (alg_deltas, _) = ilass::align(
+            &timings_to_alg_timespans(&reference, interval),
+            &timings_to_alg_timespans(&incorrect, interval),
+            7.,
+            None,
+            ilass::standard_scoring,
+            ilass::NoProgressHandler,
+        );
+        assert!(alg_deltas_to_timing_deltas(&alg_deltas, interval).contains(&TimeDelta::from_msecs(-1257)));
+
+        let deltas = alg_deltas_to_applied_deltas(&alg_deltas, interval, Some(40));
+        let groups = get_subtitle_delta_groups(deltas.into_iter().zip(incorrect.iter().cloned()).collect());
+        for (delta, _) in &groups {
+            assert_eq!(delta.msecs() % 40, 0, "shift of {}ms", delta.msecs());
+        }
+
+        let summary: Vec<(i64, usize)> = groups
+            .iter()
+            .map(|(delta, lines)| (delta.msecs(), lines.len()))
+            .collect();
+        assert_eq!(summary, vec![(-1240, 40), (-3320, 40)]);
+
+        // without a quantum the deltas stay as they were aligned
+        assert_eq!(
+            alg_deltas_to_applied_deltas(&alg_deltas, interval, None),
+            alg_deltas_to_timing_deltas(&alg_deltas, interval)
+        );
+    }
+
+    #[test]
+    fn run_summary_counts_shifts_and_changed_lines() {
+        let span = |start: i64| TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(start + 500));
+        let summarize = |deltas: &[i64], fps_scaling_factor: f64| {
+            let lines = (0..deltas.len() as i64).map(|i| span(i * 1000));
+            let deltas = deltas.iter().map(|&msecs| TimeDelta::from_msecs(msecs));
+            summarize_shift_groups(
+                &get_subtitle_delta_groups(deltas.zip(lines).collect()),
+                fps_scaling_factor,
+            )
+        };
+
+        assert_eq!(summarize(&[-2500; 4], 1.), (Some(TimeDelta::from_msecs(-2500)), 1, 4));
+        assert_eq!(summarize(&[0, 0, 500, 500, 0], 1.), (None, 3, 2));
+        assert_eq!(summarize(&[0; 3], 1.), (Some(TimeDelta::from_msecs(0)), 1, 0));
+
+        // the framerate correction moves the lines without a delta too
+        assert_eq!(summarize(&[0, 0, 500, 500, 0], 25. / 24.), (None, 3, 5));
+
+        assert_eq!(summarize(&[], 1.), (None, 0, 0));
+    }
+
+    #[test]
+    fn lines_with_the_same_quantized_delta_are_grouped() {
+        let span = |start: i64| TimeSpan::new(TimePoint::from_msecs(start), TimePoint::from_msecs(start + 500));
+
+        let deltas = [1004, 998, 1496, 1510].map(|msecs| quantize_delta(TimeDelta::from_msecs(msecs), 100));
+        let lines = [span(0), span(1000), span(2000), span(3000)];
+        let groups = get_subtitle_delta_groups(deltas.into_iter().zip(lines).collect());
+
+        let summary: Vec<(i64, usize)> = groups
+            .iter()
+            .map(|(delta, lines)| (delta.msecs(), lines.len()))
+            .collect();
+        assert_eq!(summary, vec![(1000, 2), (1500, 2)]);
+    }
+}
@@ -11,18 +11,23 @@ extern crate subparse;
 
 use crate::subparse::SubtitleFileInterface;
 
+use clap::parser::ValueSource;
 use clap::{Arg, ArgAction, command};
 use encoding_rs::Encoding;
 use failure::ResultExt;
 use ilass::{TimeDelta as AlgTimeDelta, align};
+use std::cell::RefCell;
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::result::Result;
 use std::str::FromStr;
+use std::time::Instant;
 
 use subparse::timetypes::*;
 use subparse::{SubtitleEntry, SubtitleFormat};
 
+use ilass_cli::decode_cache::DecodeCache;
 use ilass_cli::errors::*;
 use ilass_cli::*;
 
@@ -54,6 +59,22 @@ fn unpack_clap_number_i64(
         .map_err(InputArgumentsError::from)
 }
 
+fn unpack_optional_clap_number_f64(
+    matches: &clap::ArgMatches,
+    parameter_name: &'static str,
+) -> Result<Option<f64>, InputArgumentsError> {
+    match matches.get_one::<String>(parameter_name) {
+        None => Ok(None),
+        Some(parameter_value_str) => f64::from_str(parameter_value_str)
+            .with_context(|_| InputArgumentsErrorKind::ArgumentParseError {
+                argument_name: parameter_name.to_string(),
+                value: parameter_value_str.to_string(),
+            })
+            .map(Some)
+            .map_err(InputArgumentsError::from),
+    }
+}
+
 fn unpack_optional_clap_number_usize(
     matches: &clap::ArgMatches,
     parameter_name: &'static str,
@@ -113,6 +134,58 @@ struct Arguments {
     speed_optimization: Option<f64>,
 
     audio_index: Option<usize>,
+
+    /// length of a voice activity detection frame in milliseconds
+    vad_frame_ms: u32,
+
+    /// round the applied offsets to multiples of this many milliseconds
+    offset_quantum: Option<i64>,
+
+    /// write newline-delimited JSON events to this path (`-` for stderr)
+    json_events_path: Option<PathBuf>,
+
+    /// copy the modification time of this file to the output file
+    mtime_source_path: Option<PathBuf>,
+
+    /// copy an existing output file to `<output>.bak` before overwriting it
+    backup: bool,
+
+    /// overwrite an existing backup file
+    force: bool,
+
+    /// print a one-line summary of the run
+    stats: bool,
+
+    /// the reference file is a list of intervals instead of a subtitle or video file
+    reference_intervals: bool,
+
+    /// write this many alternative constant-shift alignments to numbered files
+    candidates: Option<usize>,
+
+    /// merge the alignment until it shifts at most this many blocks of lines
+    max_shifted_blocks: Option<usize>,
+
+    /// only analyze this many seconds of the reference audio
+    max_analysis_seconds: Option<f64>,
+
+    /// decode at most this many samples of the reference audio per second
+    decode_rate_limit: Option<u64>,
+
+    /// where the voice segments of decoded videos are cached (`None` if caching is not enabled)
+    decode_cache_dir: Option<PathBuf>,
+
+    /// use this embedded subtitle stream of the reference video instead of its audio
+    reference_subtitle_stream: Option<usize>,
+
+    /// use the embedded subtitle stream of the reference video selected by its disposition instead
+    /// of its audio
+    reference_subtitle_track: Option<video_decoder::SubtitleTrackPolicy>,
+
+    /// shift the subtitles (the default) or describe how to shift the reference media instead
+    direction: ilass::Direction,
+
+    /// where the rating curve of the reference is written to as CSV
+    dump_rating_path: Option<PathBuf>,
 }
 
 fn parse_args() -> Result<Arguments, InputArgumentsError> {
@@ -163,6 +236,12 @@ fn parse_args() -> Result<Arguments, InputArgumentsError> {
             .value_name("encoding")
             .help("Charset encoding of the incorrect subtitle file.")
             .default_value("auto"))
+        .arg(Arg::new("quality")
+            .long("quality")
+            .value_name("preset")
+            .value_parser(["fast", "balanced", "precise"])
+            .default_value("balanced")
+            .help("Sets '--interval', '--speed-optimization' and '--vad-frame-length' at once: 'fast' uses 10, 3 and 30, 'balanced' uses 1, 1 and 10, 'precise' uses 1, 0 and 10; each of them can still be set individually"))
         .arg(Arg::new("speed-optimization")
             .long("speed-optimization")
             .short('O')
@@ -192,6 +271,99 @@ fn parse_args() -> Result<Arguments, InputArgumentsError> {
             .long("index")
             .value_name("audio-index")
             .required(false))
+        .arg(Arg::new("reference-subtitle-stream")
+            .help("Uses the embedded text subtitle stream with this index (as listed by ffprobe) of the reference video as reference instead of its audio")
+            .long("reference-subtitle-stream")
+            .value_name("stream index")
+            .conflicts_with("reference-intervals")
+            .required(false))
+        .arg(Arg::new("reference-subtitle-track")
+            .help("Uses an embedded text subtitle stream of the reference video as reference instead of its audio, selected by its disposition: \"first\" takes the first one, \"no-forced\" skips forced-narrative tracks, \"regular\" skips forced and SDH tracks and \"prefer-sdh\" takes an SDH track if there is one")
+            .long("reference-subtitle-track")
+            .value_name("policy")
+            .value_parser(["first", "no-forced", "regular", "prefer-sdh"])
+            .conflicts_with_all(["reference-intervals", "reference-subtitle-stream"])
+            .required(false))
+        .arg(Arg::new("vad-frame-length")
+            .help("Length of the frames in which the voice activity of a reference video is detected; longer frames are more robust against noise, shorter frames are more precise")
+            .long("vad-frame-length")
+            .value_name("milliseconds")
+            .value_parser(["10", "20", "30"])
+            .default_value("10"))
+        .arg(Arg::new("offset-quantum")
+            .help("Rounds every applied offset to the nearest multiple of this value (e.g. 40 for the frames of a 25fps video), for players that misbehave with sub-frame offsets")
+            .long("offset-quantum")
+            .value_name("integer in milliseconds")
+            .required(false))
+        .arg(Arg::new("json-events")
+            .help("Additionally writes the progress and the result as newline-delimited JSON events to this file (\"-\" for stderr), so other tools can follow the synchronization")
+            .long("json-events")
+            .value_name("path")
+            .required(false))
+        .arg(Arg::new("preserve-mtime")
+            .help("Sets the modification time of the output file to the one of the incorrect subtitle file (\"input\", the default) or of the reference file (\"reference\")")
+            .long("preserve-mtime")
+            .value_name("source")
+            .value_parser(["input", "reference"])
+            .num_args(0..=1)
+            .default_missing_value("input")
+            .required(false))
+        .arg(Arg::new("direction")
+            .help("With \"media\", the subtitles are assumed to be timed correctly and the output file is a JSON description of how to shift the reference media to match them (including the ffmpeg option for a constant shift) instead of a corrected subtitle file")
+            .long("direction")
+            .value_name("shifted side")
+            .value_parser(["subtitle", "media"])
+            .default_value("subtitle"))
+        .arg(Arg::new("backup")
+            .help("If the output file already exists (e.g. when synchronizing a file in place), copies it to '<output-file-path>.bak' before overwriting it")
+            .long("backup")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("force")
+            .help("Allows '--backup' to overwrite an existing '.bak' file")
+            .long("force")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("stats")
+            .help("Prints a one-line summary of the run (paths, shift, score, number of offset blocks, changed lines, elapsed time and warnings)")
+            .long("stats")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("reference-intervals")
+            .help("Reads the reference file as a list of intervals (one 'start end' pair in seconds per line), e.g. the times in which OCR found hardcoded subtitles on screen")
+            .long("reference-intervals")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("candidates")
+            .help("Instead of choosing one alignment, writes the best distinct constant-shift alignments to numbered output files (e.g. 'output.1.srt', 'output.2.srt', ...), so you can pick the right one for ambiguous content")
+            .long("candidates")
+            .value_name("count")
+            .required(false))
+        .arg(Arg::new("max-shifted-blocks")
+            .help("Shifts at most this many blocks of consecutive lines (each by one offset) by merging the blocks that lose the least alignment score, which avoids shifting every line by a slightly different offset; '1' shifts all lines by the same offset")
+            .long("max-shifted-blocks")
+            .value_name("integer")
+            .required(false))
+        .arg(Arg::new("max-analysis-seconds")
+            .help("Only analyzes this many seconds of the reference audio, regardless of the duration the file claims to have (bounds the runtime for corrupt files)")
+            .long("max-analysis-seconds")
+            .value_name("seconds")
+            .required(false))
+        .arg(Arg::new("decode-rate-limit")
+            .help("Decodes at most this many samples of the reference audio per second to cap the CPU usage (the audio is analyzed at 8000 samples per second, so 80000 is ten times real-time speed) [default: the environment variable ILASS_DECODE_RATE_LIMIT, or no limit]")
+            .long("decode-rate-limit")
+            .value_name("samples per second")
+            .required(false))
+        .arg(Arg::new("cache")
+            .help("Caches the voice segments extracted from a reference video in the user's cache directory, so aligning against the same video again does not decode its audio again")
+            .long("cache")
+            .action(ArgAction::SetTrue))
+        .arg(Arg::new("cache-dir")
+            .help("Like --cache, but caches the voice segments in this directory")
+            .long("cache-dir")
+            .value_name("path")
+            .required(false))
+        .arg(Arg::new("dump-rating")
+            .help("Writes the rating curve of the reference (one where the reference has a line or voice and zero elsewhere) as CSV of the time in milliseconds and the rating for every frame of the voice activity detection to this file, e.g. to plot it against the waveform")
+            .long("dump-rating")
+            .value_name("path")
+            .required(false))
         .after_help("This program works with .srt, .ass/.ssa, .idx and .sub files. The corrected file will have the same format as the incorrect file.")
         .get_matches();
 
@@ -199,7 +371,21 @@ fn parse_args() -> Result<Arguments, InputArgumentsError> {
     let incorrect_file_path: PathBuf = matches.get_one::<String>("incorrect-sub-file").unwrap().into();
     let output_file_path: PathBuf = matches.get_one::<String>("output-file-path").unwrap().into();
 
-    let interval: i64 = unpack_clap_number_i64(&matches, "interval")?;
+    let quality = match matches.get_one::<String>("quality").map(|s| s.as_str()) {
+        Some("fast") => Quality::Fast,
+        Some("precise") => Quality::Precise,
+        _ => Quality::Balanced,
+    };
+    let quality_options = QualityOptions::with_quality(quality);
+
+    // the preset only fills in the parameters that were not given explicitly
+    let is_explicit = |name: &str| matches.value_source(name) == Some(ValueSource::CommandLine);
+
+    let interval: i64 = if is_explicit("interval") {
+        unpack_clap_number_i64(&matches, "interval")?
+    } else {
+        quality_options.interval_ms
+    };
     if interval < 1 {
         return Err(InputArgumentsErrorKind::ExpectedPositiveNumber {
             argument_name: "interval".to_string(),
@@ -220,7 +406,17 @@ fn parse_args() -> Result<Arguments, InputArgumentsError> {
         .into());
     }
 
-    let speed_optimization: f64 = unpack_clap_number_f64(&matches, "speed-optimization")?;
+    let speed_optimization: f64 = if is_explicit("speed-optimization") {
+        unpack_clap_number_f64(&matches, "speed-optimization")?
+    } else {
+        quality_options.speed_optimization.unwrap_or(0.)
+    };
+
+    let vad_frame_ms: u32 = if is_explicit("vad-frame-length") {
+        unpack_clap_number_i64(&matches, "vad-frame-length")? as u32
+    } else {
+        quality_options.vad_frame_ms
+    };
     if speed_optimization < 0.0 {
         return Err(InputArgumentsErrorKind::ExpectedNonNegativeNumber {
             argument_name: "speed-optimization".to_string(),
@@ -231,6 +427,84 @@ fn parse_args() -> Result<Arguments, InputArgumentsError> {
 
     let no_split_mode: bool = matches.get_flag("no-split");
 
+    let offset_quantum: Option<i64> = unpack_optional_clap_number_usize(&matches, "offset-quantum")?.map(|v| v as i64);
+    if offset_quantum == Some(0) {
+        return Err(InputArgumentsErrorKind::ExpectedPositiveNumber {
+            argument_name: "offset-quantum".to_string(),
+            value: 0,
+        }
+        .into());
+    }
+
+    let max_shifted_blocks: Option<usize> = unpack_optional_clap_number_usize(&matches, "max-shifted-blocks")?;
+    if max_shifted_blocks == Some(0) {
+        return Err(InputArgumentsErrorKind::ExpectedPositiveNumber {
+            argument_name: "max-shifted-blocks".to_string(),
+            value: 0,
+        }
+        .into());
+    }
+
+    let max_analysis_seconds: Option<f64> = unpack_optional_clap_number_f64(&matches, "max-analysis-seconds")?;
+    if let Some(value) = max_analysis_seconds
+        && (!value.is_finite() || value <= 0.)
+    {
+        return Err(InputArgumentsErrorKind::ExpectedPositiveSeconds {
+            argument_name: "max-analysis-seconds".to_string(),
+            value,
+        }
+        .into());
+    }
+
+    let decode_rate_limit: Option<u64> = match unpack_optional_clap_number_usize(&matches, "decode-rate-limit")? {
+        Some(value) => Some(value as u64),
+        None => match std::env::var("ILASS_DECODE_RATE_LIMIT") {
+            Ok(value) => Some(
+                u64::from_str(&value)
+                    .with_context(|_| InputArgumentsErrorKind::ArgumentParseError {
+                        argument_name: "ILASS_DECODE_RATE_LIMIT".to_string(),
+                        value: value.clone(),
+                    })
+                    .map_err(InputArgumentsError::from)?,
+            ),
+            Err(_) => None,
+        },
+    };
+    if decode_rate_limit == Some(0) {
+        return Err(InputArgumentsErrorKind::ExpectedPositiveNumber {
+            argument_name: "decode-rate-limit".to_string(),
+            value: 0,
+        }
+        .into());
+    }
+
+    let candidates: Option<usize> = unpack_optional_clap_number_usize(&matches, "candidates")?;
+    if candidates == Some(0) {
+        return Err(InputArgumentsErrorKind::ExpectedPositiveNumber {
+            argument_name: "candidates".to_string(),
+            value: 0,
+        }
+        .into());
+    }
+
+    let decode_cache_dir: Option<PathBuf> = match matches.get_one::<String>("cache-dir") {
+        Some(cache_dir) => Some(PathBuf::from(cache_dir)),
+        None if matches.get_flag("cache") => {
+            let cache_dir = DecodeCache::default_dir();
+            if cache_dir.is_none() {
+                println!("warn: found no cache directory (set --cache-dir), so the voice segments are not cached");
+            }
+            cache_dir
+        }
+        None => None,
+    };
+
+    let mtime_source_path: Option<PathBuf> = match matches.get_one::<String>("preserve-mtime").map(|s| s.as_str()) {
+        Some("reference") => Some(reference_file_path.clone()),
+        Some(_) => Some(incorrect_file_path.clone()),
+        None => None,
+    };
+
     Ok(Arguments {
         reference_file_path,
         incorrect_file_path,
@@ -250,22 +524,88 @@ fn parse_args() -> Result<Arguments, InputArgumentsError> {
             Some(speed_optimization)
         },
         audio_index: unpack_optional_clap_number_usize(&matches, "audio-index")?,
+        vad_frame_ms,
+        offset_quantum,
+        json_events_path: matches.get_one::<String>("json-events").map(PathBuf::from),
+        mtime_source_path,
+        stats: matches.get_flag("stats"),
+        reference_intervals: matches.get_flag("reference-intervals"),
+        candidates,
+        backup: matches.get_flag("backup"),
+        force: matches.get_flag("force"),
+        max_shifted_blocks,
+        max_analysis_seconds,
+        decode_rate_limit,
+        decode_cache_dir,
+        reference_subtitle_stream: unpack_optional_clap_number_usize(&matches, "reference-subtitle-stream")?,
+        reference_subtitle_track: matches
+            .get_one::<String>("reference-subtitle-track")
+            .map(|s| match s.as_str() {
+                "no-forced" => video_decoder::SubtitleTrackPolicy::ExcludeForced,
+                "regular" => video_decoder::SubtitleTrackPolicy::ExcludeForcedAndSdh,
+                "prefer-sdh" => video_decoder::SubtitleTrackPolicy::PreferSdh,
+                _ => video_decoder::SubtitleTrackPolicy::First,
+            }),
+        direction: match matches.get_one::<String>("direction").map(|s| s.as_str()) {
+            Some("media") => ilass::Direction::ShiftMedia,
+            _ => ilass::Direction::ShiftSubtitle,
+        },
+        dump_rating_path: matches.get_one::<String>("dump-rating").map(PathBuf::from),
     })
 }
 
-fn prepare_reference_file(args: &Arguments) -> Result<InputFileHandler, failure::Error> {
+fn prepare_reference_file(
+    args: &Arguments,
+    json_events: Option<&SharedJsonEventWriter>,
+) -> Result<InputFileHandler, failure::Error> {
+    if args.reference_intervals {
+        return Ok(InputFileHandler::open_intervals(&args.reference_file_path)?);
+    }
+
+    #[cfg(feature = "ffmpeg-binary")]
+    if args.reference_subtitle_stream.is_some() || args.reference_subtitle_track.is_some() {
+        return Ok(InputFileHandler::open_embedded_subtitle(
+            &args.reference_file_path,
+            args.reference_subtitle_stream,
+            args.reference_subtitle_track.unwrap_or_default(),
+            args.sub_fps_ref,
+        )?);
+    }
+
+    #[cfg(not(feature = "ffmpeg-binary"))]
+    if args.reference_subtitle_stream.is_some() || args.reference_subtitle_track.is_some() {
+        let argument_name = if args.reference_subtitle_stream.is_some() {
+            "reference-subtitle-stream"
+        } else {
+            "reference-subtitle-track"
+        };
+        return Err(
+            InputArgumentsError::from(InputArgumentsErrorKind::RequiresFfmpegBinary {
+                argument_name: argument_name.to_string(),
+            })
+            .into(),
+        );
+    }
+
+    let decode_cache: Option<DecodeCache> = args.decode_cache_dir.clone().map(DecodeCache::new);
+
     let mut ref_file = InputFileHandler::open(
         &args.reference_file_path,
         args.audio_index,
+        args.vad_frame_ms,
+        args.max_analysis_seconds,
+        args.decode_rate_limit,
         args.encoding_ref,
         args.sub_fps_ref,
+        decode_cache.as_ref(),
         ProgressInfo::new(
-            500,
+            video_decoder::PHASED_PROGRESS_STEPS / 100,
             Some(format!(
                 "extracting audio from reference file '{}'...",
                 args.reference_file_path.display()
             )),
-        ),
+        )
+        .with_json_events(json_events, "extract_audio"),
     )?;
 
     ref_file.filter_video_with_min_span_length_ms(500);
@@ -276,11 +616,17 @@ fn prepare_reference_file(args: &Arguments) -> Result<InputFileHandler, failure:
 // //////////////////////////////////////////////////////////////////////////////////////////////////
 
 fn run() -> Result<(), failure::Error> {
+    let start_time = Instant::now();
     let args = parse_args()?;
 
+    let json_events: Option<SharedJsonEventWriter> = match &args.json_events_path {
+        Some(path) => Some(Rc::new(RefCell::new(JsonEventWriter::open(path)?))),
+        None => None,
+    };
+
     if args.incorrect_file_path.eq(OsStr::new("_")) {
         // DEBUG MODE FOR REFERENCE FILE WAS ACTIVATED
-        let ref_file = prepare_reference_file(&args)?;
+        let ref_file = prepare_reference_file(&args, json_events.as_ref())?;
 
         println!("input file path was given as '_'");
         println!("the output file is a .srt file only containing timing information from the reference file");
@@ -310,13 +656,16 @@ fn run() -> Result<(), failure::Error> {
     let inc_file =
         SubtitleFileHandler::open_sub_file(args.incorrect_file_path.as_path(), args.encoding_inc, args.sub_fps_inc)?;
 
-    let ref_file = prepare_reference_file(&args)?;
+    let ref_file = prepare_reference_file(&args, json_events.as_ref())?;
 
     let output_file_format = inc_file.file_format();
 
     // this program internally stores the files in a non-destructable way (so
-    // formatting is preserved) but has no abilty to convert between formats
-    if !subparse::is_valid_extension_for_subtitle_format(args.output_file_path.extension(), output_file_format) {
+    // formatting is preserved) but has no abilty to convert between formats - the
+    // media correction is written as JSON, so there is no subtitle format to keep
+    if args.direction != ilass::Direction::ShiftMedia
+        && !subparse::is_valid_extension_for_subtitle_format(args.output_file_path.extension(), output_file_format)
+    {
         return Err(TopLevelErrorKind::FileFormatMismatch {
             input_file_path: args.incorrect_file_path,
             output_file_path: args.output_file_path,
@@ -329,6 +678,30 @@ fn run() -> Result<(), failure::Error> {
     let mut inc_aligner_timespans: Vec<ilass::TimeSpan> = timings_to_alg_timespans(inc_file.timespans(), args.interval);
     let ref_aligner_timespans: Vec<ilass::TimeSpan> = timings_to_alg_timespans(ref_file.timespans(), args.interval);
 
+    if let Some(dump_rating_path) = &args.dump_rating_path {
+        match reference_rating(&ref_aligner_timespans) {
+            Some(rating) => {
+                let file =
+                    std::fs::File::create(dump_rating_path).with_context(|_| FileOperationErrorKind::FileWrite {
+                        path: dump_rating_path.clone(),
+                    })?;
+                write_rating_csv(
+                    std::io::BufWriter::new(file),
+                    &rating,
+                    i64::from(args.vad_frame_ms),
+                    args.interval,
+                )
+                .with_context(|_| FileOperationErrorKind::FileWrite {
+                    path: dump_rating_path.clone(),
+                })?;
+            }
+            None => {
+                println!("warn: the reference has no lines or voice, so there is no rating to write");
+                println!();
+            }
+        }
+    }
+
     let mut fps_scaling_factor = 1.;
     if args.guess_fps_ratio {
         let a = 25.;
@@ -341,7 +714,8 @@ fn run() -> Result<(), failure::Error> {
             &ref_aligner_timespans,
             &inc_aligner_timespans,
             &ratios,
-            ProgressInfo::new(1, Some("Guessing framerate ratio...".to_string())),
+            ProgressInfo::new(1, Some("Guessing framerate ratio...".to_string()))
+                .with_json_events(json_events.as_ref(), "guess_fps"),
         );
 
         fps_scaling_factor = if let Some(idx) = opt_ratio_idx { ratios[idx] } else { 1. };
@@ -358,45 +732,90 @@ fn run() -> Result<(), failure::Error> {
             .collect();
     }
 
+    // short names of the printed warnings for `--stats`
+    let mut warnings: Vec<&'static str> = Vec::new();
+
+    if let Some(ilass::AlignWarning::AnalysisTruncated { analyzed_seconds }) = ref_file.analysis_warning() {
+        println!(
+            "warn: only the first {} seconds of the reference audio were analyzed (see '--max-analysis-seconds')",
+            analyzed_seconds
+        );
+        println!();
+        warnings.push("analysis-truncated");
+    }
+
+    if let Some(candidate_count) = args.candidates {
+        return write_candidates(
+            &args,
+            candidate_count,
+            &ref_aligner_timespans,
+            &inc_aligner_timespans,
+            fps_scaling_factor,
+        );
+    }
+
     let align_start_msg = format!(
         "synchronizing '{}' to reference file '{}'...",
         args.incorrect_file_path.display(),
         args.reference_file_path.display()
     );
-    let alg_deltas = if args.no_split_mode {
+    // the same scoring has to be used for every step that compares alignment scores
+    let score_fn = ilass::standard_scoring;
+
+    let (mut alg_deltas, mut score) = if args.no_split_mode {
         let num_inc_timespans = inc_aligner_timespans.len();
 
-        let alg_delta = ilass::align_nosplit(
+        let (alg_delta, score) = ilass::align_nosplit(
             &ref_aligner_timespans,
             &inc_aligner_timespans,
-            ilass::standard_scoring,
-            ProgressInfo::new(1, Some(align_start_msg)),
-        )
-        .0;
+            score_fn,
+            ProgressInfo::new(1, Some(align_start_msg)).with_json_events(json_events.as_ref(), "align"),
+        );
 
-        std::vec::from_elem(alg_delta, num_inc_timespans)
+        (std::vec::from_elem(alg_delta, num_inc_timespans), score)
     } else {
         align(
             &ref_aligner_timespans,
             &inc_aligner_timespans,
             args.split_penalty,
             args.speed_optimization,
-            ilass::standard_scoring,
-            ProgressInfo::new(1, Some(align_start_msg)),
+            score_fn,
+            ProgressInfo::new(1, Some(align_start_msg)).with_json_events(json_events.as_ref(), "align"),
         )
-        .0
     };
-    let deltas = alg_deltas_to_timing_deltas(&alg_deltas, args.interval);
+    if let Some(max_shifted_blocks) = args.max_shifted_blocks {
+        let (limited_alg_deltas, score_loss) = ilass::limit_offset_changes(
+            &ref_aligner_timespans,
+            &inc_aligner_timespans,
+            &alg_deltas,
+            max_shifted_blocks,
+            score_fn,
+        );
+        if limited_alg_deltas != alg_deltas {
+            println!(
+                "info: limited the alignment to {} shifted blocks, which lowered the alignment score by {:.2}",
+                max_shifted_blocks, score_loss
+            );
+            println!();
+        }
+        alg_deltas = limited_alg_deltas;
+        score -= score_loss;
+    }
+
+    let deltas = alg_deltas_to_applied_deltas(&alg_deltas, args.interval, args.offset_quantum);
 
-    // group subtitles lines which have the same offset
-    let shift_groups: Vec<(AlgTimeDelta, Vec<TimeSpan>)> = get_subtitle_delta_groups(
-        alg_deltas
+    // group subtitles lines which have the same (quantized) offset
+    let shift_groups: Vec<(TimeDelta, Vec<TimeSpan>)> = get_subtitle_delta_groups(
+        deltas
             .iter()
             .cloned()
             .zip(inc_file.timespans().iter().cloned())
             .collect(),
     );
 
+    let (shift, offset_segments, lines_changed) = summarize_shift_groups(&shift_groups, fps_scaling_factor);
+
+    let mut json_shift_groups: Vec<JsonShiftGroup> = Vec::new();
     for (shift_group_delta, shift_group_lines) in shift_groups {
         // computes the first and last timestamp for all lines with that delta
         // -> that way we can provide the user with an information like
@@ -416,21 +835,155 @@ fn run() -> Result<(), failure::Error> {
             "shifted block of {} subtitles with length {} by {}",
             shift_group_lines.len(),
             max - min,
-            alg_delta_to_delta(shift_group_delta, args.interval)
+            shift_group_delta
         );
+
+        json_shift_groups.push(JsonShiftGroup {
+            lines: shift_group_lines.len(),
+            first_start_ms: min.msecs(),
+            last_start_ms: max.msecs(),
+            offset_ms: shift_group_delta.msecs(),
+        });
+    }
+
+    if let Some(json_events) = &json_events {
+        json_events.borrow_mut().emit(&JsonEvent::Result {
+            fps_scaling_factor,
+            shifts: &json_shift_groups,
+        });
     }
 
     println!();
 
-    if ref_file.timespans().is_empty() {
+    // empty files get their own warning below
+    if !ref_aligner_timespans.is_empty()
+        && !inc_aligner_timespans.is_empty()
+        && let Some(ilass::AlignWarning::LowOverlap { fraction }) =
+            check_aligned_overlap(&ref_aligner_timespans, &inc_aligner_timespans, &alg_deltas)
+    {
+        println!(
+            "warn: even after the alignment the reference and the incorrect subtitles only share {:.0}% of their time range - are these the same content?",
+            fraction * 100.
+        );
+        println!();
+        warnings.push("low-overlap");
+    }
+
+    if let InputFileHandler::Video(_) = ref_file {
+        if let Some(ilass::AlignWarning::InsufficientAudio) = ilass::check_reference(&ref_aligner_timespans) {
+            println!("warn: no voice was detected in the reference audio - it is too short or silent");
+            println!();
+            warnings.push("insufficient-audio");
+        }
+    } else if ref_file.timespans().is_empty() {
         println!("warn: reference file has no subtitle lines");
         println!();
+        warnings.push("empty-reference");
     }
     if inc_file.timespans().is_empty() {
         println!("warn: file with incorrect subtitles has no lines");
         println!();
+        warnings.push("empty-input");
+    }
+
+    if args.direction == ilass::Direction::ShiftMedia {
+        write_media_correction(&args, &inc_aligner_timespans, &alg_deltas, fps_scaling_factor)?;
+    } else {
+        write_corrected_file(
+            &args,
+            inc_file,
+            &deltas,
+            fps_scaling_factor,
+            &args.output_file_path,
+            &mut warnings,
+        )?;
+    }
+
+    if args.stats {
+        println!(
+            "{}",
+            RunSummary {
+                input_path: args.incorrect_file_path.clone(),
+                output_path: args.output_file_path.clone(),
+                shift,
+                score,
+                offset_segments,
+                lines_changed,
+                elapsed: start_time.elapsed(),
+                warnings,
+            }
+        );
     }
 
+    Ok(())
+}
+
+/// Writes the inverse of the alignment, which shifts the reference media onto the (correctly timed)
+/// subtitles, to the output file as JSON (see `--direction`).
+fn write_media_correction(
+    args: &Arguments,
+    inc_aligner_timespans: &[ilass::TimeSpan],
+    alg_deltas: &[AlgTimeDelta],
+    fps_scaling_factor: f64,
+) -> Result<(), failure::Error> {
+    let shifts: Vec<JsonMediaShift> =
+        ilass::offset_curve(inc_aligner_timespans, alg_deltas, ilass::Direction::ShiftMedia)
+            .into_iter()
+            .map(|piece| {
+                let mut offset = alg_delta_to_delta(piece.delta, args.interval);
+                if let Some(offset_quantum) = args.offset_quantum {
+                    offset = quantize_delta(offset, offset_quantum);
+                }
+                JsonMediaShift {
+                    start_ms: alg_timepoint_to_timing(piece.start, args.interval).msecs(),
+                    offset_ms: offset.msecs(),
+                }
+            })
+            .collect();
+
+    // `-itsoffset` delays all streams of the following input
+    let ffmpeg_input_args = match shifts.as_slice() {
+        [shift] => Some(format!("-itsoffset {:.3}", shift.offset_ms as f64 / 1000.)),
+        _ => None,
+    };
+    match &ffmpeg_input_args {
+        Some(ffmpeg_input_args) => println!(
+            "info: pass '{}' to ffmpeg before '-i {}' to shift the media onto the subtitles",
+            ffmpeg_input_args,
+            args.reference_file_path.display()
+        ),
+        None => println!(
+            "info: the media needs {} different shifts to match the subtitles (see '{}')",
+            shifts.len(),
+            args.output_file_path.display()
+        ),
+    }
+    println!();
+
+    let correction = JsonMediaCorrection {
+        fps_scaling_factor,
+        shifts,
+        ffmpeg_input_args,
+    };
+    std::fs::write(&args.output_file_path, serde_json::to_string_pretty(&correction)?).with_context(|_| {
+        FileOperationErrorKind::FileWrite {
+            path: args.output_file_path.clone(),
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Applies the `deltas` and the framerate correction to the incorrect subtitle file and writes the
+/// result to `output_file_path`.
+fn write_corrected_file(
+    args: &Arguments,
+    inc_file: SubtitleFileHandler,
+    deltas: &[TimeDelta],
+    fps_scaling_factor: f64,
+    output_file_path: &Path,
+    warnings: &mut Vec<&'static str>,
+) -> Result<(), failure::Error> {
     fn scaled_timespan(ts: TimeSpan, fps_scaling_factor: f64) -> TimeSpan {
         TimeSpan::new(
             TimePoint::from_msecs((ts.start.msecs() as f64 * fps_scaling_factor) as i64),
@@ -447,6 +1000,7 @@ fn run() -> Result<(), failure::Error> {
 
     if corrected_timespans.iter().any(|ts| ts.start.is_negative()) {
         println!("warn: some subtitles now have negative timings, which can cause invalid subtitle files");
+        warnings.push("negative-timestamps");
         if args.allow_negative_timestamps {
             println!(
                 "warn: negative timestamps will be written to file, because you passed '-n' or '--allow-negative-timestamps'",
@@ -469,8 +1023,9 @@ fn run() -> Result<(), failure::Error> {
 
     // .idx only has start timepoints (the subtitle is shown until the next subtitle starts) - so retiming with gaps might
     // produce errors
-    if output_file_format == SubtitleFormat::VobSubIdx {
+    if inc_file.file_format() == SubtitleFormat::VobSubIdx {
         println!("warn: writing to an '.idx' file can lead to unexpected results due to restrictions of this format");
+        warnings.push("idx-output");
     }
 
     // incorrect file -> correct file
@@ -482,12 +1037,81 @@ fn run() -> Result<(), failure::Error> {
         .update_subtitle_entries(&shifted_timespans)
         .with_context(|_| TopLevelErrorKind::FailedToUpdateSubtitle)?;
 
-    write_data_to_file(
-        &args.output_file_path,
-        correct_file
-            .to_data()
-            .with_context(|_| TopLevelErrorKind::FailedToGenerateSubtitleData)?,
-    )?;
+    let correct_data = correct_file
+        .to_data()
+        .with_context(|_| TopLevelErrorKind::FailedToGenerateSubtitleData)?;
+
+    if args.backup
+        && let Some(backup_path) = backup_file(output_file_path, args.force)?
+    {
+        println!("info: saved the previous output file to '{}'", backup_path.display());
+        println!();
+    }
+
+    write_data_to_file(output_file_path, correct_data)?;
+
+    if let Some(mtime_source_path) = &args.mtime_source_path {
+        copy_modification_time(mtime_source_path, output_file_path)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the best distinct constant-shift alignments to numbered output files, so the user can pick
+/// the right one for ambiguous content.
+fn write_candidates(
+    args: &Arguments,
+    candidate_count: usize,
+    ref_aligner_timespans: &[ilass::TimeSpan],
+    inc_aligner_timespans: &[ilass::TimeSpan],
+    fps_scaling_factor: f64,
+) -> Result<(), failure::Error> {
+    // offsets which are less than a second apart are usually the same alignment
+    let min_distance = AlgTimeDelta::from_i64((1000 / args.interval).max(1));
+    let candidates = ilass::align_nosplit_candidates(
+        ref_aligner_timespans,
+        inc_aligner_timespans,
+        candidate_count,
+        min_distance,
+        ilass::standard_scoring,
+    );
+
+    if candidates.len() < candidate_count {
+        println!("warn: only found {} distinct alignments", candidates.len());
+        println!();
+    }
+
+    for (i, (alg_delta, score)) in candidates.into_iter().enumerate() {
+        let mut delta = alg_delta_to_delta(alg_delta, args.interval);
+        if let Some(offset_quantum) = args.offset_quantum {
+            delta = quantize_delta(delta, offset_quantum);
+        }
+
+        let candidate_path = candidate_file_path(&args.output_file_path, i + 1);
+        println!(
+            "candidate {}: shifted all subtitles by {} (score {:.2}), writing '{}'",
+            i + 1,
+            delta,
+            score,
+            candidate_path.display()
+        );
+        println!();
+
+        let inc_file = SubtitleFileHandler::open_sub_file(
+            args.incorrect_file_path.as_path(),
+            args.encoding_inc,
+            args.sub_fps_inc,
+        )?;
+        let deltas = vec![delta; inc_file.timespans().len()];
+        write_corrected_file(
+            args,
+            inc_file,
+            &deltas,
+            fps_scaling_factor,
+            &candidate_path,
+            &mut Vec::new(),
+        )?;
+    }
 
     Ok(())
 }
@@ -12,6 +12,7 @@ use std::str::from_utf8;
 use byteorder::ByteOrder;
 use serde::{Deserialize, Deserializer};
 
+use super::{RateLimiter, SubtitleTrackPolicy};
 use crate::define_error;
 
 #[derive(Debug, PartialEq, Eq)]
@@ -41,6 +42,28 @@ struct Stream {
     /// `.mkv` does not store the duration in the streams; we have to use `format -> duration` instead
     pub duration: Option<String>,
     pub codec_type: CodecType,
+    pub codec_name: Option<String>,
+    #[serde(default)]
+    pub disposition: Disposition,
+}
+
+impl Stream {
+    fn is_forced(&self) -> bool {
+        self.disposition.forced != 0
+    }
+
+    fn is_hearing_impaired(&self) -> bool {
+        self.disposition.hearing_impaired != 0
+    }
+}
+
+/// The flags of a stream which ffprobe writes as `0` or `1` (see `SubtitleTrackPolicy`).
+#[derive(Debug, Default, Deserialize)]
+struct Disposition {
+    #[serde(default)]
+    pub forced: u8,
+    #[serde(default)]
+    pub hearing_impaired: u8,
 }
 
 #[derive(Debug, Deserialize)]
@@ -59,7 +82,11 @@ define_error!(DecoderError, DecoderErrorKind);
 
 #[derive(Debug, Fail)]
 pub enum DecoderErrorKind {
-    FailedToDecodeVideoStreamInfo,
+    /// ffprobe printed something that is not UTF-8 (often a locale or encoding issue)
+    FfprobeOutputNotUtf8 {
+        /// the start of the output, lossily decoded
+        snippet: String,
+    },
     ExtractingMetadataFailed {
         cmd_path: PathBuf,
         file_path: PathBuf,
@@ -67,6 +94,8 @@ pub enum DecoderErrorKind {
     },
     NoAudioStream {
         path: PathBuf,
+        /// `true` if the file was probed with increased `-analyzeduration`/`-probesize` too
+        deep_probe: bool,
     },
     FailedExtractingAudio {
         file_path: PathBuf,
@@ -96,6 +125,27 @@ pub enum DecoderErrorKind {
     },
     AudioSegmentProcessingFailed,
     NoDurationInformation,
+    NoSubtitleStream {
+        path: PathBuf,
+        /// the requested stream index (`None` if any subtitle stream would have done)
+        index: Option<usize>,
+    },
+    /// all subtitle streams were excluded because of their disposition
+    NoSubtitleStreamForPolicy {
+        path: PathBuf,
+        policy: SubtitleTrackPolicy,
+    },
+    /// picture-based subtitles (e.g. PGS or VobSub) can not be converted to text
+    BitmapSubtitleStream {
+        path: PathBuf,
+        index: usize,
+        codec_name: String,
+    },
+    FailedExtractingSubtitle {
+        file_path: PathBuf,
+        cmd_path: PathBuf,
+        args: Vec<OsString>,
+    },
 }
 
 fn format_cmd(cmd_path: &Path, args: &[OsString]) -> String {
@@ -110,11 +160,24 @@ fn format_cmd(cmd_path: &Path, args: &[OsString]) -> String {
 impl fmt::Display for DecoderErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            DecoderErrorKind::FailedToDecodeVideoStreamInfo => write!(f, "failed to decode video stream info"),
+            DecoderErrorKind::FfprobeOutputNotUtf8 { snippet } => {
+                write!(f, "output of ffprobe is not valid UTF-8: '{}'", snippet)
+            }
             DecoderErrorKind::DeserializingMetadataFailed { path } => {
                 write!(f, "failed to deserialize metadata of file '{}'", path.display())
             }
-            DecoderErrorKind::NoAudioStream { path } => write!(f, "no audio stream in file '{}'", path.display()),
+            DecoderErrorKind::NoAudioStream { path, deep_probe: true } => write!(
+                f,
+                "no audio stream in file '{}' (even with analyzeduration/probesize increased to {})",
+                path.display(),
+                DEEP_PROBE_SIZE
+            ),
+            DecoderErrorKind::NoAudioStream {
+                path,
+                deep_probe: false,
+            } => {
+                write!(f, "no audio stream in file '{}'", path.display())
+            }
             DecoderErrorKind::FailedExtractingAudio {
                 file_path,
                 cmd_path,
@@ -155,6 +218,45 @@ impl fmt::Display for DecoderErrorKind {
             }
             DecoderErrorKind::AudioSegmentProcessingFailed => write!(f, "processing audio segment failed"),
             DecoderErrorKind::NoDurationInformation => write!(f, "no audio duration information found"),
+            DecoderErrorKind::NoSubtitleStream { path, index: None } => {
+                write!(f, "no subtitle stream in file '{}'", path.display())
+            }
+            DecoderErrorKind::NoSubtitleStream {
+                path,
+                index: Some(index),
+            } => write!(
+                f,
+                "stream {} of file '{}' is not a subtitle stream",
+                index,
+                path.display()
+            ),
+            DecoderErrorKind::NoSubtitleStreamForPolicy { path, policy } => write!(
+                f,
+                "no subtitle stream in file '{}' is allowed by the subtitle track policy '{}'",
+                path.display(),
+                policy
+            ),
+            DecoderErrorKind::BitmapSubtitleStream {
+                path,
+                index,
+                codec_name,
+            } => write!(
+                f,
+                "subtitle stream {} of file '{}' contains pictures ({}), only text subtitles can be used as reference",
+                index,
+                path.display(),
+                codec_name
+            ),
+            DecoderErrorKind::FailedExtractingSubtitle {
+                file_path,
+                cmd_path,
+                args,
+            } => write!(
+                f,
+                "failed to extract subtitles from '{}' with '{}' ",
+                file_path.display(),
+                format_cmd(cmd_path, args)
+            ),
         }
     }
 }
@@ -172,145 +274,451 @@ pub struct VideoDecoderFFmpegBinary {}
 
 static PROGRESS_PRESCALER: i64 = 200;
 
+/// Value for `-analyzeduration` (in microseconds) and `-probesize` (in bytes) when the default probe
+/// of ffprobe finds no audio stream (some containers store the audio in a way the default probe misses).
+static DEEP_PROBE_SIZE: &str = "100M";
+
+/// Number of characters of the ffprobe output shown when it is not valid UTF-8.
+static FFPROBE_OUTPUT_SNIPPET_LEN: usize = 200;
+
+/// ffmpeg codec names of picture-based subtitles, which ffmpeg can not convert to SubRip.
+static BITMAP_SUBTITLE_CODECS: [&str; 4] = ["hdmv_pgs_subtitle", "dvd_subtitle", "dvb_subtitle", "xsub"];
+
+/// Raw sample format which ffmpeg writes to the pipe (the parser reads the same format).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SampleFormat {
+    /// 16-bit signed little endian
+    S16Le,
+    /// 32-bit signed little endian
+    S32Le,
+    /// 32-bit float little endian (in range -1 to 1)
+    F32Le,
+}
+
+impl SampleFormat {
+    fn codec_name(self) -> &'static str {
+        match self {
+            SampleFormat::S16Le => "pcm_s16le",
+            SampleFormat::S32Le => "pcm_s32le",
+            SampleFormat::F32Le => "pcm_f32le",
+        }
+    }
+
+    fn format_name(self) -> &'static str {
+        match self {
+            SampleFormat::S16Le => "s16le",
+            SampleFormat::S32Le => "s32le",
+            SampleFormat::F32Le => "f32le",
+        }
+    }
+
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            SampleFormat::S16Le => 2,
+            SampleFormat::S32Le | SampleFormat::F32Le => 4,
+        }
+    }
+
+    /// Converts the bytes of one sample to the 16-bit sample an `AudioReceiver` expects.
+    fn parse_sample(self, bytes: &[u8]) -> i16 {
+        match self {
+            SampleFormat::S16Le => byteorder::LittleEndian::read_i16(bytes),
+            SampleFormat::S32Le => (byteorder::LittleEndian::read_i32(bytes) >> 16) as i16,
+            SampleFormat::F32Le => {
+                (byteorder::LittleEndian::read_f32(bytes).clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16
+            }
+        }
+    }
+}
+
+/// Kills and reaps the wrapped ffmpeg process when it is dropped, so returning early (e.g. on a
+/// read error) does not leave the process running. On the success path the process has already
+/// been waited for, and killing an exited child does nothing.
+struct ChildGuard(Child);
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        // errors only mean that the process is already gone
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
 impl VideoDecoderFFmpegBinary {
     /// Samples are pushed in 8kHz mono/single-channel format.
+    ///
+    /// Only the first `max_seconds` seconds of the audio are decoded if a limit is given, and at
+    /// most `rate_limit` samples per second (see `RateLimiter`).
     pub fn decode<T>(
         file_path: impl AsRef<Path>,
         audio_index: Option<usize>,
+        max_seconds: Option<f64>,
+        rate_limit: Option<u64>,
+        receiver: impl super::AudioReceiver<Output = T>,
+        progress_handler: impl super::ProgressHandler,
+    ) -> Result<T, DecoderError> {
+        Self::decode_with_sample_format(
+            file_path,
+            audio_index,
+            max_seconds,
+            rate_limit,
+            SampleFormat::S16Le,
+            receiver,
+            progress_handler,
+        )
+    }
+
+    /// Like `decode`, but lets ffmpeg write the samples in `sample_format` (they are still
+    /// pushed as 16-bit samples).
+    pub fn decode_with_sample_format<T>(
+        file_path: impl AsRef<Path>,
+        audio_index: Option<usize>,
+        max_seconds: Option<f64>,
+        rate_limit: Option<u64>,
+        sample_format: SampleFormat,
         receiver: impl super::AudioReceiver<Output = T>,
         mut progress_handler: impl super::ProgressHandler,
     ) -> Result<T, DecoderError> {
         let file_path_buf: PathBuf = file_path.as_ref().into();
 
-        let args = vec![
-            OsString::from("-v"),
-            OsString::from("error"),
-            OsString::from("-show_entries"),
-            OsString::from("format=duration:stream=index,channels,duration,codec_type"),
-            OsString::from("-of"),
-            OsString::from("json"),
-            OsString::from(file_path.as_ref()),
-        ];
-
         let ffprobe_path: PathBuf = std::env::var_os("ILASS_FFPROBE_PATH")
             .unwrap_or(OsString::from("ffprobe"))
             .into();
 
-        let metadata: Metadata =
-            Self::get_metadata(file_path_buf.clone(), ffprobe_path.clone(), &args).with_context(|_| {
-                DecoderErrorKind::ExtractingMetadataFailed {
+        let probe = |deep_probe: bool| -> Result<Metadata, DecoderError> {
+            let args = Self::probe_args(file_path.as_ref(), deep_probe);
+            Self::get_metadata(file_path_buf.clone(), ffprobe_path.clone(), &args)
+                .with_context(|_| DecoderErrorKind::ExtractingMetadataFailed {
                     file_path: file_path_buf.clone(),
                     cmd_path: ffprobe_path.clone(),
                     args,
-                }
+                })
+                .map_err(DecoderError::from)
+        };
+
+        let (metadata, best_stream, deep_probe) = Self::probe_audio_stream(&file_path_buf, audio_index, probe)?;
+
+        let ffmpeg_path: PathBuf = std::env::var_os("ILASS_FFMPEG_PATH")
+            .unwrap_or(OsString::from("ffmpeg"))
+            .into();
+
+        let args = Self::decode_args(
+            file_path.as_ref(),
+            best_stream.index,
+            deep_probe,
+            sample_format,
+            max_seconds,
+        );
+
+        let format_opt: Option<Format> = metadata.format;
+
+        // `.mkv` containers do not store duration info in streams, only the format information does contain it
+        let duration_str = best_stream
+            .duration
+            .or_else(|| format_opt.and_then(|format| format.duration))
+            .ok_or_else(|| DecoderError::from(DecoderErrorKind::NoDurationInformation))?;
+
+        let duration = duration_str
+            .parse::<f64>()
+            .with_context(|_| DecoderErrorKind::FailedToParseDuration { s: duration_str })?;
+
+        let num_samples: i64 = Self::progress_steps(duration, max_seconds);
+
+        progress_handler.init(num_samples);
+
+        Self::extract_audio_stream(
+            receiver,
+            progress_handler,
+            num_samples,
+            sample_format,
+            rate_limit.map(RateLimiter::new),
+            ffmpeg_path.clone(),
+            &args,
+        )
+        .with_context(|_| DecoderErrorKind::FailedExtractingAudio {
+            file_path: file_path_buf.clone(),
+            cmd_path: ffmpeg_path.clone(),
+            args,
+        })?
+        .into_ok()
+    }
+
+    /// Converts the embedded subtitle stream with the (ffprobe) stream index `subtitle_index`, or the
+    /// subtitle stream selected by `policy` if no index is given, to a SubRip file and returns its
+    /// (UTF-8) content.
+    ///
+    /// Only text subtitles can be converted; picture-based formats like the PGS subtitles of
+    /// Blu-rays give a `BitmapSubtitleStream` error.
+    pub fn extract_subtitle_stream(
+        file_path: impl AsRef<Path>,
+        subtitle_index: Option<usize>,
+        policy: SubtitleTrackPolicy,
+    ) -> Result<Vec<u8>, DecoderError> {
+        let file_path_buf: PathBuf = file_path.as_ref().into();
+
+        let ffprobe_path: PathBuf = std::env::var_os("ILASS_FFPROBE_PATH")
+            .unwrap_or(OsString::from("ffprobe"))
+            .into();
+
+        let probe_args = Self::probe_args(file_path.as_ref(), false);
+        let metadata: Metadata = Self::get_metadata(file_path_buf.clone(), ffprobe_path.clone(), &probe_args)
+            .with_context(|_| DecoderErrorKind::ExtractingMetadataFailed {
+                file_path: file_path_buf.clone(),
+                cmd_path: ffprobe_path,
+                args: probe_args,
             })?;
 
-        let mut audio_streams = metadata
+        let subtitle_stream: Stream = Self::select_subtitle_stream(&file_path_buf, metadata, subtitle_index, policy)?;
+
+        let ffmpeg_path: PathBuf = std::env::var_os("ILASS_FFMPEG_PATH")
+            .unwrap_or(OsString::from("ffmpeg"))
+            .into();
+
+        let args: Vec<OsString> = vec![
+            // only print errors
+            OsString::from("-v"),
+            OsString::from("error"),
+            // input file
+            OsString::from("-i"),
+            file_path.as_ref().into(),
+            // select stream
+            OsString::from("-map"),
+            format!("0:{}", subtitle_stream.index).into(),
+            // convert to SubRip
+            OsString::from("-f"),
+            OsString::from("srt"),
+            // output to stdout pipe
+            OsString::from("-"),
+        ];
+
+        Self::run_to_completion(ffmpeg_path.clone(), &args)
+            .with_context(|_| DecoderErrorKind::FailedExtractingSubtitle {
+                file_path: file_path_buf,
+                cmd_path: ffmpeg_path,
+                args,
+            })?
+            .into_ok()
+    }
+
+    /// Takes the subtitle stream with the index `subtitle_index` (or the one selected by `policy`)
+    /// out of the metadata. Fails if there is no such stream or if it contains pictures instead of
+    /// text.
+    fn select_subtitle_stream(
+        file_path: &Path,
+        metadata: Metadata,
+        subtitle_index: Option<usize>,
+        policy: SubtitleTrackPolicy,
+    ) -> Result<Stream, DecoderError> {
+        let mut subtitle_streams: Vec<Stream> = metadata
             .streams
             .into_iter()
-            .filter(|s| s.codec_type == CodecType::Audio && s.channels.is_some());
+            .filter(|s| s.codec_type == CodecType::Subtitle)
+            .collect();
 
-        let best_stream_opt = match audio_index {
-            None => audio_streams.min_by_key(|s| s.channels.unwrap()),
-            Some(ai) => audio_streams.find(|s| s.index == ai),
+        let no_subtitle_stream = || DecoderErrorKind::NoSubtitleStream {
+            path: file_path.to_path_buf(),
+            index: subtitle_index,
         };
 
-        let best_stream: Stream = match best_stream_opt {
-            Some(x) => x,
+        let position = match subtitle_index {
+            Some(index) => subtitle_streams
+                .iter()
+                .position(|s| s.index == index)
+                .ok_or_else(no_subtitle_stream)?,
             None => {
-                return Err(DecoderError::from(DecoderErrorKind::NoAudioStream {
-                    path: file_path.as_ref().into(),
-                }));
+                if subtitle_streams.is_empty() {
+                    return Err(no_subtitle_stream().into());
+                }
+
+                let position = |is_allowed: &dyn Fn(&Stream) -> bool| subtitle_streams.iter().position(is_allowed);
+                match policy {
+                    SubtitleTrackPolicy::First => Some(0),
+                    SubtitleTrackPolicy::ExcludeForced => position(&|s| !s.is_forced()),
+                    SubtitleTrackPolicy::ExcludeForcedAndSdh => {
+                        position(&|s| !s.is_forced() && !s.is_hearing_impaired())
+                    }
+                    SubtitleTrackPolicy::PreferSdh => position(&|s| !s.is_forced() && s.is_hearing_impaired())
+                        .or_else(|| position(&|s| !s.is_forced())),
+                }
+                .ok_or_else(|| DecoderErrorKind::NoSubtitleStreamForPolicy {
+                    path: file_path.to_path_buf(),
+                    policy,
+                })?
             }
         };
+        let subtitle_stream = subtitle_streams.swap_remove(position);
 
-        let ffmpeg_path: PathBuf = std::env::var_os("ILASS_FFMPEG_PATH")
-            .unwrap_or(OsString::from("ffmpeg"))
-            .into();
+        if let Some(codec_name) = &subtitle_stream.codec_name
+            && BITMAP_SUBTITLE_CODECS.contains(&codec_name.as_str())
+        {
+            return Err(DecoderError::from(DecoderErrorKind::BitmapSubtitleStream {
+                path: file_path.to_path_buf(),
+                index: subtitle_stream.index,
+                codec_name: codec_name.clone(),
+            }));
+        }
 
-        let args: Vec<OsString> = vec![
+        Ok(subtitle_stream)
+    }
+
+    /// The arguments for ffmpeg which write the raw samples of the stream `stream_index` to stdout.
+    fn decode_args(
+        file_path: &Path,
+        stream_index: usize,
+        deep_probe: bool,
+        sample_format: SampleFormat,
+        max_seconds: Option<f64>,
+    ) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![
             // only print errors
             OsString::from("-v"),
             OsString::from("error"),
             // "yes" -> disables user interaction
             OsString::from("-y"),
+        ];
+        if deep_probe {
+            // the audio stream was only found with a deeper probe
+            args.extend(Self::deep_probe_args());
+        }
+        args.extend([
             // input file
             OsString::from("-i"),
-            file_path.as_ref().into(),
+            file_path.into(),
             // select stream
             OsString::from("-map"),
-            format!("0:{}", best_stream.index).into(),
-            // audio codec: 16-bit signed little endian
+            format!("0:{}", stream_index).into(),
+            // audio codec: raw samples
             OsString::from("-acodec"),
-            OsString::from("pcm_s16le"),
+            OsString::from(sample_format.codec_name()),
             // resample to 8khz
             OsString::from("-ar"),
             OsString::from("8000"),
             // resample to single channel
             OsString::from("-ac"),
             OsString::from("1"),
-            // output 16-bit signed little endian stream directly (no wav, etc.)
+        ]);
+        if let Some(max_seconds) = max_seconds {
+            // stop writing output after this duration
+            args.extend([OsString::from("-t"), OsString::from(max_seconds.to_string())]);
+        }
+        args.extend([
+            // output the raw samples directly (no wav, etc.)
             OsString::from("-f"),
-            OsString::from("s16le"),
+            OsString::from(sample_format.format_name()),
             // output to stdout pipe
             OsString::from("-"),
-        ];
+        ]);
+        args
+    }
 
-        let format_opt: Option<Format> = metadata.format;
+    /// The number of progress steps for decoding `duration` seconds of audio, of which at most
+    /// `max_seconds` are decoded.
+    fn progress_steps(duration: f64, max_seconds: Option<f64>) -> i64 {
+        let duration = match max_seconds {
+            Some(max_seconds) => duration.min(max_seconds),
+            None => duration,
+        };
 
-        // `.mkv` containers do not store duration info in streams, only the format information does contain it
-        let duration_str = best_stream
-            .duration
-            .or_else(|| format_opt.and_then(|format| format.duration))
-            .ok_or_else(|| DecoderError::from(DecoderErrorKind::NoDurationInformation))?;
+        // zero-duration media (or a bogus negative duration) gives zero progress steps
+        std::cmp::max((duration * 8000.0) as i64 / PROGRESS_PRESCALER, 0)
+    }
 
-        let duration = duration_str
-            .parse::<f64>()
-            .with_context(|_| DecoderErrorKind::FailedToParseDuration { s: duration_str })?;
+    fn probe_args(file_path: &Path, deep_probe: bool) -> Vec<OsString> {
+        let mut args = vec![OsString::from("-v"), OsString::from("error")];
+        if deep_probe {
+            args.extend(Self::deep_probe_args());
+        }
+        args.extend([
+            OsString::from("-show_entries"),
+            OsString::from(
+                "format=duration:stream=index,channels,duration,codec_type,codec_name                 :stream_disposition=forced,hearing_impaired",
+            ),
+            OsString::from("-of"),
+            OsString::from("json"),
+            OsString::from(file_path),
+        ]);
+        args
+    }
+
+    fn deep_probe_args() -> [OsString; 4] {
+        [
+            OsString::from("-analyzeduration"),
+            OsString::from(DEEP_PROBE_SIZE),
+            OsString::from("-probesize"),
+            OsString::from(DEEP_PROBE_SIZE),
+        ]
+    }
 
-        let num_samples: i64 = (duration * 8000.0) as i64 / PROGRESS_PRESCALER;
+    /// Selects the audio stream in the metadata returned by `probe(false)`, or by the deeper
+    /// `probe(true)` if the first probe found none. Also returns whether the deep probe was needed.
+    fn probe_audio_stream(
+        file_path: &Path,
+        audio_index: Option<usize>,
+        probe: impl Fn(bool) -> Result<Metadata, DecoderError>,
+    ) -> Result<(Metadata, Stream, bool), DecoderError> {
+        let mut metadata: Metadata = probe(false)?;
+        if let Some(stream) = Self::select_audio_stream(&mut metadata, audio_index) {
+            return Ok((metadata, stream, false));
+        }
 
-        progress_handler.init(num_samples);
+        // a failing deep probe is reported as it is
+        let mut metadata: Metadata = probe(true)?;
+        match Self::select_audio_stream(&mut metadata, audio_index) {
+            Some(stream) => Ok((metadata, stream, true)),
+            None => Err(DecoderError::from(DecoderErrorKind::NoAudioStream {
+                path: file_path.to_path_buf(),
+                deep_probe: true,
+            })),
+        }
+    }
 
-        Self::extract_audio_stream(receiver, progress_handler, ffmpeg_path.clone(), &args)
-            .with_context(|_| DecoderErrorKind::FailedExtractingAudio {
-                file_path: file_path_buf.clone(),
-                cmd_path: ffmpeg_path.clone(),
-                args,
-            })?
-            .into_ok()
+    /// Takes the requested audio stream (or the one with the fewest channels) out of the metadata.
+    fn select_audio_stream(metadata: &mut Metadata, audio_index: Option<usize>) -> Option<Stream> {
+        let mut audio_streams = std::mem::take(&mut metadata.streams)
+            .into_iter()
+            .filter(|s| s.codec_type == CodecType::Audio && s.channels.is_some());
+
+        match audio_index {
+            None => audio_streams.min_by_key(|s| s.channels.unwrap()),
+            Some(ai) => audio_streams.find(|s| s.index == ai),
+        }
     }
 
     fn extract_audio_stream<T>(
         mut receiver: impl super::AudioReceiver<Output = T>,
         mut progress_handler: impl super::ProgressHandler,
+        progress_steps: i64,
+        sample_format: SampleFormat,
+        mut rate_limiter: Option<RateLimiter>,
         ffmpeg_path: PathBuf,
         args: &[OsString],
     ) -> Result<T, DecoderError> {
-        let mut ffmpeg_process: Child = Command::new(ffmpeg_path.clone())
-            .args(args)
-            .stdin(Stdio::null())
-            .stderr(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-            .with_context(|_| DecoderErrorKind::FailedSpawningSubprocess {
-                path: ffmpeg_path.clone(),
-                args: args.to_vec(),
-            })?;
+        let mut ffmpeg_process = ChildGuard(
+            Command::new(ffmpeg_path.clone())
+                .args(args)
+                .stdin(Stdio::null())
+                .stderr(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .with_context(|_| DecoderErrorKind::FailedSpawningSubprocess {
+                    path: ffmpeg_path.clone(),
+                    args: args.to_vec(),
+                })?,
+        );
 
-        let mut stdout: ChildStdout = ffmpeg_process.stdout.take().unwrap();
-
-        enum ParserState {
-            Start,
-            SingleByte(u8),
-        }
+        let mut stdout: ChildStdout = ffmpeg_process.0.stdout.take().unwrap();
 
         let mut data: Vec<u8> = std::vec::from_elem(0, 200 * 1024 * 1024);
         let data2_cap = 1024 * 1024;
         let mut data2: Vec<i16> = Vec::with_capacity(data2_cap);
-        let mut parser_state: ParserState = ParserState::Start;
+        let bytes_per_sample = sample_format.bytes_per_sample();
+        let mut sample_bytes: [u8; 4] = [0; 4];
+        let mut filled_sample_bytes = 0;
         let mut progress_prescaler_counter = 0;
 
+        // the number of steps is only estimated from the duration, so ffmpeg might deliver more samples
+        let mut remaining_progress_steps = progress_steps;
+
         loop {
             // improves performance by allowing ffmpeg to generate more data in pipe
             // TODO: an async tokio read might also have the same effect (without being as machine dependent)
@@ -323,6 +731,7 @@ impl VideoDecoderFFmpegBinary {
 
             if read_bytes == 0 {
                 match ffmpeg_process
+                    .0
                     .wait()
                     .with_context(|_| DecoderErrorKind::WaitingForProcessFailed {
                         cmd_path: ffmpeg_path.clone(),
@@ -347,7 +756,9 @@ impl VideoDecoderFFmpegBinary {
 
                         let mut stderr_data = Vec::new();
                         ffmpeg_process
+                            .0
                             .stderr
+                            .take()
                             .unwrap()
                             .read_to_end(&mut stderr_data)
                             .with_context(|_| DecoderErrorKind::ReadError)?;
@@ -368,55 +779,65 @@ impl VideoDecoderFFmpegBinary {
             }
 
             for &byte in &data[0..read_bytes] {
-                match parser_state {
-                    ParserState::Start => parser_state = ParserState::SingleByte(byte),
-                    ParserState::SingleByte(last_byte) => {
-                        let two_bytes = [last_byte, byte];
-                        let sample = byteorder::LittleEndian::read_i16(&two_bytes);
-                        receiver
-                            .push_samples(&[sample])
-                            .with_context(|_| DecoderErrorKind::AudioSegmentProcessingFailed)?;
-
-                        if progress_prescaler_counter == PROGRESS_PRESCALER {
-                            progress_handler.inc();
-                            progress_prescaler_counter = 0;
-                        }
+                sample_bytes[filled_sample_bytes] = byte;
+                filled_sample_bytes += 1;
+                if filled_sample_bytes < bytes_per_sample {
+                    continue;
+                }
+                filled_sample_bytes = 0;
 
-                        progress_prescaler_counter += 1;
+                let sample = sample_format.parse_sample(&sample_bytes[..bytes_per_sample]);
+                receiver
+                    .push_samples(&[sample])
+                    .with_context(|_| DecoderErrorKind::AudioSegmentProcessingFailed)?;
 
-                        /*data2.push(sample);
-                        if data2.len() == data2_cap {
-                            receiver.push_samples(&data2);
-                            data2.clear();
-                        }*/
-                        parser_state = ParserState::Start;
+                if progress_prescaler_counter == PROGRESS_PRESCALER {
+                    if remaining_progress_steps > 0 {
+                        progress_handler.inc();
+                        remaining_progress_steps -= 1;
                     }
+                    progress_prescaler_counter = 0;
                 }
+
+                progress_prescaler_counter += 1;
+
+                /*data2.push(sample);
+                if data2.len() == data2_cap {
+                    receiver.push_samples(&data2);
+                    data2.clear();
+                }*/
+            }
+
+            if let Some(rate_limiter) = &mut rate_limiter {
+                rate_limiter.processed((read_bytes / bytes_per_sample) as u64);
             }
         }
     }
 
     fn get_metadata(file_path: PathBuf, ffprobe_path: PathBuf, args: &[OsString]) -> Result<Metadata, DecoderError> {
-        let ffprobe_process: Output = Command::new(ffprobe_path.clone())
+        let stdout = Self::run_to_completion(ffprobe_path, args)?;
+        Self::parse_metadata(file_path, &stdout)
+    }
+
+    /// Runs `cmd_path` and returns everything it wrote to stdout.
+    fn run_to_completion(cmd_path: PathBuf, args: &[OsString]) -> Result<Vec<u8>, DecoderError> {
+        let process: Output = Command::new(cmd_path.clone())
             .args(args)
             .stdin(Stdio::null())
             .stderr(Stdio::piped())
             .stdout(Stdio::piped())
             .output()
             .with_context(|_| DecoderErrorKind::FailedSpawningSubprocess {
-                path: ffprobe_path.clone(),
+                path: cmd_path.clone(),
                 args: args.to_vec(),
             })?;
 
-        if !ffprobe_process.status.success() {
-            let stderr: String = String::from_utf8_lossy(&ffprobe_process.stderr)
-                .to_string()
-                .trim_end()
-                .to_string();
+        if !process.status.success() {
+            let stderr: String = String::from_utf8_lossy(&process.stderr).trim_end().to_string();
 
             let err = DecoderErrorKind::ProcessErrorCode {
-                cmd_path: ffprobe_path.clone(),
-                code: ffprobe_process.status.code(),
+                cmd_path,
+                code: process.status.code(),
             };
 
             if stderr.is_empty() {
@@ -430,8 +851,16 @@ impl VideoDecoderFFmpegBinary {
             }
         }
 
-        let stdout =
-            from_utf8(&ffprobe_process.stdout).with_context(|_| DecoderErrorKind::FailedToDecodeVideoStreamInfo)?;
+        Ok(process.stdout)
+    }
+
+    fn parse_metadata(file_path: PathBuf, stdout: &[u8]) -> Result<Metadata, DecoderError> {
+        let stdout = from_utf8(stdout).with_context(|_| DecoderErrorKind::FfprobeOutputNotUtf8 {
+            snippet: String::from_utf8_lossy(stdout)
+                .chars()
+                .take(FFPROBE_OUTPUT_SNIPPET_LEN)
+                .collect(),
+        })?;
 
         let metadata: Metadata = serde_json::from_str(stdout)
             .with_context(|_| DecoderErrorKind::DeserializingMetadataFailed { path: file_path })?;
@@ -439,3 +868,290 @@ impl VideoDecoderFFmpegBinary {
         Ok(metadata)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::video_decoder::{FnReceiver, ProgressHandler};
+    use std::cell::{Cell, RefCell};
+
+    /// Records the calls of the progress handler in `calls`.
+    struct RecordingProgressHandler<'a> {
+        calls: &'a RefCell<Vec<String>>,
+    }
+
+    impl ProgressHandler for RecordingProgressHandler<'_> {
+        fn init(&mut self, steps: i64) {
+            self.calls.borrow_mut().push(format!("init({})", steps));
+        }
+
+        fn inc(&mut self) {
+            self.calls.borrow_mut().push(String::from("inc"));
+        }
+
+        fn finish(&mut self) {
+            self.calls.borrow_mut().push(String::from("finish"));
+        }
+    }
+
+    #[test]
+    fn non_utf8_ffprobe_output_is_reported() {
+        let stdout = b"{\"streams\": \xff\xfe]}";
+        let error = VideoDecoderFFmpegBinary::parse_metadata(PathBuf::from("video.mkv"), stdout).unwrap_err();
+        match error.kind() {
+            DecoderErrorKind::FfprobeOutputNotUtf8 { snippet } => {
+                assert_eq!(snippet, "{\"streams\": \u{fffd}\u{fffd}]}")
+            }
+            kind => panic!("unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn deep_probe_is_only_used_without_audio_stream() {
+        let path = Path::new("video.mkv");
+        let metadata = |json: &str| -> Result<Metadata, DecoderError> { Ok(serde_json::from_str(json).unwrap()) };
+        let no_audio = r#"{"streams": [{"index": 0, "codec_type": "video"}]}"#;
+        let audio = r#"{"streams": [{"index": 1, "channels": 2, "codec_type": "audio"}]}"#;
+
+        let (_, stream, deep_probe) = VideoDecoderFFmpegBinary::probe_audio_stream(path, None, |deep| {
+            assert!(!deep);
+            metadata(audio)
+        })
+        .unwrap();
+        assert_eq!((stream.index, deep_probe), (1, false));
+
+        let (_, stream, deep_probe) = VideoDecoderFFmpegBinary::probe_audio_stream(path, None, |deep| {
+            metadata(if deep { audio } else { no_audio })
+        })
+        .unwrap();
+        assert_eq!((stream.index, deep_probe), (1, true));
+
+        let error = VideoDecoderFFmpegBinary::probe_audio_stream(path, None, |_| metadata(no_audio)).unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            DecoderErrorKind::NoAudioStream { deep_probe: true, .. }
+        ));
+    }
+
+    #[test]
+    fn failing_deep_probe_is_reported_as_it_is() {
+        let error = VideoDecoderFFmpegBinary::probe_audio_stream(Path::new("video.mkv"), None, |deep| {
+            if deep {
+                Err(DecoderError::from(DecoderErrorKind::ReadError))
+            } else {
+                Ok(serde_json::from_str(r#"{"streams": []}"#).unwrap())
+            }
+        })
+        .unwrap_err();
+        assert!(matches!(error.kind(), DecoderErrorKind::ReadError));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn child_is_reaped_on_drop() {
+        let is_alive = |pid: u32| unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+
+        let guard = ChildGuard(Command::new("sleep").arg("60").spawn().unwrap());
+        let pid = guard.0.id();
+        assert!(is_alive(pid));
+
+        // a killed child that was not waited for would still exist as a zombie
+        drop(guard);
+        assert!(!is_alive(pid));
+    }
+
+    #[test]
+    fn bitmap_subtitle_streams_are_rejected() {
+        let path = Path::new("video.mkv");
+        let metadata = || -> Metadata {
+            serde_json::from_str(
+                r#"{"streams": [
+                    {"index": 0, "codec_type": "video", "codec_name": "h264"},
+                    {"index": 2, "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle"},
+                    {"index": 3, "codec_type": "subtitle", "codec_name": "subrip"}
+                ]}"#,
+            )
+            .unwrap()
+        };
+
+        let error =
+            VideoDecoderFFmpegBinary::select_subtitle_stream(path, metadata(), Some(2), SubtitleTrackPolicy::First)
+                .unwrap_err();
+        match error.kind() {
+            DecoderErrorKind::BitmapSubtitleStream { index, codec_name, .. } => {
+                assert_eq!((*index, codec_name.as_str()), (2, "hdmv_pgs_subtitle"))
+            }
+            kind => panic!("unexpected error: {}", kind),
+        }
+
+        // the first subtitle stream contains pictures too
+        let error =
+            VideoDecoderFFmpegBinary::select_subtitle_stream(path, metadata(), None, SubtitleTrackPolicy::First)
+                .unwrap_err();
+        assert!(matches!(error.kind(), DecoderErrorKind::BitmapSubtitleStream { .. }));
+
+        let stream =
+            VideoDecoderFFmpegBinary::select_subtitle_stream(path, metadata(), Some(3), SubtitleTrackPolicy::First)
+                .unwrap();
+        assert_eq!(stream.index, 3);
+
+        let error =
+            VideoDecoderFFmpegBinary::select_subtitle_stream(path, metadata(), Some(0), SubtitleTrackPolicy::First)
+                .unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            DecoderErrorKind::NoSubtitleStream { index: Some(0), .. }
+        ));
+    }
+
+    #[test]
+    fn subtitle_track_policy_selects_by_disposition() {
+        let path = Path::new("movie.mkv");
+        // ffprobe output for a forced-narrative, an SDH and a regular track
+        let metadata = || -> Metadata {
+            serde_json::from_str(
+                r#"{"streams": [
+                    {"index": 1, "codec_type": "audio", "channels": 2},
+                    {"index": 2, "codec_type": "subtitle", "codec_name": "subrip",
+                     "disposition": {"default": 0, "forced": 1, "hearing_impaired": 0}},
+                    {"index": 3, "codec_type": "subtitle", "codec_name": "subrip",
+                     "disposition": {"default": 0, "forced": 0, "hearing_impaired": 1}},
+                    {"index": 4, "codec_type": "subtitle", "codec_name": "subrip",
+                     "disposition": {"default": 1, "forced": 0, "hearing_impaired": 0}}
+                ]}"#,
+            )
+            .unwrap()
+        };
+        let select = |subtitle_index, policy| {
+            VideoDecoderFFmpegBinary::select_subtitle_stream(path, metadata(), subtitle_index, policy)
+                .unwrap()
+                .index
+        };
+
+        assert_eq!(select(None, SubtitleTrackPolicy::First), 2);
+        assert_eq!(select(None, SubtitleTrackPolicy::ExcludeForced), 3);
+        assert_eq!(select(None, SubtitleTrackPolicy::ExcludeForcedAndSdh), 4);
+        assert_eq!(select(None, SubtitleTrackPolicy::PreferSdh), 3);
+
+        // an explicit index is used even if the policy would exclude the stream
+        assert_eq!(select(Some(2), SubtitleTrackPolicy::ExcludeForcedAndSdh), 2);
+
+        // only the forced track is left
+        let only_forced: Metadata = serde_json::from_str(
+            r#"{"streams": [{"index": 2, "codec_type": "subtitle", "codec_name": "subrip",
+                "disposition": {"forced": 1, "hearing_impaired": 0}}]}"#,
+        )
+        .unwrap();
+        let error = VideoDecoderFFmpegBinary::select_subtitle_stream(
+            path,
+            only_forced,
+            None,
+            SubtitleTrackPolicy::ExcludeForced,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            DecoderErrorKind::NoSubtitleStreamForPolicy {
+                policy: SubtitleTrackPolicy::ExcludeForced,
+                ..
+            }
+        ));
+
+        // a stream without disposition flags counts as a regular track
+        let no_disposition: Metadata =
+            serde_json::from_str(r#"{"streams": [{"index": 5, "codec_type": "subtitle"}]}"#).unwrap();
+        let stream = VideoDecoderFFmpegBinary::select_subtitle_stream(
+            path,
+            no_disposition,
+            None,
+            SubtitleTrackPolicy::ExcludeForcedAndSdh,
+        )
+        .unwrap();
+        assert_eq!(stream.index, 5);
+    }
+
+    #[test]
+    fn analysis_cap_stops_the_decoding() {
+        let args = |max_seconds| {
+            VideoDecoderFFmpegBinary::decode_args(Path::new("video.mkv"), 1, false, SampleFormat::S16Le, max_seconds)
+        };
+        let position = |args: &[OsString], arg: &str| args.iter().position(|a| a == arg);
+
+        // `-t` has to be an output option (between the input and the output), so ffmpeg stops
+        // writing samples after the limit
+        let capped = args(Some(12.5));
+        let t = position(&capped, "-t").unwrap();
+        assert_eq!(capped[t + 1], "12.5");
+        assert!(position(&capped, "-i").unwrap() < t && t < position(&capped, "-f").unwrap());
+
+        assert_eq!(position(&args(None), "-t"), None);
+
+        // a bogus 10 hour duration does not make the progress wait for samples that never come
+        assert_eq!(
+            VideoDecoderFFmpegBinary::progress_steps(36000., Some(60.)),
+            60 * 8000 / PROGRESS_PRESCALER
+        );
+        assert_eq!(
+            VideoDecoderFFmpegBinary::progress_steps(30., Some(60.)),
+            30 * 8000 / PROGRESS_PRESCALER
+        );
+        assert_eq!(VideoDecoderFFmpegBinary::progress_steps(-1., None), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn zero_progress_steps_never_call_inc() {
+        // `sh` stands in for ffmpeg and writes `bytes` bytes of (16-bit) silence
+        let extract = |bytes: usize| {
+            let calls = RefCell::new(Vec::new());
+            let sample_count = Cell::new(0);
+            let receiver = FnReceiver::new(
+                |samples: &[i16]| sample_count.set(sample_count.get() + samples.len()),
+                || sample_count.get(),
+            );
+
+            // zero-length media
+            let steps = VideoDecoderFFmpegBinary::progress_steps(0., None);
+            let mut progress_handler = RecordingProgressHandler { calls: &calls };
+            progress_handler.init(steps);
+
+            let args = [OsString::from("-c"), format!("head -c {} /dev/zero", bytes).into()];
+            let samples = VideoDecoderFFmpegBinary::extract_audio_stream(
+                receiver,
+                progress_handler,
+                steps,
+                SampleFormat::S16Le,
+                None,
+                PathBuf::from("sh"),
+                &args,
+            )
+            .unwrap();
+            (samples, calls.into_inner())
+        };
+
+        assert_eq!(extract(0), (0, vec![String::from("init(0)"), String::from("finish")]));
+
+        // samples that were not announced by the duration do not make any progress either
+        let bytes = 10 * PROGRESS_PRESCALER as usize * 2;
+        assert_eq!(
+            extract(bytes),
+            (bytes / 2, vec![String::from("init(0)"), String::from("finish")])
+        );
+    }
+
+    #[test]
+    fn samples_are_converted_to_16_bit() {
+        assert_eq!(SampleFormat::S16Le.parse_sample(&(-1234i16).to_le_bytes()), -1234);
+
+        assert_eq!(SampleFormat::S32Le.parse_sample(&i32::MIN.to_le_bytes()), i16::MIN);
+        assert_eq!(SampleFormat::S32Le.parse_sample(&i32::MAX.to_le_bytes()), i16::MAX);
+        assert_eq!(SampleFormat::S32Le.parse_sample(&0i32.to_le_bytes()), 0);
+
+        assert_eq!(SampleFormat::F32Le.parse_sample(&1.0f32.to_le_bytes()), i16::MAX);
+        assert_eq!(SampleFormat::F32Le.parse_sample(&(-1.0f32).to_le_bytes()), -i16::MAX);
+        assert_eq!(SampleFormat::F32Le.parse_sample(&0.0f32.to_le_bytes()), 0);
+
+        // out of range samples are clipped
+        assert_eq!(SampleFormat::F32Le.parse_sample(&2.5f32.to_le_bytes()), i16::MAX);
+    }
+}
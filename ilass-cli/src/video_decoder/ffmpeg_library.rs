@@ -6,6 +6,7 @@ use std::fmt;
 use std::path::{Path, PathBuf};
 use std::ptr::null_mut;
 
+use super::RateLimiter;
 use crate::define_error;
 
 fn av_err2str(errnum: libc::c_int) -> String {
@@ -42,9 +43,14 @@ pub struct VideoDecoderFFmpegLibrary {}
 
 impl VideoDecoderFFmpegLibrary {
     /// Samples are pushed in 8kHz mono/single-channel format.
+    ///
+    /// Only the first `max_seconds` seconds of the audio are decoded if a limit is given, and at
+    /// most `rate_limit` samples per second (see `RateLimiter`).
     pub(crate) fn decode<T>(
         file_path: impl AsRef<Path>,
         audio_index: Option<usize>,
+        max_seconds: Option<f64>,
+        rate_limit: Option<u64>,
         mut receiver: impl super::AudioReceiver<Output = T>,
         mut progress_handler: impl super::ProgressHandler,
     ) -> Result<T, DecoderError> {
@@ -201,16 +207,26 @@ impl VideoDecoderFFmpegLibrary {
             let packet: *mut AVPacket = av_packet_alloc();
             let frame: *mut AVFrame = av_frame_alloc();
 
-            progress_handler.init((*audio_stream).nb_frames);
+            // `nb_frames` is zero if the container does not know the number of frames
+            let mut remaining_progress_steps = std::cmp::max((*audio_stream).nb_frames, 0);
+            progress_handler.init(remaining_progress_steps);
 
-            while av_read_frame(format_context, packet) >= 0 {
+            let mut remaining_samples: Option<usize> =
+                max_seconds.map(|max_seconds| (max_seconds * out_sample_rate as f64) as usize);
+
+            let mut rate_limiter: Option<RateLimiter> = rate_limit.map(RateLimiter::new);
+
+            while remaining_samples != Some(0) && av_read_frame(format_context, packet) >= 0 {
                 //println!("read frame {:?}", packet);
 
                 if (*packet).stream_index != (*audio_stream).index {
                     continue;
                 }
 
-                progress_handler.inc();
+                if remaining_progress_steps > 0 {
+                    progress_handler.inc();
+                    remaining_progress_steps -= 1;
+                }
 
                 //println!("stream fits");
 
@@ -257,10 +273,18 @@ impl VideoDecoderFFmpegLibrary {
                     );
 
                     //println!("Samples: {} Predicted: {} Frames: {}", (*frame).nb_samples, out_sample_count, frame_count);
-                    let out_slice = std::slice::from_raw_parts_mut(buffer, frame_count as usize);
+                    let mut out_slice = std::slice::from_raw_parts_mut(buffer, frame_count as usize);
+                    if let Some(remaining) = remaining_samples.as_mut() {
+                        out_slice = &mut out_slice[..std::cmp::min(*remaining, out_slice.len())];
+                        *remaining -= out_slice.len();
+                    }
 
                     receiver.push_samples(out_slice);
 
+                    if let Some(rate_limiter) = &mut rate_limiter {
+                        rate_limiter.processed(out_slice.len() as u64);
+                    }
+
                     /*for v in out_slice {
                         println!("{}", v);
                     }*/
@@ -10,6 +10,11 @@ mod ffmpeg_binary;
 #[cfg(feature = "ffmpeg-binary")]
 pub use ffmpeg_binary::VideoDecoderFFmpegBinary as VideoDecoder;
 
+#[cfg(feature = "ffmpeg-binary")]
+pub use ffmpeg_binary::SampleFormat;
+
+use std::time::{Duration, Instant};
+
 pub trait AudioReceiver {
     type Output;
     type Error: failure::Fail;
@@ -20,6 +25,22 @@ pub trait AudioReceiver {
     fn finish(self) -> Result<Self::Output, Self::Error>;
 }
 
+/// Passes the samples on in chunks of exactly `size` samples. Samples that do not fill a last
+/// chunk are dropped, so audio shorter than one chunk gives no chunks at all (and no voice
+/// segments, see `ilass::AlignWarning::InsufficientAudio`).
+///
+/// ```
+/// use ilass_cli::video_decoder::{AudioReceiver, ChunkedAudioReceiver, FnReceiver};
+/// use std::cell::Cell;
+///
+/// for samples in [&[][..], &[42][..]] {
+///     let chunks = Cell::new(0);
+///     let mut receiver =
+///         ChunkedAudioReceiver::new(80, FnReceiver::new(|_: &[i16]| chunks.set(chunks.get() + 1), || chunks.get()));
+///     receiver.push_samples(samples).unwrap();
+///     assert_eq!(receiver.finish().unwrap(), 0);
+/// }
+/// ```
 pub struct ChunkedAudioReceiver<R: AudioReceiver> {
     buffer: Vec<i16>,
     filled: usize,
@@ -69,22 +90,316 @@ impl<R: AudioReceiver> AudioReceiver for ChunkedAudioReceiver<R> {
     }
 }
 
+/// An `AudioReceiver` built from two closures: `push` is called with every chunk of samples
+/// and `finish` produces the output.
+///
+/// ```
+/// use ilass_cli::video_decoder::{AudioReceiver, FnReceiver};
+/// use std::cell::Cell;
+///
+/// let sum = Cell::new(0i64);
+/// let mut receiver = FnReceiver::new(
+///     |samples: &[i16]| sum.set(sum.get() + samples.iter().map(|&s| i64::from(s)).sum::<i64>()),
+///     || sum.get(),
+/// );
+///
+/// receiver.push_samples(&[1, 2, 3]).unwrap();
+/// receiver.push_samples(&[-10]).unwrap();
+/// assert_eq!(receiver.finish().unwrap(), -4);
+/// ```
+pub struct FnReceiver<F, G> {
+    push: F,
+    finish: G,
+}
+
+impl<F, G> FnReceiver<F, G> {
+    pub fn new(push: F, finish: G) -> FnReceiver<F, G> {
+        FnReceiver { push, finish }
+    }
+}
+
+impl<F, G, O> AudioReceiver for FnReceiver<F, G>
+where
+    F: FnMut(&[i16]),
+    G: FnOnce() -> O,
+{
+    type Output = O;
+    type Error = std::convert::Infallible;
+
+    fn push_samples(&mut self, samples: &[i16]) -> Result<(), Self::Error> {
+        (self.push)(samples);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<O, Self::Error> {
+        Ok((self.finish)())
+    }
+}
+
+/// Slows a processing loop down to at most `samples_per_second` samples (e.g. to cap the CPU
+/// usage on shared servers). Samples are processed at 8kHz, so `8000` is real-time speed.
+pub struct RateLimiter {
+    samples_per_second: u64,
+    start: Instant,
+    samples: u64,
+}
+
+impl RateLimiter {
+    pub fn new(samples_per_second: u64) -> RateLimiter {
+        assert!(samples_per_second > 0);
+        RateLimiter {
+            samples_per_second,
+            start: Instant::now(),
+            samples: 0,
+        }
+    }
+
+    /// Sleeps until the processing of `samples` further samples is allowed by the limit.
+    pub fn processed(&mut self, samples: u64) {
+        self.samples += samples;
+        let target = Duration::from_secs_f64(self.samples as f64 / self.samples_per_second as f64);
+        let elapsed = self.start.elapsed();
+        if target > elapsed {
+            std::thread::sleep(target - elapsed);
+        }
+    }
+}
+
+/// Selects the embedded subtitle stream which is used as reference if no stream index is given,
+/// based on the `disposition` flags of the streams. Forced-narrative tracks only contain the few
+/// lines of foreign dialogue and SDH tracks (for the deaf and hard of hearing) add lines for
+/// sounds, so both tend to be poor references for regular subtitles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SubtitleTrackPolicy {
+    /// The first subtitle stream, whatever its disposition.
+    #[default]
+    First,
+
+    /// The first subtitle stream that is not forced.
+    ExcludeForced,
+
+    /// The first subtitle stream that is neither forced nor SDH.
+    ExcludeForcedAndSdh,
+
+    /// The first SDH stream that is not forced (e.g. to align SDH subtitles), or the first stream
+    /// that is not forced if there is no such stream.
+    PreferSdh,
+}
+
+impl std::fmt::Display for SubtitleTrackPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            SubtitleTrackPolicy::First => "first",
+            SubtitleTrackPolicy::ExcludeForced => "no-forced",
+            SubtitleTrackPolicy::ExcludeForcedAndSdh => "regular",
+            SubtitleTrackPolicy::PreferSdh => "prefer-sdh",
+        })
+    }
+}
+
 /// Use this trait if you want more detailed information about the progress of operations.
 pub trait ProgressHandler {
     /// Will be called one time before `inc()` is called. `steps` is the
-    /// number of times `inc()` will be called.
+    /// maximum number of times `inc()` will be called.
     ///
-    /// The number of steps is around the number of lines in the "incorrect" subtitle.
-    /// Be aware that this number can be zero!
+    /// The number of steps is estimated from the duration of the media.
+    /// Be aware that this number can be zero (e.g. for zero-length media),
+    /// in which case `finish()` follows without any `inc()`. Implementors
+    /// must not divide by `steps`!
     #[allow(unused_variables)]
     fn init(&mut self, steps: i64) {}
 
     /// We made (small) progress!
     fn inc(&mut self) {}
 
-    /// Will be called after the last `inc()`, when `inc()` was called `steps` times.
+    /// Will be called after the last `inc()`. If the estimate of `steps` was
+    /// too high, `inc()` was called fewer than `steps` times.
     fn finish(&mut self) {}
 }
 
+/// Reports several consecutive phases (e.g. decoding the audio and then building the voice
+/// segments from it) as one progress of `PHASED_PROGRESS_STEPS` steps. Every phase gets a share
+/// of these steps proportional to its weight, so the progress keeps moving in phases whose work
+/// is not measured in decoded samples.
+///
+/// The phases are started one after another with `next_phase()`; a phase that ends early (or
+/// never calls `inc()`) jumps to its end when it finishes.
+///
+/// ```
+/// use ilass_cli::video_decoder::{PHASED_PROGRESS_STEPS, PhasedProgressHandler, ProgressHandler};
+/// use std::cell::Cell;
+/// use std::rc::Rc;
+///
+/// // records how many steps were reported
+/// struct Recorder(Rc<Cell<i64>>);
+///
+/// impl ProgressHandler for Recorder {
+///     fn init(&mut self, steps: i64) {
+///         assert_eq!(steps, PHASED_PROGRESS_STEPS);
+///     }
+///     fn inc(&mut self) {
+///         self.0.set(self.0.get() + 1);
+///     }
+/// }
+///
+/// let reported = Rc::new(Cell::new(0));
+/// let mut phased = PhasedProgressHandler::new(Recorder(reported.clone()), &[60, 30, 10]);
+///
+/// // decoding has many small steps, the later phases only a few
+/// let mut history = vec![0];
+/// let mut phase_ends = Vec::new();
+/// for phase_steps in [4000, 250, 7] {
+///     let mut phase = phased.next_phase();
+///     phase.init(phase_steps);
+///     for _ in 0..phase_steps {
+///         phase.inc();
+///         history.push(reported.get());
+///     }
+///     phase.finish();
+///     phase_ends.push(reported.get());
+/// }
+/// phased.finish();
+///
+/// assert_eq!(phase_ends, vec![600, 900, 1000]);
+///
+/// // the progress never goes back and even the 7 steps of the last phase each move it by only
+/// // 100 / 7 steps
+/// assert!(history.windows(2).all(|w| w[0] <= w[1] && w[1] - w[0] <= 15));
+/// ```
+pub struct PhasedProgressHandler<P: ProgressHandler> {
+    inner: P,
+    weights: Vec<u32>,
+    next_phase: usize,
+    reported_steps: i64,
+    started: bool,
+}
+
+/// The number of steps `PhasedProgressHandler` reports for all phases together.
+pub static PHASED_PROGRESS_STEPS: i64 = 1000;
+
+impl<P: ProgressHandler> PhasedProgressHandler<P> {
+    /// Creates a handler for `weights.len()` phases; the `i`-th phase takes
+    /// `weights[i] / sum(weights)` of the whole progress.
+    pub fn new(inner: P, weights: &[u32]) -> PhasedProgressHandler<P> {
+        PhasedProgressHandler {
+            inner,
+            weights: weights.to_vec(),
+            next_phase: 0,
+            reported_steps: 0,
+            started: false,
+        }
+    }
+
+    /// The progress handler of the next phase.
+    pub fn next_phase(&mut self) -> PhaseProgress<'_, P> {
+        assert!(self.next_phase < self.weights.len(), "more phases than weights");
+
+        let start = self.phase_start(self.next_phase);
+        let end = self.phase_start(self.next_phase + 1);
+        self.next_phase += 1;
+
+        PhaseProgress {
+            handler: self,
+            start,
+            end,
+            steps: 0,
+            step: 0,
+        }
+    }
+
+    /// The number of steps that were reported to the wrapped handler so far.
+    pub fn reported_steps(&self) -> i64 {
+        self.reported_steps
+    }
+
+    /// Completes the progress, also if not all phases were run.
+    pub fn finish(mut self) {
+        self.start();
+        self.advance_to(PHASED_PROGRESS_STEPS);
+        self.inner.finish();
+    }
+
+    /// The overall step at which `phase` starts.
+    fn phase_start(&self, phase: usize) -> i64 {
+        let total_weight: i64 = self.weights.iter().map(|&weight| i64::from(weight)).sum();
+        if total_weight == 0 {
+            return 0;
+        }
+
+        let weight_before: i64 = self.weights[..phase].iter().map(|&weight| i64::from(weight)).sum();
+        weight_before * PHASED_PROGRESS_STEPS / total_weight
+    }
+
+    fn start(&mut self) {
+        if !self.started {
+            self.inner.init(PHASED_PROGRESS_STEPS);
+            self.started = true;
+        }
+    }
+
+    fn advance_to(&mut self, step: i64) {
+        while self.reported_steps < step {
+            self.inner.inc();
+            self.reported_steps += 1;
+        }
+    }
+}
+
+/// The progress handler of a single phase of a `PhasedProgressHandler`.
+pub struct PhaseProgress<'a, P: ProgressHandler> {
+    handler: &'a mut PhasedProgressHandler<P>,
+    start: i64,
+    end: i64,
+    steps: i64,
+    step: i64,
+}
+
+impl<P: ProgressHandler> ProgressHandler for PhaseProgress<'_, P> {
+    fn init(&mut self, steps: i64) {
+        self.handler.start();
+        self.steps = steps;
+        self.step = 0;
+    }
+
+    fn inc(&mut self) {
+        // the number of steps might be an estimate that is too low
+        if self.step < self.steps {
+            self.step += 1;
+            self.handler
+                .advance_to(self.start + (self.end - self.start) * self.step / self.steps);
+        }
+    }
+
+    fn finish(&mut self) {
+        self.handler.start();
+        self.handler.advance_to(self.end);
+    }
+}
+
 /*struct NoProgressHandler {}
 impl ProgressHandler for NoProgressHandler {}*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limiter_sleeps_until_the_samples_are_allowed() {
+        let start = Instant::now();
+        let mut rate_limiter = RateLimiter::new(100);
+
+        // 10 samples at 100 samples per second take 100ms each
+        rate_limiter.processed(10);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        rate_limiter.processed(10);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        // without sleeping much longer than needed
+        assert!(start.elapsed() < Duration::from_millis(2000));
+
+        // processing no samples does not wait
+        let before = Instant::now();
+        rate_limiter.processed(0);
+        assert!(before.elapsed() < Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,117 @@
+#![deny(
+    missing_debug_implementations,
+    trivial_casts,
+    unstable_features,
+    unused_import_braces
+)]
+
+//! WebAssembly bindings for the `ilass` aligner, so browser-based subtitle tools can align
+//! subtitles client-side (build with `wasm-pack build ilass-wasm`).
+//!
+//! Only the time spans are aligned here: extracting voice segments from a video is left to the
+//! JavaScript side (e.g. with the Web Audio API). The spans are passed as flat arrays of
+//! `[start0, end0, start1, end1, ...]` in milliseconds (any other resolution works as long as
+//! both arrays use the same one).
+
+use ilass::{NoProgressHandler, TimeDelta, TimePoint, TimeSpan};
+use wasm_bindgen::prelude::*;
+
+/// Converts a flat `[start0, end0, start1, end1, ...]` array into time spans.
+fn spans_from_flat(name: &str, flat: &[f64]) -> Result<Vec<TimeSpan>, String> {
+    if !flat.len().is_multiple_of(2) {
+        return Err(format!("`{}` has an odd number of values ({})", name, flat.len()));
+    }
+
+    flat.chunks(2)
+        .enumerate()
+        .map(|(i, pair)| {
+            let (start, end) = (pair[0], pair[1]);
+            if !start.is_finite() || !end.is_finite() || start > end {
+                return Err(format!("span {} of `{}` is invalid ({} to {})", i, name, start, end));
+            }
+            Ok(TimeSpan::new(
+                TimePoint::from(start.round() as i64),
+                TimePoint::from(end.round() as i64),
+            ))
+        })
+        .collect()
+}
+
+/// Returns `[index0, delta0, index1, delta1, ...]` for the first span of every run of spans with
+/// the same delta.
+fn breakpoints(deltas: &[TimeDelta]) -> Vec<f64> {
+    let mut result = Vec::new();
+    for (i, &delta) in deltas.iter().enumerate() {
+        if i == 0 || deltas[i - 1] != delta {
+            result.push(i as f64);
+            result.push(delta.as_i64() as f64);
+        }
+    }
+    result
+}
+
+fn align_flat(
+    reference: &[f64],
+    incorrect: &[f64],
+    split_penalty: f64,
+    speed_optimization: Option<f64>,
+) -> Result<Vec<f64>, String> {
+    let reference = spans_from_flat("reference", reference)?;
+    let incorrect = spans_from_flat("incorrect", incorrect)?;
+
+    let (deltas, _) = ilass::align(
+        &reference,
+        &incorrect,
+        split_penalty,
+        speed_optimization,
+        ilass::standard_scoring,
+        NoProgressHandler,
+    );
+
+    Ok(breakpoints(&deltas))
+}
+
+/// Aligns the `incorrect` spans to the `reference` spans (see `ilass::align` for the meaning of
+/// `split_penalty` and `speed_optimization`).
+///
+/// Returns the offset breakpoints as a flat `[index0, delta0, index1, delta1, ...]` array: the
+/// incorrect spans from `index0` up to (excluding) `index1` have to be shifted by `delta0`, and so
+/// on until the last span. Throws if a span array has an odd length or contains a span that ends
+/// before it starts.
+#[wasm_bindgen]
+pub fn align(
+    reference: &[f64],
+    incorrect: &[f64],
+    split_penalty: f64,
+    speed_optimization: Option<f64>,
+) -> Result<Vec<f64>, JsError> {
+    align_flat(reference, incorrect, split_penalty, speed_optimization).map_err(|message| JsError::new(&message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakpoints_of_two_runs() {
+        let deltas: Vec<TimeDelta> = [5, 5, -3, -3, -3].iter().map(|&d| TimeDelta::from_i64(d)).collect();
+        assert_eq!(breakpoints(&deltas), vec![0., 5., 2., -3.]);
+        assert_eq!(breakpoints(&[]), Vec::<f64>::new());
+    }
+
+    #[test]
+    fn invalid_spans() {
+        assert!(spans_from_flat("reference", &[0., 1000., 2000.]).is_err());
+        assert!(spans_from_flat("reference", &[1000., 0.]).is_err());
+        assert!(spans_from_flat("reference", &[0., f64::NAN]).is_err());
+        assert_eq!(spans_from_flat("reference", &[]), Ok(vec![]));
+    }
+
+    #[test]
+    fn align_constant_shift() {
+        // the incorrect spans are 1000 too late
+        let reference = [0., 1000., 3000., 3500., 4000., 6000.];
+        let incorrect: Vec<f64> = reference.iter().map(|t| t + 1000.).collect();
+        assert_eq!(align_flat(&reference, &incorrect, 7., None), Ok(vec![0., -1000.]));
+    }
+}
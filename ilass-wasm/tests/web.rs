@@ -0,0 +1,26 @@
+//! Run with `wasm-pack test --node ilass-wasm`.
+
+#![cfg(target_arch = "wasm32")]
+
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test]
+fn align_two_span_arrays() {
+    // the incorrect spans are 1000 too late
+    let mut reference = Vec::new();
+    let mut incorrect = Vec::new();
+    for span in ilass::test_util::test_spans(20) {
+        let (start, end) = (span.start().as_i64() as f64, span.end().as_i64() as f64);
+        reference.extend_from_slice(&[start, end]);
+        incorrect.extend_from_slice(&[start + 1000., end + 1000.]);
+    }
+
+    let breakpoints =
+        ilass_wasm::align(&reference, &incorrect, 7., None).unwrap_or_else(|_| panic!("alignment failed"));
+    assert_eq!(breakpoints, vec![0., -1000.]);
+}
+
+#[wasm_bindgen_test]
+fn align_rejects_odd_span_array() {
+    assert!(ilass_wasm::align(&[0., 1000.], &[0.], 7., None).is_err());
+}
@@ -0,0 +1,33 @@
+//! Aligns two lists of time spans with the library built without the standard library:
+//!
+//! ```text
+//! cargo run -p ilass --example no_std --no-default-features
+//! ```
+//!
+//! Only `ilass` is `no_std` here (its buffers need `alloc`); the example itself prints the result
+//! with `std`.
+
+use ilass::{NoProgressHandler, TimeDelta, TimePoint, TimeSpan};
+
+fn main() {
+    let span = |start: i64, end: i64| TimeSpan::new(TimePoint::from(start), TimePoint::from(end));
+
+    // the incorrect spans are 1000 too late
+    let reference = [span(0, 1000), span(3000, 3500), span(4000, 6000), span(8000, 9200)];
+    let incorrect: Vec<TimeSpan> = reference
+        .iter()
+        .map(|s| span(s.start().as_i64() + 1000, s.end().as_i64() + 1000))
+        .collect();
+
+    let (deltas, score) = ilass::align(
+        &reference,
+        &incorrect,
+        7.,
+        None,
+        ilass::standard_scoring,
+        NoProgressHandler,
+    );
+
+    assert!(deltas.iter().all(|&delta| delta == TimeDelta::from_i64(-1000)));
+    println!("shifted all {} spans by {} (score {})", deltas.len(), deltas[0], score);
+}
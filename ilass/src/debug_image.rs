@@ -0,0 +1,316 @@
+use crate::rating_type::{Rating, RatingExt};
+use crate::segments::RatingBuffer;
+use crate::time_types::TimeDelta;
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+/// Maximum payload of a single uncompressed ("stored") deflate block.
+const MAX_STORED_BLOCK_LEN: usize = 0xffff;
+
+impl RatingBuffer {
+    /// Renders the rating curve into a grayscale PNG image for visual debugging.
+    ///
+    /// The x axis is the time from `start()` to `end()`, the y axis is the rating (scaled
+    /// so that the smallest rating touches the bottom and the largest rating touches the
+    /// top of the image). Everything below the curve is white, everything above is black.
+    pub fn render_png(&self, path: impl AsRef<Path>, width: u32, height: u32) -> std::io::Result<()> {
+        let pixels = self.rasterize(width, height);
+        let mut file = File::create(path)?;
+        file.write_all(&encode_grayscale_png(width, height, &pixels))
+    }
+
+    /// Returns `width * height` grayscale pixels in row-major order (top row first).
+    fn rasterize(&self, width: u32, height: u32) -> Vec<u8> {
+        assert!(width > 0 && height > 0);
+
+        let (width, height) = (width as usize, height as usize);
+        let mut pixels = vec![0u8; width * height];
+
+        let end = match self.end() {
+            Some(end) => end,
+            None => return pixels,
+        };
+        let total_len = (end - self.start).as_i64();
+
+        // sample the rating at the start of every column
+        let column_ratings: Vec<Rating> = self
+            .sample_ratings((0..width).map(|x| self.start + TimeDelta::from_i64(x as i64 * total_len / width as i64)));
+
+        let min_rating = column_ratings.iter().cloned().min().unwrap_or_else(Rating::zero);
+        let max_rating = column_ratings.iter().cloned().max().unwrap_or_else(Rating::zero);
+        let rating_range = (max_rating - min_rating) as f64;
+
+        for (x, rating) in column_ratings.into_iter().enumerate() {
+            // number of pixels (counted from the bottom) that are below the curve
+            let filled = if rating_range > 0. {
+                1 + ((rating - min_rating) as f64 / rating_range * (height - 1) as f64).round() as usize
+            } else {
+                1
+            };
+
+            for y in height - filled..height {
+                pixels[y * width + x] = 255;
+            }
+        }
+
+        pixels
+    }
+
+    /// Rasterizes the rating curve into a mono PCM envelope with `sample_rate` samples per
+    /// second, where one tick of the buffer's time points is `1 / ticks_per_second` seconds.
+    ///
+    /// Sample `n` is the rating at `start() + n / sample_rate` seconds. The ratings are scaled
+    /// so that the rating with the largest magnitude becomes `i16::MAX` (or `-i16::MAX` if it is
+    /// negative) and a rating of zero stays zero, so the loudest part of the envelope is where
+    /// the alignment is most confident.
+    pub fn to_envelope(&self, sample_rate: u32, ticks_per_second: f64) -> Vec<i16> {
+        assert!(sample_rate > 0);
+
+        let end = match self.end() {
+            Some(end) => end,
+            None => return Vec::new(),
+        };
+        let duration = (end - self.start).to_seconds(ticks_per_second);
+        let sample_count = (duration * f64::from(sample_rate)).ceil() as usize;
+
+        let ratings: Vec<Rating> = self.sample_ratings(
+            (0..sample_count)
+                .map(|n| self.start + TimeDelta::from_seconds(n as f64 / f64::from(sample_rate), ticks_per_second))
+                .filter(|&t| t < end),
+        );
+
+        let max_magnitude = ratings
+            .iter()
+            .map(|rating| rating.abs())
+            .max()
+            .unwrap_or_else(Rating::zero);
+        if max_magnitude == Rating::zero() {
+            return vec![0; ratings.len()];
+        }
+        ratings
+            .into_iter()
+            .map(|rating| (rating as f64 / max_magnitude as f64 * f64::from(i16::MAX)).round() as i16)
+            .collect()
+    }
+
+    /// Writes the envelope of `to_envelope` as a mono 16-bit PCM WAV file, so the rating curve
+    /// can be overlaid on the media in an audio editor.
+    pub fn write_envelope_wav(
+        &self,
+        path: impl AsRef<Path>,
+        sample_rate: u32,
+        ticks_per_second: f64,
+    ) -> std::io::Result<()> {
+        let samples = self.to_envelope(sample_rate, ticks_per_second);
+        let mut file = File::create(path)?;
+        file.write_all(&encode_mono_wav(sample_rate, &samples))
+    }
+
+    /// Returns the rating at each of the `times`, which have to be ascending and inside the
+    /// buffer (so the segments can be walked with a cursor).
+    fn sample_ratings(&self, times: impl Iterator<Item = TimeDelta>) -> Vec<Rating> {
+        let mut segments = self.buffer.iter();
+        let mut segment = match segments.next() {
+            Some(segment) => segment,
+            None => return Vec::new(),
+        };
+        let mut segment_start = self.start;
+
+        times
+            .map(|t| {
+                while t >= segment.end_point {
+                    segment_start = segment.end_point;
+                    segment = segments
+                        .next()
+                        .expect("sampled time point should lie inside the buffer");
+                }
+                Rating::add_mul(segment.data.rating, segment.data.delta, t - segment_start)
+            })
+            .collect()
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn write_png_chunk(out: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let crc_start = out.len();
+    out.extend_from_slice(chunk_type);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[crc_start..]);
+
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Encodes 16-bit mono samples as a PCM WAV file.
+fn encode_mono_wav(sample_rate: u32, samples: &[i16]) -> Vec<u8> {
+    let data_len = (samples.len() * 2) as u32;
+
+    let mut wav: Vec<u8> = Vec::with_capacity(44 + samples.len() * 2);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    // format 1 (PCM), 1 channel, 2 bytes per frame, 16 bits per sample
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+/// Encodes 8-bit grayscale pixels as PNG. The image data is not compressed (it is stored
+/// in uncompressed deflate blocks), which is good enough for debugging output.
+fn encode_grayscale_png(width: u32, height: u32, pixels: &[u8]) -> Vec<u8> {
+    assert_eq!(pixels.len(), width as usize * height as usize);
+
+    // every scanline starts with its filter type (0 = no filter)
+    let mut scanlines: Vec<u8> = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks(width as usize) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    // zlib header (deflate, 32K window, no compression)
+    let mut zlib: Vec<u8> = vec![0x78, 0x01];
+    let mut blocks = scanlines.chunks(MAX_STORED_BLOCK_LEN).peekable();
+    while let Some(block) = blocks.next() {
+        let is_final = blocks.peek().is_none();
+        let len = block.len() as u16;
+        zlib.push(is_final as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&scanlines).to_be_bytes());
+
+    let mut header: Vec<u8> = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // bit depth 8, color type 0 (grayscale), default compression/filter, no interlace
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+    let mut png: Vec<u8> = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+    write_png_chunk(&mut png, b"IHDR", &header);
+    write_png_chunk(&mut png, b"IDAT", &zlib);
+    write_png_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::segments::{RatingInfo, RatingSegment};
+
+    /// Rises from zero to a peak at t=49 and then falls back to zero.
+    fn peaked_rating_buffer() -> RatingBuffer {
+        let slope = Rating::convert_from_f64(0.01);
+        RatingBuffer {
+            start: TimeDelta::zero(),
+            buffer: vec![
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(50),
+                    data: RatingInfo {
+                        rating: Rating::zero(),
+                        delta: slope,
+                    },
+                },
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(100),
+                    data: RatingInfo {
+                        rating: Rating::add_mul(Rating::zero(), slope, TimeDelta::from_i64(48)),
+                        delta: -slope,
+                    },
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn render_png_dimensions() {
+        let path = std::env::temp_dir().join(format!("ilass-render-png-{}.png", std::process::id()));
+        peaked_rating_buffer().render_png(&path, 100, 40).unwrap();
+        let png = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&png[0..8], &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(u32::from_be_bytes([png[16], png[17], png[18], png[19]]), 100);
+        assert_eq!(u32::from_be_bytes([png[20], png[21], png[22], png[23]]), 40);
+    }
+
+    #[test]
+    fn rasterize_peak() {
+        let (width, height) = (100, 40);
+        let pixels = peaked_rating_buffer().rasterize(width, height);
+
+        // only the column of the peak reaches the top row
+        let top_row = &pixels[0..width as usize];
+        assert_ne!(top_row[49], 0);
+        assert_eq!(top_row.iter().filter(|&&p| p != 0).count(), 1);
+
+        // the bottom row is completely below the curve
+        let bottom_row = &pixels[((height - 1) * width) as usize..];
+        assert!(bottom_row.iter().all(|&p| p != 0));
+    }
+
+    #[test]
+    fn envelope_peaks_at_rating_maximum() {
+        // one tick is a millisecond, so 100 samples per second is one sample every 10 ticks
+        let envelope = peaked_rating_buffer().to_envelope(100, 1000.);
+        assert_eq!(envelope.len(), 10);
+        assert_eq!(envelope[0], 0);
+
+        // the sample at t=50 is the closest one to the peak at t=49
+        let peak = (0..envelope.len()).max_by_key(|&n| envelope[n]).unwrap();
+        assert_eq!(peak, 5);
+        assert_eq!(envelope[peak], i16::MAX);
+        assert!(envelope[..peak].windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(envelope[peak..].windows(2).all(|pair| pair[0] > pair[1]));
+    }
+
+    #[test]
+    fn envelope_wav_header() {
+        let path = std::env::temp_dir().join(format!("ilass-envelope-{}.wav", std::process::id()));
+        peaked_rating_buffer().write_envelope_wav(&path, 100, 1000.).unwrap();
+        let wav = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[8..12], b"WAVE");
+        assert_eq!(u32::from_le_bytes([wav[24], wav[25], wav[26], wav[27]]), 100);
+        assert_eq!(wav.len(), 44 + 10 * 2);
+    }
+}
@@ -1,11 +1,22 @@
 use crate::rating_type::{Rating, RatingDelta, RatingDeltaDelta, RatingDeltaExt, RatingExt};
 use crate::segments::{
-    DifferentialRatingBufferBuilder, OffsetBuffer, RatingBuffer, RatingIterator, RatingSegment, SeparateDualBuffer,
-    combined_maximum_of_dual_iterators,
+    DifferentialRatingBufferBuilder, OffsetBuffer, PointSpan, RatingBuffer, SegmentError, SeparateDualBuffer,
+    SimplifyTolerance, combined_maximum_of_dual_iterators,
 };
 use crate::time_types::{TimeDelta, TimePoint, TimeSpan};
 
-use std::convert::TryInto;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[cfg(feature = "rayon")]
+use crate::segments::par_build_rating_buffer;
+
+/// The number of rating changepoints that are built in one chunk with the `rayon` feature (fewer
+/// changepoints are built sequentially).
+#[cfg(feature = "rayon")]
+const PARALLEL_RATING_CHUNK_LEN: usize = 1 << 12;
 
 /// Use this trait if you want more detailed information about the progress of the align operation
 /// (which might take some seconds).
@@ -30,6 +41,65 @@ pub trait ProgressHandler {
 pub struct NoProgressHandler;
 impl ProgressHandler for NoProgressHandler {}
 
+/// Errors of the align operation.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum AlignError {
+    /// The cancel flag was set while aligning.
+    Cancelled,
+
+    /// The rating of a line does not fit into a `Rating` at `offset`, e.g. because the score
+    /// function returns huge values (this can not happen with the `saturating-rating` feature).
+    RatingOverflow { offset: TimeDelta },
+}
+
+impl AlignError {
+    /// Converts the error of building a rating buffer, where everything except an overflow is a bug
+    /// of the aligner.
+    fn from_rating_build_error(error: SegmentError) -> AlignError {
+        match error {
+            SegmentError::RatingOverflow { end_point } => AlignError::RatingOverflow { offset: end_point },
+            error => panic!(
+                "rating changepoints should be sorted and lie within the offset range: {}",
+                error
+            ),
+        }
+    }
+}
+
+impl core::fmt::Display for AlignError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            AlignError::Cancelled => write!(f, "alignment was cancelled"),
+            AlignError::RatingOverflow { offset } => write!(f, "rating overflows at the offset {}", offset),
+        }
+    }
+}
+
+impl core::error::Error for AlignError {}
+
+/// Returns the first index of every plateau (a run of equal ratings) that is higher than the
+/// plateaus on both sides of it. A plateau between a lower and a higher one is not a peak, even
+/// though its first rating is not lower than the next one.
+fn plateau_peaks(ratings: &[Rating]) -> Vec<usize> {
+    let mut plateaus: Vec<(usize, Rating)> = Vec::new();
+    for (i, &rating) in ratings.iter().enumerate() {
+        if plateaus
+            .last()
+            .is_none_or(|&(_, plateau_rating)| plateau_rating != rating)
+        {
+            plateaus.push((i, rating));
+        }
+    }
+
+    (0..plateaus.len())
+        .filter(|&k| {
+            let rating = plateaus[k].1;
+            (k == 0 || rating > plateaus[k - 1].1) && (k + 1 == plateaus.len() || rating > plateaus[k + 1].1)
+        })
+        .map(|k| plateaus[k].0)
+        .collect()
+}
+
 /// The "main" structure which holds the infomation needed to align the subtitles to each other.
 pub struct Aligner;
 
@@ -57,9 +127,55 @@ impl Aligner {
     ) -> (TimeDelta, Rating) {
         let (min_offset, max_offset) = Self::get_offsets_bounds(ref_spans, in_spans);
 
+        //let ta = std::time::Instant::now();
+        let deltas = Self::constant_delta_jumps(ref_spans, in_spans, min_offset, max_offset, score_fn);
+
+        //let tc = std::time::Instant::now();
+
+        // compute maximum rating
+        let mut delta: RatingDelta = RatingDelta::zero();
+        let mut rating: Rating = Rating::zero();
+        let mut maximum: (Rating, TimeDelta) = (Rating::zero(), min_offset);
+        //let mut nonzero: i64 = 0;
+        for (sigma, jump_value) in deltas.into_iter().enumerate() {
+            /*if !RatingDeltaDelta::is_zero(jump_value) {
+                nonzero = nonzero + 1;
+            }*/
+            rating += delta;
+            delta += jump_value;
+            if rating > maximum.0 {
+                maximum = (rating, sigma as i64 * TimeDelta::one() + min_offset);
+            }
+        }
+
+        /*let td = std::time::Instant::now();
+        println!("init {}ms", (tb - ta).as_millis());
+        println!("insert {}ms", (tc - tb).as_millis());
+        println!("calc {}ms", (td - tc).as_millis());
+
+        println!(
+            "{}MB {}% nonzero {}% max",
+            len * core::mem::size_of::<RatingDeltaDelta>() / (1024 * 1024),
+            nonzero as f64 / len as f64 * 100.0,
+            (self.list.len() * self.reference.len() * 4) as f64 / len as f64 * 100.0
+        );*/
+
+        assert_eq!(rating, 0);
+
+        (maximum.1, maximum.0)
+    }
+
+    /// Returns the change of the rating slope at every offset from `min_offset` to `max_offset`
+    /// (inclusive) when all `in_spans` are shifted by the same offset.
+    fn constant_delta_jumps(
+        ref_spans: &[TimeSpan],
+        in_spans: &[TimeSpan],
+        min_offset: TimeDelta,
+        max_offset: TimeDelta,
+        score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+    ) -> Vec<RatingDeltaDelta> {
         let len: usize = (max_offset - min_offset).as_i64().try_into().unwrap();
 
-        //let ta = std::time::Instant::now();
         let mut deltas: Vec<RatingDeltaDelta> = vec![RatingDeltaDelta::zero(); len + 1];
 
         //let tb = std::time::Instant::now();
@@ -100,39 +216,162 @@ impl Aligner {
                 );
             }
         }
-        //let tc = std::time::Instant::now();
 
-        // compute maximum rating
+        deltas
+    }
+
+    /// Returns up to `count` offsets that are local maxima of the constant-delta rating, best first.
+    /// Every returned offset is at least `min_distance` away from all better ones, so neighbouring
+    /// offsets of the same peak are not reported twice. Offsets without a positive rating are never
+    /// returned.
+    pub fn constant_delta_candidates(
+        ref_spans: &[TimeSpan],
+        in_spans: &[TimeSpan],
+        count: usize,
+        min_distance: TimeDelta,
+        score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+    ) -> Vec<(TimeDelta, Rating)> {
+        let (min_offset, max_offset) = Self::get_offsets_bounds(ref_spans, in_spans);
+
         let mut delta: RatingDelta = RatingDelta::zero();
         let mut rating: Rating = Rating::zero();
-        let mut maximum: (Rating, TimeDelta) = (Rating::zero(), min_offset);
-        //let mut nonzero: i64 = 0;
-        for (sigma, jump_value) in deltas.into_iter().enumerate() {
-            /*if !RatingDeltaDelta::is_zero(jump_value) {
-                nonzero = nonzero + 1;
-            }*/
+        let mut ratings: Vec<Rating> = Vec::new();
+        for jump_value in Self::constant_delta_jumps(ref_spans, in_spans, min_offset, max_offset, score_fn) {
             rating += delta;
             delta += jump_value;
-            if rating > maximum.0 {
-                maximum = (rating, sigma as i64 * TimeDelta::one() + min_offset);
+            ratings.push(rating);
+        }
+
+        let mut peaks: Vec<(TimeDelta, Rating)> = plateau_peaks(&ratings)
+            .into_iter()
+            .filter(|&i| ratings[i] > Rating::zero())
+            .map(|i| (min_offset + TimeDelta::from_i64(i as i64), ratings[i]))
+            .collect();
+        peaks.sort_by(|(offset_a, rating_a), (offset_b, rating_b)| rating_b.cmp(rating_a).then(offset_a.cmp(offset_b)));
+
+        let mut candidates: Vec<(TimeDelta, Rating)> = Vec::new();
+        for (offset, rating) in peaks {
+            if candidates.len() == count {
+                break;
+            }
+            let distinct = candidates
+                .iter()
+                .all(|&(candidate_offset, _)| (offset - candidate_offset).as_i64().abs() >= min_distance.as_i64());
+            if distinct {
+                candidates.push((offset, rating));
             }
         }
 
-        /*let td = std::time::Instant::now();
-        println!("init {}ms", (tb - ta).as_millis());
-        println!("insert {}ms", (tc - tb).as_millis());
-        println!("calc {}ms", (td - tc).as_millis());
+        candidates
+    }
 
-        println!(
-            "{}MB {}% nonzero {}% max",
-            len * std::mem::size_of::<RatingDeltaDelta>() / (1024 * 1024),
-            nonzero as f64 / len as f64 * 100.0,
-            (self.list.len() * self.reference.len() * 4) as f64 / len as f64 * 100.0
-        );*/
+    /// Like `align_constant_delta_bucket_sort`, but only offsets from `-max_shift` to `max_shift`
+    /// (inclusive) are considered. Returns `None` if none of them has a positive rating.
+    pub fn align_constant_delta_bounded(
+        ref_spans: &[TimeSpan],
+        in_spans: &[TimeSpan],
+        max_shift: TimeDelta,
+        score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+    ) -> Option<(TimeDelta, Rating)> {
+        let (min_offset, max_offset) = Self::get_offsets_bounds(ref_spans, in_spans);
 
-        assert_eq!(rating, 0);
+        // the jumps have to be summed up from `min_offset` even if it is out of the bounds
+        let mut delta: RatingDelta = RatingDelta::zero();
+        let mut rating: Rating = Rating::zero();
+        let mut maximum: Option<(TimeDelta, Rating)> = None;
+        let jumps = Self::constant_delta_jumps(ref_spans, in_spans, min_offset, max_offset, score_fn);
+        for (sigma, jump_value) in jumps.into_iter().enumerate() {
+            rating += delta;
+            delta += jump_value;
 
-        (maximum.1, maximum.0)
+            let offset = sigma as i64 * TimeDelta::one() + min_offset;
+            if offset > max_shift {
+                break;
+            }
+            if offset >= -max_shift && rating > maximum.map_or(Rating::zero(), |(_, max_rating)| max_rating) {
+                maximum = Some((offset, rating));
+            }
+        }
+
+        maximum
+    }
+
+    /// Like `align_constant_delta`, but the lines are rated against the rating of every point of the
+    /// reference instead of reference spans: a line at an offset gets the sum of the `reference`
+    /// ratings at all of its points. Returns `None` if no offset has a positive rating.
+    pub fn align_constant_delta_to_rating(
+        reference: &RatingBuffer,
+        in_spans: &[TimeSpan],
+    ) -> Option<(TimeDelta, Rating)> {
+        /// From `offset` on, the rating change to the next offset changes by `value` while its
+        /// slope changes by `slope`.
+        struct Event {
+            offset: TimeDelta,
+            value: i128,
+            slope: i128,
+        }
+
+        // moving all lines to the next offset adds the reference ratings behind their end points and
+        // removes the ones at their start points, so every reference segment contributes a linear
+        // piece to the rating change for every end point of a line
+        let zero = TimePoint::from(0);
+        let mut events: Vec<Event> = Vec::with_capacity(reference.buffer.len() * in_spans.len() * 4);
+        for segment in reference.iter().annotate_with_segment_start_points().into_iter() {
+            let (rating, delta) = (segment.data.rating as i128, segment.data.delta as i128);
+            let end_rating = rating + delta * (segment.span.len().as_i64() - 1) as i128;
+            for span in in_spans {
+                for (point, sign) in [(span.end() - zero, 1), (span.start() - zero, -1)] {
+                    events.push(Event {
+                        offset: segment.span.start - point,
+                        value: sign * rating,
+                        slope: sign * delta,
+                    });
+                    // the piece ends behind the last point of the segment
+                    events.push(Event {
+                        offset: segment.span.end - point,
+                        value: -sign * (end_rating + delta),
+                        slope: -sign * delta,
+                    });
+                }
+            }
+        }
+        events.sort_unstable_by_key(|event| event.offset);
+
+        // the lines don't overlap the reference before the first event, so the rating is zero there
+        let mut maximum: Option<(TimeDelta, i128)> = None;
+        let mut consider = |offset: TimeDelta, rating: i128| {
+            if rating > maximum.map_or(0, |(_, max_rating)| max_rating) {
+                maximum = Some((offset, rating));
+            }
+        };
+
+        let (mut rating, mut value, mut slope) = (0i128, 0i128, 0i128);
+        let mut events = events.into_iter().peekable();
+        while let Some(event) = events.next() {
+            value += event.value;
+            slope += event.slope;
+            let Some(next) = events.peek() else {
+                break;
+            };
+
+            // from `event.offset` on, the `k`-th rating change is `value + slope * k`, so the rating
+            // increases up to the last `k` with a positive change
+            let len = (next.offset - event.offset).as_i64() as i128;
+            let rating_after = |k: i128| rating + k * value + slope * k * (k - 1) / 2;
+            consider(event.offset, rating);
+            if slope < 0 && value > 0 {
+                let k = (value - 1) / -slope + 1;
+                if k < len {
+                    consider(event.offset + TimeDelta::from_i64(k as i64), rating_after(k));
+                }
+            }
+
+            rating = rating_after(len);
+            value += slope * len;
+        }
+
+        // a rating that doesn't fit is as good as the best representable one
+        maximum.map(|(offset, rating)| (offset, Rating::try_from(rating).unwrap_or(Rating::MAX)))
     }
 
     pub fn align_constant_delta(
@@ -246,8 +485,8 @@ impl Aligner {
 
         #[cfg(feature = "nosplit-heap-sort")]
         {
-            use std::cmp::Ordering;
-            use std::collections::BinaryHeap;
+            use alloc::collections::BinaryHeap;
+            use core::cmp::Ordering;
 
             #[derive(PartialEq, Eq)]
             struct MaxHeapInfo {
@@ -330,14 +569,45 @@ impl Aligner {
         (maximum.1, maximum.0)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn align_with_splits(
         ref_spans: &[TimeSpan],
         in_spans: &[TimeSpan],
         split_penalty: RatingDelta,
         speed_optimization_opt: Option<f64>,
+        identity_bias: RatingDelta,
+        score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+        progress_handler: impl ProgressHandler,
+        cancel_flag: Option<&AtomicBool>,
+    ) -> Result<(Vec<TimeDelta>, Rating), AlignError> {
+        Self::align_with_splits_weighted(
+            &[WeightedReference {
+                spans: ref_spans,
+                weight: 1.,
+            }],
+            in_spans,
+            split_penalty,
+            speed_optimization_opt,
+            identity_bias,
+            score_fn,
+            progress_handler,
+            cancel_flag,
+        )
+    }
+
+    /// Like `align_with_splits`, but the rating of every line is the weighted sum of its ratings
+    /// against all `references`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn align_with_splits_weighted(
+        references: &[WeightedReference],
+        in_spans: &[TimeSpan],
+        split_penalty: RatingDelta,
+        speed_optimization_opt: Option<f64>,
+        identity_bias: RatingDelta,
         score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
         mut progress_handler: impl ProgressHandler,
-    ) -> (Vec<TimeDelta>, Rating) {
+        cancel_flag: Option<&AtomicBool>,
+    ) -> Result<(Vec<TimeDelta>, Rating), AlignError> {
         // For each segment the full rating can only be 1. So the maximum rating
         // without the split penalty is `min(list.len(), reference.len())`. So to get
         // from the normalized rating `[0, 1]` to an unnormalized rating (where only
@@ -345,27 +615,61 @@ impl Aligner {
         // `min(list.len(), reference.len())`.
 
         assert!(!in_spans.is_empty());
-        assert!(!ref_spans.is_empty());
+        assert!(references.iter().all(|reference| !reference.spans.is_empty()));
 
         progress_handler.init(in_spans.len() as i64);
 
         let speed_optimization = speed_optimization_opt.unwrap_or(0.0);
 
-        let (min_offset, max_offset) = Self::get_offsets_bounds(ref_spans, in_spans);
+        let (min_offset, max_offset) = references
+            .iter()
+            .map(|reference| Self::get_offsets_bounds(reference.spans, in_spans))
+            .reduce(|(min_a, max_a), (min_b, max_b)| (min_a.min(min_b), max_a.max(max_b)))
+            .expect("at least one reference is required");
         let (min_offset, max_offset) = (min_offset - TimeDelta::one(), max_offset + TimeDelta::one());
 
+        // a spike of height `identity_bias` at the zero offset, which rewards every line that keeps its original timing
+        let identity_bias_timepoints: Vec<(TimeDelta, RatingDeltaDelta)> =
+            if identity_bias > RatingDelta::zero() && min_offset < -TimeDelta::one() && TimeDelta::one() < max_offset {
+                vec![
+                    (-TimeDelta::one(), identity_bias),
+                    (TimeDelta::zero(), -(identity_bias + identity_bias)),
+                    (TimeDelta::one(), identity_bias),
+                ]
+            } else {
+                Vec::new()
+            };
+
+        // the spike is narrower than anything the speed optimization could keep, so its points
+        // are never simplified
+        let identity_bias_span: Option<PointSpan> = if identity_bias_timepoints.is_empty() {
+            None
+        } else {
+            Some(PointSpan::new(-TimeDelta::one(), TimeDelta::from_i64(2)))
+        };
+
         // these buffers save the offsets of a subtitle line dependent on the offset of the next line,
         //  -> this allows to compute the final corrected line offsets
         let mut offset_buffers: Vec<OffsetBuffer> = Vec::new();
 
-        let mut culmulative_rating_buffer: RatingBuffer =
-            Self::single_span_ratings(ref_spans, in_spans[0], score_fn, min_offset, max_offset).save();
+        let mut culmulative_rating_buffer: RatingBuffer = Self::single_span_ratings(
+            references,
+            in_spans[0],
+            score_fn,
+            min_offset,
+            max_offset,
+            &identity_bias_timepoints,
+        )?;
 
         progress_handler.inc();
 
         for (line_nr, (&last_incorrect_span, &incorrect_span)) in
             in_spans.iter().zip(in_spans.iter().skip(1)).enumerate()
         {
+            if cancel_flag.is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+                return Err(AlignError::Cancelled);
+            }
+
             assert!(last_incorrect_span.len() > TimeDelta::zero()); // otherwise shift_simple/extend_to creates a zero-length segment
             assert!(incorrect_span.len() > TimeDelta::zero()); // otherwise shift_simple/extend_to creates a zero-length segment
 
@@ -395,18 +699,34 @@ impl Aligner {
             //.simplify()
             //.discard_start_times();
 
-            let single_span_ratings =
-                Self::single_span_ratings(ref_spans, incorrect_span, score_fn, min_offset, max_offset).save();
+            let single_span_ratings = Self::single_span_ratings(
+                references,
+                incorrect_span,
+                score_fn,
+                min_offset,
+                max_offset,
+                &identity_bias_timepoints,
+            )?;
 
             let progress_factor = (line_nr + 1) as f64 / in_spans.len() as f64;
             let epsilon = Rating::convert_from_f64(speed_optimization * 0.05 * (progress_factor * 0.8 + 0.2));
+            let mut tolerance = SimplifyTolerance::from_rating_delta(epsilon);
+            if let Some(span) = identity_bias_span {
+                tolerance = tolerance.exact_within(span);
+            }
 
             let combined_maximum_buffer: SeparateDualBuffer =
                 combined_maximum_of_dual_iterators(nosplit_offsets, best_split_offsets)
                     .discard_start_times()
                     .add_ratings_from(single_span_ratings.iter())
                     .discard_start_times()
-                    .save_separate(epsilon);
+                    .stop_when_set(cancel_flag)
+                    .save_separate(tolerance);
+
+            // the combination stops early if the flag is set in the meantime
+            if cancel_flag.is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+                return Err(AlignError::Cancelled);
+            }
 
             culmulative_rating_buffer = combined_maximum_buffer.rating_buffer;
 
@@ -442,7 +762,7 @@ impl Aligner {
             // out of bounds error - we simply copy the current result delta
             // to all remaining spans.
             /*if span_offset < self.get_min_offset() {
-                span_offset = std::cmp::max(span_offset, self.get_start());
+                span_offset = core::cmp::max(span_offset, self.get_start());
                 let error_delta = span_offset - incorrect_span.start;
                 for _ in 0..self.list.len() - result_deltas.len() {
                     result_deltas.push(error_delta);
@@ -458,22 +778,100 @@ impl Aligner {
 
         progress_handler.finish();
 
-        (result_deltas, total_rating)
+        Ok((result_deltas, total_rating))
     }
 
     /// Requires "start1 <= start2". Returns the compressed rating vector for
     /// the overlapping ratings of a timespan of length
     /// "length" on all start offset from "start1" to "start2".
     ///
-    /// This function has O(n) runtime, where n is the number of spans in the
-    /// reference list.
+    /// The rating is the sum of the ratings against all `references`, each scaled
+    /// by its weight. This function has O(n) runtime, where n is the total number
+    /// of spans in the reference lists.
+    ///
+    /// The sorted changepoints `bias_timepoints` are added on top of the rating
+    /// (this is used for the identity bias).
     fn single_span_ratings(
-        ref_spans: &[TimeSpan],
+        references: &[WeightedReference],
         in_span: TimeSpan,
         score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
         min_offset: TimeDelta,
         max_offset: TimeDelta,
-    ) -> RatingIterator<impl Iterator<Item = RatingSegment>> {
+        bias_timepoints: &[(TimeDelta, RatingDeltaDelta)],
+    ) -> Result<RatingBuffer, AlignError> {
+        let mut timepoints: Vec<(TimeDelta, RatingDeltaDelta)> = Vec::new();
+        for reference in references {
+            let weight = reference.weight;
+            let reference_timepoints =
+                Self::single_span_timepoints(reference.spans, in_span, move |a, b| score_fn(a, b) * weight);
+            timepoints = merge_timepoints(&timepoints, &reference_timepoints);
+        }
+        let timepoints = merge_timepoints(&timepoints, bias_timepoints);
+
+        Self::ratings_from_timepoints(timepoints, min_offset, max_offset)
+    }
+
+    /// Builds the rating from its sorted changepoints, in parallel chunks if there are many of them
+    /// (see `par_build_rating_buffer`).
+    #[cfg(feature = "rayon")]
+    fn ratings_from_timepoints(
+        timepoints: Vec<(TimeDelta, RatingDeltaDelta)>,
+        min_offset: TimeDelta,
+        max_offset: TimeDelta,
+    ) -> Result<RatingBuffer, AlignError> {
+        if timepoints.len() <= PARALLEL_RATING_CHUNK_LEN {
+            return Self::sequential_ratings_from_timepoints(timepoints, min_offset, max_offset);
+        }
+
+        par_build_rating_buffer(min_offset, max_offset, &timepoints, PARALLEL_RATING_CHUNK_LEN)
+            .map_err(AlignError::from_rating_build_error)
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    fn ratings_from_timepoints(
+        timepoints: Vec<(TimeDelta, RatingDeltaDelta)>,
+        min_offset: TimeDelta,
+        max_offset: TimeDelta,
+    ) -> Result<RatingBuffer, AlignError> {
+        Self::sequential_ratings_from_timepoints(timepoints, min_offset, max_offset)
+    }
+
+    /// Builds the rating from its sorted changepoints.
+    fn sequential_ratings_from_timepoints(
+        timepoints: Vec<(TimeDelta, RatingDeltaDelta)>,
+        min_offset: TimeDelta,
+        max_offset: TimeDelta,
+    ) -> Result<RatingBuffer, AlignError> {
+        let mut builder = DifferentialRatingBufferBuilder::new(min_offset, max_offset)
+            .map_err(AlignError::from_rating_build_error)?;
+        for (segment_end, segment_end_delta_delta) in timepoints {
+            builder
+                .add_segment(segment_end, segment_end_delta_delta)
+                .map_err(AlignError::from_rating_build_error)?;
+        }
+
+        // the rating values are continuous, so the first value of a segment is the
+        // last value of the previous segment.
+        // To avoid having each of these segment-break values two times in the buffer,
+        // every segments stops one timepoint
+        // before the real segment end. The real segment end is then the first value of
+        // the next value.
+        //
+        // The last rating has to be 0, so we extend the last segment with the missing
+        // timepoint.
+        builder.extend_to_end().map_err(AlignError::from_rating_build_error)?;
+
+        let buffer = builder.build().map_err(AlignError::from_rating_build_error)?;
+        Ok(buffer.into_rating_buffer())
+    }
+
+    /// Returns the sorted changepoints (and their delta-delta) of the rating of `in_span` against
+    /// the sorted and non-overlapping `ref_spans` over all offsets.
+    fn single_span_timepoints(
+        ref_spans: &[TimeSpan],
+        in_span: TimeSpan,
+        score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+    ) -> Vec<(TimeDelta, RatingDeltaDelta)> {
         // If we fix one timespan and let an other timespan variable, we get such a
         // curve for the rating:
         //
@@ -495,7 +893,6 @@ impl Aligner {
         // previous total delta to the buffer. This way we get the segments with the
         // same delta very efficently in O(n).
 
-        let mut builder = DifferentialRatingBufferBuilder::new(min_offset, max_offset);
         let len = ref_spans.len();
         let mut timepoints: Vec<Option<(TimeDelta, RatingDeltaDelta)>> = vec![None; 4 * len];
         for (i, &ref_span) in ref_spans.iter().enumerate() {
@@ -509,63 +906,54 @@ impl Aligner {
 
         let timepoints: Vec<(TimeDelta, RatingDeltaDelta)> = timepoints.into_iter().map(|x| x.unwrap()).collect();
 
-        // standard merge sort
-        fn merge(
-            a: &[(TimeDelta, RatingDeltaDelta)],
-            b: &[(TimeDelta, RatingDeltaDelta)],
-        ) -> Vec<(TimeDelta, RatingDeltaDelta)> {
-            let mut ai = 0;
-            let mut bi = 0;
-            let mut result = Vec::with_capacity(a.len() + b.len());
-            loop {
-                if ai == a.len() && bi == b.len() {
-                    return result;
-                }
-                if bi == b.len() {
-                    while ai < a.len() {
-                        result.push(a[ai]);
-                        ai += 1;
-                    }
-                    return result;
-                }
-                if ai == a.len() {
-                    while bi < b.len() {
-                        result.push(b[bi]);
-                        bi += 1;
-                    }
-                    return result;
-                }
-                if a[ai].0 <= b[bi].0 {
-                    result.push(a[ai]);
-                    ai += 1;
-                } else {
-                    result.push(b[bi]);
-                    bi += 1;
-                }
-            }
-        }
+        let x = merge_timepoints(&timepoints[0..len], &timepoints[len..len * 2]);
+        let y = merge_timepoints(&timepoints[len * 2..len * 3], &timepoints[len * 3..len * 4]);
 
-        let x = merge(&timepoints[0..len], &timepoints[len..len * 2]);
-        let y = merge(&timepoints[len * 2..len * 3], &timepoints[len * 3..len * 4]);
+        merge_timepoints(&x, &y)
+    }
+}
 
-        let timepoints = merge(&x, &y);
+/// A reference span list (sorted and non-overlapping) whose ratings are scaled by `weight` (see
+/// `Aligner::align_with_splits_weighted`).
+#[derive(Debug, Clone, Copy)]
+pub struct WeightedReference<'a> {
+    pub spans: &'a [TimeSpan],
+    pub weight: f64,
+}
 
-        for (segment_end, segment_end_delta_delta) in timepoints {
-            builder.add_segment(segment_end, segment_end_delta_delta);
+/// Merges two sorted lists of changepoints (standard merge sort).
+fn merge_timepoints(
+    a: &[(TimeDelta, RatingDeltaDelta)],
+    b: &[(TimeDelta, RatingDeltaDelta)],
+) -> Vec<(TimeDelta, RatingDeltaDelta)> {
+    let mut ai = 0;
+    let mut bi = 0;
+    let mut result = Vec::with_capacity(a.len() + b.len());
+    loop {
+        if ai == a.len() && bi == b.len() {
+            return result;
+        }
+        if bi == b.len() {
+            while ai < a.len() {
+                result.push(a[ai]);
+                ai += 1;
+            }
+            return result;
+        }
+        if ai == a.len() {
+            while bi < b.len() {
+                result.push(b[bi]);
+                bi += 1;
+            }
+            return result;
+        }
+        if a[ai].0 <= b[bi].0 {
+            result.push(a[ai]);
+            ai += 1;
+        } else {
+            result.push(b[bi]);
+            bi += 1;
         }
-
-        // the rating values are continuous, so the first value of a segment is the
-        // last value of the previous segment.
-        // To avoid having each of these segment-break values two times in the buffer,
-        // every segments stops one timepoint
-        // before the real segment end. The real segment end is then the first value of
-        // the next value.
-        //
-        // The last rating has to be 0, so we extend the last segment with the missing
-        // timepoint.
-        builder.extend_to_end();
-
-        builder.build().into_rating_iter()
     }
 }
 
@@ -574,8 +962,9 @@ mod tests {
     use super::*;
 
     use crate::rating_type::RatingExt;
-    use crate::segments::RatingFullSegment;
+    use crate::segments::{RatingFullSegment, RatingInfo, RatingSegment};
     use crate::tests::get_random_prepared_test_time_spans;
+    use core::cell::Cell;
 
     fn get_dummy_spans() -> Vec<TimeSpan> {
         loop {
@@ -602,10 +991,178 @@ mod tests {
                 &in_spans,
                 RatingDelta::convert_from_f64(0.001),
                 None,
+                RatingDelta::zero(),
                 crate::standard_scoring,
                 NoProgressHandler,
-            );
+                None,
+            )
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn cancel_alignment() {
+        /// Sets the cancel flag in the `cancel_at`-th call of `inc()`.
+        struct CancelAt<'a> {
+            cancel_at: usize,
+            calls: &'a Cell<usize>,
+            flag: &'a AtomicBool,
+        }
+
+        impl ProgressHandler for CancelAt<'_> {
+            fn inc(&mut self) {
+                self.calls.set(self.calls.get() + 1);
+                if self.calls.get() == self.cancel_at {
+                    self.flag.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+
+        let in_spans: Vec<TimeSpan> = (0..100)
+            .map(|i| TimeSpan::new(TimePoint::from(i * 1000), TimePoint::from(i * 1000 + 500)))
+            .collect();
+
+        let flag = AtomicBool::new(false);
+        let calls = Cell::new(0);
+        let result = Aligner::align_with_splits(
+            &in_spans,
+            &in_spans,
+            RatingDelta::convert_from_f64(0.001),
+            None,
+            RatingDelta::zero(),
+            crate::standard_scoring,
+            CancelAt {
+                cancel_at: 10,
+                calls: &calls,
+                flag: &flag,
+            },
+            Some(&flag),
+        );
+        assert_eq!(result, Err(AlignError::Cancelled));
+        assert_eq!(calls.get(), 10);
+
+        // a set flag stops the alignment before the first line is combined
+        let result = Aligner::align_with_splits(
+            &in_spans,
+            &in_spans,
+            RatingDelta::convert_from_f64(0.001),
+            None,
+            RatingDelta::zero(),
+            crate::standard_scoring,
+            NoProgressHandler,
+            Some(&flag),
+        );
+        assert_eq!(result, Err(AlignError::Cancelled));
+    }
+
+    #[test]
+    fn cancel_flag_interrupts_a_line() {
+        /// Counts the calls of `inc()`.
+        struct CountCalls<'a>(&'a Cell<usize>);
+        impl ProgressHandler for CountCalls<'_> {
+            fn inc(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
         }
+
+        // only the incorrect line 10 is 510 long
+        let ref_spans: Vec<TimeSpan> = (0..100)
+            .map(|i| TimeSpan::new(TimePoint::from(i * 1000), TimePoint::from(i * 1000 + 500)))
+            .collect();
+        let in_spans: Vec<TimeSpan> = (0..100)
+            .map(|i| TimeSpan::new(TimePoint::from(i * 1000), TimePoint::from(i * 1000 + 500 + i)))
+            .collect();
+
+        // the flag is set while the ratings of line 10 are computed, after the check at the start of the line
+        let flag = AtomicBool::new(false);
+        let score_fn = |a: TimeDelta, b: TimeDelta| {
+            if a.max(b) == TimeDelta::from_i64(510) {
+                flag.store(true, Ordering::Relaxed);
+            }
+            crate::standard_scoring(a, b)
+        };
+
+        let calls = Cell::new(0);
+        let result = Aligner::align_with_splits(
+            &ref_spans,
+            &in_spans,
+            RatingDelta::convert_from_f64(0.001),
+            None,
+            RatingDelta::zero(),
+            score_fn,
+            CountCalls(&calls),
+            Some(&flag),
+        );
+        assert_eq!(result, Err(AlignError::Cancelled));
+        // line 10 was never finished
+        assert_eq!(calls.get(), 10);
+    }
+
+    #[cfg(not(feature = "saturating-rating"))]
+    #[test]
+    fn rating_overflow_is_returned_as_error() {
+        let in_spans: Vec<TimeSpan> = (0..10)
+            .map(|i| TimeSpan::new(TimePoint::from(i * 1000), TimePoint::from(i * 1000 + 500)))
+            .collect();
+
+        // a full overlap rates far beyond the range of a `Rating`
+        let result = Aligner::align_with_splits(
+            &in_spans,
+            &in_spans,
+            RatingDelta::convert_from_f64(0.001),
+            None,
+            RatingDelta::zero(),
+            |_, _| 1e10,
+            NoProgressHandler,
+            None,
+        );
+        assert!(matches!(result, Err(AlignError::RatingOverflow { .. })));
+    }
+
+    #[test]
+    /// The last incorrect line lies behind the end of the reference, so its offset can only come
+    /// from the tail of the rating curve, which the left-to-right maximum fills with the last good
+    /// offset.
+    fn line_past_reference_end_keeps_last_offset() {
+        let span = |start: i64, end: i64| TimeSpan::new(TimePoint::from(start), TimePoint::from(end));
+
+        let ref_spans = vec![span(0, 1000), span(2000, 3000), span(4000, 5000)];
+        let in_spans = vec![span(100, 1100), span(2100, 3100), span(4100, 5100), span(8000, 9000)];
+
+        let (deltas, _) = Aligner::align_with_splits(
+            &ref_spans,
+            &in_spans,
+            RatingDelta::convert_from_f64(0.001),
+            None,
+            RatingDelta::zero(),
+            crate::standard_scoring,
+            NoProgressHandler,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(deltas, vec![TimeDelta::from_i64(-100); in_spans.len()]);
+    }
+
+    #[test]
+    fn peaks_are_counted_per_plateau() {
+        // the plateau at 3 is followed by a higher one, only the one at 5 is a peak
+        assert_eq!(plateau_peaks(&[1, 3, 3, 5, 5, 2]), vec![3]);
+        assert_eq!(plateau_peaks(&[4, 4, 1, 2, 2, 2]), vec![0, 3]);
+        assert_eq!(plateau_peaks(&[7, 7, 7]), vec![0]);
+        assert_eq!(plateau_peaks(&[]), Vec::<usize>::new());
+
+        // one of the incorrect lines always lies in the long reference line from -1000 to 400, which
+        // is a plateau below the peak at 500 (where the second line fits the short reference line);
+        // at 1500 the first line fits the short reference line
+        let span = |start: i64, end: i64| TimeSpan::new(TimePoint::from(start), TimePoint::from(end));
+        let ref_spans = vec![span(0, 1000), span(1500, 1600)];
+        let in_spans = vec![span(0, 100), span(1000, 1100)];
+
+        let candidates =
+            Aligner::constant_delta_candidates(&ref_spans, &in_spans, 3, TimeDelta::one(), crate::standard_scoring);
+        let offsets: Vec<TimeDelta> = candidates.into_iter().map(|(offset, _)| offset).collect();
+        assert_eq!(offsets, vec![TimeDelta::from_i64(500), TimeDelta::from_i64(1500)]);
     }
 
     #[test]
@@ -616,18 +1173,125 @@ mod tests {
             let (min_offset, max_offset) = (min_offset - TimeDelta::one(), max_offset + TimeDelta::one());
 
             for in_span in in_spans {
-                let last: RatingFullSegment =
-                    Aligner::single_span_ratings(&ref_spans, in_span, crate::standard_scoring, min_offset, max_offset)
-                        .annotate_with_segment_start_points()
-                        .into_iter()
-                        .last()
-                        .unwrap();
+                let last: RatingFullSegment = Aligner::single_span_ratings(
+                    &[WeightedReference {
+                        spans: &ref_spans,
+                        weight: 1.,
+                    }],
+                    in_span,
+                    crate::standard_scoring,
+                    min_offset,
+                    max_offset,
+                    &[],
+                )
+                .unwrap()
+                .iter()
+                .annotate_with_segment_start_points()
+                .into_iter()
+                .last()
+                .unwrap();
                 assert_eq!(last.end_rating(), 0);
                 //assert_eq!(dbg!(last.data.delta), RatingDelta::zero());
             }
         }
     }
 
+    #[test]
+    /// Compares the rating of every constant offset with the sum of the ratings at the points of
+    /// the shifted lines.
+    fn constant_delta_to_rating_matches_pointwise_sum() {
+        let p = TimeDelta::from_i64;
+        let slope = RatingDelta::convert_from_f64(0.25);
+        let segment = |end_point: i64, rating: Rating, delta: RatingDelta| RatingSegment {
+            end_point: p(end_point),
+            data: RatingInfo { rating, delta },
+        };
+        let reference = RatingBuffer {
+            start: p(-5),
+            buffer: vec![
+                segment(10, Rating::zero(), slope),
+                segment(20, Rating::add_mul(Rating::zero(), slope, p(15)), -slope),
+                segment(26, Rating::convert_from_f64(1.), RatingDelta::zero()),
+                segment(40, Rating::convert_from_f64(-2.), slope),
+                segment(41, Rating::convert_from_f64(3.), RatingDelta::zero()),
+            ],
+        };
+        let span = |start: i64, end: i64| TimeSpan::new(TimePoint::from(start), TimePoint::from(end));
+        let in_spans = [span(0, 3), span(7, 12), span(30, 31)];
+
+        let expected = |in_spans: &[TimeSpan]| -> Option<(TimeDelta, Rating)> {
+            let mut maximum: Option<(TimeDelta, Rating)> = None;
+            for offset in -60..60 {
+                let rating: Rating = in_spans
+                    .iter()
+                    .flat_map(|span| (span.start().as_i64()..span.end().as_i64()).map(|t| p(t + offset)))
+                    .filter(|&t| reference.start() <= t && t < reference.end().unwrap())
+                    .map(|t| reference.get_rating_at(t))
+                    .sum();
+                if rating > maximum.map_or(Rating::zero(), |(_, max_rating)| max_rating) {
+                    maximum = Some((p(offset), rating));
+                }
+            }
+            maximum
+        };
+
+        // the first span alone is best in the middle of the ramps, between the offsets at which it
+        // reaches their ends
+        assert_eq!(expected(&in_spans[..1]).unwrap().0, p(9));
+        for spans in [&in_spans[..], &in_spans[..1], &in_spans[1..]] {
+            assert_eq!(
+                Aligner::align_constant_delta_to_rating(&reference, spans),
+                expected(spans)
+            );
+        }
+    }
+
+    #[test]
+    /// A span of a single tick has the rating "one during the reference spans" over all offsets,
+    /// which is what `RatingBuffer::from_speech_intervals` builds from the reference spans.
+    fn speech_intervals_match_aligner_ratings() {
+        let unit_scoring = |a: TimeDelta, b: TimeDelta| core::cmp::min(a, b).as_f64();
+        let zero = TimePoint::from(0);
+
+        for _ in 0..30 {
+            let ref_spans = get_dummy_spans();
+            let in_span = TimeSpan::new(zero, TimePoint::from(1));
+            let (min_offset, max_offset) = Aligner::get_offsets_bounds(&ref_spans, &[in_span]);
+            let (min_offset, max_offset) = (min_offset - TimeDelta::one(), max_offset + TimeDelta::one());
+
+            let aligner_ratings = Aligner::single_span_ratings(
+                &[WeightedReference {
+                    spans: &ref_spans,
+                    weight: 1.,
+                }],
+                in_span,
+                unit_scoring,
+                min_offset,
+                max_offset,
+                &[],
+            )
+            .unwrap();
+
+            let intervals: Vec<(TimeDelta, TimeDelta)> = ref_spans
+                .iter()
+                .map(|span| (span.start() - zero, span.end() - zero))
+                .collect();
+            let speech_ratings = RatingBuffer::from_speech_intervals(min_offset, max_offset, &intervals);
+
+            // the aligner ramps between the values within a single tick, so compare point by point
+            assert_eq!(aligner_ratings.start(), speech_ratings.start());
+            assert_eq!(aligner_ratings.end(), speech_ratings.end());
+            let mut offset = min_offset;
+            while offset < max_offset {
+                assert_eq!(
+                    aligner_ratings.get_rating_at(offset),
+                    speech_ratings.get_rating_at(offset)
+                );
+                offset += TimeDelta::one();
+            }
+        }
+    }
+
     /*#[test]
     /// `get_compressed_overlapping_ratings()` is highly optimized -> compare the results of slow and fast
     /// implemntations.
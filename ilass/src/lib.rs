@@ -9,6 +9,7 @@
     unused_qualifications
 )]
 #![allow(unknown_lints)] // for clippy
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
 //! `ilass` takes two timespan arrays (e.g. from two subtitle files) and
 //! tries to align the `incorrect` subtitles
@@ -16,23 +17,35 @@
 //! introduces/removes breaks between subtitles in the `incorrect`
 //! subtitle to achive the best alignment.
 
+extern crate alloc;
 #[cfg(test)]
 extern crate rand;
 
+#[cfg(feature = "image-debug")]
+mod debug_image;
 mod ilass;
 mod rating_type;
-#[allow(dead_code)]
-mod segments;
+// the buffers have an inherent `into_iter` which keeps the start point of the segments
+#[allow(clippy::should_implement_trait)]
+pub mod segments;
+#[doc(hidden)]
+pub mod test_util;
 mod time_types;
 mod timespan_ops;
 
-use crate::ilass::Aligner;
+pub use crate::ilass::AlignError;
 pub use crate::ilass::NoProgressHandler;
 pub use crate::ilass::ProgressHandler;
+use crate::ilass::{Aligner, WeightedReference};
 use crate::rating_type::{Rating, RatingDelta, RatingExt};
+use crate::segments::RatingBuffer;
+pub use crate::segments::{PointSpan, SegmentError};
 pub use crate::time_types::{TimeDelta, TimePoint, TimeSpan};
 use crate::timespan_ops::prepare_time_spans;
-use std::cmp::{max, min};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::{max, min};
+use core::sync::atomic::AtomicBool;
 
 fn denormalize_split_penalty(ref_list_len: usize, in_list_len: usize, split_penalty_normalized: f64) -> RatingDelta {
     RatingDelta::convert_from_f64(min(ref_list_len, in_list_len) as f64 * split_penalty_normalized / 1000.0)
@@ -84,6 +97,81 @@ pub fn align_nosplit(
     (delta, score.as_readable_f64())
 }
 
+/// Like `align_nosplit`, but the reference is a rating of every point instead of time spans, e.g.
+/// a `RatingBuffer::from_speech_intervals` of word timestamps from a forced alignment tool (so no
+/// audio has to be decoded).
+///
+/// A line is rated with the sum of the `reference` ratings at its points (the rating is zero
+/// outside of the buffer), so against speech intervals the score is the length of the lines during
+/// speech. This is the score of `align_nosplit` against spans at the speech intervals with the
+/// score function `|a, b| min(a, b).as_f64()`.
+///
+/// Returns a delta of zero and a score of zero if no delta gives the lines a positive rating.
+pub fn align_nosplit_to_rating(reference: &RatingBuffer, list: &[TimeSpan]) -> (TimeDelta, Score) {
+    let (list_nonoverlapping, _) = prepare_time_spans(list);
+
+    match Aligner::align_constant_delta_to_rating(reference, &list_nonoverlapping) {
+        Some((delta, rating)) => (delta, rating.as_readable_f64()),
+        None => (TimeDelta::zero(), 0.),
+    }
+}
+
+/// Like `align_nosplit`, but only searches deltas from `-max_shift` to `max_shift`, for when the
+/// offset is known to be small (a far away delta with a better score, e.g. a repeated scene, is
+/// ignored).
+///
+/// Returns a delta of zero and a score of zero if no delta in that range lets any lines overlap.
+///
+/// The score of a delta is the overlap of the shifted lines with the reference lines (weighted by
+/// `score_fn`), i.e. the cross-correlation of the two subtitles as curves that are one during their
+/// lines. As these curves are determined by the spans, the best delta is found with one sweep over
+/// the changes of the slope, and no `RatingBuffer` of the subtitles is needed (see
+/// `align_nosplit_to_rating` for a reference that is a rating, e.g. speech intervals).
+pub fn align_nosplit_bounded(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    max_shift: TimeDelta,
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+) -> (TimeDelta, Score) {
+    let (ref_nonoverlapping, _) = prepare_time_spans(reference);
+    let (list_nonoverlapping, _) = prepare_time_spans(list);
+
+    if list_nonoverlapping.is_empty() || ref_nonoverlapping.is_empty() {
+        return (TimeDelta::zero(), 0.);
+    }
+
+    match Aligner::align_constant_delta_bounded(&ref_nonoverlapping, &list_nonoverlapping, max_shift, score_fn) {
+        Some((delta, rating)) => (delta, rating.as_readable_f64()),
+        None => (TimeDelta::zero(), 0.),
+    }
+}
+
+/// Like `align_nosplit`, but returns up to `count` different constant deltas, best first, for
+/// content where more than one offset is plausible (e.g. repeated scenes).
+///
+/// Every returned delta is a local maximum of the no-split score and at least `min_distance` away
+/// from all better deltas, so the candidates are distinct alignments and not neighbours of the same
+/// peak. Fewer than `count` deltas are returned if there are not enough such maxima.
+pub fn align_nosplit_candidates(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    count: usize,
+    min_distance: TimeDelta,
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+) -> Vec<(TimeDelta, Score)> {
+    let (ref_nonoverlapping, _) = prepare_time_spans(reference);
+    let (list_nonoverlapping, _) = prepare_time_spans(list);
+
+    if list_nonoverlapping.is_empty() || ref_nonoverlapping.is_empty() {
+        return Vec::new();
+    }
+
+    Aligner::constant_delta_candidates(&ref_nonoverlapping, &list_nonoverlapping, count, min_distance, score_fn)
+        .into_iter()
+        .map(|(delta, rating)| (delta, rating.as_readable_f64()))
+        .collect()
+}
+
 /// Matches an `incorrect` subtitle list to a `reference` subtitle list.
 ///
 /// Returns the delta for every time span in list.
@@ -103,6 +191,10 @@ pub fn align_nosplit(
 /// will have greatly degraded. It is recommended to supply a value around `3`.
 ///
 /// Use `standard_scoring` as score function if no fine tuning is required.
+///
+/// The alignment is deterministic: identical inputs always produce identical deltas and scores.
+/// There is no randomness involved, and with the `rayon` feature the ratings that are built in
+/// parallel chunks are exactly the ones of the sequential build.
 pub fn align(
     reference: &[TimeSpan],
     list: &[TimeSpan],
@@ -111,11 +203,526 @@ pub fn align(
     score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
     progress_handler: impl ProgressHandler,
 ) -> (Vec<TimeDelta>, f64) {
+    align_with_options(
+        reference,
+        list,
+        split_penalty,
+        score_fn,
+        AlignOptions::new(speed_optimization, progress_handler),
+    )
+    .expect("alignment without cancel flag can only fail if the rating overflows")
+}
+
+/// The optional settings of `align_with_options`.
+///
+/// `AlignOptions::new` returns the settings `align` uses, so single options can be changed with
+/// the struct update syntax:
+///
+/// ```
+/// use core::sync::atomic::AtomicBool;
+/// use ilass::{AlignOptions, NoProgressHandler, TimePoint, TimeSpan};
+///
+/// let spans = [TimeSpan::new(TimePoint::from(0), TimePoint::from(1000))];
+/// let cancel_flag = AtomicBool::new(false);
+/// let options = AlignOptions {
+///     identity_weight: 0.1,
+///     cancel_flag: Some(&cancel_flag),
+///     ..AlignOptions::new(Some(3.), NoProgressHandler)
+/// };
+/// let (deltas, _) = ilass::align_with_options(&spans, &spans, 7., ilass::standard_scoring, options).unwrap();
+/// assert_eq!(deltas, [ilass::TimeDelta::zero()]);
+/// ```
+#[derive(Debug)]
+pub struct AlignOptions<'a, P: ProgressHandler> {
+    /// See `align`.
+    pub speed_optimization: Option<f64>,
+
+    /// Every line that is not moved gets this weight added to its rating, so the alignment prefers
+    /// to keep lines at their original timing. A line scores at most `1` for a perfect match, so a
+    /// moderate weight like `0.1` only keeps a line in place where the reference does not clearly
+    /// prefer another offset (e.g. for mostly-correct subtitles with some bad lines). The returned
+    /// score includes the bias, which the `speed_optimization` never simplifies away.
+    pub identity_weight: f64,
+
+    pub progress_handler: P,
+
+    /// Once this flag is set (e.g. from another thread), the alignment stops as soon as possible
+    /// with `AlignError::Cancelled`. The flag is also checked while a line is combined with the
+    /// previous lines, so even a single line with huge rating buffers is interrupted.
+    pub cancel_flag: Option<&'a AtomicBool>,
+}
+
+impl<P: ProgressHandler> AlignOptions<'_, P> {
+    /// The options of `align` with these arguments.
+    pub fn new(speed_optimization: Option<f64>, progress_handler: P) -> Self {
+        AlignOptions {
+            speed_optimization,
+            identity_weight: 0.,
+            progress_handler,
+            cancel_flag: None,
+        }
+    }
+}
+
+/// Like `align`, but with the settings of `options`. A rating overflow is returned as
+/// `AlignError::RatingOverflow` instead of panicking.
+pub fn align_with_options(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    split_penalty: f64,
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+    options: AlignOptions<impl ProgressHandler>,
+) -> Result<(Vec<TimeDelta>, f64), AlignError> {
+    align_impl(
+        reference,
+        list,
+        split_penalty,
+        options.speed_optimization,
+        RatingDelta::convert_from_f64(options.identity_weight),
+        score_fn,
+        options.progress_handler,
+        options.cancel_flag,
+    )
+}
+
+/// Aligns in two stages: first the best constant delta for all lines is searched (like
+/// `align_nosplit`), then the lines are aligned like `align`, but among equally rated alignments
+/// the one with the most lines at that constant delta is chosen.
+///
+/// Only ties are decided by the constant delta, so the score is (up to the rating precision) the
+/// same as the one of `align`. This keeps the lines of ambiguous regions (e.g. where a reference
+/// line is longer than the incorrect line) together at the global delta instead of an arbitrary
+/// offset. The progress handler only reports the second stage.
+pub fn align_two_stage(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    split_penalty: f64,
+    speed_optimization: Option<f64>,
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+    progress_handler: impl ProgressHandler,
+) -> (Vec<TimeDelta>, f64) {
+    let (global_delta, _) = align_nosplit(reference, list, score_fn, NoProgressHandler);
+
+    // the smallest representable rating, so the bias never outweighs a better match
+    let tie_breaking_bias: RatingDelta = 1;
+
+    let shifted_list: Vec<TimeSpan> = list.iter().map(|&span| span + global_delta).collect();
+    let (deltas, score) = align_impl(
+        reference,
+        &shifted_list,
+        split_penalty,
+        speed_optimization,
+        tie_breaking_bias,
+        score_fn,
+        progress_handler,
+        None,
+    )
+    .expect("alignment without cancel flag can only fail if the rating overflows");
+
+    (deltas.into_iter().map(|delta| delta + global_delta).collect(), score)
+}
+
+/// Like `align`, but aligns against two references at once: the voice segments of the audio
+/// track and the lines of a reference subtitle.
+///
+/// The rating of every line is `audio_weight` times its rating against `audio_reference` plus
+/// `subtitle_weight` times its rating against `subtitle_reference`, so an offset that only one of
+/// the references supports (e.g. because the audio contains a repeated scene, or the reference
+/// subtitle lacks some lines) loses against an offset that both support. The weights have to be
+/// non-negative, and a reference with a weight of zero or without spans is ignored. The split
+/// penalty is scaled with the sum of the weights of the used references.
+#[allow(clippy::too_many_arguments)]
+pub fn align_fused(
+    audio_reference: &[TimeSpan],
+    audio_weight: f64,
+    subtitle_reference: &[TimeSpan],
+    subtitle_weight: f64,
+    list: &[TimeSpan],
+    split_penalty: f64,
+    speed_optimization: Option<f64>,
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+    progress_handler: impl ProgressHandler,
+) -> (Vec<TimeDelta>, f64) {
+    assert!(audio_weight >= 0. && subtitle_weight >= 0.);
+
+    let (list_nonoverlapping, list_indices) = prepare_time_spans(list);
+    let (audio_nonoverlapping, _) = prepare_time_spans(audio_reference);
+    let (subtitle_nonoverlapping, _) = prepare_time_spans(subtitle_reference);
+
+    let references: Vec<WeightedReference> = [
+        (&audio_nonoverlapping, audio_weight),
+        (&subtitle_nonoverlapping, subtitle_weight),
+    ]
+    .into_iter()
+    .filter(|&(spans, weight)| !spans.is_empty() && weight > 0.)
+    .map(|(spans, weight)| WeightedReference { spans, weight })
+    .collect();
+
+    if list_nonoverlapping.is_empty() || references.is_empty() {
+        return (vec![TimeDelta::zero(); list.len()], 0.);
+    }
+
+    let ref_len: usize = references
+        .iter()
+        .map(|reference| reference.spans.len())
+        .max()
+        .unwrap_or(0);
+    let total_weight: f64 = references.iter().map(|reference| reference.weight).sum();
+    let nosplit_bonus = denormalize_split_penalty(ref_len, list_nonoverlapping.len(), split_penalty * total_weight);
+
+    let (deltas, score) = Aligner::align_with_splits_weighted(
+        &references,
+        &list_nonoverlapping,
+        nosplit_bonus,
+        speed_optimization,
+        RatingDelta::zero(),
+        score_fn,
+        progress_handler,
+        None,
+    )
+    .expect("alignment without cancel flag can only fail if the rating overflows");
+
+    (
+        list_indices.into_iter().map(|i| deltas[i]).collect(),
+        score.as_readable_f64(),
+    )
+}
+
+/// Like `align`, but aligns every region between two `breaks` (e.g. the commercial breaks of a
+/// broadcast recording) independently, so each region gets its own offsets.
+///
+/// A line belongs to the region its start lies in (this applies to the `reference` and the
+/// `list`), so the breaks should be placed in gaps where no line of either file starts. The
+/// returned score is the sum of the region scores.
+///
+/// The progress handler is advanced once per region.
+pub fn align_regions(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    breaks: &[TimePoint],
+    split_penalty: f64,
+    speed_optimization: Option<f64>,
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+    mut progress_handler: impl ProgressHandler,
+) -> (Vec<TimeDelta>, f64) {
+    let mut breaks: Vec<TimePoint> = breaks.to_vec();
+    breaks.sort();
+
+    let region_of = |span: &TimeSpan| {
+        breaks
+            .iter()
+            .take_while(|&&region_break| region_break <= span.start)
+            .count()
+    };
+
+    progress_handler.init(breaks.len() as i64 + 1);
+
+    let mut deltas = vec![TimeDelta::zero(); list.len()];
+    let mut total_score = 0.;
+    for region in 0..=breaks.len() {
+        let region_reference: Vec<TimeSpan> = reference
+            .iter()
+            .filter(|span| region_of(span) == region)
+            .cloned()
+            .collect();
+        let region_indices: Vec<usize> = (0..list.len()).filter(|&i| region_of(&list[i]) == region).collect();
+        let region_list: Vec<TimeSpan> = region_indices.iter().map(|&i| list[i]).collect();
+
+        let (region_deltas, region_score) = align(
+            &region_reference,
+            &region_list,
+            split_penalty,
+            speed_optimization,
+            score_fn,
+            NoProgressHandler,
+        );
+
+        for (i, delta) in region_indices.into_iter().zip(region_deltas) {
+            deltas[i] = delta;
+        }
+        total_score += region_score;
+
+        progress_handler.inc();
+    }
+
+    progress_handler.finish();
+
+    (deltas, total_score)
+}
+
+/// Which side of an alignment is corrected (see `offset_curve`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Direction {
+    /// The subtitle lines are shifted to match the reference.
+    #[default]
+    ShiftSubtitle,
+
+    /// The reference (e.g. the audio and video of a movie) is shifted to match the subtitles, which
+    /// are already timed correctly.
+    ShiftMedia,
+}
+
+/// A piece of a piecewise constant offset curve: from `start` on (up to the `start` of the next
+/// piece) times have to be shifted by `delta`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OffsetPiece {
+    pub start: TimePoint,
+    pub delta: TimeDelta,
+}
+
+/// Turns the `deltas` that one of the align functions returned for the lines in `list` into an
+/// offset curve with one piece for every run of lines with the same delta.
+///
+/// For `Direction::ShiftSubtitle` the curve is in the time of the subtitles and shifts them onto
+/// the reference. For `Direction::ShiftMedia` it is the inverse: it is in the time of the reference
+/// and shifts the reference onto the subtitles, so every piece starts where the first line of its
+/// run ends up and has the negated delta. The pieces are ordered as long as the shifted lines keep
+/// their order.
+pub fn offset_curve(list: &[TimeSpan], deltas: &[TimeDelta], direction: Direction) -> Vec<OffsetPiece> {
+    assert_eq!(list.len(), deltas.len());
+
+    let mut curve: Vec<OffsetPiece> = Vec::new();
+    let mut previous_delta: Option<TimeDelta> = None;
+    for (span, &delta) in list.iter().zip(deltas) {
+        if previous_delta == Some(delta) {
+            continue;
+        }
+        previous_delta = Some(delta);
+
+        curve.push(match direction {
+            Direction::ShiftSubtitle => OffsetPiece {
+                start: span.start(),
+                delta,
+            },
+            Direction::ShiftMedia => OffsetPiece {
+                start: span.start() + delta,
+                delta: -delta,
+            },
+        });
+    }
+    curve
+}
+
+/// Returns the delta of the offset curve at `t`: the delta of the last piece that starts at or
+/// before `t`. A `t` before the first piece gets the delta of the first piece, and an empty curve
+/// has a delta of zero everywhere.
+///
+/// The pieces have to be sorted by their start, like the ones `offset_curve` returns.
+pub fn offset_at(curve: &[OffsetPiece], t: TimePoint) -> TimeDelta {
+    debug_assert!(
+        curve.windows(2).all(|pair| pair[0].start <= pair[1].start),
+        "the pieces of the offset curve have to be sorted by their start"
+    );
+
+    curve
+        .iter()
+        .take_while(|piece| piece.start <= t)
+        .last()
+        .or(curve.first())
+        .map_or_else(TimeDelta::zero, |piece| piece.delta)
+}
+
+/// Reduces the `deltas` returned by one of the align functions to at most `max_runs` runs of
+/// consecutive lines with the same delta (so at most `max_runs - 1` offset changes), so a drifting
+/// alignment does not shift every line by a slightly different offset.
+///
+/// While there are too many runs, the two neighbouring runs whose merge loses the least score are
+/// merged and shifted by the best constant delta for their lines. That delta is only searched
+/// against the reference lines around the two runs, so every merge takes time proportional to the
+/// size of the runs and not to the whole reference. A `max_runs` of `0` is treated like `1`, which
+/// shifts all lines by one constant delta. Use the `score_fn` the `deltas` were aligned with.
+///
+/// Returns the new deltas and the score that was lost by merging (the fidelity loss).
+pub fn limit_offset_changes(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    deltas: &[TimeDelta],
+    max_runs: usize,
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+) -> (Vec<TimeDelta>, Score) {
+    assert_eq!(list.len(), deltas.len());
+
+    #[derive(Clone, Copy)]
+    struct Run {
+        start: usize,
+        end: usize,
+        delta: TimeDelta,
+        score: Score,
+    }
+
+    /// The sorted and non-overlapping `spans` which overlap the time range of `lines` moved by any
+    /// delta from `min_delta` to `max_delta`.
+    fn spans_around<'a>(
+        spans: &'a [TimeSpan],
+        lines: &[TimeSpan],
+        min_delta: TimeDelta,
+        max_delta: TimeDelta,
+    ) -> &'a [TimeSpan] {
+        let range_start = lines.iter().map(|line| line.start()).min().expect("runs are not empty") + min_delta;
+        let range_end = lines.iter().map(|line| line.end()).max().expect("runs are not empty") + max_delta;
+
+        let first = spans.partition_point(|span| span.end() <= range_start);
+        let last = spans.partition_point(|span| span.start() < range_end);
+        &spans[first..max(first, last)]
+    }
+
+    let (ref_nonoverlapping, _) = prepare_time_spans(reference);
+    let constant_delta_score = |lines: &[TimeSpan], delta: TimeDelta| -> Score {
+        let shifted: Vec<TimeSpan> = lines.iter().map(|&span| span + delta).collect();
+        let (shifted_nonoverlapping, _) = prepare_time_spans(&shifted);
+        get_nosplit_score(
+            spans_around(&ref_nonoverlapping, lines, delta, delta).iter().cloned(),
+            shifted_nonoverlapping.into_iter(),
+            score_fn,
+        )
+    };
+    let merged = |a: &Run, b: &Run| -> Run {
+        let lines = &list[a.start..b.end];
+        let reference_around = spans_around(&ref_nonoverlapping, lines, min(a.delta, b.delta), max(a.delta, b.delta));
+        let (delta, score) = if reference_around.is_empty() {
+            (a.delta, 0.)
+        } else {
+            align_nosplit(reference_around, lines, score_fn, NoProgressHandler)
+        };
+        Run {
+            start: a.start,
+            end: b.end,
+            delta,
+            score,
+        }
+    };
+
+    let mut runs: Vec<Run> = Vec::new();
+    for (i, &delta) in deltas.iter().enumerate() {
+        match runs.last_mut() {
+            Some(run) if run.delta == delta => run.end = i + 1,
+            _ => runs.push(Run {
+                start: i,
+                end: i + 1,
+                delta,
+                score: 0.,
+            }),
+        }
+    }
+    for run in &mut runs {
+        run.score = constant_delta_score(&list[run.start..run.end], run.delta);
+    }
+    let original_score: Score = runs.iter().map(|run| run.score).sum();
+
+    // `merges[i]` is the result of merging `runs[i]` and `runs[i + 1]`
+    let mut merges: Vec<Run> = runs.windows(2).map(|pair| merged(&pair[0], &pair[1])).collect();
+    while runs.len() > max(max_runs, 1) {
+        let loss = |i: usize| runs[i].score + runs[i + 1].score - merges[i].score;
+        let i = (0..merges.len())
+            .min_by(|&a, &b| loss(a).total_cmp(&loss(b)))
+            .expect("more than one run has at least one merge candidate");
+
+        runs[i] = merges.remove(i);
+        runs.remove(i + 1);
+        if i > 0 {
+            merges[i - 1] = merged(&runs[i - 1], &runs[i]);
+        }
+        if i < merges.len() {
+            merges[i] = merged(&runs[i], &runs[i + 1]);
+        }
+    }
+
+    let mut limited_deltas = vec![TimeDelta::zero(); list.len()];
+    for run in &runs {
+        for delta in &mut limited_deltas[run.start..run.end] {
+            *delta = run.delta;
+        }
+    }
+    let limited_score: Score = runs.iter().map(|run| run.score).sum();
+
+    (limited_deltas, original_score - limited_score)
+}
+
+/// Updates the `previous_deltas` of an alignment after the lines `changed_lines` of the `list`
+/// were edited, without aligning the whole list again.
+///
+/// Every changed line is realigned together with `context_lines` lines before and after it (windows
+/// that touch are merged). A window is aligned with `align` against the reference lines between the
+/// corrected end of the line before the window and the corrected start of the line after it, so the
+/// realigned lines can not move past the kept lines. All lines outside of the windows keep their
+/// previous delta, as does a window that has no reference lines left to align to.
+#[allow(clippy::too_many_arguments)]
+pub fn realign_changed_lines(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    previous_deltas: &[TimeDelta],
+    changed_lines: &[usize],
+    context_lines: usize,
+    split_penalty: f64,
+    speed_optimization: Option<f64>,
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+) -> Vec<TimeDelta> {
+    assert_eq!(list.len(), previous_deltas.len());
+
+    let mut changed_lines: Vec<usize> = changed_lines.to_vec();
+    changed_lines.sort();
+
+    // half-open index ranges of the lines that get realigned
+    let mut windows: Vec<(usize, usize)> = Vec::new();
+    for &line in &changed_lines {
+        assert!(line < list.len(), "changed line {} is out of range", line);
+        let window = (
+            line.saturating_sub(context_lines),
+            min(line + context_lines + 1, list.len()),
+        );
+        match windows.last_mut() {
+            Some(last) if last.1 >= window.0 => last.1 = max(last.1, window.1),
+            _ => windows.push(window),
+        }
+    }
+
+    let mut deltas = previous_deltas.to_vec();
+    for (window_start, window_end) in windows {
+        let lower_bound = window_start.checked_sub(1).map(|i| list[i].end() + previous_deltas[i]);
+        let upper_bound = list
+            .get(window_end)
+            .map(|span| span.start() + previous_deltas[window_end]);
+
+        let window_reference: Vec<TimeSpan> = reference
+            .iter()
+            .filter(|span| lower_bound.is_none_or(|bound| span.start() >= bound))
+            .filter(|span| upper_bound.is_none_or(|bound| span.end() <= bound))
+            .cloned()
+            .collect();
+        if window_reference.is_empty() {
+            continue;
+        }
+
+        let (window_deltas, _) = align(
+            &window_reference,
+            &list[window_start..window_end],
+            split_penalty,
+            speed_optimization,
+            score_fn,
+            NoProgressHandler,
+        );
+        deltas[window_start..window_end].copy_from_slice(&window_deltas);
+    }
+
+    deltas
+}
+
+#[allow(clippy::too_many_arguments)]
+fn align_impl(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    split_penalty: f64,
+    speed_optimization: Option<f64>,
+    identity_bias: RatingDelta,
+    score_fn: impl Fn(TimeDelta, TimeDelta) -> f64 + Copy,
+    progress_handler: impl ProgressHandler,
+    cancel_flag: Option<&AtomicBool>,
+) -> Result<(Vec<TimeDelta>, f64), AlignError> {
     let (list_nonoverlapping, list_indices) = prepare_time_spans(list);
     let (ref_nonoverlapping, _) = prepare_time_spans(reference);
 
     if list_nonoverlapping.is_empty() || ref_nonoverlapping.is_empty() {
-        return (vec![TimeDelta::zero(); list.len()], 0.);
+        return Ok((vec![TimeDelta::zero(); list.len()], 0.));
     }
 
     let nosplit_bonus = denormalize_split_penalty(ref_nonoverlapping.len(), list_nonoverlapping.len(), split_penalty);
@@ -126,15 +733,128 @@ pub fn align(
         &list_nonoverlapping,
         nosplit_bonus,
         speed_optimization,
+        identity_bias,
         score_fn,
         progress_handler,
-    );
+        cancel_flag,
+    )?;
 
     // get deltas for overlapping timspan-list
-    (
+    Ok((
         list_indices.into_iter().map(|i| deltas[i]).collect(),
         score.as_readable_f64(),
-    )
+    ))
+}
+
+/// Warnings about the input of the align operation, which hint at a probably useless result.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlignWarning {
+    /// The reference and the incorrect subtitles cover (almost) disjoint time ranges, e.g. because
+    /// they belong to different content. `fraction` is the shared part of the shorter time range.
+    LowOverlap { fraction: f64 },
+
+    /// The reference has no span with a positive length, e.g. because the reference audio is
+    /// shorter than one frame of the voice activity detection (or has no voice at all). Every
+    /// line keeps a delta of zero.
+    InsufficientAudio,
+
+    /// Only the first `analyzed_seconds` seconds of the reference audio were analyzed because of a
+    /// limit on the analysis duration, so lines after that point have no reference to align to.
+    AnalysisTruncated { analyzed_seconds: f64 },
+}
+
+/// Below this overlap fraction `check_overlap` returns `AlignWarning::LowOverlap`.
+pub const LOW_OVERLAP_FRACTION: f64 = 0.2;
+
+/// Returns the fraction (between 0 and 1) of the shorter time range covered by `reference` or `list`
+/// that is covered by both lists.
+///
+/// The time range of a list reaches from the start of its first span to the end of its last span.
+/// Empty spans are ignored. If one of the lists is empty the fraction is zero.
+pub fn get_overlap_fraction(reference: &[TimeSpan], list: &[TimeSpan]) -> f64 {
+    fn nonzero_range(spans: &[TimeSpan]) -> Option<TimeSpan> {
+        let mut nonempty = spans.iter().filter(|span| !span.is_empty());
+        let first = *nonempty.next()?;
+        Some(nonempty.fold(first, |range, span| {
+            TimeSpan::new(min(range.start, span.start), max(range.end, span.end))
+        }))
+    }
+
+    let (ref_range, list_range) = match (nonzero_range(reference), nonzero_range(list)) {
+        (Some(ref_range), Some(list_range)) => (ref_range, list_range),
+        _ => return 0.,
+    };
+
+    let shorter_len = min(ref_range.len(), list_range.len());
+    ref_range.get_overlapping_length(list_range).as_f64() / shorter_len.as_f64()
+}
+
+/// Checks whether the reference and incorrect subtitles cover enough of the same time range to give a
+/// meaningful alignment (see `get_overlap_fraction`).
+pub fn check_overlap(reference: &[TimeSpan], list: &[TimeSpan]) -> Option<AlignWarning> {
+    let fraction = get_overlap_fraction(reference, list);
+    if fraction < LOW_OVERLAP_FRACTION {
+        Some(AlignWarning::LowOverlap { fraction })
+    } else {
+        None
+    }
+}
+
+/// Checks whether the reference contains anything to align to (see
+/// `AlignWarning::InsufficientAudio`).
+pub fn check_reference(reference: &[TimeSpan]) -> Option<AlignWarning> {
+    if reference.iter().all(|span| span.is_empty()) {
+        Some(AlignWarning::InsufficientAudio)
+    } else {
+        None
+    }
+}
+
+/// A rough estimate of the memory `align` needs (see `estimate_memory`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryEstimate {
+    pub peak_bytes: u64,
+}
+
+/// Estimates the peak memory usage of `align_with_options` with these arguments before running it
+/// (e.g. to warn about a 10 hour file on a machine with little RAM). The split penalty and the
+/// score function don't change the memory usage, so they are not needed.
+///
+/// The estimate ignores the `speed_optimization` of the `options`, which can only reduce the
+/// memory usage.
+pub fn estimate_memory(
+    reference: &[TimeSpan],
+    list: &[TimeSpan],
+    options: &AlignOptions<impl ProgressHandler>,
+) -> MemoryEstimate {
+    let (ref_nonoverlapping, _) = prepare_time_spans(reference);
+    let (list_nonoverlapping, _) = prepare_time_spans(list);
+    let (Some(ref_first), Some(ref_last), Some(list_first), Some(list_last)) = (
+        ref_nonoverlapping.first(),
+        ref_nonoverlapping.last(),
+        list_nonoverlapping.first(),
+        list_nonoverlapping.last(),
+    ) else {
+        return MemoryEstimate { peak_bytes: 0 };
+    };
+
+    // every line keeps an offset buffer until the end of the alignment; a buffer can not have more
+    // segments than offsets and usually has a few per reference line
+    let offset_range = ((ref_last.end() - list_first.start()) - (ref_first.start() - list_last.end())).as_i64();
+    // the spike of the identity weight adds up to three segments to every buffer
+    let identity_segments = if options.identity_weight > 0. { 3 } else { 0 };
+    let segments_per_line =
+        min(max(offset_range, 1) as u64, 8 * ref_nonoverlapping.len() as u64 + 8) + identity_segments;
+
+    let offset_buffers =
+        list_nonoverlapping.len() as u64 * segments_per_line * size_of::<segments::OffsetSegment>() as u64;
+
+    // the culmulative rating buffer, the single line ratings and the combined maximum of one step
+    let rating_buffers = 4 * segments_per_line * size_of::<segments::RatingSegment>() as u64;
+
+    MemoryEstimate {
+        peak_bytes: offset_buffers + rating_buffers,
+    }
 }
 
 /// Calculate the split score (see thesis in repository of source code).
@@ -217,6 +937,7 @@ fn get_nosplit_rating_iter(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::test_spans;
     use crate::{TimePoint, prepare_time_spans};
     use rand::RngCore;
 
@@ -258,6 +979,420 @@ mod tests {
         v
     }
 
+    /// The end point, rating and slope of every segment (segments can not be compared directly).
+    pub fn rating_summary(buffer: &RatingBuffer) -> Vec<(TimeDelta, Rating, RatingDelta)> {
+        buffer
+            .buffer
+            .iter()
+            .map(|segment| (segment.end_point, segment.data.rating, segment.data.delta))
+            .collect()
+    }
+
+    /// Shifts the `i`-th span by `shift(i)`.
+    fn shifted(spans: &[TimeSpan], shift: impl Fn(usize) -> i64) -> Vec<TimeSpan> {
+        spans
+            .iter()
+            .enumerate()
+            .map(|(i, &span)| span + TimeDelta::from_i64(shift(i)))
+            .collect()
+    }
+
+    #[test]
+    fn identity_bias_keeps_correct_lines_in_place() {
+        // the incorrect lines are shorter than the reference lines, so each correct line fits
+        // equally well anywhere within +-100 of its original position
+        let mut reference = Vec::new();
+        let mut list = Vec::new();
+        let mut start = 0;
+        for i in 0..40 {
+            reference.push(TimeSpan::new(TimePoint::from(start), TimePoint::from(start + 1000)));
+
+            // lines 20 to 25 are 700 too late
+            let error = if (20..26).contains(&i) { 700 } else { 0 };
+            list.push(TimeSpan::new(
+                TimePoint::from(start + 100 + error),
+                TimePoint::from(start + 900 + error),
+            ));
+
+            start += 3000 + (i * 379) % 1500;
+        }
+
+        let (unbiased_deltas, _) = align(&reference, &list, 7., None, standard_scoring, NoProgressHandler);
+        // without the bias the correct lines are shifted to an arbitrary offset of the plateau
+        assert!(unbiased_deltas.iter().any(|&delta| delta != TimeDelta::zero()));
+
+        // the speed optimization simplifies the ratings by more than the bias in later lines
+        for speed_optimization in [None, Some(3.)] {
+            let options = AlignOptions {
+                identity_weight: 0.1,
+                ..AlignOptions::new(speed_optimization, NoProgressHandler)
+            };
+            let (deltas, _) = align_with_options(&reference, &list, 7., standard_scoring, options).unwrap();
+            for (i, delta) in deltas.into_iter().enumerate() {
+                if (20..26).contains(&i) {
+                    assert!(delta >= TimeDelta::from_i64(-800) && delta <= TimeDelta::from_i64(-600));
+                } else {
+                    assert_eq!(delta, TimeDelta::zero(), "line {} with {:?}", i, speed_optimization);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn align_regions_with_different_shifts() {
+        // the lines before the break at 100000 are 1000 too late, the lines after it 3000
+        let mut reference = test_spans(20);
+        reference.extend(shifted(&test_spans(20), |_| 110000));
+        let list = shifted(&reference, |i| if i < 20 { 1000 } else { 3000 });
+
+        let (deltas, _) = align_regions(
+            &reference,
+            &list,
+            &[TimePoint::from(100000)],
+            1000.,
+            None,
+            standard_scoring,
+            NoProgressHandler,
+        );
+
+        for (i, delta) in deltas.into_iter().enumerate() {
+            let expected = if i < 20 { -1000 } else { -3000 };
+            assert_eq!(delta, TimeDelta::from_i64(expected));
+        }
+    }
+
+    #[test]
+    fn fused_references_resolve_ambiguity() {
+        // the list is 5000 too late; the audio repeats the scene 200000 later and the reference
+        // subtitle repeats it 200000 earlier, so each of them alone fits two offsets equally well
+        let scene = shifted(&test_spans(20), |_| 300000);
+        let list = shifted(&scene, |_| 5000);
+        let repeated = |shift: i64| -> Vec<TimeSpan> {
+            let mut spans = scene.clone();
+            spans.extend(shifted(&scene, |_| shift));
+            spans.sort_by_key(|span| span.start());
+            spans
+        };
+        let audio_reference = repeated(200000);
+        let subtitle_reference = repeated(-200000);
+
+        let min_distance = TimeDelta::from_i64(1000);
+        for reference in [&audio_reference, &subtitle_reference] {
+            let candidates = align_nosplit_candidates(reference, &list, 2, min_distance, standard_scoring);
+            assert_eq!(candidates[0].1, candidates[1].1);
+        }
+
+        let (deltas, score) = align_fused(
+            &audio_reference,
+            1.,
+            &subtitle_reference,
+            1.,
+            &list,
+            1000.,
+            None,
+            standard_scoring,
+            NoProgressHandler,
+        );
+        assert!(deltas.into_iter().all(|delta| delta == TimeDelta::from_i64(-5000)));
+
+        // without the subtitle reference the fused alignment is the plain alignment
+        let (audio_deltas, audio_score) = align(
+            &audio_reference,
+            &list,
+            1000.,
+            None,
+            standard_scoring,
+            NoProgressHandler,
+        );
+        assert_eq!(
+            align_fused(
+                &audio_reference,
+                1.,
+                &subtitle_reference,
+                0.,
+                &list,
+                1000.,
+                None,
+                standard_scoring,
+                NoProgressHandler,
+            ),
+            (audio_deltas, audio_score)
+        );
+        assert!(score > audio_score);
+    }
+
+    #[test]
+    fn limit_offset_changes_to_one() {
+        // the first half of the lines is 1000 too late, the second half 3000
+        let reference = test_spans(40);
+        let list = shifted(&reference, |i| if i < 20 { 1000 } else { 3000 });
+
+        let (deltas, _) = align(&reference, &list, 7., None, standard_scoring, NoProgressHandler);
+        assert_eq!(deltas[0], TimeDelta::from_i64(-1000));
+        assert_eq!(deltas[39], TimeDelta::from_i64(-3000));
+
+        let (limited_deltas, loss) = limit_offset_changes(&reference, &list, &deltas, 1, standard_scoring);
+        let (best_delta, _) = align_nosplit(&reference, &list, standard_scoring, NoProgressHandler);
+
+        assert!(limited_deltas.into_iter().all(|delta| delta == best_delta));
+        assert!(loss > 0.);
+
+        // a limit that is not exceeded keeps the deltas
+        assert_eq!(
+            limit_offset_changes(&reference, &list, &deltas, 2, standard_scoring),
+            (deltas, 0.)
+        );
+    }
+
+    #[test]
+    fn realign_only_around_changed_line() {
+        // the list is 1000 too late
+        let reference = test_spans(40);
+        let mut list = shifted(&reference, |_| 1000);
+
+        let (previous_deltas, _) = align(&reference, &list, 7., None, standard_scoring, NoProgressHandler);
+        assert!(previous_deltas.iter().all(|&delta| delta == TimeDelta::from_i64(-1000)));
+
+        // line 20 is edited to be another 300 later
+        list[20] = list[20] + TimeDelta::from_i64(300);
+
+        let deltas = realign_changed_lines(
+            &reference,
+            &list,
+            &previous_deltas,
+            &[20],
+            2,
+            7.,
+            None,
+            standard_scoring,
+        );
+
+        for (i, delta) in deltas.into_iter().enumerate() {
+            let expected = if i == 20 { -1300 } else { -1000 };
+            assert_eq!(delta, TimeDelta::from_i64(expected));
+        }
+
+        // without changed lines nothing is realigned
+        assert_eq!(
+            realign_changed_lines(&reference, &list, &previous_deltas, &[], 2, 7., None, standard_scoring),
+            previous_deltas
+        );
+    }
+
+    #[test]
+    fn nosplit_candidates_for_repeated_content() {
+        // the reference contains the same scene twice, the list is 5000 too late
+        let scene = test_spans(20);
+        let mut reference = scene.clone();
+        reference.extend(shifted(&scene, |_| 200000));
+        let list = shifted(&scene, |_| 5000);
+
+        let min_distance = TimeDelta::from_i64(1000);
+        let candidates = align_nosplit_candidates(&reference, &list, 3, min_distance, standard_scoring);
+
+        assert_eq!(candidates.len(), 3);
+        assert_eq!(candidates[0].0, TimeDelta::from_i64(-5000));
+        assert_eq!(candidates[1].0, TimeDelta::from_i64(195000));
+        assert_eq!(candidates[0].1, candidates[1].1);
+        assert!(candidates[2].1 < candidates[1].1);
+        for (i, &(a, _)) in candidates.iter().enumerate() {
+            for &(b, _) in &candidates[i + 1..] {
+                assert!((a - b).as_i64().abs() >= min_distance.as_i64());
+            }
+        }
+    }
+
+    #[test]
+    fn nosplit_bounded_ignores_far_repetition() {
+        // the reference contains the same scene twice, the list is 5000 too late
+        let mut reference = test_spans(20);
+        let mut list = shifted(&reference, |_| 5000);
+        // the second repetition has one line more, so it matches better than the first one
+        reference.extend(shifted(&test_spans(20), |_| 200000));
+        reference.push(TimeSpan::new(TimePoint::from(300000), TimePoint::from(301000)));
+        list.push(TimeSpan::new(TimePoint::from(105000), TimePoint::from(106000)));
+
+        let (unbounded_delta, _) = align_nosplit(&reference, &list, standard_scoring, NoProgressHandler);
+        assert_eq!(unbounded_delta, TimeDelta::from_i64(195000));
+
+        let (delta, score) = align_nosplit_bounded(&reference, &list, TimeDelta::from_i64(10000), standard_scoring);
+        assert_eq!(delta, TimeDelta::from_i64(-5000));
+        assert!(score > 0.);
+
+        // the bound is inclusive
+        let (delta, _) = align_nosplit_bounded(&reference, &list, TimeDelta::from_i64(5000), standard_scoring);
+        assert_eq!(delta, TimeDelta::from_i64(-5000));
+        let (delta, _) = align_nosplit_bounded(&reference, &list, TimeDelta::from_i64(4999), standard_scoring);
+        assert!(delta.as_i64().abs() <= 4999);
+    }
+
+    #[test]
+    fn nosplit_to_rating_scores_like_unit_scoring() {
+        let zero = TimePoint::from(0);
+        let unit_scoring = |a: TimeDelta, b: TimeDelta| min(a, b).as_f64();
+
+        for _ in 0..10 {
+            let (reference, list) = (
+                get_random_prepared_test_time_spans(),
+                get_random_prepared_test_time_spans(),
+            );
+            let (Some(first), Some(last)) = (reference.first(), reference.last()) else {
+                continue;
+            };
+
+            let intervals: Vec<(TimeDelta, TimeDelta)> = reference
+                .iter()
+                .map(|span| (span.start() - zero, span.end() - zero))
+                .collect();
+            let rating = RatingBuffer::from_speech_intervals(first.start() - zero, last.end() - zero, &intervals);
+
+            let (delta, score) = align_nosplit_to_rating(&rating, &list);
+            let (_, expected_score) = align_nosplit(&reference, &list, unit_scoring, NoProgressHandler);
+            assert_eq!(score, expected_score);
+
+            let shifted_list: Vec<TimeSpan> = list.iter().map(|&span| span + delta).collect();
+            let delta_score = get_nosplit_score(reference.iter().cloned(), shifted_list.into_iter(), unit_scoring);
+            assert_eq!(delta_score, score);
+        }
+    }
+
+    #[test]
+    fn word_level_speech_intervals_recover_shift_tightly() {
+        let p = TimeDelta::from_i64;
+
+        // 5 phrases, each with 4 words of length 30 which are separated by pauses of 20
+        let words: Vec<(TimeDelta, TimeDelta)> = (0..5)
+            .flat_map(|phrase| (0..4).map(move |word| 1000 + phrase * 500 + word * 50))
+            .map(|word_start| (p(word_start), p(word_start + 30)))
+            .collect();
+        // a coarse (e.g. energy based) VAD only finds the padded phrases
+        let phrases: Vec<(TimeDelta, TimeDelta)> =
+            words.chunks(4).map(|w| (w[0].0 - p(100), w[3].1 + p(100))).collect();
+
+        // the incorrect (e.g. word-level subtitle) timings are shifted by 137
+        let list: Vec<TimeSpan> = words
+            .iter()
+            .map(|&(start, end)| {
+                TimeSpan::new(
+                    TimePoint::from(start.as_i64() + 137),
+                    TimePoint::from(end.as_i64() + 137),
+                )
+            })
+            .collect();
+
+        let word_rating = RatingBuffer::from_speech_intervals(p(0), p(5000), &words);
+        let (delta, score) = align_nosplit_to_rating(&word_rating, &list);
+        assert_eq!(delta, p(-137));
+        assert_eq!(score, (words.len() * 30) as f64);
+
+        // every line lies in a phrase for a range of deltas, so the first of them is found
+        let phrase_rating = RatingBuffer::from_speech_intervals(p(0), p(5000), &phrases);
+        let (phrase_delta, phrase_score) = align_nosplit_to_rating(&phrase_rating, &list);
+        assert_eq!(phrase_score, score);
+        assert_eq!(phrase_delta, p(-237));
+    }
+
+    #[test]
+    fn align_is_deterministic() {
+        let reference = get_random_prepared_test_time_spans();
+        let list = get_random_prepared_test_time_spans();
+
+        let run = || {
+            let (deltas, score) = align(&reference, &list, 7., Some(3.), standard_scoring, NoProgressHandler);
+            (deltas, score.to_bits())
+        };
+
+        let first = run();
+        for _ in 0..100 {
+            assert_eq!(run(), first);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn align_is_deterministic_with_parallel_ratings() {
+        // every line has about four rating changepoints per reference line, so 1500 reference
+        // lines are enough to build the ratings in parallel chunks
+        let reference = test_spans(1500);
+        let list = shifted(&test_spans(40), |i| if i < 20 { 300 } else { -700 });
+
+        let run = || {
+            let (deltas, score) = align(&reference, &list, 7., Some(3.), standard_scoring, NoProgressHandler);
+            (deltas, score.to_bits())
+        };
+
+        let first = run();
+        assert_eq!(first.0[0], TimeDelta::from_i64(-300));
+        for _ in 0..5 {
+            assert_eq!(run(), first);
+        }
+    }
+
+    #[test]
+    fn memory_estimate_scales_with_duration() {
+        // 1000 subtitle lines and one voice segment every 3 seconds (in milliseconds)
+        let estimate = |hours: i64| {
+            let duration = hours * 60 * 60 * 1000;
+            let spans = |count: i64| -> Vec<TimeSpan> {
+                let distance = duration / count;
+                (0..count)
+                    .map(|i| TimeSpan::new(TimePoint::from(i * distance), TimePoint::from(i * distance + 1000)))
+                    .collect()
+            };
+            let options = AlignOptions::new(Some(3.), NoProgressHandler);
+            estimate_memory(&spans(duration / 3000), &spans(1000), &options).peak_bytes as f64
+        };
+
+        assert!(estimate(1) > 0.);
+        for hours in 2..10 {
+            let ratio = estimate(hours) / estimate(1) / hours as f64;
+            assert!(ratio > 0.9 && ratio < 1.1);
+        }
+
+        let options = AlignOptions::new(None, NoProgressHandler);
+        assert_eq!(estimate_memory(&[], &test_spans(10), &options).peak_bytes, 0);
+    }
+
+    #[test]
+    fn two_stage_alignment_scores_like_single_stage_on_drift() {
+        // the subtitle is 20000 too late and drifts another 25 per line
+        let reference = test_spans(40);
+        let list = shifted(&reference, |i| 20000 + i as i64 * 25);
+
+        let split_rating = |deltas: &[TimeDelta]| {
+            let aligned: Vec<TimeSpan> = list.iter().zip(deltas).map(|(&span, &delta)| span + delta).collect();
+            get_split_rating(&reference, &aligned, deltas, 7., standard_scoring)
+        };
+
+        let (single_stage_deltas, _) = align(&reference, &list, 7., None, standard_scoring, NoProgressHandler);
+        let (two_stage_deltas, _) = align_two_stage(&reference, &list, 7., None, standard_scoring, NoProgressHandler);
+
+        assert!(split_rating(&two_stage_deltas) >= split_rating(&single_stage_deltas) - 1e-6);
+        // the drift is followed instead of keeping the global delta
+        assert!(two_stage_deltas.windows(2).any(|w| w[0] != w[1]));
+    }
+
+    #[test]
+    fn two_stage_alignment_decides_ties_with_the_global_delta() {
+        // the incorrect lines are shorter than the reference lines, so each line fits equally well
+        // anywhere within +-100 of the global delta
+        let mut reference = Vec::new();
+        let mut list = Vec::new();
+        let mut start = 0;
+        for i in 0..40 {
+            reference.push(TimeSpan::new(TimePoint::from(start), TimePoint::from(start + 1000)));
+            list.push(TimeSpan::new(
+                TimePoint::from(start + 20100),
+                TimePoint::from(start + 20900),
+            ));
+            start += 3000 + (i * 379) % 1500;
+        }
+
+        let (deltas, _) = align_two_stage(&reference, &list, 7., None, standard_scoring, NoProgressHandler);
+        let (global_delta, _) = align_nosplit(&reference, &list, standard_scoring, NoProgressHandler);
+        assert_eq!(deltas, vec![global_delta; list.len()]);
+    }
+
     /// All test time span sequences (some are predefined some are random).
     pub fn get_test_time_spans() -> Vec<Vec<TimeSpan>> {
         (0..1000)
@@ -266,6 +1401,99 @@ mod tests {
             .collect()
     }
 
+    #[test]
+    fn low_overlap_warning() {
+        let span = |start: i64, end: i64| TimeSpan::new(TimePoint::from(start), TimePoint::from(end));
+
+        let reference = vec![span(0, 1000), span(5000, 10000)];
+        let same_content = vec![span(200, 1200), span(5200, 9000)];
+        let other_content = vec![span(9500, 12000), span(15000, 19500)];
+
+        assert_eq!(get_overlap_fraction(&reference, &same_content), 1.);
+        assert_eq!(check_overlap(&reference, &same_content), None);
+
+        assert_eq!(get_overlap_fraction(&reference, &other_content), 0.05);
+        assert_eq!(
+            check_overlap(&reference, &other_content),
+            Some(AlignWarning::LowOverlap { fraction: 0.05 })
+        );
+
+        assert_eq!(
+            check_overlap(&reference, &[]),
+            Some(AlignWarning::LowOverlap { fraction: 0. })
+        );
+    }
+
+    #[test]
+    fn insufficient_audio_reference() {
+        let span = |start: i64, end: i64| TimeSpan::new(TimePoint::from(start), TimePoint::from(end));
+        let list = vec![span(1000, 2000), span(4000, 4500)];
+
+        // no sample fills a frame, so there are no voice segments at all; a single sample (or
+        // frame) gives a voice segment without length
+        for reference in [vec![], vec![span(0, 0)]] {
+            assert_eq!(check_reference(&reference), Some(AlignWarning::InsufficientAudio));
+
+            let (deltas, score) = align(&reference, &list, 7., None, standard_scoring, NoProgressHandler);
+            assert_eq!(deltas, vec![TimeDelta::zero(); list.len()]);
+            assert_eq!(score, 0.);
+
+            let (delta, _) = align_nosplit(&reference, &list, standard_scoring, NoProgressHandler);
+            assert_eq!(delta, TimeDelta::zero());
+        }
+
+        assert_eq!(check_reference(&[span(0, 0), span(100, 900)]), None);
+    }
+
+    #[test]
+    fn offset_curves_of_both_directions_are_inverse() {
+        let span = |start: i64, end: i64| TimeSpan::new(TimePoint::from(start), TimePoint::from(end));
+
+        // the second half of the lines is 3000 later than the first half
+        let mut reference = Vec::new();
+        let mut list = Vec::new();
+        for i in 0..30 {
+            let start = i * 4000 + (i * 379) % 1500;
+            let len = 800 + (i * 137) % 700;
+            reference.push(span(start, start + len));
+
+            let list_start = if i < 15 { start - 1000 } else { start + 2000 };
+            list.push(span(list_start, list_start + len));
+        }
+
+        let (deltas, _) = align(&reference, &list, 7., None, standard_scoring, NoProgressHandler);
+        let subtitle_curve = offset_curve(&list, &deltas, Direction::ShiftSubtitle);
+        let media_curve = offset_curve(&list, &deltas, Direction::ShiftMedia);
+
+        assert_eq!(subtitle_curve.len(), 2);
+        assert_eq!(media_curve.len(), 2);
+        for (span, &delta) in list.iter().zip(&deltas) {
+            for t in [span.start(), span.end()] {
+                assert_eq!(offset_at(&subtitle_curve, t), delta);
+
+                // shifting the media back undoes shifting the subtitle
+                let shifted = t + offset_at(&subtitle_curve, t);
+                assert_eq!(shifted + offset_at(&media_curve, shifted), t);
+            }
+        }
+
+        // the first piece also covers the time before it
+        let before_first = list[0].start() - TimeDelta::from_i64(100_000);
+        assert_eq!(offset_at(&subtitle_curve, before_first), deltas[0]);
+        assert_eq!(offset_at(&[], before_first), TimeDelta::zero());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "sorted")]
+    fn offset_at_rejects_unsorted_curves() {
+        let piece = |start: i64, delta: i64| OffsetPiece {
+            start: TimePoint::from(start),
+            delta: TimeDelta::from_i64(delta),
+        };
+        offset_at(&[piece(100, 1), piece(0, 2)], TimePoint::from(50));
+    }
+
     /// All test time span sequences (some are predefined some are random).
     pub fn get_random_prepared_test_time_spans() -> Vec<TimeSpan> {
         prepare_time_spans(&generate_random_time_spans()).0
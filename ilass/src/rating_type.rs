@@ -107,7 +107,7 @@ pub use rating_i64::*;
 
 mod rating_i64 {
     use crate::{TimeDelta, TimeSpan};
-    use std::cmp::min;
+    use core::cmp::min;
 
     // these objects determine the precision/length of the rating (i32/i64) - lower
     // values take less space and time, higher values have higher precision
@@ -117,15 +117,47 @@ mod rating_i64 {
 
     const RATING_PRECISION: i64 = 1 << 32;
 
+    /// Whether the rating arithmetic below clamps at `i64::MIN`/`i64::MAX` instead of overflowing
+    /// (feature `saturating-rating`).
+    pub const SATURATING_RATINGS: bool = cfg!(feature = "saturating-rating");
+
     pub trait RatingExt {
         #[inline]
         fn add_mul(r: Rating, rd: RatingDelta, td: TimeDelta) -> Rating {
-            r + rd * td.as_i64()
+            if SATURATING_RATINGS {
+                r.saturating_add(rd.saturating_mul(td.as_i64()))
+            } else {
+                r + rd * td.as_i64()
+            }
+        }
+
+        /// Like `add_mul`, but returns `None` if the result does not fit into a `Rating` (instead
+        /// of wrapping around in release builds).
+        #[inline]
+        fn checked_add_mul(r: Rating, rd: RatingDelta, td: TimeDelta) -> Option<Rating> {
+            rd.checked_mul(td.as_i64()).and_then(|product| r.checked_add(product))
         }
 
         #[inline]
         fn add_mul_usize(r: Rating, rd: RatingDelta, td: usize) -> Rating {
-            r + rd * td as i64
+            if SATURATING_RATINGS {
+                r.saturating_add(rd.saturating_mul(td as i64))
+            } else {
+                r + rd * td as i64
+            }
+        }
+
+        /// `a + b` for ratings and rating deltas, which should be used wherever two curves are
+        /// combined (see `SATURATING_RATINGS`).
+        #[inline]
+        fn add_ratings(a: Rating, b: Rating) -> Rating {
+            if SATURATING_RATINGS { a.saturating_add(b) } else { a + b }
+        }
+
+        /// `a - b` for ratings and rating deltas (see `add_ratings`).
+        #[inline]
+        fn sub_ratings(a: Rating, b: Rating) -> Rating {
+            if SATURATING_RATINGS { a.saturating_sub(b) } else { a - b }
         }
 
         #[inline]
@@ -149,7 +181,11 @@ mod rating_i64 {
 
         #[inline]
         fn div_by_delta_to_i64(r: Rating, other: RatingDelta) -> i64 {
-            r / other
+            if SATURATING_RATINGS {
+                r.saturating_div(other)
+            } else {
+                r / other
+            }
         }
 
         fn as_readable_f64(&self) -> f64;
@@ -1,12 +1,26 @@
-use crate::rating_type::{Rating, RatingDelta, RatingDeltaDelta, RatingExt};
+//! The piecewise linear rating and offset curves the aligner is built from, and the iterators
+//! that combine them segment by segment.
+
+use crate::rating_type::{Rating, RatingDelta, RatingDeltaDelta, RatingExt, SATURATING_RATINGS};
 use crate::time_types::TimeDelta;
-use std::cmp::min;
-use std::iter::once;
-use std::ops::Add;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::iter::{Peekable, once};
+use core::ops::Add;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// The number of segments after which `SegmentIterator::stop_when_set` reads the cancel flag again.
+const CANCEL_POLL_INTERVAL: usize = 1 << 10;
 
 type Point = TimeDelta;
 type PointDiff = TimeDelta; // Pos - Pos
 
+/// A non-empty, half-open span of points: `start` is part of the span, `end` is not.
+///
+/// All predicates below use these half-open semantics, so two spans where one ends where the
+/// other starts touch but do not overlap. (Where the crate needs the last point inside of a span,
+/// it uses `end - 1` explicitly.)
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub struct PointSpan {
     pub start: Point,
@@ -17,21 +31,61 @@ pub struct PointSpan {
 type Offset = Point;
 
 impl PointSpan {
-    fn new(start: Point, end: Point) -> PointSpan {
+    /// Creates a non-empty span from `start` (inclusive) to `end` (exclusive).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `start` is not less than `end`.
+    pub fn new(start: Point, end: Point) -> PointSpan {
         assert!(start < end);
         PointSpan { start, end }
     }
 
-    fn len(self) -> PointDiff {
+    /// Like `new`, but returns an error instead of panicking for an empty span.
+    pub fn try_new(start: Point, end: Point) -> Result<PointSpan, SegmentError> {
+        if start < end {
+            Ok(PointSpan { start, end })
+        } else {
+            Err(SegmentError::EmptySpan { start, end })
+        }
+    }
+
+    /// The number of points in the span.
+    pub fn len(self) -> PointDiff {
         self.end - self.start
     }
 
-    fn half(self) -> Point {
+    /// The point in the middle of the span (rounded towards zero).
+    pub fn half(self) -> Point {
         Point::from_i64((self.end.as_i64() + self.start.as_i64()) / 2)
     }
+
+    /// Returns `true` if `start <= p < end`.
+    pub fn contains(&self, p: Point) -> bool {
+        self.start <= p && p < self.end
+    }
+
+    /// Returns `true` if both spans have at least one point in common.
+    pub fn overlaps(&self, other: PointSpan) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+
+    /// Returns the points both spans have in common, or `None` if they do not overlap (which
+    /// includes spans that only touch).
+    pub fn intersect(&self, other: PointSpan) -> Option<PointSpan> {
+        if self.overlaps(other) {
+            Some(PointSpan {
+                start: core::cmp::max(self.start, other.start),
+                end: min(self.end, other.end),
+            })
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OffsetInfo {
     pub offset: Point,
     pub drag: bool, // if true, offset has a "delta" of 1; if false it has a delta of 0
@@ -99,6 +153,7 @@ impl OffsetInfo {
 }
 
 #[derive(Default, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RatingInfo {
     pub rating: Rating,
     pub delta: RatingDelta,
@@ -154,6 +209,35 @@ pub struct DualInfo {
 }
 
 impl DualInfo {
+    #[inline]
+    pub fn new(rating_info: RatingInfo, offset_info: OffsetInfo) -> DualInfo {
+        DualInfo {
+            offset_info,
+            rating_info,
+        }
+    }
+
+    #[inline]
+    pub fn rating_info(&self) -> RatingInfo {
+        self.rating_info
+    }
+
+    #[inline]
+    pub fn offset_info(&self) -> OffsetInfo {
+        self.offset_info
+    }
+
+    #[inline]
+    fn negated_rating(self) -> DualInfo {
+        DualInfo {
+            offset_info: self.offset_info,
+            rating_info: RatingInfo {
+                rating: -self.rating_info.rating,
+                delta: -self.rating_info.delta,
+            },
+        }
+    }
+
     #[inline]
     fn advanced(self, len: TimeDelta) -> DualInfo {
         DualInfo {
@@ -175,7 +259,149 @@ impl Add<RatingInfo> for RatingInfo {
     }
 }
 
+/// Errors of `RatingBuffer::concat`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ConcatError {
+    /// The second buffer starts after the end of the first buffer.
+    Gap { end: Point, start: Point },
+
+    /// The second buffer starts before the end of the first buffer.
+    Overlap { end: Point, start: Point },
+}
+
+impl core::fmt::Display for ConcatError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ConcatError::Gap { end, start } => write!(f, "gap between buffer end {} and next start {}", end, start),
+            ConcatError::Overlap { end, start } => write!(f, "buffer end {} overlaps next start {}", end, start),
+        }
+    }
+}
+
+impl core::error::Error for ConcatError {}
+
+/// Errors of `PointSpan::try_new`, `DifferentialRatingBufferBuilder` and
+/// `SegmentIterator::try_annotate_with_segment_start_points`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SegmentError {
+    /// A span or segment would not contain any point.
+    EmptySpan { start: Point, end: Point },
+
+    /// A segment was added that ends before the previous segment.
+    NonMonotonicEndPoint { previous_end: Point, end: Point },
+
+    /// A segment was added that ends after the end of the buffer.
+    OutOfRange { end_point: Point, end: Point },
+
+    /// The buffer was built before its segments reached its end.
+    Incomplete { end_point: Point, end: Point },
+
+    /// The accumulated rating (or its slope) does not fit into a `Rating` at the segment ending at
+    /// `end_point`.
+    RatingOverflow { end_point: Point },
+}
+
+impl core::fmt::Display for SegmentError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            SegmentError::EmptySpan { start, end } => write!(f, "span from {} to {} is empty", start, end),
+            SegmentError::NonMonotonicEndPoint { previous_end, end } => write!(
+                f,
+                "segment ends at {} before the previous segment ends at {}",
+                end, previous_end
+            ),
+            SegmentError::OutOfRange { end_point, end } => {
+                write!(f, "segment ends at {} after the buffer end {}", end_point, end)
+            }
+            SegmentError::Incomplete { end_point, end } => {
+                write!(f, "segments end at {} before the buffer end {}", end_point, end)
+            }
+            SegmentError::RatingOverflow { end_point } => {
+                write!(f, "rating overflows in the segment ending at {}", end_point)
+            }
+        }
+    }
+}
+
+impl core::error::Error for SegmentError {}
+
+/// A problem of a segment buffer that was found by `RatingBuffer::validate`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum BufferDefect {
+    /// The first segment does not end after the start of the buffer.
+    StartNotBeforeFirstEnd { start: Point, first_end: Point },
+
+    /// The segment at `index` ends where the segment before it ends.
+    ZeroLengthSegment { index: usize, end: Point },
+
+    /// The segment at `index` ends before the segment before it.
+    NonMonotonicEndPoint {
+        index: usize,
+        previous_end: Point,
+        end: Point,
+    },
+}
+
+impl core::fmt::Display for BufferDefect {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            BufferDefect::StartNotBeforeFirstEnd { start, first_end } => {
+                write!(
+                    f,
+                    "first segment ends at {} but the buffer starts at {}",
+                    first_end, start
+                )
+            }
+            BufferDefect::ZeroLengthSegment { index, end } => {
+                write!(f, "segment {} ending at {} has no length", index, end)
+            }
+            BufferDefect::NonMonotonicEndPoint {
+                index,
+                previous_end,
+                end,
+            } => write!(
+                f,
+                "segment {} ends at {} before the previous segment ends at {}",
+                index, end, previous_end
+            ),
+        }
+    }
+}
+
+/// Selects the point `RatingBuffer::maximum_with` returns if several points have the maximal rating.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum MaxTiePolicy {
+    /// The earliest point with the maximal rating.
+    #[default]
+    First,
+
+    /// The latest point with the maximal rating.
+    Last,
+
+    /// The middle of the first run of consecutive points with the maximal rating (rounded down),
+    /// which keeps the result away from the edges of a flat top.
+    Central,
+}
+
+/// Selects the fill of `RatingIterator::extend_to_with_policy`.
+///
+/// The aligner extends the shifted rating of the previous lines with zeros (the rating is missing
+/// where the previous lines would be moved behind the end of the reference). It doesn't matter
+/// which fill it uses: the best split offsets are the left-to-right maximum of the extended rating,
+/// which is at least the last rating before the fill, so with either policy a line in the tail keeps
+/// the best offset found before it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum TailPolicy {
+    /// The fill holds the rating of the last point.
+    HoldLastOffset,
+
+    /// The fill is rated zero (like `extend_to`).
+    #[default]
+    DecayToZero,
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RatingBuffer {
     pub start: Point,
     pub buffer: Vec<RatingSegment>,
@@ -191,13 +417,127 @@ impl RatingBuffer {
     }
 
     #[inline]
-    pub fn iter(&self) -> RatingIterator<std::iter::Cloned<impl Iterator<Item = &RatingSegment>>> {
+    pub fn iter(&self) -> RatingIterator<core::iter::Cloned<impl Iterator<Item = &RatingSegment>>> {
         RatingIterator::<_> {
             start: self.start,
             iter: self.buffer.iter().cloned(),
         }
     }
 
+    /// Builds a rectangular rating from `start` to `end`, which is one during the given speech
+    /// intervals (e.g. word timestamps from a forced alignment tool) and zero otherwise.
+    ///
+    /// The intervals are `(start, end)` pairs. They may be unsorted or overlap each other and are
+    /// clipped to `start..end`.
+    ///
+    /// At every point this is the rating the `Aligner` computes over all offsets for a span of a
+    /// single tick against reference spans at the intervals (with one rating per overlapping tick).
+    /// `align_nosplit_to_rating` aligns subtitle lines against it.
+    pub fn from_speech_intervals(start: Point, end: Point, intervals: &[(Point, Point)]) -> RatingBuffer {
+        assert!(start < end);
+
+        let mut intervals: Vec<(Point, Point)> = intervals
+            .iter()
+            .map(|&(interval_start, interval_end)| (interval_start.max(start), interval_end.min(end)))
+            .filter(|&(interval_start, interval_end)| interval_start < interval_end)
+            .collect();
+        intervals.sort();
+
+        let speech = RatingInfo::constant(Rating::convert_from_f64(1.));
+        let silence = RatingInfo::constant(Rating::zero());
+
+        let mut buffer: Vec<RatingSegment> = Vec::new();
+        let mut push = |end_point: Point, data: RatingInfo| match buffer.last_mut() {
+            Some(last) if last.data.rating == data.rating => last.end_point = end_point,
+            _ => buffer.push(RatingSegment { end_point, data }),
+        };
+
+        let mut current = start;
+        for (interval_start, interval_end) in intervals {
+            if interval_end <= current {
+                continue;
+            }
+            if current < interval_start {
+                push(interval_start, silence);
+            }
+            push(interval_end, speech);
+            current = interval_end;
+        }
+        if current < end {
+            push(end, silence);
+        }
+
+        RatingBuffer { start, buffer }
+    }
+
+    /// Appends `other`, which has to start exactly where this buffer ends.
+    ///
+    /// If the rating continues seamlessly over the boundary (same delta and no jump), the two
+    /// boundary segments are merged into one.
+    pub fn concat(mut self, other: RatingBuffer) -> Result<RatingBuffer, ConcatError> {
+        let end = self.end().unwrap_or(self.start);
+        if other.start > end {
+            return Err(ConcatError::Gap {
+                end,
+                start: other.start,
+            });
+        }
+        if other.start < end {
+            return Err(ConcatError::Overlap {
+                end,
+                start: other.start,
+            });
+        }
+
+        let mut other_segments = other.buffer.into_iter();
+
+        let last_start = if self.buffer.len() >= 2 {
+            self.buffer[self.buffer.len() - 2].end_point
+        } else {
+            self.start
+        };
+        if let (Some(last), Some(first)) = (self.buffer.last_mut(), other_segments.as_slice().first())
+            && last.data.delta == first.data.delta
+            && last.data.exclusive_end_rating(last.end_point - last_start) == first.data.rating
+        {
+            last.end_point = first.end_point;
+            other_segments.next();
+        }
+
+        self.buffer.extend(other_segments);
+        Ok(self)
+    }
+
+    /// Checks the buffer for all defects that would trip the assertions of the segment
+    /// operations (e.g. for buffers that were built by hand) and returns every one of them.
+    pub fn validate(&self) -> Result<(), Vec<BufferDefect>> {
+        let mut defects = Vec::new();
+
+        let mut previous_end = self.start;
+        for (index, segment) in self.buffer.iter().enumerate() {
+            let end = segment.end_point;
+            if index == 0 {
+                if end <= self.start {
+                    defects.push(BufferDefect::StartNotBeforeFirstEnd {
+                        start: self.start,
+                        first_end: end,
+                    });
+                }
+            } else if end == previous_end {
+                defects.push(BufferDefect::ZeroLengthSegment { index, end });
+            } else if end < previous_end {
+                defects.push(BufferDefect::NonMonotonicEndPoint {
+                    index,
+                    previous_end,
+                    end,
+                });
+            }
+            previous_end = end;
+        }
+
+        if defects.is_empty() { Ok(()) } else { Err(defects) }
+    }
+
     #[inline]
     pub fn first_end_point(&self) -> Option<Point> {
         self.buffer.first().map(|rating_segment| rating_segment.end_point)
@@ -213,29 +553,178 @@ impl RatingBuffer {
         self.start
     }
 
+    /// Returns the rating at `t`, which has to be inside of the buffer. The segment containing `t`
+    /// is found by binary search over the end points (like `OffsetBuffer::get_offset_at`).
+    #[inline]
+    pub fn get_rating_at(&self, t: Point) -> Rating {
+        assert!(t >= self.start && t < self.end().unwrap());
+
+        // the first segment that ends after `t`
+        let index = self.buffer.partition_point(|segment| segment.end_point <= t);
+        let segment_start = if index == 0 {
+            self.start
+        } else {
+            self.buffer[index - 1].end_point
+        };
+
+        self.buffer[index].data.get_at(t - segment_start)
+    }
+
+    /// Returns the ratings at `start`, `start + step`, `start + 2 * step`, ... and the last point of
+    /// the buffer (`end - 1`), so the samples always cover the whole buffer. The buffer is walked
+    /// once for all samples. An empty buffer has no samples.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `step` is not positive.
+    pub fn sample(&self, step: TimeDelta) -> impl Iterator<Item = (Point, Rating)> + '_ {
+        assert!(step > TimeDelta::zero(), "the sample step has to be positive");
+
+        let start = self.start;
+        let points = self.end().into_iter().flat_map(move |end| {
+            let last_point = end - TimeDelta::one();
+            core::iter::successors(Some(start), move |&point| Some(point + step))
+                .take_while(move |&point| point < last_point)
+                .chain(once(last_point))
+        });
+
+        let mut cursor = RatingCursor::new(self);
+        points.map(move |point| (point, cursor.rating_at(self, point)))
+    }
+
+    /// Like `sample`, but the ratings are converted to the scale of the scores (e.g. for plotting
+    /// them), on which a line that perfectly matches the reference is rated `1`.
+    pub fn sample_readable(&self, step: TimeDelta) -> impl Iterator<Item = (Point, f64)> + '_ {
+        self.sample(step)
+            .map(|(point, rating)| (point, rating.as_readable_f64()))
+    }
+
+    /// Returns the maximal rating and the first point where it is reached. Ratings below zero are
+    /// not reported: if there is no point with a rating of at least zero, `(0, start)` is returned.
     #[inline]
     pub fn maximum(&self) -> (Rating, Point) {
-        let (max, max_point, _) = self.buffer.iter().fold(
-            (Rating::zero(), self.start, self.start),
-            #[inline]
-            |(current_max, current_max_point, segment_start): (Rating, Point, Point), segment: &RatingSegment| {
-                assert!(segment_start < segment.end_point);
-                let start_rating = segment.start_rating();
-                let end_rating = segment.end_rating(segment.end_point - segment_start);
-
-                if start_rating > current_max {
-                    (start_rating, segment_start, segment.end_point)
-                } else if end_rating > current_max {
-                    (start_rating, segment.end_point - TimeDelta::one(), segment.end_point)
+        self.maximum_with(MaxTiePolicy::First)
+    }
+
+    /// Like `maximum`, but the `tie_policy` selects the point if several points have the maximal
+    /// rating.
+    #[inline]
+    pub fn maximum_with(&self, tie_policy: MaxTiePolicy) -> (Rating, Point) {
+        segments_maximum(self.start, self.buffer.iter().copied(), tie_policy)
+    }
+
+    /// Returns the minimal rating and the first point where it is reached, or `(0, start)` for an
+    /// empty buffer. Like in `maximum`, the last point of a segment is `end_point - 1`.
+    #[inline]
+    pub fn minimum(&self) -> (Rating, Point) {
+        let mut minimum: Option<(Rating, Point)> = None;
+        let mut segment_start = self.start;
+        for segment in &self.buffer {
+            assert!(segment_start < segment.end_point);
+
+            // a falling segment is smallest at its last point, all others at their first point
+            let candidate = if segment.data.delta < RatingDelta::zero() {
+                (
+                    segment.end_rating(segment.end_point - segment_start),
+                    segment.end_point - TimeDelta::one(),
+                )
+            } else {
+                (segment.start_rating(), segment_start)
+            };
+
+            match minimum {
+                Some((rating, _)) if rating <= candidate.0 => {}
+                _ => minimum = Some(candidate),
+            }
+
+            segment_start = segment.end_point;
+        }
+
+        minimum.unwrap_or((Rating::zero(), self.start))
+    }
+}
+
+/// Returns the maximal rating of consecutive segments starting at `start` and the point where it
+/// is reached (see `RatingBuffer::maximum`).
+fn segments_maximum(
+    start: Point,
+    segments: impl Iterator<Item = RatingSegment>,
+    tie_policy: MaxTiePolicy,
+) -> (Rating, Point) {
+    struct Maximum {
+        rating: Rating,
+        first: Point,
+        last: Point,
+
+        // the first run of consecutive points with the maximal rating (inclusive), and whether
+        // it reaches the end of the current segment so the next segment can extend it
+        run_start: Point,
+        run_end: Point,
+        run_open: bool,
+    }
+
+    let mut maximum: Option<Maximum> = None;
+    let mut segment_start = start;
+    for segment in segments {
+        assert!(segment_start < segment.end_point);
+        let last_point = segment.end_point - TimeDelta::one();
+
+        // the largest rating in this segment and the first and last point where it is reached
+        let (rating, first, last) = if segment.data.delta > RatingDelta::zero() {
+            (
+                segment.end_rating(segment.end_point - segment_start),
+                last_point,
+                last_point,
+            )
+        } else if segment.data.delta < RatingDelta::zero() {
+            (segment.start_rating(), segment_start, segment_start)
+        } else {
+            (segment.start_rating(), segment_start, last_point)
+        };
+
+        match &mut maximum {
+            Some(current) if rating < current.rating => current.run_open = false,
+            Some(current) if rating == current.rating => {
+                current.last = last;
+                if current.run_open && first == segment_start {
+                    current.run_end = last;
+                    current.run_open = last == last_point;
                 } else {
-                    (current_max, current_max_point, segment.end_point)
+                    current.run_open = false;
                 }
-            },
-        );
-        (max, max_point)
+            }
+            None if rating < Rating::zero() => {}
+            _ => {
+                maximum = Some(Maximum {
+                    rating,
+                    first,
+                    last,
+                    run_start: first,
+                    run_end: last,
+                    run_open: last == last_point,
+                })
+            }
+        }
+
+        segment_start = segment.end_point;
+    }
+
+    match maximum {
+        None => (Rating::zero(), start),
+        Some(maximum) => {
+            let point = match tie_policy {
+                MaxTiePolicy::First => maximum.first,
+                MaxTiePolicy::Last => maximum.last,
+                MaxTiePolicy::Central => {
+                    maximum.run_start + TimeDelta::from_i64((maximum.run_end - maximum.run_start).as_i64() / 2)
+                }
+            };
+            (maximum.rating, point)
+        }
     }
 }
 
+#[derive(Debug)]
 pub struct DifferentialRatingBufferBuilder {
     start: TimeDelta,
     end: TimeDelta,
@@ -243,9 +732,20 @@ pub struct DifferentialRatingBufferBuilder {
 }
 
 impl DifferentialRatingBufferBuilder {
+    /// Creates a builder for the segments from `start` (inclusive) to `end` (exclusive).
     #[inline]
-    pub fn new(start: Point, end: Point) -> DifferentialRatingBufferBuilder {
-        assert!(start < end);
+    pub fn new(start: Point, end: Point) -> Result<DifferentialRatingBufferBuilder, SegmentError> {
+        if start >= end {
+            return Err(SegmentError::EmptySpan { start, end });
+        }
+
+        Ok(Self::new_unchecked(start, end))
+    }
+
+    /// Like `new`, but the caller has to guarantee that `start < end`.
+    #[inline]
+    pub fn new_unchecked(start: Point, end: Point) -> DifferentialRatingBufferBuilder {
+        debug_assert!(start < end);
 
         DifferentialRatingBufferBuilder {
             start,
@@ -254,17 +754,53 @@ impl DifferentialRatingBufferBuilder {
         }
     }
 
+    /// Adds a segment ending at `segment_end`. Segments have to be added in the order of their end
+    /// points; a segment ending where the previous one ends is merged into it.
+    #[inline]
+    pub fn add_segment(
+        &mut self,
+        segment_end: Point,
+        segment_end_delta_delta: RatingDeltaDelta,
+    ) -> Result<(), SegmentError> {
+        match self.buffer.last() {
+            Some(last_segment) if segment_end < last_segment.end_point => {
+                return Err(SegmentError::NonMonotonicEndPoint {
+                    previous_end: last_segment.end_point,
+                    end: segment_end,
+                });
+            }
+            None if segment_end <= self.start => {
+                return Err(SegmentError::EmptySpan {
+                    start: self.start,
+                    end: segment_end,
+                });
+            }
+            _ => {}
+        }
+
+        if segment_end > self.end {
+            return Err(SegmentError::OutOfRange {
+                end_point: segment_end,
+                end: self.end,
+            });
+        }
+
+        self.add_segment_unchecked(segment_end, segment_end_delta_delta);
+        Ok(())
+    }
+
+    /// Like `add_segment`, but the caller has to guarantee the order of the segments.
     #[inline]
-    pub fn add_segment(&mut self, segment_end: Point, segment_end_delta_delta: RatingDeltaDelta) {
+    pub fn add_segment_unchecked(&mut self, segment_end: Point, segment_end_delta_delta: RatingDeltaDelta) {
         if let Some(last_segment) = self.buffer.last_mut() {
-            assert!(last_segment.end_point <= segment_end);
+            debug_assert!(last_segment.end_point <= segment_end);
 
             if last_segment.end_point == segment_end {
                 last_segment.data += segment_end_delta_delta;
                 return;
             }
         } else {
-            assert!(self.start < segment_end);
+            debug_assert!(self.start < segment_end);
         }
 
         self.buffer.push(Segment {
@@ -274,67 +810,210 @@ impl DifferentialRatingBufferBuilder {
     }
 
     #[inline]
-    pub fn extend_to_end(&mut self) {
+    pub fn extend_to_end(&mut self) -> Result<(), SegmentError> {
         self.add_segment(self.end, RatingDeltaDelta::zero())
     }
 
+    /// Fails if the segments do not reach the end of the buffer (see `extend_to_end`) or if the
+    /// accumulated rating would overflow (unless the `saturating-rating` feature clamps it).
     #[inline]
-    pub fn build(self) -> DifferentialRatingBuffer {
-        DifferentialRatingBuffer {
-            start: self.start,
-            buffer: self.buffer,
+    pub fn build(self) -> Result<DifferentialRatingBuffer, SegmentError> {
+        let end_point = self.buffer.last().map_or(self.start, |segment| segment.end_point);
+        if end_point != self.end {
+            return Err(SegmentError::Incomplete {
+                end_point,
+                end: self.end,
+            });
+        }
+
+        // the ratings are accumulated right away, so the overflow check needs no extra pass (with
+        // saturating ratings `advance_rating` clamps instead)
+        let mut segments: Vec<RatingSegment> = Vec::with_capacity(self.buffer.len());
+        let (mut rating, mut delta, mut last_segment_end) = (Rating::zero(), RatingDelta::zero(), self.start);
+        for segment in &self.buffer {
+            segments.push(RatingSegment {
+                end_point: segment.end_point,
+                data: RatingInfo { rating, delta },
+            });
+            (rating, delta) = advance_rating(rating, delta, last_segment_end, segment)?;
+            last_segment_end = segment.end_point;
         }
+
+        Ok(DifferentialRatingBuffer {
+            ratings: RatingBuffer {
+                start: self.start,
+                buffer: segments,
+            },
+        })
     }
 }
 
+/// The rating accumulated by `DifferentialRatingBufferBuilder::build`.
+#[derive(Debug)]
 pub struct DifferentialRatingBuffer {
-    start: TimeDelta,
-    buffer: Vec<Segment<RatingDeltaDelta>>,
+    ratings: RatingBuffer,
 }
 
 impl DifferentialRatingBuffer {
     #[inline]
     pub fn into_rating_iter(self) -> RatingIterator<impl Iterator<Item = RatingSegment>> {
-        struct ScanState {
-            rating: Rating,
-            delta: RatingDelta,
-            last_segment_end: TimeDelta,
-        }
-
-        let start_state = ScanState {
-            rating: Rating::zero(),
-            delta: RatingDelta::zero(),
-            last_segment_end: self.start,
-        };
-
-        let iter = self.buffer.into_iter().scan(
-            start_state,
-            #[inline]
-            |state: &mut ScanState, segment: Segment<RatingDeltaDelta>| {
-                let result = Segment {
-                    end_point: segment.end_point,
-                    data: RatingInfo {
-                        rating: state.rating,
-                        delta: state.delta,
-                    },
-                };
-
-                state.rating = Rating::add_mul(state.rating, state.delta, segment.end_point - state.last_segment_end);
-                state.delta += segment.data;
-                state.last_segment_end = segment.end_point;
+        self.ratings.into_iter()
+    }
 
-                Some(result)
-            },
-        );
+    #[inline]
+    pub fn into_rating_buffer(self) -> RatingBuffer {
+        self.ratings
+    }
+}
 
-        RatingIterator::<_> {
-            start: self.start,
-            iter,
+/// Builds the same rating as adding the sorted `timepoints` (segment end and delta-delta, see
+/// `DifferentialRatingBufferBuilder::add_segment`) to a builder from `start` to `end`, but builds
+/// the segments of about `chunk_len` consecutive timepoints concurrently (feature `rayon`).
+///
+/// Every chunk only covers the range from the last end point of the previous chunk (or `start`)
+/// to its own last end point (or `end` for the last chunk), and timepoints with the same end point
+/// are never split between two chunks. The rating and slope at the start of each chunk follow from
+/// the rating gain and slope change of all previous chunks, so the concatenated chunks are exactly
+/// the segments of the sequential builder. The rating gain is computed from a zero start, so a chunk
+/// can fail with `SegmentError::RatingOverflow` even if the complete rating would not overflow.
+#[cfg(feature = "rayon")]
+pub fn par_build_rating_buffer(
+    start: Point,
+    end: Point,
+    timepoints: &[(Point, RatingDeltaDelta)],
+    chunk_len: usize,
+) -> Result<RatingBuffer, SegmentError> {
+    use rayon::prelude::*;
+
+    assert!(chunk_len > 0, "the chunk length has to be positive");
+    DifferentialRatingBufferBuilder::new(start, end)?;
+
+    let mut chunk_starts = vec![0];
+    let mut split = chunk_len;
+    while split < timepoints.len() {
+        if timepoints[split].0 == timepoints[split - 1].0 {
+            split += 1;
+        } else {
+            chunk_starts.push(split);
+            split += chunk_len;
         }
     }
+    let chunk_ranges: Vec<(usize, usize)> = chunk_starts
+        .iter()
+        .zip(chunk_starts.iter().skip(1).chain(Some(&timepoints.len())))
+        .map(|(&first, &last)| (first, last))
+        .collect();
+
+    let chunks: Vec<DifferentialRatingBufferBuilder> = chunk_ranges
+        .par_iter()
+        .map(|&(first, last)| {
+            let mut builder = DifferentialRatingBufferBuilder {
+                start: if first == 0 { start } else { timepoints[first - 1].0 },
+                end,
+                buffer: Vec::with_capacity(last - first + 1),
+            };
+            // the builder only checks the order within the chunk
+            if first > 0 && timepoints[first].0 < builder.start {
+                return Err(SegmentError::NonMonotonicEndPoint {
+                    previous_end: builder.start,
+                    end: timepoints[first].0,
+                });
+            }
+            for &(segment_end, segment_end_delta_delta) in &timepoints[first..last] {
+                builder.add_segment(segment_end, segment_end_delta_delta)?;
+            }
+            if last == timepoints.len() {
+                builder.extend_to_end()?;
+            }
+            Ok(builder)
+        })
+        .collect::<Result<_, _>>()?;
+
+    // the rating gain and slope change of every chunk on its own
+    let chunk_changes: Vec<(Rating, RatingDelta)> = chunks
+        .par_iter()
+        .map(|chunk| {
+            let (mut rating, mut delta, mut last_segment_end) = (Rating::zero(), RatingDelta::zero(), chunk.start);
+            for segment in &chunk.buffer {
+                (rating, delta) = advance_rating(rating, delta, last_segment_end, segment)?;
+                last_segment_end = segment.end_point;
+            }
+            Ok((rating, delta))
+        })
+        .collect::<Result<_, _>>()?;
+
+    let mut chunk_start_states = Vec::with_capacity(chunks.len());
+    let (mut rating, mut delta) = (Rating::zero(), RatingDelta::zero());
+    for (chunk, (rating_gain, delta_change)) in chunks.iter().zip(chunk_changes) {
+        chunk_start_states.push((rating, delta));
+        let chunk_end = Segment {
+            end_point: chunk.buffer.last().map_or(chunk.start, |segment| segment.end_point),
+            data: delta_change,
+        };
+        (rating, delta) = advance_rating(rating, delta, chunk.start, &chunk_end)?;
+        rating = if SATURATING_RATINGS {
+            Rating::add_ratings(rating, rating_gain)
+        } else {
+            rating.checked_add(rating_gain).ok_or(SegmentError::RatingOverflow {
+                end_point: chunk_end.end_point,
+            })?
+        };
+    }
+
+    let chunk_ratings: Vec<Vec<RatingSegment>> = chunks
+        .into_par_iter()
+        .zip(chunk_start_states)
+        .map(|(chunk, (mut rating, mut delta))| {
+            let mut last_segment_end = chunk.start;
+            chunk
+                .buffer
+                .iter()
+                .map(|segment| {
+                    let result = Segment {
+                        end_point: segment.end_point,
+                        data: RatingInfo { rating, delta },
+                    };
+                    (rating, delta) = advance_rating(rating, delta, last_segment_end, segment)?;
+                    last_segment_end = segment.end_point;
+                    Ok(result)
+                })
+                .collect::<Result<_, _>>()
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(RatingBuffer {
+        start,
+        buffer: chunk_ratings.concat(),
+    })
+}
+
+/// The rating and slope after `segment` (which starts at `last_segment_end`), or
+/// `SegmentError::RatingOverflow` (unless the `saturating-rating` feature clamps them).
+#[inline]
+fn advance_rating(
+    rating: Rating,
+    delta: RatingDelta,
+    last_segment_end: Point,
+    segment: &Segment<RatingDeltaDelta>,
+) -> Result<(Rating, RatingDelta), SegmentError> {
+    if SATURATING_RATINGS {
+        return Ok((
+            Rating::add_mul(rating, delta, segment.end_point - last_segment_end),
+            Rating::add_ratings(delta, segment.data),
+        ));
+    }
+
+    let overflow = SegmentError::RatingOverflow {
+        end_point: segment.end_point,
+    };
+    Ok((
+        Rating::checked_add_mul(rating, delta, segment.end_point - last_segment_end).ok_or(overflow)?,
+        delta.checked_add(segment.data).ok_or(overflow)?,
+    ))
 }
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Segment<D> {
     pub end_point: Point,
     pub data: D,
@@ -378,6 +1057,7 @@ impl<D> FullSegment<D> {
 }
 
 #[derive(Debug)]
+#[must_use = "iterator adapters are lazy and do nothing unless consumed"]
 pub struct FullSegmentIterator<D, I>
 where
     I: Iterator<Item = FullSegment<D>>,
@@ -390,12 +1070,34 @@ impl<D, I> FullSegmentIterator<D, I>
 where
     I: Iterator<Item = FullSegment<D>>,
 {
+    /// Creates an iterator over consecutive segments, the first of which starts at `start`.
+    pub fn new(start: TimeDelta, iter: I) -> Self {
+        FullSegmentIterator { start, iter }
+    }
+
     pub fn into_iter(self) -> I {
         self.iter
     }
 }
 
+/// Consecutive segments, the first of which starts at `start`.
+///
+/// The adapters (like `shift_simple`) are lazy and only return a new iterator, so dropping their
+/// result without consuming it is a mistake:
+///
+/// ```compile_fail
+/// #![deny(unused_must_use)]
+/// use ilass::TimeDelta;
+/// use ilass::segments::RatingBuffer;
+///
+/// let ratings = RatingBuffer {
+///     start: TimeDelta::zero(),
+///     buffer: Vec::new(),
+/// };
+/// ratings.iter().shift_simple(TimeDelta::one());
+/// ```
 #[derive(Debug)]
+#[must_use = "iterator adapters are lazy and do nothing unless consumed"]
 pub struct SegmentIterator<D, I>
 where
     I: Iterator<Item = Segment<D>>,
@@ -408,6 +1110,8 @@ impl<D, I> SegmentIterator<D, I>
 where
     I: Iterator<Item = Segment<D>>,
 {
+    /// Panics if an end point is not larger than the previous one (see
+    /// `try_annotate_with_segment_start_points` to detect this instead).
     #[inline]
     pub fn annotate_with_segment_start_points(self) -> FullSegmentIterator<D, impl Iterator<Item = FullSegment<D>>> {
         FullSegmentIterator::<D, _> {
@@ -416,14 +1120,12 @@ where
                 self.start,
                 #[inline]
                 |last_segment_end: &mut Point, segment: Segment<D>| {
-                    if *last_segment_end >= segment.end_point {
-                        // DEBUG TODO: remove
-                        println!(
-                            "ANNOTATE segment start {}   segment end {}",
-                            *last_segment_end, segment.end_point
-                        );
-                    }
-                    assert!(*last_segment_end < segment.end_point);
+                    assert!(
+                        *last_segment_end < segment.end_point,
+                        "segment end point {} is not after the previous end point {}",
+                        segment.end_point,
+                        *last_segment_end
+                    );
 
                     let result = Some(FullSegment {
                         span: PointSpan::new(*last_segment_end, segment.end_point),
@@ -437,36 +1139,79 @@ where
         }
     }
 
+    /// Ends early once `cancel_flag` is set. The flag is read before the first and then every
+    /// `CANCEL_POLL_INTERVAL`th segment, so the consumer has to check the flag again afterwards to
+    /// tell a cancelled iteration from a complete one.
+    #[inline]
+    pub fn stop_when_set(
+        self,
+        cancel_flag: Option<&AtomicBool>,
+    ) -> SegmentIterator<D, impl Iterator<Item = Segment<D>>> {
+        SegmentIterator::<D, _> {
+            start: self.start,
+            iter: self
+                .iter
+                .enumerate()
+                .take_while(move |&(i, _)| {
+                    i % CANCEL_POLL_INTERVAL != 0 || !cancel_flag.is_some_and(|flag| flag.load(Ordering::Relaxed))
+                })
+                .map(|(_, segment)| segment),
+        }
+    }
+
+    /// Like `annotate_with_segment_start_points`, but yields a
+    /// `SegmentError::NonMonotonicEndPoint` (and then ends) instead of panicking if an end point is
+    /// not larger than the previous one.
+    #[inline]
+    pub fn try_annotate_with_segment_start_points(self) -> impl Iterator<Item = Result<FullSegment<D>, SegmentError>> {
+        self.iter.scan(
+            Some(self.start),
+            #[inline]
+            |last_segment_end: &mut Option<Point>, segment: Segment<D>| {
+                let previous_end = (*last_segment_end)?;
+                if previous_end >= segment.end_point {
+                    *last_segment_end = None;
+                    return Some(Err(SegmentError::NonMonotonicEndPoint {
+                        previous_end,
+                        end: segment.end_point,
+                    }));
+                }
+
+                *last_segment_end = Some(segment.end_point);
+                Some(Ok(FullSegment {
+                    span: PointSpan::new(previous_end, segment.end_point),
+                    data: segment.data,
+                }))
+            },
+        )
+    }
+
     #[inline]
     pub fn into_iter(self) -> I {
         self.iter
     }
 
+    /// Shifts the start and all end points by `t`.
+    ///
+    /// The points saturate at the smallest/largest representable time point instead of
+    /// wrapping around. Segments that would lie completely beyond that bound are dropped, so the
+    /// shifted segments stay non-empty and ordered even for pathological shifts.
     #[inline]
     pub fn shift(self, t: TimeDelta) -> SegmentIterator<D, impl Si<D>> {
+        let start = self.start.saturating_add(t);
         SegmentIterator::<D, _> {
-            start: self.start + t,
-            iter: self.iter.map(
-                #[inline]
-                move |mut segment: Segment<D>| {
-                    segment.end_point += t;
-                    segment
-                },
-            ),
+            start,
+            iter: saturating_shift_end_points(self.iter, start, t),
         }
     }
 
+    /// Shifts all end points (but not the start) by `t`, saturating like `shift`. Segments that
+    /// would end before the start afterwards are dropped.
     #[inline]
     pub fn shift_simple(self, t: TimeDelta) -> SegmentIterator<D, impl Si<D>> {
         SegmentIterator::<D, _> {
             start: self.start,
-            iter: self.iter.map(
-                #[inline]
-                move |mut segment: Segment<D>| {
-                    segment.end_point += t;
-                    segment
-                },
-            ),
+            iter: saturating_shift_end_points(self.iter, self.start, t),
         }
     }
 
@@ -477,6 +1222,18 @@ where
             iter: self.iter.chain(once(Segment::<D> { end_point, data })),
         }
     }
+
+    /// Yields every segment together with its predecessor, so `n` segments yield `n - 1` pairs.
+    #[inline]
+    pub fn windows2(self) -> impl Iterator<Item = (Segment<D>, Segment<D>)>
+    where
+        D: Copy,
+    {
+        Windows2Iterator {
+            previous: None,
+            iter: self.iter,
+        }
+    }
 }
 
 impl<D, I: Sfi<D>> FullSegmentIterator<D, I> {
@@ -490,6 +1247,18 @@ impl<D, I: Sfi<D>> FullSegmentIterator<D, I> {
             }),
         }
     }
+
+    /// Yields every segment together with its predecessor, like `SegmentIterator::windows2`.
+    #[inline]
+    pub fn windows2(self) -> impl Iterator<Item = (FullSegment<D>, FullSegment<D>)>
+    where
+        D: Copy,
+    {
+        Windows2Iterator {
+            previous: None,
+            iter: self.iter,
+        }
+    }
 }
 
 pub type OffsetSegment = Segment<OffsetInfo>;
@@ -579,6 +1348,14 @@ impl DualSegment {
 pub type RatingFullSegment = FullSegment<RatingInfo>;
 pub type DualFullSegment = FullSegment<DualInfo>;
 impl DualFullSegment {
+    #[inline]
+    pub fn new(span: PointSpan, rating_info: RatingInfo, offset_info: OffsetInfo) -> DualFullSegment {
+        DualFullSegment {
+            span,
+            data: DualInfo::new(rating_info, offset_info),
+        }
+    }
+
     #[inline]
     pub fn start_rating(&self) -> Rating {
         self.data.rating_info.rating
@@ -609,6 +1386,28 @@ impl DualFullSegment {
     }
 }
 
+/// Shifts the end points of `iter` by `t` (saturating) and drops the segments that do not end
+/// after the previous segment (or `start`) anymore.
+#[inline]
+fn saturating_shift_end_points<D>(
+    iter: impl Iterator<Item = Segment<D>>,
+    start: Point,
+    t: TimeDelta,
+) -> impl Iterator<Item = Segment<D>> {
+    let mut previous_end = start;
+    iter.filter_map(
+        #[inline]
+        move |mut segment: Segment<D>| {
+            segment.end_point = segment.end_point.saturating_add(t);
+            if segment.end_point <= previous_end {
+                return None;
+            }
+            previous_end = segment.end_point;
+            Some(segment)
+        },
+    )
+}
+
 pub trait Si<D>: Iterator<Item = Segment<D>> {}
 impl<T, D> Si<D> for T where T: Iterator<Item = Segment<D>> {}
 
@@ -672,21 +1471,25 @@ impl<I: Di> DualIterator<I> {
         }
     }
 
+    /// Saves the ratings aggressively simplified (see `SimplifyTolerance`) and the offsets
+    /// losslessly simplified into separate buffers.
     #[inline]
-    pub fn save_separate(self, epsilon: RatingDelta) -> SeparateDualBuffer {
+    pub fn save_separate(self, tolerance: SimplifyTolerance) -> SeparateDualBuffer {
         let (rating_buffer, offset_buffer): (Vec<RatingSegment>, Vec<OffsetSegment>) = into_push_iter(
             self.iter,
             dual_push_iter(
-                only_ratings_push_iter(simplify_ratings_push_iter(
+                only_ratings_push_iter(simplify_push_iter(
                     self.start,
+                    LosslessSimplifier,
                     discard_start_times_push_iter(aggressive_simplify_ratings_push_iter(
                         self.start,
-                        epsilon,
+                        tolerance,
                         discard_start_times_push_iter(collect_to_vec_push_iter()),
                     )),
                 )),
-                only_offsets_push_iter(simplify_offsets_push_iter(
+                only_offsets_push_iter(simplify_push_iter(
                     self.start,
+                    LosslessSimplifier,
                     discard_start_times_push_iter(collect_to_vec_push_iter()),
                 )),
             ),
@@ -729,12 +1532,28 @@ impl<I: Di> DualIterator<I> {
     }
 
     #[inline]
-    pub fn simplify(mut self) -> DualFullSegmentIterator<impl Dfi> {
+    pub fn simplify(self) -> DualFullSegmentIterator<impl Dfi> {
+        self.simplify_with(LosslessSimplifier)
+    }
+
+    /// Like `simplify`, but stays a `DualIterator`, e.g. to simplify segments before `save`.
+    ///
+    /// Neighbouring segments are merged if they have the same rating delta and drag flag and the
+    /// next one continues where the previous one ends (see `LosslessSimplifier`).
+    #[inline]
+    pub fn simplify_stream(self) -> DualIterator<impl Di> {
+        self.simplify().discard_start_times()
+    }
+
+    /// Like `simplify`, but neighbouring segments are merged as decided by `simplifier`.
+    #[inline]
+    pub fn simplify_with<S: Simplifier<DualInfo>>(mut self, simplifier: S) -> DualFullSegmentIterator<impl Dfi> {
         DualFullSegmentIterator {
             start: self.start,
             iter: DualSimplifyIterator {
                 current_segment: self.iter.next().map(|seg| seg.with_start_point(self.start)),
                 iter: self.iter,
+                simplifier,
             },
         }
     }
@@ -760,13 +1579,41 @@ impl<I: Ri> RatingIterator<I> {
             buffer: self.iter.collect(),
         }
     }
+    /// Merges neighbouring segments which describe the same linear function (see
+    /// `LosslessSimplifier`). The merged segment continues the linear function of all its parts, so
+    /// whether a segment is merged only depends on its predecessor.
     #[inline]
     pub fn save_simplified(self) -> RatingBuffer {
+        let start = self.start;
+        let mut segments = self.iter.peekable();
+        let mut buffer: Vec<RatingSegment> = segments.peek().copied().into_iter().collect();
+
+        let pairs = RatingIterator { start, iter: segments }
+            .annotate_with_segment_start_points()
+            .windows2();
+        for (previous, next) in pairs {
+            let next = next.discard_start_time();
+            match buffer.last_mut() {
+                Some(last) if LosslessSimplifier.should_merge(&previous, &next) => last.end_point = next.end_point,
+                _ => buffer.push(next),
+            }
+        }
+
+        RatingBuffer { start, buffer }
+    }
+
+    /// Like `save_simplified`, but neighbouring segments are merged as decided by `simplifier`.
+    #[inline]
+    pub fn save_simplified_with<S: Simplifier<RatingInfo>>(self, simplifier: S) -> RatingBuffer {
         RatingBuffer {
             start: self.start,
             buffer: into_push_iter(
                 self.iter,
-                simplify_ratings_push_iter(self.start, discard_start_times_push_iter(collect_to_vec_push_iter())),
+                simplify_push_iter(
+                    self.start,
+                    simplifier,
+                    discard_start_times_push_iter(collect_to_vec_push_iter()),
+                ),
             ),
         }
     }
@@ -776,30 +1623,108 @@ impl<I: Ri> RatingIterator<I> {
             start: self.start,
             iter: ExtendToIterator {
                 input_iter: self.iter,
-                data_to_extend: Some(RatingInfo {
-                    rating: Rating::zero(),
-                    delta: RatingDelta::zero(),
-                }),
+                data_to_extend: Some(RatingInfo::constant(Rating::zero())),
+                next_data_to_extend: |_: &RatingSegment| RatingInfo::constant(Rating::zero()),
+                end_point,
+            },
+        }
+    }
+
+    /// Like `extend_to`, but the fill segment is chosen by the `TailPolicy`.
+    #[inline]
+    pub fn extend_to_with_policy(self, end_point: Point, tail_policy: TailPolicy) -> RatingIterator<impl Ri> {
+        let mut segment_start = self.start;
+        RatingIterator {
+            start: self.start,
+            iter: ExtendToIterator {
+                input_iter: self.iter,
+                data_to_extend: Some(RatingInfo::constant(Rating::zero())),
+                next_data_to_extend: move |segment: &RatingSegment| {
+                    let end_rating = segment.data.end_rating(segment.end_point - segment_start);
+                    segment_start = segment.end_point;
+                    match tail_policy {
+                        TailPolicy::HoldLastOffset => RatingInfo::constant(end_rating),
+                        TailPolicy::DecayToZero => RatingInfo::constant(Rating::zero()),
+                    }
+                },
                 end_point,
             },
         }
     }
 
+    /// Smooths the rating with a moving average over `window` points. The window of the point `t`
+    /// is `t - window / 2 .. t - window / 2 + window`, so a step becomes a ramp of the window width
+    /// which is centered on the step.
+    ///
+    /// The average of a point is the sum of the ratings of the points in its window divided by
+    /// `window` (rounded towards zero). Outside of the iterated range the rating counts as zero, so
+    /// the average falls off within half a window of both ends. The result covers the same range as
+    /// the input.
+    ///
+    /// The average is computed per phase (the points for which both ends of the window stay in the
+    /// same input segments), not per point, and only the input segments within the current window
+    /// are kept. Within a phase the window sum changes by the difference of the ratings at both ends
+    /// of the window, so the average is linear where both ends lie in segments with the same slope
+    /// (e.g. everywhere for a piecewise constant rating). Otherwise, within half a window of a
+    /// change of the slope, it is a parabola, of which the result is the line through the averages
+    /// of the first and the last point of the phase. The averages are rounded at the start of each
+    /// phase, so within a phase they may differ from the pointwise rounded ones by the rounding
+    /// error of the slope. Consecutive phases on the same line form one segment.
+    pub fn moving_average(self, window: TimeDelta) -> RatingIterator<impl Ri> {
+        assert!(window > TimeDelta::zero());
+
+        let leading_distance = window - TimeDelta::from_i64(window.as_i64() / 2);
+        RatingIterator {
+            start: self.start,
+            iter: MovingAverageIterator {
+                input_iter: self.iter,
+                input_end: self.start,
+                input_done: false,
+                window_segments: VecDeque::new(),
+                start: self.start,
+                window,
+                leading_distance,
+                // the whole window lies in front of the input, so its sum is zero
+                point: self.start - leading_distance,
+                sum: 0,
+                pending: None,
+            },
+        }
+    }
+
+    /// Merges neighbouring segments into single lines, as far as `tolerance` allows.
     #[inline]
-    pub fn save_aggressively_simplified(self, epsilon: RatingDelta) -> RatingBuffer {
+    pub fn save_aggressively_simplified(self, tolerance: SimplifyTolerance) -> RatingBuffer {
         RatingBuffer {
             start: self.start,
             buffer: into_push_iter(
                 self.iter,
                 aggressive_simplify_ratings_push_iter(
                     self.start,
-                    epsilon,
+                    tolerance,
                     discard_start_times_push_iter(collect_to_vec_push_iter()),
                 ),
             ),
         }
     }
 
+    /// Like `save_aggressively_simplified`, but also reports how much the curve was simplified
+    /// and how far the result deviates from it (to tune the `tolerance`).
+    pub fn save_aggressively_simplified_with_report(
+        self,
+        tolerance: SimplifyTolerance,
+    ) -> (RatingBuffer, SimplifyReport) {
+        let original = self.save();
+        let simplified = original.iter().save_aggressively_simplified(tolerance);
+
+        let report = SimplifyReport {
+            input_segments: original.buffer.len(),
+            output_segments: simplified.buffer.len(),
+            max_introduced_error: max_rating_difference(&original, &simplified),
+        };
+        (simplified, report)
+    }
+
     #[inline]
     pub fn add_rating(self, rating_delta: RatingDelta) -> RatingIterator<impl Ri> {
         RatingIterator::<_> {
@@ -830,18 +1755,144 @@ impl<I: Ri> RatingIterator<I> {
             }),
         }
     }
+
+    /// Like `RatingBuffer::maximum_with`, but consumes the segments in one pass without saving them
+    /// into a buffer first (for pipelines that only need the peak).
+    #[inline]
+    pub fn running_maximum(self, tie_policy: MaxTiePolicy) -> (Rating, Point) {
+        segments_maximum(self.start, self.iter, tie_policy)
+    }
+
+    /// Keeps only the first `n` segments (e.g. for a quick preview of the beginning). Unlike the
+    /// simplifications, nothing is merged: the result ends where the `n`-th segment ends.
+    #[inline]
+    pub fn truncate_to_segments(self, n: usize) -> RatingIterator<impl Ri> {
+        RatingIterator::<_> {
+            start: self.start,
+            iter: self.iter.take(n),
+        }
+    }
+
+    /// Caps all ratings at `max`. Segments that cross `max` are split into a linear part
+    /// and a constant part at `max`.
+    #[inline]
+    pub fn clamp_rating(self, max: Rating) -> RatingIterator<impl Ri> {
+        RatingIterator {
+            start: self.start,
+            iter: ClampRatingIterator {
+                input_iter: self.iter,
+                max,
+                segment_start: self.start,
+                stored_segment: None,
+            },
+        }
+    }
+}
+
+struct ClampRatingIterator<I>
+where
+    I: Ri,
+{
+    input_iter: I,
+    max: Rating,
+    segment_start: Point,
+    stored_segment: Option<RatingSegment>,
+}
+
+impl<I: Ri> Iterator for ClampRatingIterator<I> {
+    type Item = RatingSegment;
+
+    #[inline]
+    fn next(&mut self) -> Option<RatingSegment> {
+        if let Some(stored_segment) = self.stored_segment.take() {
+            return Some(stored_segment);
+        }
+
+        let segment = self.input_iter.next()?;
+        let segment_start = self.segment_start;
+        self.segment_start = segment.end_point;
+
+        let len = segment.end_point - segment_start;
+        let start_rating = segment.data.start_rating();
+        let end_rating = segment.data.end_rating(len);
+        let delta = segment.data.delta;
+
+        let capped = RatingInfo::constant(self.max);
+
+        if start_rating <= self.max && end_rating <= self.max {
+            Some(segment)
+        } else if start_rating >= self.max && end_rating >= self.max {
+            Some(RatingSegment {
+                end_point: segment.end_point,
+                data: capped,
+            })
+        } else if start_rating < self.max {
+            // rising segment: `switch` is the first point above `max`
+            let switch = TimeDelta::from_i64(RatingDelta::div_by_delta_to_i64(self.max - start_rating, delta) + 1);
+            assert!(TimeDelta::zero() < switch && switch < len);
+
+            self.stored_segment = Some(RatingSegment {
+                end_point: segment.end_point,
+                data: capped,
+            });
+            Some(RatingSegment {
+                end_point: segment_start + switch,
+                data: segment.data,
+            })
+        } else {
+            // falling segment: `switch` is the first point at or below `max`
+            let switch = TimeDelta::from_i64(RatingDelta::div_by_delta_to_i64(
+                start_rating - self.max - delta - 1,
+                -delta,
+            ));
+            assert!(TimeDelta::zero() < switch && switch < len);
+
+            self.stored_segment = Some(RatingSegment {
+                end_point: segment.end_point,
+                data: segment.data.advanced(switch),
+            });
+            Some(RatingSegment {
+                end_point: segment_start + switch,
+                data: capped,
+            })
+        }
+    }
 }
 
-struct ExtendToIterator<T, I>
+struct Windows2Iterator<T, I> {
+    previous: Option<T>,
+    iter: I,
+}
+
+impl<T: Copy, I: Iterator<Item = T>> Iterator for Windows2Iterator<T, I> {
+    type Item = (T, T);
+
+    #[inline]
+    fn next(&mut self) -> Option<(T, T)> {
+        let previous = match self.previous {
+            Some(previous) => previous,
+            None => self.iter.next()?,
+        };
+        let current = self.iter.next()?;
+        self.previous = Some(current);
+        Some((previous, current))
+    }
+}
+
+struct ExtendToIterator<T, I, F>
 where
     I: Si<T>,
+    F: FnMut(&Segment<T>) -> T,
 {
     input_iter: I,
     data_to_extend: Option<T>,
+
+    /// Returns the data to extend with if the given segment is the last of the input iterator
+    next_data_to_extend: F,
     end_point: Point,
 }
 
-impl<T, I: Si<T>> Iterator for ExtendToIterator<T, I> {
+impl<T, I: Si<T>, F: FnMut(&Segment<T>) -> T> Iterator for ExtendToIterator<T, I, F> {
     type Item = Segment<T>;
 
     #[inline]
@@ -849,9 +1900,11 @@ impl<T, I: Si<T>> Iterator for ExtendToIterator<T, I> {
         match self.input_iter.next() {
             Some(s) => {
                 assert!(s.end_point <= self.end_point);
-                if s.end_point == self.end_point {
-                    self.data_to_extend.take();
-                }
+                self.data_to_extend = if s.end_point == self.end_point {
+                    None
+                } else {
+                    Some((self.next_data_to_extend)(&s))
+                };
                 Some(s)
             }
             None => match self.data_to_extend.take() {
@@ -865,6 +1918,283 @@ impl<T, I: Si<T>> Iterator for ExtendToIterator<T, I> {
     }
 }
 
+/// How far the aggressive simplifications (`save_aggressively_simplified` and `save_separate`)
+/// may move the rating.
+///
+/// Neighbouring segments are merged into one line through the center (the pivot) of the first of
+/// them, as long as the rating at the first and last point of every merged segment stays within
+/// the tolerance of that line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SimplifyTolerance {
+    kind: ToleranceKind,
+    exact_span: Option<PointSpan>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ToleranceKind {
+    Absolute(RatingDelta),
+    Relative(f64),
+}
+
+impl SimplifyTolerance {
+    /// Allows a deviation of `epsilon` from the original rating.
+    pub fn from_rating_delta(epsilon: RatingDelta) -> SimplifyTolerance {
+        SimplifyTolerance {
+            kind: ToleranceKind::Absolute(epsilon),
+            exact_span: None,
+        }
+    }
+
+    /// Allows a deviation of `fraction` times the (absolute) rating at each point, so regions with
+    /// small ratings keep their detail while regions with large ratings are still simplified. A
+    /// fraction of `0` only merges segments which continue the same line.
+    pub fn relative(fraction: f64) -> SimplifyTolerance {
+        assert!(fraction >= 0.);
+        SimplifyTolerance {
+            kind: ToleranceKind::Relative(fraction),
+            exact_span: None,
+        }
+    }
+
+    /// Keeps the ratings in `span` exact, so a narrow peak there does not get flattened into its
+    /// neighbourhood.
+    pub fn exact_within(self, span: PointSpan) -> SimplifyTolerance {
+        SimplifyTolerance {
+            exact_span: Some(span),
+            ..self
+        }
+    }
+
+    /// The maximum deviation from `rating` at `point`.
+    #[inline]
+    fn max_diff_at(self, point: Point, rating: Rating) -> RatingDelta {
+        if self.exact_span.is_some_and(|span| span.contains(point)) {
+            return RatingDelta::zero();
+        }
+        match self.kind {
+            ToleranceKind::Absolute(epsilon) => epsilon,
+            ToleranceKind::Relative(fraction) => (rating.abs() as f64 * fraction) as RatingDelta,
+        }
+    }
+}
+
+/// The result of `save_aggressively_simplified_with_report`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SimplifyReport {
+    /// Number of segments before the simplification.
+    pub input_segments: usize,
+
+    /// Number of segments after the simplification.
+    pub output_segments: usize,
+
+    /// The largest difference between the original and the simplified rating at any point.
+    pub max_introduced_error: Rating,
+}
+
+/// Returns the largest difference between the ratings of `a` and `b` at any point inside of
+/// them. Both are piecewise linear, so only the first and last point of every segment of either
+/// buffer have to be compared.
+fn max_rating_difference(a: &RatingBuffer, b: &RatingBuffer) -> Rating {
+    let mut points: Vec<Point> = Vec::with_capacity(2 * (a.buffer.len() + b.buffer.len()));
+    for rating_buffer in [a, b] {
+        let mut segment_start = rating_buffer.start;
+        for segment in &rating_buffer.buffer {
+            if segment_start < segment.end_point {
+                points.push(segment_start);
+                points.push(segment.end_point - TimeDelta::one());
+            }
+            segment_start = segment.end_point;
+        }
+    }
+    points.sort();
+    points.dedup();
+
+    let (mut cursor_a, mut cursor_b) = (RatingCursor::new(a), RatingCursor::new(b));
+    points
+        .into_iter()
+        .map(|point| (cursor_a.rating_at(a, point) - cursor_b.rating_at(b, point)).abs())
+        .max()
+        .unwrap_or_else(Rating::zero)
+}
+
+/// Reads the ratings of a buffer at increasing points (zero outside of the buffer).
+struct RatingCursor {
+    index: usize,
+    segment_start: Point,
+}
+
+impl RatingCursor {
+    fn new(buffer: &RatingBuffer) -> RatingCursor {
+        RatingCursor {
+            index: 0,
+            segment_start: buffer.start,
+        }
+    }
+
+    /// Returns the rating at `point`, which must not be smaller than in the previous call.
+    fn rating_at(&mut self, buffer: &RatingBuffer, point: Point) -> Rating {
+        if point < buffer.start {
+            return Rating::zero();
+        }
+        while self.index < buffer.buffer.len() && buffer.buffer[self.index].end_point <= point {
+            self.segment_start = buffer.buffer[self.index].end_point;
+            self.index += 1;
+        }
+        match buffer.buffer.get(self.index) {
+            Some(segment) => Rating::add_mul(segment.data.rating, segment.data.delta, point - self.segment_start),
+            None => Rating::zero(),
+        }
+    }
+}
+
+struct MovingAverageIterator<I: Ri> {
+    input_iter: I,
+
+    /// End point of the last segment taken from the input iterator
+    input_end: Point,
+    input_done: bool,
+
+    /// The input segments from the one at the trailing end of the window to the one at its leading end
+    window_segments: VecDeque<RatingFullSegment>,
+
+    start: Point,
+    window: TimeDelta,
+
+    /// The leading end of the window of `point` is `point + leading_distance` (exclusive)
+    leading_distance: TimeDelta,
+
+    /// The first point of the next phase; `sum` is the sum of its window
+    point: Point,
+    sum: i128,
+
+    /// The averaged points that might be continued by the next phase
+    pending: Option<RatingFullSegment>,
+}
+
+impl<I: Ri> MovingAverageIterator<I> {
+    /// The rating and slope of the input at `point`, and the number of points from `point` to the
+    /// end of its segment (`None` behind the end of the input).
+    fn edge(&self, point: Point) -> (Rating, RatingDelta, Option<TimeDelta>) {
+        let zero = (Rating::zero(), RatingDelta::zero());
+        let ((rating, delta), len) = match self.window_segments.iter().find(|segment| point < segment.span.end) {
+            Some(segment) if point < segment.span.start => (zero, Some(segment.span.start - point)),
+            Some(segment) => (
+                (
+                    Rating::add_mul(segment.data.rating, segment.data.delta, point - segment.span.start),
+                    segment.data.delta,
+                ),
+                Some(segment.span.end - point),
+            ),
+            None if self.input_done => (zero, None),
+            None => (zero, Some(self.start - point)),
+        };
+        (rating, delta, len)
+    }
+
+    /// Averages the points of the next phase.
+    fn next_phase(&mut self) -> Option<RatingFullSegment> {
+        loop {
+            let leading = self.point + self.leading_distance;
+            let trailing = leading - self.window;
+
+            while !self.input_done && leading >= self.input_end {
+                match self.input_iter.next() {
+                    Some(segment) => {
+                        self.window_segments.push_back(FullSegment {
+                            span: PointSpan::new(self.input_end, segment.end_point),
+                            data: segment.data,
+                        });
+                        self.input_end = segment.end_point;
+                    }
+                    None => self.input_done = true,
+                }
+            }
+            if self.input_done && self.point >= self.input_end {
+                return None;
+            }
+            while self
+                .window_segments
+                .front()
+                .is_some_and(|segment| segment.span.end <= trailing)
+            {
+                self.window_segments.pop_front();
+            }
+
+            let (leading_rating, leading_delta, leading_len) = self.edge(leading);
+            let (trailing_rating, trailing_delta, trailing_len) = self.edge(trailing);
+            let output_len = if self.point < self.start {
+                self.start - self.point
+            } else {
+                self.input_end - self.point
+            };
+            let len = [leading_len, trailing_len]
+                .into_iter()
+                .flatten()
+                .fold(output_len, min)
+                .as_i64() as i128;
+
+            // the window sum `k` points after the start of the phase
+            let (rating_difference, delta_difference) = (
+                leading_rating as i128 - trailing_rating as i128,
+                leading_delta as i128 - trailing_delta as i128,
+            );
+            let sum_after = |k: i128| self.sum + k * rating_difference + delta_difference * k * (k - 1) / 2;
+
+            let window = self.window.as_i64() as i128;
+            let phase = FullSegment {
+                span: PointSpan::new(self.point, self.point + TimeDelta::from_i64(len as i64)),
+                data: RatingInfo {
+                    rating: (self.sum / window) as Rating,
+                    delta: if len > 1 {
+                        ((sum_after(len - 1) - self.sum) / (len - 1) / window) as RatingDelta
+                    } else {
+                        RatingDelta::zero()
+                    },
+                },
+            };
+
+            self.sum = sum_after(len);
+            self.point = phase.span.end;
+            if phase.span.start >= self.start {
+                return Some(phase);
+            }
+        }
+    }
+}
+
+impl<I: Ri> Iterator for MovingAverageIterator<I> {
+    type Item = RatingSegment;
+
+    fn next(&mut self) -> Option<RatingSegment> {
+        loop {
+            let Some(phase) = self.next_phase() else {
+                return self.pending.take().map(|segment| RatingSegment {
+                    end_point: segment.span.end,
+                    data: segment.data,
+                });
+            };
+
+            match &mut self.pending {
+                Some(pending)
+                    if pending.data.delta == phase.data.delta
+                        && Rating::add_mul(pending.data.rating, pending.data.delta, pending.span.len())
+                            == phase.data.rating =>
+                {
+                    pending.span.end = phase.span.end;
+                }
+                pending => {
+                    if let Some(segment) = pending.replace(phase) {
+                        return Some(RatingSegment {
+                            end_point: segment.span.end,
+                            data: segment.data,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
 struct LeftToRightMaximumIterator<I>
 where
     I: Dfi,
@@ -877,6 +2207,9 @@ where
     current_best_timepoint: Point,
 
     stored_segment: Option<DualFullSegment>,
+
+    /// The next segment of `next_unmerged()`, which could not be merged into the previous one
+    pending_segment: Option<DualFullSegment>,
 }
 
 impl<I: Dfi> LeftToRightMaximumIterator<I> {
@@ -887,9 +2220,25 @@ impl<I: Dfi> LeftToRightMaximumIterator<I> {
             current_best_rating: Rating::zero(),
             current_best_timepoint: start,
             stored_segment: None,
+            pending_segment: None,
         }
     }
 
+    /// Whether both segments are constant with the same rating and offset, so `next` can be
+    /// appended to `previous`.
+    #[inline]
+    fn mergeable(previous: &DualFullSegment, next: &DualFullSegment) -> bool {
+        let is_constant = |segment: &DualFullSegment| {
+            segment.data.rating_info.delta == RatingDelta::zero() && !segment.data.offset_info.drag
+        };
+
+        is_constant(previous)
+            && is_constant(next)
+            && previous.span.end == next.span.start
+            && previous.data.rating_info.rating == next.data.rating_info.rating
+            && previous.data.offset_info.offset == next.data.offset_info.offset
+    }
+
     #[inline]
     fn constant_dual_info(&self) -> DualInfo {
         DualInfo {
@@ -910,13 +2259,30 @@ impl<I: Dfi> LeftToRightMaximumIterator<I> {
 impl<I: Dfi> Iterator for LeftToRightMaximumIterator<I> {
     type Item = DualFullSegment;
 
-    #[inline] // XXX: is this really faster?
+    /// Merges the constant segments of `next_unmerged()` while the maximum stays flat.
+    #[inline]
     fn next(&mut self) -> Option<DualFullSegment> {
+        let mut result = self.pending_segment.take().or_else(|| self.next_unmerged())?;
+
+        while let Some(segment) = self.next_unmerged() {
+            if !Self::mergeable(&result, &segment) {
+                self.pending_segment = Some(segment);
+                break;
+            }
+            result.span = PointSpan::new(result.span.start, segment.span.end);
+        }
+
+        Some(result)
+    }
+}
+
+impl<I: Dfi> LeftToRightMaximumIterator<I> {
+    #[inline] // XXX: is this really faster?
+    fn next_unmerged(&mut self) -> Option<DualFullSegment> {
         if let Some(stored_segment) = self.stored_segment.take() {
             return Some(stored_segment);
         }
 
-        // TODO: unify segments when possible
         let segment: DualFullSegment = self.input_iter.next()?;
 
         let segment_start_rating = segment.start_rating();
@@ -985,6 +2351,7 @@ impl<I: Dfi> Iterator for LeftToRightMaximumIterator<I> {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OffsetBuffer {
     start: Point,
     buffer: Vec<OffsetSegment>,
@@ -1000,7 +2367,7 @@ impl OffsetBuffer {
     }
 
     #[inline]
-    pub fn iter(&self) -> OffsetIterator<std::iter::Cloned<impl Iterator<Item = &OffsetSegment>>> {
+    pub fn iter(&self) -> OffsetIterator<core::iter::Cloned<impl Iterator<Item = &OffsetSegment>>> {
         OffsetIterator::<_> {
             start: self.start,
             iter: self.buffer.iter().cloned(),
@@ -1012,6 +2379,11 @@ impl OffsetBuffer {
         self.buffer.len()
     }
 
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
     #[inline]
     pub fn end_point(&self) -> Option<Point> {
         self.buffer.last().map(|rating_segment| rating_segment.end_point)
@@ -1041,64 +2413,92 @@ impl OffsetBuffer {
         last_segment.data.end_offset(last_segment.end_point - segment_start)
     }
 
+    /// Returns the offset at `t`, which has to be inside of the buffer. The segment containing `t`
+    /// is found by binary search over the end points.
     #[inline]
     pub fn get_offset_at(&self, t: Point) -> Offset {
         assert!(t >= self.start && t < self.end_point().unwrap());
-        let mut segment_start = self.start;
 
-        for segment in &self.buffer {
-            if t >= segment_start && t < segment.end_point {
-                return segment.data.advanced_offset(t - segment_start);
-            }
-            segment_start = segment.end_point;
-        }
+        // the first segment that ends after `t`
+        let index = self.buffer.partition_point(|segment| segment.end_point <= t);
+        let segment_start = if index == 0 {
+            self.start
+        } else {
+            self.buffer[index - 1].end_point
+        };
 
-        unreachable!()
+        self.buffer[index].data.advanced_offset(t - segment_start)
     }
 
-    /*#[inline]
-    pub fn maximum(&self) -> Point {
-        let state: (Offset, Point) = (self.buffer.first().unwrap().start_offset(), self.start);
+    /// Subtracts the offset at `anchor` from all offsets, so the curve is zero at `anchor` and
+    /// every other offset is relative to it. Dragging segments keep dragging (only their starting
+    /// offset moves), so the shape of the curve does not change.
+    ///
+    /// `anchor` has to be inside of the buffer (like for `get_offset_at`).
+    pub fn rebase(&self, anchor: Point) -> OffsetBuffer {
+        let anchor_offset = self.get_offset_at(anchor);
 
-        self.buffer
-            .iter()
-            .fold(
-                state,
-                #[inline]
-                |(current_max, segment_start): (Offset, Point), segment: &OffsetSegment| {
-                    assert!(segment_start < segment.end_point);
-                    let start_rating: TimePoint = segment.start_offset();
-                    let end_rating: TimePoint = segment.end_offset(segment.end_point - segment_start);
+        OffsetBuffer {
+            start: self.start,
+            buffer: self
+                .buffer
+                .iter()
+                .map(|segment| OffsetSegment {
+                    end_point: segment.end_point,
+                    data: OffsetInfo {
+                        offset: segment.data.offset - anchor_offset,
+                        drag: segment.data.drag,
+                    },
+                })
+                .collect(),
+        }
+    }
 
-                    let new_max = max(max(current_max, start_rating), end_rating);
+    /// Returns every point where the offset curve jumps, together with the offset the previous segment
+    /// would have continued with (`from`) and the offset the next segment starts with (`to`).
+    pub fn discontinuities(&self) -> Vec<(Point, Offset, Offset)> {
+        self.iter()
+            .annotate_with_segment_start_points()
+            .windows2()
+            .filter(|(previous, next)| previous.exclusive_end_offset() != next.start_offset())
+            .map(|(previous, next)| (next.span.start, previous.exclusive_end_offset(), next.start_offset()))
+            .collect()
+    }
 
-                    (new_max, segment.end_point)
-                },
-            )
-            .0
+    /// The largest offset of the whole buffer (for a dragging segment this is the offset at its
+    /// last point).
+    #[inline]
+    pub fn maximum(&self) -> Offset {
+        self.extreme_offset(core::cmp::max)
+    }
+
+    /// The smallest offset of the whole buffer.
+    #[inline]
+    pub fn minimum(&self) -> Offset {
+        self.extreme_offset(min)
     }
 
     #[inline]
-    pub fn minimum(&self) -> Point {
-        let state: (TimePoint, TimePoint) = (self.buffer.first().unwrap().start_offset(), self.start);
+    fn extreme_offset(&self, pick: impl Fn(Offset, Offset) -> Offset) -> Offset {
+        assert!(!self.buffer.is_empty());
+
+        let state: (Offset, Point) = (self.buffer[0].start_offset(), self.start);
 
         self.buffer
             .iter()
             .fold(
                 state,
                 #[inline]
-                |(current_max, segment_start): (TimePoint, TimePoint), segment: &OffsetSegment| {
+                |(current_extreme, segment_start): (Offset, Point), segment: &OffsetSegment| {
                     assert!(segment_start < segment.end_point);
-                    let start_rating: TimePoint = segment.start_offset();
-                    let end_rating: TimePoint = segment.end_offset(segment.end_point - segment_start);
+                    let start_offset: Offset = segment.start_offset();
+                    let end_offset: Offset = segment.end_offset(segment.end_point - segment_start);
 
-                    let new_max = min(min(current_max, start_rating), end_rating);
-
-                    (new_max, segment.end_point)
+                    (pick(pick(current_extreme, start_offset), end_offset), segment.end_point)
                 },
             )
             .0
-    }*/
+    }
 }
 
 #[derive(Debug)]
@@ -1117,7 +2517,7 @@ impl DualBuffer {
     }
 
     #[inline]
-    pub fn iter(&self) -> DualIterator<std::iter::Cloned<impl Iterator<Item = &DualSegment>>> {
+    pub fn iter(&self) -> DualIterator<core::iter::Cloned<impl Iterator<Item = &DualSegment>>> {
         DualIterator::<_> {
             start: self.start,
             iter: self.buffer.iter().cloned(),
@@ -1140,9 +2540,102 @@ impl<I: Dfi> DualFullSegmentIterator<I> {
             iter: LeftToRightMaximumIterator::<_>::new(self.iter, self.start),
         }
     }
-}
 
-struct CombinedMaximumDualIterator<I1, I2>
+    /// The mirrored `left_to_right_maximum`: every point gets the maximal rating (and its offset)
+    /// of all points at or after it, so ties are resolved towards later offsets.
+    ///
+    /// The segments are scanned from the end, so all of them are buffered first.
+    pub fn right_to_left_maximum(self) -> DualFullSegmentIterator<impl Dfi> {
+        let segments: Vec<DualFullSegment> = self.iter.collect();
+
+        let mut current_best_rating = Rating::zero();
+        let mut current_best_timepoint: Point = segments
+            .last()
+            .map_or(self.start, |segment| segment.span.end - TimeDelta::one());
+
+        let constant_segment = |span: PointSpan, rating: Rating, timepoint: Point| DualFullSegment {
+            span,
+            data: DualInfo {
+                rating_info: RatingInfo::constant(rating),
+                offset_info: OffsetInfo::constant(timepoint),
+            },
+        };
+
+        // collected from right to left and reversed at the end
+        let mut result: Vec<DualFullSegment> = Vec::with_capacity(segments.len());
+
+        for segment in segments.into_iter().rev() {
+            let segment_start_rating = segment.start_rating();
+            let segment_end_rating = segment.end_rating();
+
+            let start_offset = segment.data.offset_info.offset;
+            let end_offset = segment.data.offset_info.end_offset(segment.span.len());
+
+            if segment_start_rating <= current_best_rating && segment_end_rating <= current_best_rating {
+                result.push(constant_segment(
+                    segment.span,
+                    current_best_rating,
+                    current_best_timepoint,
+                ));
+            } else if segment_end_rating >= current_best_rating {
+                if segment_end_rating >= segment_start_rating {
+                    current_best_rating = segment_end_rating;
+                    current_best_timepoint = end_offset;
+
+                    result.push(constant_segment(
+                        segment.span,
+                        current_best_rating,
+                        current_best_timepoint,
+                    ));
+                } else {
+                    current_best_rating = segment_start_rating;
+                    current_best_timepoint = start_offset;
+
+                    result.push(segment);
+                }
+            } else {
+                // the segment falls below the current best somewhere inside
+                assert!(segment_start_rating > current_best_rating);
+                assert!(segment_end_rating < current_best_rating);
+
+                // the first point that is not better than the current best
+                let delta = segment.data.rating_info.delta;
+                let mut switch_i64 =
+                    RatingDelta::div_by_delta_to_i64(segment_start_rating - current_best_rating, -delta);
+                if Rating::add_mul(segment_start_rating, delta, TimeDelta::from_i64(switch_i64)) > current_best_rating {
+                    switch_i64 += 1;
+                }
+                assert!(0 < switch_i64);
+                assert!(switch_i64 < segment.span.len().as_i64());
+
+                let switch_point = segment.span.start + TimeDelta::from_i64(switch_i64);
+
+                result.push(constant_segment(
+                    PointSpan::new(switch_point, segment.span.end),
+                    current_best_rating,
+                    current_best_timepoint,
+                ));
+
+                current_best_rating = segment_start_rating;
+                current_best_timepoint = start_offset;
+
+                result.push(DualFullSegment {
+                    span: PointSpan::new(segment.span.start, switch_point),
+                    data: segment.data,
+                });
+            }
+        }
+
+        result.reverse();
+
+        DualFullSegmentIterator::<_> {
+            start: self.start,
+            iter: result.into_iter(),
+        }
+    }
+}
+
+struct CombinedMaximumDualIterator<I1, I2>
 where
     I1: Di,
     I2: Di,
@@ -1151,12 +2644,38 @@ where
     segment_start: Point,
     dual_seg1: DualSegment,
     dual_seg2: DualSegment,
-    input_iter1: I1,
-    input_iter2: I2,
+    input_iter1: Peekable<I1>,
+    input_iter2: Peekable<I2>,
     finished: bool,
 }
 
 impl<I1: Di, I2: Di> CombinedMaximumDualIterator<I1, I2> {
+    /// Returns the maximum directly if both inputs consist of a single constant segment over the
+    /// same span (which is common for short clips).
+    #[inline]
+    fn single_constant_segment_maximum(&mut self) -> Option<DualFullSegment> {
+        if self.dual_seg1.end_point != self.dual_seg2.end_point
+            || self.dual_seg1.data.rating_info.delta != RatingDelta::zero()
+            || self.dual_seg2.data.rating_info.delta != RatingDelta::zero()
+            || self.input_iter1.peek().is_some()
+            || self.input_iter2.peek().is_some()
+        {
+            return None;
+        }
+
+        // same tie breaking as `generate_maximum_segments()`
+        let data = if self.dual_seg1.data.rating_info.rating >= self.dual_seg2.data.rating_info.rating {
+            self.dual_seg1.data.clone()
+        } else {
+            self.dual_seg2.data.clone()
+        };
+
+        Some(DualFullSegment {
+            span: PointSpan::new(self.segment_start, self.dual_seg1.end_point),
+            data,
+        })
+    }
+
     #[inline]
     fn generate_maximum_segments(&mut self, len: TimeDelta, segment_end: Point) -> DualFullSegment {
         let start_rating1 = self.dual_seg1.data.rating_info.rating;
@@ -1185,7 +2704,12 @@ impl<I1: Di, I2: Di> CombinedMaximumDualIterator<I1, I2> {
             // segments switch somewhere in this segment
 
             // spoint is the first offset where the second better segment is better
-            let spoint = Self::get_switch_point(start_rating1, start_rating2, delta1, delta2);
+            let mut spoint = Self::get_switch_point(start_rating1, start_rating2, delta1, delta2);
+            if SATURATING_RATINGS {
+                // clamped end ratings (or a clamped difference) can move the switch point out of
+                // the segment, which at worst misplaces the switch by the clamped amount
+                spoint = spoint.clamp(1, len.as_i64() - 1);
+            }
             assert!(0 < spoint);
             assert!(spoint < len.as_i64());
 
@@ -1233,7 +2757,10 @@ impl<I1: Di, I2: Di> CombinedMaximumDualIterator<I1, I2> {
         // solving for x:
         //
         // x = (start_rating2 - start_rating1) / (delta1 - delta2)
-        RatingDelta::div_by_delta_to_i64(start_rating2 - start_rating1, delta1 - delta2) + 1
+        RatingDelta::div_by_delta_to_i64(
+            Rating::sub_ratings(start_rating2, start_rating1),
+            Rating::sub_ratings(delta1, delta2),
+        ) + 1
     }
 }
 
@@ -1335,15 +2862,213 @@ pub fn combined_maximum_of_dual_iterators<I1: Di, I2: Di>(
         .next()
         .expect("Second iterator should have at least one element");
 
+    let mut combined_iter = CombinedMaximumDualIterator::<_, _> {
+        stored_segment: None,
+        segment_start: start,
+        dual_seg1,
+        dual_seg2,
+        input_iter1: iter1.iter.peekable(),
+        input_iter2: iter2.iter.peekable(),
+        finished: false,
+    };
+
+    if let Some(segment) = combined_iter.single_constant_segment_maximum() {
+        combined_iter.stored_segment = Some(segment);
+        combined_iter.finished = true;
+    }
+
     DualFullSegmentIterator::<_> {
         start,
-        iter: CombinedMaximumDualIterator::<_, _> {
-            stored_segment: None,
+        iter: combined_iter,
+    }
+}
+
+/// Turns the pointwise maximum of the negated ratings back into the pointwise minimum of the
+/// original ratings.
+///
+/// Negating both inputs inverts all comparisons of `CombinedMaximumDualIterator` (the lower segment
+/// is kept, and ties still keep the first iterator), while `get_switch_point` gives the same point
+/// for the negated segments, so both combinations split crossing segments at the same point.
+struct CombinedMinimumDualIterator<I>
+where
+    I: Dfi,
+{
+    maximum_of_negated: I,
+}
+
+impl<I: Dfi> Iterator for CombinedMinimumDualIterator<I> {
+    type Item = DualFullSegment;
+
+    #[inline]
+    fn next(&mut self) -> Option<DualFullSegment> {
+        self.maximum_of_negated.next().map(
+            #[inline]
+            |segment| DualFullSegment {
+                span: segment.span,
+                data: segment.data.negated_rating(),
+            },
+        )
+    }
+}
+
+#[inline]
+fn negate_ratings<I: Di>(iter: DualIterator<I>) -> DualIterator<impl Di> {
+    DualIterator::<_> {
+        start: iter.start,
+        iter: iter.iter.map(
+            #[inline]
+            |segment| DualSegment {
+                end_point: segment.end_point,
+                data: segment.data.negated_rating(),
+            },
+        ),
+    }
+}
+
+/// Like `combined_maximum_of_dual_iterators`, but keeps the lower rating at every point.
+#[inline]
+pub fn combined_minimum_of_dual_iterators<I1: Di, I2: Di>(
+    iter1: DualIterator<I1>,
+    iter2: DualIterator<I2>,
+) -> DualFullSegmentIterator<impl Dfi> {
+    let maximum_of_negated = combined_maximum_of_dual_iterators(negate_ratings(iter1), negate_ratings(iter2));
+
+    DualFullSegmentIterator::<_> {
+        start: maximum_of_negated.start,
+        iter: CombinedMinimumDualIterator {
+            maximum_of_negated: maximum_of_negated.iter,
+        },
+    }
+}
+
+struct CombinedMaximumOfManyIterator<I>
+where
+    I: Di,
+{
+    inputs: Vec<I>,
+
+    /// The current segment of every input, advanced to `segment_start`.
+    segments: Vec<DualSegment>,
+    segment_start: Point,
+    finished: bool,
+}
+
+impl<I: Di> Iterator for CombinedMaximumOfManyIterator<I> {
+    type Item = DualFullSegment;
+
+    #[inline]
+    fn next(&mut self) -> Option<DualFullSegment> {
+        if self.finished {
+            return None;
+        }
+
+        let frontier = self.segments.iter().map(|segment| segment.end_point).min().unwrap();
+        let len = frontier - self.segment_start;
+        assert!(len > TimeDelta::zero());
+
+        // the best segment at `segment_start`; like `CombinedMaximumDualIterator` ties are
+        // resolved towards the larger end rating and then towards the earlier input
+        let ratings = |segment: &DualSegment| {
+            let rating_info = segment.data.rating_info;
+            (rating_info.rating, rating_info.end_rating(len), rating_info.delta)
+        };
+        let mut best = 0;
+        for (i, segment) in self.segments.iter().enumerate().skip(1) {
+            let (start_rating, end_rating, _) = ratings(segment);
+            let (best_start_rating, best_end_rating, _) = ratings(&self.segments[best]);
+            if (start_rating, end_rating) > (best_start_rating, best_end_rating) {
+                best = i;
+            }
+        }
+
+        // the best segment is kept until the first point where another one becomes better
+        let (best_start_rating, best_end_rating, best_delta) = ratings(&self.segments[best]);
+        let piece_end = self
+            .segments
+            .iter()
+            .filter(|segment| ratings(segment).1 > best_end_rating)
+            .map(|segment| {
+                let (start_rating, _, delta) = ratings(segment);
+                let spoint = CombinedMaximumDualIterator::<I, I>::get_switch_point(
+                    best_start_rating,
+                    start_rating,
+                    best_delta,
+                    delta,
+                );
+                assert!(0 < spoint);
+                assert!(spoint < len.as_i64());
+                self.segment_start + TimeDelta::from_i64(spoint)
+            })
+            .min()
+            .unwrap_or(frontier);
+
+        let result = DualFullSegment {
+            span: PointSpan::new(self.segment_start, piece_end),
+            data: self.segments[best].data.clone(),
+        };
+
+        for segment in &mut self.segments {
+            segment.advance(piece_end - self.segment_start);
+        }
+        self.segment_start = piece_end;
+
+        if piece_end == frontier {
+            let mut ended = 0;
+            for (input, segment) in self.inputs.iter_mut().zip(self.segments.iter_mut()) {
+                if segment.end_point == frontier {
+                    match input.next() {
+                        Some(next_segment) => *segment = next_segment,
+                        None => ended += 1,
+                    }
+                }
+            }
+
+            if ended == self.inputs.len() {
+                self.finished = true;
+            } else if ended > 0 {
+                panic!("combined_maximum_of_many: an iterator ended before the others");
+            }
+        }
+
+        Some(result)
+    }
+}
+
+/// Like `combined_maximum_of_dual_iterators`, but for any number of iterators, which are all
+/// advanced together (instead of combining the result of every combination with the next
+/// iterator again).
+///
+/// All iterators have to start at the same point, contain at least one segment and end at the
+/// same point; like in the pairwise version, an iterator that ends before the others panics.
+#[inline]
+pub fn combined_maximum_of_many<I: Di>(iters: Vec<DualIterator<I>>) -> DualFullSegmentIterator<impl Dfi> {
+    assert!(
+        !iters.is_empty(),
+        "combined_maximum_of_many needs at least one iterator"
+    );
+    let start = iters[0].start;
+    assert!(
+        iters.iter().all(|iter| iter.start == start),
+        "all iterators should start at the same point"
+    );
+
+    let mut inputs: Vec<I> = Vec::with_capacity(iters.len());
+    let mut segments: Vec<DualSegment> = Vec::with_capacity(iters.len());
+    for mut iter in iters {
+        segments.push(
+            iter.iter
+                .next()
+                .expect("every iterator should have at least one element"),
+        );
+        inputs.push(iter.iter);
+    }
+
+    DualFullSegmentIterator::<_> {
+        start,
+        iter: CombinedMaximumOfManyIterator {
+            inputs,
+            segments,
             segment_start: start,
-            dual_seg1,
-            dual_seg2,
-            input_iter1: iter1.iter,
-            input_iter2: iter2.iter,
             finished: false,
         },
     }
@@ -1373,23 +3098,114 @@ impl<I: Rfi> RatingFullIterator<I> {
             ),
         }
     }
+
+    /// Like `annotate_with_offset_info`, but takes the offsets from `offset_buffer`, which has to
+    /// cover the same time range. Both are walked in lockstep (instead of looking up the offset of
+    /// every segment), and segments are split where an offset segment ends.
+    #[inline]
+    pub fn annotate_with_offset_buffer(self, offset_buffer: &OffsetBuffer) -> DualFullSegmentIterator<impl Dfi> {
+        assert_eq!(self.start, offset_buffer.start());
+
+        DualFullSegmentIterator::<_> {
+            start: self.start,
+            iter: AnnotateWithOffsetBufferIterator {
+                ratings: self.iter,
+                offsets: offset_buffer.iter().annotate_with_segment_start_points().into_iter(),
+                stored_rating: None,
+                stored_offset: None,
+            },
+        }
+    }
+}
+
+struct AnnotateWithOffsetBufferIterator<I, O>
+where
+    I: Rfi,
+    O: Pfi,
+{
+    ratings: I,
+    offsets: O,
+    stored_rating: Option<RatingFullSegment>,
+    stored_offset: Option<OffsetFullSegment>,
+}
+
+impl<I: Rfi, O: Pfi> Iterator for AnnotateWithOffsetBufferIterator<I, O> {
+    type Item = DualFullSegment;
+
+    #[inline]
+    fn next(&mut self) -> Option<DualFullSegment> {
+        let rating = match self.stored_rating.take().or_else(|| self.ratings.next()) {
+            Some(rating) => rating,
+            None => {
+                assert!(
+                    self.stored_offset.is_none() && self.offsets.next().is_none(),
+                    "offset buffer ends after the ratings"
+                );
+                return None;
+            }
+        };
+        let offset = self
+            .stored_offset
+            .take()
+            .or_else(|| self.offsets.next())
+            .expect("offset buffer ends before the ratings");
+        assert_eq!(rating.span.start, offset.span.start);
+
+        let end = min(rating.span.end, offset.span.end);
+        if end < rating.span.end {
+            self.stored_rating = Some(RatingFullSegment {
+                span: PointSpan::new(end, rating.span.end),
+                data: rating.data.advanced(end - rating.span.start),
+            });
+        }
+        if end < offset.span.end {
+            self.stored_offset = Some(OffsetFullSegment {
+                span: PointSpan::new(end, offset.span.end),
+                data: offset.data.advanced(end - offset.span.start),
+            });
+        }
+
+        Some(DualFullSegment {
+            span: PointSpan::new(rating.span.start, end),
+            data: DualInfo {
+                rating_info: rating.data,
+                offset_info: offset.data,
+            },
+        })
+    }
 }
 
-/*#[inline]
-pub fn zero_rating_iterator(start: TimePoint, end: TimePoint) -> RatingIterator<impl RI> {
+/// A rating curve with the constant `rating` from `start` to (excluding) `end`.
+///
+/// Panics if `start` is not smaller than `end`.
+#[inline]
+pub fn constant_rating_iterator(start: Point, end: Point, rating: Rating) -> RatingIterator<impl Ri> {
+    assert!(start < end, "constant rating from {} to {} is empty", start, end);
+
     RatingIterator::<_> {
-        start: start,
+        start,
         iter: once(Segment {
             end_point: end,
-            data: RatingInfo {
-                rating: Rating::zero(),
-                delta: RatingDelta::zero(),
-            },
+            data: RatingInfo::constant(rating),
         }),
     }
-}*/
+}
+
+/// A rating curve which is zero from `start` to (excluding) `end` (see `constant_rating_iterator`).
+#[inline]
+pub fn zero_rating_iterator(start: Point, end: Point) -> RatingIterator<impl Ri> {
+    constant_rating_iterator(start, end, Rating::zero())
+}
 
 impl RatingFullSegment {
+    #[inline]
+    pub fn new(span: PointSpan, rating_info: RatingInfo) -> RatingFullSegment {
+        RatingFullSegment {
+            span,
+            data: rating_info,
+        }
+    }
+
     #[inline]
     pub fn start_rating(self) -> Rating {
         self.data.rating
@@ -1438,8 +3254,8 @@ impl<I1: Ri, I2: Ri> RatingAdderIterator<I1, I2> {
         RatingFullSegment {
             span: PointSpan::new(self.segment_start, segment_end),
             data: RatingInfo {
-                rating: start_rating1 + start_rating2,
-                delta: delta1 + delta2,
+                rating: Rating::add_ratings(start_rating1, start_rating2),
+                delta: Rating::add_ratings(delta1, delta2),
             },
         }
     }
@@ -1522,13 +3338,17 @@ impl<I1: Ri, I2: Ri> Iterator for RatingAdderIterator<I1, I2> {
     }
 }
 
-/*pub fn add_rating_iterators(
-    mut iter1: RatingIterator<impl RI>,
-    mut iter2: RatingIterator<impl RI>,
-) -> RatingFullIterator<impl RFI> {
+/// Adds two rating curves point by point. The result has a segment boundary wherever one of the
+/// inputs has one.
+///
+/// Both iterators have to start at the same point and end at the same point.
+#[inline]
+pub fn add_rating_iterators(
+    mut iter1: RatingIterator<impl Ri>,
+    mut iter2: RatingIterator<impl Ri>,
+) -> RatingFullIterator<impl Rfi> {
     assert!(iter1.start == iter2.start);
     let start = iter1.start;
-    //println!("ADDER segment start {}", start);
 
     let dual_seg1 = iter1
         .iter
@@ -1540,17 +3360,40 @@ impl<I1: Ri, I2: Ri> Iterator for RatingAdderIterator<I1, I2> {
         .expect("Second iterator should have at least one element");
 
     RatingFullIterator::<_> {
-        start: start,
+        start,
         iter: RatingAdderIterator::<_, _> {
             segment_start: start,
-            dual_seg1: dual_seg1,
-            dual_seg2: dual_seg2,
+            dual_seg1,
+            dual_seg2,
             input_iter1: iter1.iter,
             input_iter2: iter2.iter,
             finished: false,
         },
     }
-}*/
+}
+
+/// Subtracts the rating curve of `iter2` from the one of `iter1` (see `add_rating_iterators`).
+#[inline]
+pub fn subtract_rating_iterators(
+    iter1: RatingIterator<impl Ri>,
+    iter2: RatingIterator<impl Ri>,
+) -> RatingFullIterator<impl Rfi> {
+    let negated2 = RatingIterator::<_> {
+        start: iter2.start,
+        iter: iter2.iter.map(
+            #[inline]
+            |segment| RatingSegment {
+                end_point: segment.end_point,
+                data: RatingInfo {
+                    rating: Rating::sub_ratings(Rating::zero(), segment.data.rating),
+                    delta: Rating::sub_ratings(RatingDelta::zero(), segment.data.delta),
+                },
+            },
+        ),
+    };
+
+    add_rating_iterators(iter1, negated2)
+}
 
 // ----------------------------------------------------------------------------------------------------
 
@@ -1585,8 +3428,8 @@ impl<I1: Di, I2: Ri> RatingAdderIterator2<I1, I2> {
             span: PointSpan::new(self.segment_start, segment_end),
             data: DualInfo {
                 rating_info: RatingInfo {
-                    rating: start_rating1 + start_rating2,
-                    delta: delta1 + delta2,
+                    rating: Rating::add_ratings(start_rating1, start_rating2),
+                    delta: Rating::add_ratings(delta1, delta2),
                 },
                 offset_info: self.dual_seg1.data.offset_info,
             },
@@ -1685,50 +3528,98 @@ trait PushIterator {
 
 // //////////////////////////////////////////////////////////////////////////////////////////////////
 
-/*#[derive(Clone, Copy, PartialEq, Eq)]
+/// The exact slope `delta / diff` (with `diff > 0`), or an unbounded end of an interval of slopes.
+///
+/// Slopes are compared by cross-multiplication (in `i128`), so unlike `f64` the comparison stays
+/// exact for the large ratings of long videos.
+#[derive(Clone, Copy, Debug)]
 enum RatingDivPointDiff {
-    Real {
-        delta: RatingDelta,
-        diff: PointDiff
-    },
+    NInf,
+    Real { delta: RatingDelta, diff: PointDiff },
     PInf,
-    NInf
 }
 
 impl RatingDivPointDiff {
+    #[inline]
     fn div(delta: RatingDelta, diff: PointDiff) -> RatingDivPointDiff {
-        assert!(diff.as_i64() > 0);
-        RatingDivPointDiff::Real {
-            delta,
-            diff
+        assert!(diff != PointDiff::zero());
+        if diff < PointDiff::zero() {
+            RatingDivPointDiff::Real {
+                delta: -delta,
+                diff: -diff,
+            }
+        } else {
+            RatingDivPointDiff::Real { delta, diff }
         }
     }
-}
 
-type Interval = (RatingDivPointDiff, RatingDivPointDiff);
+    /// The slope halfway between `a` and `b` (rounded towards zero).
+    #[inline]
+    fn midpoint(a: RatingDivPointDiff, b: RatingDivPointDiff) -> RatingDelta {
+        match (a, b) {
+            (
+                RatingDivPointDiff::Real {
+                    delta: delta_a,
+                    diff: diff_a,
+                },
+                RatingDivPointDiff::Real {
+                    delta: delta_b,
+                    diff: diff_b,
+                },
+            ) => {
+                let (diff_a, diff_b) = (i128::from(diff_a.as_i64()), i128::from(diff_b.as_i64()));
+                let numerator = i128::from(delta_a) * diff_b + i128::from(delta_b) * diff_a;
+                (numerator / (2 * diff_a * diff_b)) as RatingDelta
+            }
+
+            // an interval that is unbounded on one side contains no midpoint; use its finite end
+            (RatingDivPointDiff::Real { delta, diff }, _) | (_, RatingDivPointDiff::Real { delta, diff }) => {
+                (i128::from(delta) / i128::from(diff.as_i64())) as RatingDelta
+            }
+            _ => RatingDelta::zero(),
+        }
+    }
+}
 
 impl Ord for RatingDivPointDiff {
-    fn cmp(&self, other: &RatingDivPointDiff) -> std::cmp::Ordering {
+    #[inline]
+    fn cmp(&self, other: &RatingDivPointDiff) -> core::cmp::Ordering {
+        use core::cmp::Ordering;
+
         match (self, other) {
-            (RatingDivPointDiff::PInf, RatingDivPointDiff::NInf) => std::cmp::Ordering::Greater,
-            (RatingDivPointDiff::NInf, RatingDivPointDiff::PInf) => std::cmp::Ordering::Less,
-            (RatingDivPointDiff::NInf, RatingDivPointDiff::Real {..}) => std::cmp::Ordering::Less,
-            (RatingDivPointDiff::PInf, RatingDivPointDiff::Real {..}) => std::cmp::Ordering::Greater,
-            (RatingDivPointDiff::Real {..}, RatingDivPointDiff::NInf) => std::cmp::Ordering::Greater,
-            (RatingDivPointDiff::Real {..}, RatingDivPointDiff::PInf) => std::cmp::Ordering::Less,
-            (RatingDivPointDiff::Real {  }, RatingDivPointDiff::PInf) => std::cmp::Ordering::Less,
-            (self.delta * other.diff.as_i64()).cmp(&(other.delta * self.diff.as_i64()))
+            (RatingDivPointDiff::NInf, RatingDivPointDiff::NInf)
+            | (RatingDivPointDiff::PInf, RatingDivPointDiff::PInf) => Ordering::Equal,
+            (RatingDivPointDiff::NInf, _) | (_, RatingDivPointDiff::PInf) => Ordering::Less,
+            (RatingDivPointDiff::PInf, _) | (_, RatingDivPointDiff::NInf) => Ordering::Greater,
+            (
+                RatingDivPointDiff::Real { delta, diff },
+                RatingDivPointDiff::Real {
+                    delta: other_delta,
+                    diff: other_diff,
+                },
+            ) => (i128::from(*delta) * i128::from(other_diff.as_i64()))
+                .cmp(&(i128::from(*other_delta) * i128::from(diff.as_i64()))),
         }
     }
+}
 
 impl PartialOrd for RatingDivPointDiff {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    #[inline]
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
         Some(self.cmp(other))
     }
 }
-}*/
 
-type Interval = (f64, f64);
+impl PartialEq for RatingDivPointDiff {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == core::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for RatingDivPointDiff {}
+
+type Interval = (RatingDivPointDiff, RatingDivPointDiff);
 
 struct AggressiveSimplifySegmentData {
     seg: RatingFullSegment,
@@ -1738,7 +3629,7 @@ struct AggressiveSimplifySegmentData {
 
 struct AggressiveSimplifyRatingPushIterator<I: PushIterator<Item = RatingFullSegment>> {
     start: Point,
-    epsilon: RatingDelta,
+    tolerance: SimplifyTolerance,
     current_segment: Option<AggressiveSimplifySegmentData>,
     iter: I,
 }
@@ -1751,16 +3642,12 @@ impl<I: PushIterator<Item = RatingFullSegment>> AggressiveSimplifyRatingPushIter
         pivot: Point,
         max_diff: RatingDelta,
     ) -> Interval {
-        use std::f64;
-
         if target == pivot {
-            return (f64::NEG_INFINITY, f64::INFINITY);
+            return (RatingDivPointDiff::NInf, RatingDivPointDiff::PInf);
         }
 
-        let x_div: f64 = 1.0 / (target - pivot).as_f64();
-
-        let min_delta = (target_rating - pivot_rating - max_diff) as f64 * x_div;
-        let max_delta = (target_rating - pivot_rating + max_diff) as f64 * x_div;
+        let min_delta = RatingDivPointDiff::div(target_rating - pivot_rating - max_diff, target - pivot);
+        let max_delta = RatingDivPointDiff::div(target_rating - pivot_rating + max_diff, target - pivot);
 
         if min_delta <= max_delta {
             (min_delta, max_delta)
@@ -1773,23 +3660,31 @@ impl<I: PushIterator<Item = RatingFullSegment>> AggressiveSimplifyRatingPushIter
         seg: RatingFullSegment,
         pivot_rating: Rating,
         pivot: Point,
-        max_diff: RatingDelta,
+        tolerance: SimplifyTolerance,
     ) -> Interval {
-        let interval1 =
-            Self::get_min_max_offset_for_target(seg.data.start_rating(), seg.span.start, pivot_rating, pivot, max_diff);
+        let start_rating = seg.data.start_rating();
+        let end_rating = seg.data.end_rating(seg.span.len());
+
+        let interval1 = Self::get_min_max_offset_for_target(
+            start_rating,
+            seg.span.start,
+            pivot_rating,
+            pivot,
+            tolerance.max_diff_at(seg.span.start, start_rating),
+        );
         let interval2 = Self::get_min_max_offset_for_target(
-            seg.data.end_rating(seg.span.len()),
+            end_rating,
             seg.span.end - TimeDelta::one(),
             pivot_rating,
             pivot,
-            max_diff,
+            tolerance.max_diff_at(seg.span.end - TimeDelta::one(), end_rating),
         );
 
         Self::intersect_intervals(interval1, interval2)
     }
 
     fn intersect_intervals(a: Interval, b: Interval) -> Interval {
-        (f64::max(a.0, b.0), f64::min(a.1, b.1))
+        (core::cmp::max(a.0, b.0), min(a.1, b.1))
     }
 
     fn create_segment(&self, seg: RatingFullSegment) -> AggressiveSimplifySegmentData {
@@ -1799,7 +3694,7 @@ impl<I: PushIterator<Item = RatingFullSegment>> AggressiveSimplifyRatingPushIter
         AggressiveSimplifySegmentData {
             seg,
             pivot,
-            offset_interval: Self::get_min_max_offset_for_segment(seg, pivot_rating, pivot, self.epsilon),
+            offset_interval: Self::get_min_max_offset_for_segment(seg, pivot_rating, pivot, self.tolerance),
         }
     }
 }
@@ -1834,12 +3729,12 @@ impl<I: PushIterator<Item = RatingFullSegment>> PushIterator for AggressiveSimpl
         let pivot_diff: TimeDelta = current_segment.pivot - current_segment.seg.span.start;
         let pivot_rating = current_segment.seg.data.get_at(pivot_diff);
 
-        let interval = Self::get_min_max_offset_for_segment(seg, pivot_rating, current_segment.pivot, self.epsilon);
+        let interval = Self::get_min_max_offset_for_segment(seg, pivot_rating, current_segment.pivot, self.tolerance);
 
         let next_interval = Self::intersect_intervals(current_segment.offset_interval, interval);
 
         if next_interval.0 <= next_interval.1 {
-            let new_delta = ((next_interval.0 + next_interval.1) / 2.) as i64;
+            let new_delta = RatingDivPointDiff::midpoint(next_interval.0, next_interval.1);
             let new_start_rating = Rating::add_mul(pivot_rating, new_delta, -pivot_diff);
 
             current_segment.seg.span.end = next_segment.end_point;
@@ -1857,7 +3752,7 @@ impl<I: PushIterator<Item = RatingFullSegment>> PushIterator for AggressiveSimpl
 
 fn aggressive_simplify_ratings_push_iter<I>(
     start: Point,
-    epsilon: RatingDelta,
+    tolerance: SimplifyTolerance,
     iter: I,
 ) -> impl PushIterator<Item = RatingSegment, Output = I::Output>
 where
@@ -1865,7 +3760,7 @@ where
 {
     AggressiveSimplifyRatingPushIterator {
         current_segment: None,
-        epsilon,
+        tolerance,
         start,
         iter,
     }
@@ -1873,14 +3768,63 @@ where
 
 // //////////////////////////////////////////////////////////////////////////////////////////////////
 
-struct SimplifyOffsetPushIterator<I: PushIterator<Item = OffsetFullSegment>> {
+/// Decides which neighbouring segments can be stored as a single segment.
+///
+/// The lossless simplifications (`save_simplified`, `simplify` and the first pass of
+/// `save_separate`) are implemented with `LosslessSimplifier`. The aggressive simplifications
+/// fit a line through many segments at once and are not expressed through this trait.
+pub trait Simplifier<D> {
+    /// Returns `true` if `next`, which starts where `prev` ends, should be merged into `prev`.
+    fn should_merge(&self, prev: &FullSegment<D>, next: &Segment<D>) -> bool;
+
+    /// Merges `next` into `prev`. By default the data of `prev` is kept and its span is extended,
+    /// which is exact if `next` continues the linear function of `prev`.
+    #[inline]
+    fn merge(&self, prev: FullSegment<D>, next: Segment<D>) -> FullSegment<D> {
+        FullSegment {
+            span: PointSpan::new(prev.span.start, next.end_point),
+            data: prev.data,
+        }
+    }
+}
+
+/// Merges two segments only if the merged segment describes exactly the same values.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LosslessSimplifier;
+
+impl Simplifier<RatingInfo> for LosslessSimplifier {
+    #[inline]
+    fn should_merge(&self, prev: &RatingFullSegment, next: &RatingSegment) -> bool {
+        prev.data.delta == next.data.delta && prev.data.exclusive_end_rating(prev.span.len()) == next.start_rating()
+    }
+}
+
+impl Simplifier<OffsetInfo> for LosslessSimplifier {
+    #[inline]
+    fn should_merge(&self, prev: &OffsetFullSegment, next: &OffsetSegment) -> bool {
+        prev.data.drag == next.data.drag && prev.exclusive_end_offset() == next.data.start_offset()
+    }
+}
+
+impl Simplifier<DualInfo> for LosslessSimplifier {
+    #[inline]
+    fn should_merge(&self, prev: &DualFullSegment, next: &DualSegment) -> bool {
+        prev.data.rating_info.delta == next.data.rating_info.delta
+            && prev.data.offset_info.drag == next.data.offset_info.drag
+            && prev.exclusive_end_offset() == next.start_offset()
+            && prev.exclusive_end_rating() == next.start_rating()
+    }
+}
+
+struct SimplifyPushIterator<D, S: Simplifier<D>, I: PushIterator<Item = FullSegment<D>>> {
     start: Point,
-    current_segment: Option<OffsetFullSegment>,
+    current_segment: Option<FullSegment<D>>,
+    simplifier: S,
     iter: I,
 }
 
-impl<I: PushIterator<Item = OffsetFullSegment>> PushIterator for SimplifyOffsetPushIterator<I> {
-    type Item = OffsetSegment;
+impl<D: Copy, S: Simplifier<D>, I: PushIterator<Item = FullSegment<D>>> PushIterator for SimplifyPushIterator<D, S, I> {
+    type Item = Segment<D>;
     type Output = I::Output;
 
     #[inline]
@@ -1892,8 +3836,8 @@ impl<I: PushIterator<Item = OffsetFullSegment>> PushIterator for SimplifyOffsetP
     }
 
     #[inline]
-    fn push(&mut self, next_segment: OffsetSegment) {
-        let mut current_segment: OffsetFullSegment;
+    fn push(&mut self, next_segment: Segment<D>) {
+        let mut current_segment: FullSegment<D>;
 
         match self.current_segment {
             None => {
@@ -1903,13 +3847,10 @@ impl<I: PushIterator<Item = OffsetFullSegment>> PushIterator for SimplifyOffsetP
             Some(v) => current_segment = v,
         }
 
-        if current_segment.data.drag == next_segment.data.drag
-            && current_segment.exclusive_end_offset() == next_segment.data.start_offset()
-        {
-            current_segment.span.end = next_segment.end_point;
+        if self.simplifier.should_merge(&current_segment, &next_segment) {
+            current_segment = self.simplifier.merge(current_segment, next_segment);
         } else {
             self.iter.push(current_segment);
-
             current_segment = next_segment.with_start_point(current_segment.span.end);
         }
 
@@ -1918,86 +3859,37 @@ impl<I: PushIterator<Item = OffsetFullSegment>> PushIterator for SimplifyOffsetP
 }
 
 #[inline]
-fn simplify_offsets_push_iter<I>(start: Point, iter: I) -> impl PushIterator<Item = OffsetSegment, Output = I::Output>
+fn simplify_push_iter<D, S, I>(
+    start: Point,
+    simplifier: S,
+    iter: I,
+) -> impl PushIterator<Item = Segment<D>, Output = I::Output>
 where
-    I: PushIterator<Item = OffsetFullSegment>,
+    D: Copy,
+    S: Simplifier<D>,
+    I: PushIterator<Item = FullSegment<D>>,
 {
-    SimplifyOffsetPushIterator {
-        current_segment: None,
+    SimplifyPushIterator {
         start,
+        current_segment: None,
+        simplifier,
         iter,
     }
 }
 
 // //////////////////////////////////////////////////////////////////////////////////////////////////
 
-struct SimplifyRatingPushIterator<I: PushIterator<Item = RatingFullSegment>> {
-    start: Point,
-    current_segment: Option<RatingFullSegment>,
-    iter: I,
+struct CollectToVecPushIterator<T> {
+    v: Vec<T>,
 }
 
-impl<I: PushIterator<Item = RatingFullSegment>> PushIterator for SimplifyRatingPushIterator<I> {
-    type Item = RatingSegment;
-    type Output = I::Output;
+impl<T> PushIterator for CollectToVecPushIterator<T> {
+    type Item = T;
+    type Output = Vec<T>;
 
     #[inline]
-    fn finish(mut self) -> Self::Output {
-        if let Some(current_segment) = self.current_segment {
-            self.iter.push(current_segment);
-        }
-        self.iter.finish()
-    }
-
-    #[inline]
-    fn push(&mut self, next_segment: RatingSegment) {
-        let mut current_segment: RatingFullSegment;
-
-        match self.current_segment {
-            None => {
-                self.current_segment = Some(next_segment.with_start_point(self.start));
-                return;
-            }
-            Some(v) => current_segment = v,
-        }
-
-        if current_segment.data.delta == next_segment.data.delta
-            && current_segment.exclusive_end_rating() == next_segment.data.start_rating()
-        {
-            current_segment.span.end = next_segment.end_point;
-        } else {
-            self.iter.push(current_segment);
-            current_segment = next_segment.with_start_point(current_segment.span.end);
-        }
-
-        self.current_segment = Some(current_segment);
-    }
-}
-
-fn simplify_ratings_push_iter<I>(start: Point, iter: I) -> impl PushIterator<Item = RatingSegment, Output = I::Output>
-where
-    I: PushIterator<Item = RatingFullSegment>,
-{
-    SimplifyRatingPushIterator {
-        current_segment: None,
-        start,
-        iter,
-    }
-}
-
-// //////////////////////////////////////////////////////////////////////////////////////////////////
-
-struct CollectToVecPushIterator<T> {
-    v: Vec<T>,
-}
-
-impl<T> PushIterator for CollectToVecPushIterator<T> {
-    type Item = T;
-    type Output = Vec<T>;
-
-    #[inline]
-    fn finish(self) -> Vec<T> {
-        self.v
+    fn finish(self) -> Vec<T> {
+        self.v
     }
 
     #[inline]
@@ -2053,7 +3945,7 @@ where
 {
     iter: I,
     f: F,
-    _marker: std::marker::PhantomData<A>,
+    _marker: core::marker::PhantomData<A>,
 }
 
 impl<I, F, A, B> PushIterator for MapPushIterator<I, F, A, B>
@@ -2126,15 +4018,17 @@ where
     })
 }
 
-struct DualSimplifyIterator<I>
+struct DualSimplifyIterator<I, S>
 where
     I: Di,
+    S: Simplifier<DualInfo>,
 {
     current_segment: Option<DualFullSegment>,
     iter: I,
+    simplifier: S,
 }
 
-impl<I: Di> Iterator for DualSimplifyIterator<I> {
+impl<I: Di, S: Simplifier<DualInfo>> Iterator for DualSimplifyIterator<I, S> {
     type Item = DualFullSegment;
 
     #[inline]
@@ -2155,12 +4049,8 @@ impl<I: Di> Iterator for DualSimplifyIterator<I> {
                     return Some(current_segment);
                 }
                 Some(next_segment) => {
-                    if next_segment.data.rating_info.delta == current_segment.data.rating_info.delta
-                        && next_segment.data.offset_info.drag == current_segment.data.offset_info.drag
-                        && current_segment.exclusive_end_offset() == next_segment.start_offset()
-                        && current_segment.exclusive_end_rating() == next_segment.start_rating()
-                    {
-                        current_segment.span.end = next_segment.end_point;
+                    if self.simplifier.should_merge(&current_segment, &next_segment) {
+                        current_segment = self.simplifier.merge(current_segment, next_segment);
                     } else {
                         self.current_segment = Some(next_segment.with_start_point(current_segment.span.end));
                         return Some(current_segment);
@@ -2170,3 +4060,1905 @@ impl<I: Di> Iterator for DualSimplifyIterator<I> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::rating_summary;
+
+    fn constant_rating_buffer(start: i64, end_points: &[i64]) -> RatingBuffer {
+        RatingBuffer {
+            start: TimeDelta::from_i64(start),
+            buffer: end_points
+                .iter()
+                .map(|&end_point| RatingSegment {
+                    end_point: TimeDelta::from_i64(end_point),
+                    data: RatingInfo::constant(Rating::zero()),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn windows2_pairs_neighbouring_segments() {
+        let end_points = |pairs: Vec<(RatingSegment, RatingSegment)>| -> Vec<(i64, i64)> {
+            pairs
+                .into_iter()
+                .map(|(previous, next)| (previous.end_point.as_i64(), next.end_point.as_i64()))
+                .collect()
+        };
+
+        let rating_buffer = constant_rating_buffer(0, &[10, 20, 30]);
+        assert_eq!(
+            end_points(rating_buffer.iter().windows2().collect()),
+            vec![(10, 20), (20, 30)]
+        );
+
+        let single_segment = constant_rating_buffer(0, &[10]);
+        assert!(single_segment.iter().windows2().next().is_none());
+    }
+
+    #[test]
+    fn running_maximum_matches_buffer_maximum() {
+        let rating_buffer = two_bumps_rating_buffer();
+        for tie_policy in [MaxTiePolicy::First, MaxTiePolicy::Last, MaxTiePolicy::Central] {
+            assert_eq!(
+                rating_buffer
+                    .iter()
+                    .moving_average(TimeDelta::from_i64(50))
+                    .running_maximum(tie_policy),
+                rating_buffer
+                    .iter()
+                    .moving_average(TimeDelta::from_i64(50))
+                    .save()
+                    .maximum_with(tie_policy)
+            );
+        }
+
+        let flat = constant_rating_buffer(-10, &[0, 10, 20]);
+        assert_eq!(
+            flat.iter().running_maximum(MaxTiePolicy::Central),
+            flat.maximum_with(MaxTiePolicy::Central)
+        );
+    }
+
+    #[test]
+    fn truncate_to_three_segments() {
+        let end_points: Vec<i64> = (1..=10).map(|i| i * 10).collect();
+        let rating_buffer = constant_rating_buffer(0, &end_points);
+
+        let truncated = rating_buffer.iter().truncate_to_segments(3).save();
+        assert_eq!(truncated.start(), TimeDelta::from_i64(0));
+        assert_eq!(truncated.buffer.len(), 3);
+        assert_eq!(truncated.end(), Some(TimeDelta::from_i64(30)));
+
+        // asking for more segments than there are keeps all of them
+        assert_eq!(rating_buffer.iter().truncate_to_segments(20).save().buffer.len(), 10);
+    }
+
+    #[test]
+    fn maximum_tie_policies() {
+        // rises until 9, is flat from 10 to 29 and falls from 30
+        let slope = Rating::convert_from_f64(0.5);
+        let top = Rating::add_mul(Rating::zero(), slope, TimeDelta::from_i64(10));
+        let rating_buffer = RatingBuffer {
+            start: TimeDelta::zero(),
+            buffer: vec![
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(10),
+                    data: RatingInfo {
+                        rating: Rating::zero(),
+                        delta: slope,
+                    },
+                },
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(20),
+                    data: RatingInfo::constant(top),
+                },
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(30),
+                    data: RatingInfo::constant(top),
+                },
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(40),
+                    data: RatingInfo {
+                        rating: top - slope,
+                        delta: -slope,
+                    },
+                },
+            ],
+        };
+
+        let maximum = |tie_policy| rating_buffer.maximum_with(tie_policy);
+        assert_eq!(maximum(MaxTiePolicy::First), (top, TimeDelta::from_i64(10)));
+        assert_eq!(maximum(MaxTiePolicy::Last), (top, TimeDelta::from_i64(29)));
+        assert_eq!(maximum(MaxTiePolicy::Central), (top, TimeDelta::from_i64(19)));
+
+        // `maximum` keeps the first of the points
+        assert_eq!(rating_buffer.maximum(), maximum(MaxTiePolicy::First));
+    }
+
+    #[test]
+    fn get_rating_at_boundaries_and_slopes() {
+        let slope = RatingDelta::convert_from_f64(0.01);
+        let rating_buffer = RatingBuffer {
+            start: TimeDelta::from_i64(-10),
+            buffer: vec![
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(10),
+                    data: RatingInfo::constant(Rating::convert_from_f64(0.5)),
+                },
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(40),
+                    data: RatingInfo {
+                        rating: Rating::convert_from_f64(0.2),
+                        delta: slope,
+                    },
+                },
+            ],
+        };
+
+        assert_eq!(
+            rating_buffer.get_rating_at(TimeDelta::from_i64(-10)),
+            Rating::convert_from_f64(0.5)
+        );
+        assert_eq!(
+            rating_buffer.get_rating_at(TimeDelta::from_i64(9)),
+            Rating::convert_from_f64(0.5)
+        );
+
+        // the boundary point belongs to the segment starting there
+        assert_eq!(
+            rating_buffer.get_rating_at(TimeDelta::from_i64(10)),
+            Rating::convert_from_f64(0.2)
+        );
+        assert_eq!(
+            rating_buffer.get_rating_at(TimeDelta::from_i64(25)),
+            Rating::add_mul(Rating::convert_from_f64(0.2), slope, TimeDelta::from_i64(15))
+        );
+        assert_eq!(
+            rating_buffer.get_rating_at(TimeDelta::from_i64(39)),
+            Rating::add_mul(Rating::convert_from_f64(0.2), slope, TimeDelta::from_i64(29))
+        );
+
+        let rating_buffer = two_bumps_rating_buffer();
+        for t in (0..2000).step_by(7) {
+            let t = TimeDelta::from_i64(t);
+            assert_eq!(rating_buffer.get_rating_at(t), rating_at(&rating_buffer, t));
+        }
+    }
+
+    #[test]
+    fn sample_rating_buffer() {
+        let rating_buffer = two_bumps_rating_buffer();
+        let samples: Vec<(Point, Rating)> = rating_buffer.sample(TimeDelta::from_i64(300)).collect();
+
+        let points: Vec<i64> = samples.iter().map(|(point, _)| point.as_i64()).collect();
+        assert_eq!(points, vec![0, 300, 600, 900, 1200, 1500, 1800, 1999]);
+        for (point, rating) in samples {
+            assert_eq!(rating, rating_at(&rating_buffer, point));
+        }
+
+        // a step beyond the end only gives the first and the last point
+        let points: Vec<i64> = constant_rating_buffer(-5, &[10, 20])
+            .sample(TimeDelta::from_i64(1000))
+            .map(|(point, _)| point.as_i64())
+            .collect();
+        assert_eq!(points, vec![-5, 19]);
+
+        assert_eq!(constant_rating_buffer(0, &[]).sample(TimeDelta::one()).count(), 0);
+        assert_eq!(constant_rating_buffer(0, &[1]).sample(TimeDelta::one()).count(), 1);
+
+        let readable: Vec<(Point, f64)> = rating_buffer.sample_readable(TimeDelta::from_i64(300)).collect();
+        for (point, rating) in readable {
+            assert_eq!(rating, rating_at(&rating_buffer, point).as_readable_f64());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "the sample step has to be positive")]
+    fn sample_with_zero_step() {
+        let _ = two_bumps_rating_buffer().sample(TimeDelta::zero());
+    }
+
+    #[test]
+    fn minimum_at_segment_boundary() {
+        // falls until 9, rises from 10 to 19 and ends with a single point at 20
+        let slope = Rating::convert_from_f64(0.5);
+        let bottom = Rating::add_mul(Rating::zero(), -slope, TimeDelta::from_i64(9));
+        let rating_buffer = RatingBuffer {
+            start: TimeDelta::zero(),
+            buffer: vec![
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(10),
+                    data: RatingInfo {
+                        rating: Rating::zero(),
+                        delta: -slope,
+                    },
+                },
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(20),
+                    data: RatingInfo {
+                        rating: bottom + slope,
+                        delta: slope,
+                    },
+                },
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(21),
+                    data: RatingInfo::constant(bottom + slope),
+                },
+            ],
+        };
+        assert_eq!(rating_buffer.minimum(), (bottom, TimeDelta::from_i64(9)));
+
+        // the single point of the trailing segment is reported as `end_point - 1`
+        let mut trailing_minimum = rating_buffer;
+        trailing_minimum.buffer[2].data = RatingInfo::constant(bottom - slope);
+        assert_eq!(trailing_minimum.minimum(), (bottom - slope, TimeDelta::from_i64(20)));
+
+        let rating_buffer = two_bumps_rating_buffer();
+        let (rating, point) = rating_buffer.minimum();
+        assert_eq!(rating_at(&rating_buffer, point), rating);
+        assert!((0..2000).all(|t| rating_at(&rating_buffer, TimeDelta::from_i64(t)) >= rating));
+
+        assert_eq!(
+            constant_rating_buffer(5, &[]).minimum(),
+            (Rating::zero(), TimeDelta::from_i64(5))
+        );
+    }
+
+    #[test]
+    fn extend_to_with_policy_fills_the_tail() {
+        let p = TimeDelta::from_i64;
+        let slope = RatingDelta::convert_from_f64(0.1);
+        let rating_buffer = RatingBuffer {
+            start: p(0),
+            buffer: vec![
+                RatingSegment {
+                    end_point: p(10),
+                    data: RatingInfo {
+                        rating: Rating::zero(),
+                        delta: slope,
+                    },
+                },
+                RatingSegment {
+                    end_point: p(15),
+                    data: RatingInfo {
+                        rating: Rating::add_mul(Rating::zero(), slope, p(10)),
+                        delta: -slope,
+                    },
+                },
+            ],
+        };
+        let extended = |end_point, tail_policy| {
+            rating_summary(
+                &rating_buffer
+                    .iter()
+                    .extend_to_with_policy(end_point, tail_policy)
+                    .save(),
+            )
+        };
+        let input = || rating_summary(&rating_buffer);
+        let tail = |rating| [(p(20), rating, RatingDelta::zero())];
+
+        // the rating at the last point 14 is held
+        let held_rating = Rating::add_mul(Rating::zero(), slope, p(6));
+        assert_eq!(
+            extended(p(20), TailPolicy::HoldLastOffset),
+            [input(), tail(held_rating).to_vec()].concat()
+        );
+        assert_eq!(
+            extended(p(20), TailPolicy::DecayToZero),
+            [input(), tail(Rating::zero()).to_vec()].concat()
+        );
+
+        // a rating that already reaches the end point is not extended
+        assert_eq!(extended(p(15), TailPolicy::HoldLastOffset), input());
+        assert_eq!(extended(p(15), TailPolicy::DecayToZero), input());
+    }
+
+    #[test]
+    fn moving_average_turns_step_into_ramp() {
+        // divisible by the window, so the ramp is exactly linear
+        let height = 10 * Rating::convert_from_f64(0.1);
+        let step = RatingBuffer {
+            start: TimeDelta::zero(),
+            buffer: vec![
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(50),
+                    data: RatingInfo::constant(Rating::zero()),
+                },
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(100),
+                    data: RatingInfo::constant(height),
+                },
+            ],
+        };
+
+        let average = step.iter().moving_average(TimeDelta::from_i64(10)).save();
+        let end_points: Vec<i64> = average.buffer.iter().map(|s| s.end_point.as_i64()).collect();
+
+        // zero until the window reaches the step at 46, a ramp over the window width from 45 up to 55,
+        // and falling again when the window leaves the buffer
+        assert_eq!(end_points, vec![45, 55, 95, 100]);
+        assert_eq!(average.buffer[0].data.rating, Rating::zero());
+        assert_eq!(average.buffer[1].data.rating, Rating::zero());
+        assert_eq!(average.buffer[1].data.delta, height / 10);
+        assert_eq!(average.buffer[2].data.rating, height);
+        assert_eq!(average.buffer[2].data.delta, RatingDelta::zero());
+        assert_eq!(average.buffer[3].data.rating, height);
+        assert_eq!(average.buffer[3].data.delta, -height / 10);
+    }
+
+    #[test]
+    fn moving_average_matches_sampled_average() {
+        // every rating is divisible by the window, so no rounding is involved
+        let window = 7;
+        let steps = [(3, 2), (20, -5), (24, 0), (60, 9), (61, 1), (90, 4)];
+        let ratings = RatingBuffer {
+            start: TimeDelta::from_i64(-10),
+            buffer: steps
+                .iter()
+                .map(|&(end_point, height)| RatingSegment {
+                    end_point: TimeDelta::from_i64(end_point),
+                    data: RatingInfo::constant(height * window * Rating::convert_from_f64(0.01)),
+                })
+                .collect(),
+        };
+        let rating_at = |point: i64| {
+            let mut start = -10;
+            for segment in &ratings.buffer {
+                if start <= point && point < segment.end_point.as_i64() {
+                    return segment.data.rating;
+                }
+                start = segment.end_point.as_i64();
+            }
+            Rating::zero()
+        };
+
+        let average = ratings.iter().moving_average(TimeDelta::from_i64(window)).save();
+        assert_eq!(average.end(), ratings.end());
+
+        let mut start = average.start.as_i64();
+        for segment in &average.buffer {
+            for point in start..segment.end_point.as_i64() {
+                let window_start = point - window / 2;
+                let sampled: Rating = (window_start..window_start + window).map(rating_at).sum::<Rating>() / window;
+                assert_eq!(
+                    Rating::add_mul(
+                        segment.data.rating,
+                        segment.data.delta,
+                        TimeDelta::from_i64(point - start)
+                    ),
+                    sampled,
+                    "at {}",
+                    point
+                );
+            }
+            start = segment.end_point.as_i64();
+        }
+    }
+
+    #[test]
+    fn custom_simplifier_that_never_merges() {
+        struct NeverMerge;
+        impl Simplifier<RatingInfo> for NeverMerge {
+            fn should_merge(&self, _prev: &RatingFullSegment, _next: &RatingSegment) -> bool {
+                false
+            }
+        }
+
+        let rating_buffer = constant_rating_buffer(0, &[10, 20, 30, 40]);
+
+        assert_eq!(rating_buffer.iter().save_simplified().buffer.len(), 1);
+        assert_eq!(rating_buffer.iter().save_simplified_with(NeverMerge).buffer.len(), 4);
+    }
+
+    #[test]
+    fn save_simplified_merges_like_lossless_simplifier() {
+        let p = TimeDelta::from_i64;
+        let slope = RatingDelta::convert_from_f64(0.5);
+        let segment = |end_point: i64, rating: Rating, delta: RatingDelta| RatingSegment {
+            end_point: p(end_point),
+            data: RatingInfo { rating, delta },
+        };
+        let rating_at = |t: i64| Rating::add_mul(Rating::zero(), slope, p(t));
+
+        // a line split into three segments, a jump, a constant split into two and a single segment
+        let rating_buffer = RatingBuffer {
+            start: p(0),
+            buffer: vec![
+                segment(10, rating_at(0), slope),
+                segment(15, rating_at(10), slope),
+                segment(30, rating_at(15), slope),
+                segment(40, rating_at(0), RatingDelta::zero()),
+                segment(50, rating_at(0), RatingDelta::zero()),
+                segment(60, rating_at(60), -slope),
+            ],
+        };
+
+        let simplified = rating_buffer.iter().save_simplified();
+        assert_eq!(
+            rating_summary(&simplified),
+            rating_summary(&rating_buffer.iter().save_simplified_with(LosslessSimplifier))
+        );
+        assert_eq!(
+            rating_summary(&simplified),
+            vec![
+                (p(30), rating_at(0), slope),
+                (p(50), rating_at(0), RatingDelta::zero()),
+                (p(60), rating_at(60), -slope),
+            ]
+        );
+
+        let empty = RatingBuffer {
+            start: p(0),
+            buffer: Vec::new(),
+        };
+        assert!(empty.iter().save_simplified().buffer.is_empty());
+    }
+
+    #[test]
+    fn shift_saturates_instead_of_wrapping() {
+        let rating_buffer = constant_rating_buffer(-10, &[0, 10, 20]);
+        let t = TimeDelta::from_i64(i64::MAX - 5);
+
+        let shifted = rating_buffer.iter().shift(t).save();
+        assert_eq!(shifted.start, TimeDelta::from_i64(i64::MAX - 15));
+        let end_points: Vec<i64> = shifted.buffer.iter().map(|s| s.end_point.as_i64()).collect();
+        assert_eq!(end_points, vec![i64::MAX - 5, i64::MAX]);
+        assert_eq!(shifted.validate(), Ok(()));
+
+        let shifted = rating_buffer.iter().shift_simple(-t).shift_simple(-t).save();
+        assert_eq!(shifted.start, TimeDelta::from_i64(-10));
+        assert!(shifted.buffer.is_empty());
+        assert_eq!(shifted.validate(), Ok(()));
+
+        assert_eq!(
+            t.checked_add(TimeDelta::from_i64(5)),
+            Some(TimeDelta::from_i64(i64::MAX))
+        );
+        assert_eq!(t.checked_add(TimeDelta::from_i64(6)), None);
+    }
+
+    #[test]
+    fn shift_keeps_buffers_valid_at_both_bounds() {
+        let rating_buffer = constant_rating_buffer(-10, &[0, 10, 20]);
+
+        // the start and the first end point saturate at the smallest time point
+        let t = TimeDelta::from_i64(i64::MIN);
+        let shifted = rating_buffer.iter().shift(t).save();
+        assert_eq!(shifted.start, TimeDelta::from_i64(i64::MIN));
+        let end_points: Vec<i64> = shifted.buffer.iter().map(|s| s.end_point.as_i64()).collect();
+        assert_eq!(end_points, vec![i64::MIN + 10, i64::MIN + 20]);
+        assert_eq!(shifted.validate(), Ok(()));
+
+        let t = TimeDelta::from_i64(i64::MAX);
+        let shifted = rating_buffer.iter().shift(t).shift(t).save();
+        assert_eq!(shifted.start, TimeDelta::from_i64(i64::MAX));
+        assert!(shifted.buffer.is_empty());
+        assert_eq!(shifted.validate(), Ok(()));
+
+        let shifted = rating_buffer.iter().shift(-t).shift(-t).save();
+        assert_eq!(shifted.start, TimeDelta::from_i64(i64::MIN));
+        assert!(shifted.buffer.is_empty());
+        assert_eq!(shifted.validate(), Ok(()));
+
+        // the end points of the first segments fall to the start
+        let shifted = rating_buffer.iter().shift_simple(TimeDelta::from_i64(-15)).save();
+        let end_points: Vec<i64> = shifted.buffer.iter().map(|s| s.end_point.as_i64()).collect();
+        assert_eq!(end_points, vec![-5, 5]);
+        assert_eq!(shifted.validate(), Ok(()));
+    }
+
+    fn single_dual_segment(end_point: i64, rating: f64, offset: i64) -> DualSegment {
+        DualSegment {
+            end_point: TimeDelta::from_i64(end_point),
+            data: DualInfo {
+                rating_info: RatingInfo::constant(Rating::convert_from_f64(rating)),
+                offset_info: OffsetInfo {
+                    offset: TimeDelta::from_i64(offset),
+                    drag: true,
+                },
+            },
+        }
+    }
+
+    fn summarize(segments: &[DualFullSegment]) -> Vec<(PointSpan, Rating, RatingDelta, Offset)> {
+        segments
+            .iter()
+            .map(|s| {
+                (
+                    s.span,
+                    s.data.rating_info.rating,
+                    s.data.rating_info.delta,
+                    s.data.offset_info.offset,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn combined_maximum_single_constant_segments() {
+        for (rating1, rating2) in [(0.5, 0.25), (0.25, 0.5), (0.5, 0.5), (0., 0.)] {
+            let seg1 = single_dual_segment(100, rating1, 7);
+            let seg2 = single_dual_segment(100, rating2, 42);
+            let start = TimeDelta::from_i64(-100);
+
+            let fast: Vec<DualFullSegment> = combined_maximum_of_dual_iterators(
+                DualIterator {
+                    start,
+                    iter: once(seg1.clone()),
+                },
+                DualIterator {
+                    start,
+                    iter: once(seg2.clone()),
+                },
+            )
+            .into_iter()
+            .collect();
+
+            let generic: Vec<DualFullSegment> = CombinedMaximumDualIterator {
+                stored_segment: None,
+                segment_start: start,
+                dual_seg1: seg1,
+                dual_seg2: seg2,
+                input_iter1: core::iter::empty().peekable(),
+                input_iter2: core::iter::empty().peekable(),
+                finished: false,
+            }
+            .collect();
+
+            assert_eq!(fast.len(), 1);
+            assert_eq!(summarize(&fast), summarize(&generic));
+        }
+    }
+
+    #[test]
+    fn combined_minimum_of_crossing_segments() {
+        // the first segment rises from 0 and the second falls from 0.5, so they cross near t=50
+        let slope = Rating::convert_from_f64(0.5) / 99;
+        let rising = DualSegment {
+            end_point: TimeDelta::from_i64(100),
+            data: DualInfo::new(
+                RatingInfo {
+                    rating: Rating::zero(),
+                    delta: slope,
+                },
+                OffsetInfo::constant(TimeDelta::from_i64(7)),
+            ),
+        };
+        let falling = DualSegment {
+            end_point: TimeDelta::from_i64(100),
+            data: DualInfo::new(
+                RatingInfo {
+                    rating: Rating::convert_from_f64(0.5),
+                    delta: -slope,
+                },
+                OffsetInfo::constant(TimeDelta::from_i64(42)),
+            ),
+        };
+        let start = TimeDelta::zero();
+        let iter = |segment: &DualSegment| DualIterator {
+            start,
+            iter: once(segment.clone()),
+        };
+
+        let maximum: Vec<DualFullSegment> = combined_maximum_of_dual_iterators(iter(&rising), iter(&falling))
+            .into_iter()
+            .collect();
+        let minimum: Vec<DualFullSegment> = combined_minimum_of_dual_iterators(iter(&rising), iter(&falling))
+            .into_iter()
+            .collect();
+
+        // both are split at the same point, but the minimum takes the lower segment on each side
+        let spans = |segments: &[DualFullSegment]| segments.iter().map(|s| s.span).collect::<Vec<_>>();
+        let offsets = |segments: &[DualFullSegment]| {
+            segments
+                .iter()
+                .map(|s| s.data.offset_info.offset.as_i64())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(spans(&maximum).len(), 2);
+        assert_eq!(spans(&minimum), spans(&maximum));
+        assert_eq!(offsets(&maximum), vec![42, 7]);
+        assert_eq!(offsets(&minimum), vec![7, 42]);
+
+        for segment in &minimum {
+            let len = segment.span.end - segment.span.start;
+            let rating_info = segment.data.rating_info;
+            for (t, rating) in [
+                (segment.span.start, rating_info.rating),
+                (segment.span.end - TimeDelta::one(), rating_info.end_rating(len)),
+            ] {
+                let at = |full: &DualSegment| full.data.rating_info.advanced(t - start).rating;
+                assert_eq!(rating, min(at(&rising), at(&falling)));
+            }
+        }
+    }
+
+    #[test]
+    fn combined_maximum_of_many_matches_pairwise_combination() {
+        let slope = RatingDelta::convert_from_f64(0.01);
+        let segment = |end_point: i64, rating: f64, delta: RatingDelta, offset: i64| DualSegment {
+            end_point: TimeDelta::from_i64(end_point),
+            data: DualInfo::new(
+                RatingInfo {
+                    rating: Rating::convert_from_f64(rating),
+                    delta,
+                },
+                OffsetInfo {
+                    offset: TimeDelta::from_i64(offset),
+                    drag: true,
+                },
+            ),
+        };
+
+        // a rising, a falling and a piecewise constant input with different segment boundaries
+        let inputs = [
+            vec![segment(40, 0., slope, 0), segment(70, 0.1, slope, 500)],
+            vec![segment(70, 0.6, -slope, 1000)],
+            vec![
+                segment(30, 0.3, RatingDelta::zero(), 2000),
+                segment(50, 0.2, RatingDelta::zero(), 3000),
+                segment(70, 0.45, RatingDelta::zero(), 4000),
+            ],
+        ];
+        let start = TimeDelta::from_i64(-30);
+        let iter = |segments: &Vec<DualSegment>| DualIterator {
+            start,
+            iter: segments.clone().into_iter(),
+        };
+
+        let many: Vec<DualFullSegment> = combined_maximum_of_many(inputs.iter().map(iter).collect())
+            .into_iter()
+            .collect();
+
+        let first_two = combined_maximum_of_dual_iterators(iter(&inputs[0]), iter(&inputs[1])).discard_start_times();
+        let pairwise: Vec<DualFullSegment> = combined_maximum_of_dual_iterators(first_two, iter(&inputs[2]))
+            .into_iter()
+            .collect();
+
+        // the same rating and offset at every point
+        let at = |segments: &[DualFullSegment], t: i64| {
+            let t = TimeDelta::from_i64(t);
+            let segment = segments.iter().find(|s| s.span.start <= t && t < s.span.end).unwrap();
+            let data = segment.data.clone().advanced(t - segment.span.start);
+            (data.rating_info.rating, data.offset_info.offset)
+        };
+        for t in -30..70 {
+            assert_eq!(at(&many, t), at(&pairwise, t), "at {}", t);
+        }
+        assert!(many.windows(2).all(|w| w[0].span.end == w[1].span.start));
+
+        // a single input is passed through
+        let single: Vec<DualFullSegment> = combined_maximum_of_many(vec![iter(&inputs[2])]).into_iter().collect();
+        assert_eq!(single.len(), 3);
+    }
+
+    /// Two parabolic bumps (peak `0.01` at `t=500` and peak `1.0` at `t=1500`), each sampled
+    /// by linear segments of length 10.
+    fn two_bumps_rating_buffer() -> RatingBuffer {
+        let bump = |t: i64| {
+            let (center, peak) = if t < 1000 { (500., 0.01) } else { (1500., 1.) };
+            let x = (t as f64 - center) / 500.;
+            Rating::convert_from_f64(peak * (1. - x * x))
+        };
+
+        RatingBuffer {
+            start: TimeDelta::zero(),
+            buffer: (0..200)
+                .map(|i| {
+                    let (t0, t1) = (i * 10, i * 10 + 10);
+                    RatingSegment {
+                        end_point: TimeDelta::from_i64(t1),
+                        data: RatingInfo {
+                            rating: bump(t0),
+                            delta: (bump(t1) - bump(t0)) / 10,
+                        },
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    fn rating_at(rating_buffer: &RatingBuffer, t: Point) -> Rating {
+        let mut segment_start = rating_buffer.start;
+        for segment in &rating_buffer.buffer {
+            if t < segment.end_point {
+                return segment.data.get_at(t - segment_start);
+            }
+            segment_start = segment.end_point;
+        }
+        panic!("time point {} is not in the rating buffer", t);
+    }
+
+    fn max_relative_error(original: &RatingBuffer, simplified: &RatingBuffer, range: core::ops::Range<i64>) -> f64 {
+        range
+            .step_by(10)
+            .filter(|&t| t % 1000 != 0) // the bumps are zero at their borders
+            .map(|t| {
+                let t = TimeDelta::from_i64(t);
+                let (a, b) = (rating_at(original, t), rating_at(simplified, t));
+                (a - b).abs() as f64 / a.abs() as f64
+            })
+            .fold(0., f64::max)
+    }
+
+    #[test]
+    fn relative_simplification_preserves_small_and_large_bumps() {
+        let original = two_bumps_rating_buffer();
+
+        let simplified = original
+            .iter()
+            .save_aggressively_simplified(SimplifyTolerance::relative(0.1));
+        assert!(simplified.buffer.len() < original.buffer.len());
+        assert_eq!(simplified.end(), original.end());
+
+        // both bumps stay within (roughly) 10% of the original curve
+        assert!(max_relative_error(&original, &simplified, 0..1000) < 0.11);
+        assert!(max_relative_error(&original, &simplified, 1000..2000) < 0.11);
+
+        // an absolute epsilon of 10% of the large peak flattens the small bump
+        let simplified = original
+            .iter()
+            .save_aggressively_simplified(SimplifyTolerance::from_rating_delta(Rating::convert_from_f64(0.1)));
+        assert!(max_relative_error(&original, &simplified, 0..1000) > 1.);
+    }
+
+    #[test]
+    fn aggressive_simplification_compares_large_slopes_exactly() {
+        // the first segment rises with a slope of 2^60 around its pivot at t=1, and the second
+        // segment needs a slope of `2^60 + 1` (exactly `(2^61 + 2) / 2`) to be reached from there;
+        // as `f64` both slopes are the same, so the segments would be merged
+        let slope: RatingDelta = 1 << 60;
+        let buffer = |second_rating: Rating| RatingBuffer {
+            start: TimeDelta::zero(),
+            buffer: vec![
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(3),
+                    data: RatingInfo {
+                        rating: Rating::zero(),
+                        delta: slope,
+                    },
+                },
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(4),
+                    data: RatingInfo::constant(second_rating),
+                },
+            ],
+        };
+
+        let simplified = buffer(3 * slope + 2)
+            .iter()
+            .save_aggressively_simplified(SimplifyTolerance::from_rating_delta(RatingDelta::zero()));
+        assert_eq!(simplified.buffer.len(), 2);
+
+        // the second segment continues the line, so it can be merged
+        let simplified = buffer(3 * slope)
+            .iter()
+            .save_aggressively_simplified(SimplifyTolerance::from_rating_delta(RatingDelta::zero()));
+        assert_eq!(simplified.buffer.len(), 1);
+        assert_eq!(simplified.buffer[0].data.delta, slope);
+    }
+
+    #[test]
+    fn relative_simplify_tolerance() {
+        let original = two_bumps_rating_buffer();
+
+        // no neighbouring segments of the bumps continue the same line
+        let simplified = original
+            .iter()
+            .save_aggressively_simplified(SimplifyTolerance::relative(0.));
+        assert_eq!(simplified.buffer.len(), original.buffer.len());
+
+        // the tolerance is resolved at every point while streaming, so the ratings stay within
+        // their own magnitude of the original
+        let simplified = original
+            .iter()
+            .save_aggressively_simplified(SimplifyTolerance::relative(1.));
+        assert!(simplified.buffer.len() < original.buffer.len());
+        assert_eq!(simplified.end(), original.end());
+        assert!(max_relative_error(&original, &simplified, 0..2000) <= 1.01);
+
+        // `save_separate` simplifies the ratings the same way
+        let dual_iter = || {
+            original
+                .iter()
+                .annotate_with_segment_start_points()
+                .annotate_with_offset_info(|start| start)
+                .discard_start_times()
+        };
+        for fraction in [0., 0.1, 1.] {
+            let tolerance = SimplifyTolerance::relative(fraction);
+            let separate = dual_iter().save_separate(tolerance);
+            let simplified = original.iter().save_aggressively_simplified(tolerance);
+            assert_eq!(separate.rating_buffer.buffer.len(), simplified.buffer.len());
+        }
+    }
+
+    #[test]
+    fn exact_span_survives_aggressive_simplification() {
+        let p = TimeDelta::from_i64;
+        let peak = Rating::convert_from_f64(0.1);
+        let segment = |end_point: i64, rating: Rating| RatingSegment {
+            end_point: p(end_point),
+            data: RatingInfo::constant(rating),
+        };
+        let original = RatingBuffer {
+            start: p(0),
+            buffer: vec![
+                segment(10, Rating::zero()),
+                segment(11, peak),
+                segment(20, Rating::zero()),
+            ],
+        };
+
+        let tolerance = SimplifyTolerance::from_rating_delta(Rating::convert_from_f64(0.2));
+        let simplified = original.iter().save_aggressively_simplified(tolerance);
+        assert_eq!(simplified.buffer.len(), 1);
+
+        let simplified = original
+            .iter()
+            .save_aggressively_simplified(tolerance.exact_within(PointSpan::new(p(9), p(12))));
+        for t in 9..12 {
+            assert_eq!(simplified.get_rating_at(p(t)), original.get_rating_at(p(t)));
+        }
+    }
+
+    #[test]
+    fn aggressive_simplification_report() {
+        let original = two_bumps_rating_buffer();
+        let epsilon = Rating::convert_from_f64(0.01);
+        let tolerance = SimplifyTolerance::from_rating_delta(epsilon);
+
+        let (simplified, report) = original.iter().save_aggressively_simplified_with_report(tolerance);
+        assert_eq!(
+            rating_summary(&simplified),
+            rating_summary(&original.iter().save_aggressively_simplified(tolerance))
+        );
+
+        assert_eq!(report.input_segments, 200);
+        assert_eq!(report.output_segments, simplified.buffer.len());
+        assert!(report.output_segments < report.input_segments);
+        assert!(report.max_introduced_error > Rating::zero());
+        assert!(report.max_introduced_error <= epsilon);
+
+        // without an allowed error the simplified curve stays exact
+        let (_, report) = original
+            .iter()
+            .save_aggressively_simplified_with_report(SimplifyTolerance::from_rating_delta(Rating::zero()));
+        assert_eq!(report.max_introduced_error, Rating::zero());
+    }
+
+    #[test]
+    fn clamp_rating_splits_crossing_segments() {
+        let slope = RatingDelta::convert_from_f64(0.01);
+        let max = Rating::convert_from_f64(0.5);
+
+        // rises from 0 to 0.99, then falls from 1 to 0.01
+        let rating_buffer = RatingBuffer {
+            start: TimeDelta::zero(),
+            buffer: vec![
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(100),
+                    data: RatingInfo {
+                        rating: Rating::zero(),
+                        delta: slope,
+                    },
+                },
+                RatingSegment {
+                    end_point: TimeDelta::from_i64(200),
+                    data: RatingInfo {
+                        rating: Rating::add_mul(Rating::zero(), slope, TimeDelta::from_i64(100)),
+                        delta: -slope,
+                    },
+                },
+            ],
+        };
+
+        let clamped = rating_buffer.iter().clamp_rating(max).save();
+        let end_points: Vec<i64> = clamped.buffer.iter().map(|s| s.end_point.as_i64()).collect();
+        let deltas: Vec<RatingDelta> = clamped.buffer.iter().map(|s| s.data.delta).collect();
+        assert_eq!(end_points, vec![51, 100, 150, 200]);
+        assert_eq!(deltas, vec![slope, 0, 0, -slope]);
+
+        // the plateau is exactly at `max` and nothing lies above it
+        assert_eq!(clamped.buffer[1].data.rating, max);
+        assert_eq!(clamped.buffer[2].data.rating, max);
+        for t in 0..200 {
+            let t = TimeDelta::from_i64(t);
+            let original = rating_at(&rating_buffer, t);
+            assert_eq!(rating_at(&clamped, t), min(original, max));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn par_build_rating_buffer_matches_sequential_builder() {
+        let p = TimeDelta::from_i64;
+
+        // 10000 changepoints with pseudo-random gaps (some of them zero, so changepoints are merged
+        // inside and across chunks) and delta-deltas
+        let mut point = 0;
+        let timepoints: Vec<(Point, RatingDeltaDelta)> = (0..10_000i64)
+            .map(|i| {
+                point += (i * 7919) % 13;
+                (p(point + 1), (i * 104_729) % 2001 - 1000)
+            })
+            .collect();
+        let end = p(point + 100);
+
+        let mut builder = DifferentialRatingBufferBuilder::new(p(0), end).unwrap();
+        for &(segment_end, segment_end_delta_delta) in &timepoints {
+            builder.add_segment(segment_end, segment_end_delta_delta).unwrap();
+        }
+        builder.extend_to_end().unwrap();
+        let sequential = builder.build().unwrap().into_rating_iter().save();
+        assert!(sequential.buffer.len() > 9000);
+
+        for chunk_len in [10, 1000, 4096, 20_000] {
+            let parallel = par_build_rating_buffer(p(0), end, &timepoints, chunk_len).unwrap();
+            assert_eq!(parallel.start, sequential.start);
+            assert_eq!(
+                rating_summary(&parallel),
+                rating_summary(&sequential),
+                "chunk length {}",
+                chunk_len
+            );
+        }
+
+        assert_eq!(par_build_rating_buffer(p(0), end, &[], 8).unwrap().buffer.len(), 1);
+        assert!(par_build_rating_buffer(end, end, &timepoints, 8).is_err());
+        assert_eq!(
+            par_build_rating_buffer(p(0), end, &[(p(5), 1), (p(3), 1)], 1).unwrap_err(),
+            SegmentError::NonMonotonicEndPoint {
+                previous_end: p(5),
+                end: p(3),
+            }
+        );
+    }
+
+    #[test]
+    fn simplify_stream_merges_continuous_segments() {
+        let p = TimeDelta::from_i64;
+        let segment = |end_point: i64, rating: RatingInfo, offset_info: OffsetInfo| DualSegment {
+            end_point: p(end_point),
+            data: DualInfo::new(rating, offset_info),
+        };
+        let dragging = |offset: i64| OffsetInfo {
+            offset: p(offset),
+            drag: true,
+        };
+        let rating = RatingInfo::constant(Rating::convert_from_f64(0.5));
+
+        let buffer = DualBuffer {
+            start: p(0),
+            buffer: vec![
+                // two constant segments with the same rating and offset are merged
+                segment(10, rating, OffsetInfo::constant(p(7))),
+                segment(20, rating, OffsetInfo::constant(p(7))),
+                // the offset continues at 7, but starts to drag
+                segment(30, rating, dragging(7)),
+                // continues the drag of the previous segment
+                segment(40, rating, dragging(17)),
+                // constant at the end offset of the drag
+                segment(50, rating, OffsetInfo::constant(p(27))),
+            ],
+        };
+
+        let simplified = buffer.into_iter().simplify_stream().save();
+        let summary: Vec<(i64, i64, bool)> = simplified
+            .buffer
+            .iter()
+            .map(|s| {
+                (
+                    s.end_point.as_i64(),
+                    s.data.offset_info().offset.as_i64(),
+                    s.data.offset_info().drag,
+                )
+            })
+            .collect();
+        assert_eq!(summary, vec![(20, 7, false), (40, 7, true), (50, 27, false)]);
+        assert_eq!(simplified.start, p(0));
+    }
+
+    #[test]
+    fn left_to_right_maximum_merges_flat_segments() {
+        let span = |start: i64, end: i64| PointSpan::new(TimeDelta::from_i64(start), TimeDelta::from_i64(end));
+        let slope = RatingDelta::convert_from_f64(0.01);
+        let peak = Rating::add_mul(Rating::zero(), slope, TimeDelta::from_i64(9));
+
+        // a rise to the peak, followed by five segments that all stay below it
+        let mut segments = vec![DualFullSegment::new(
+            span(0, 10),
+            RatingInfo {
+                rating: Rating::zero(),
+                delta: slope,
+            },
+            OffsetInfo::constant(TimeDelta::from_i64(3)),
+        )];
+        for i in 1..6 {
+            segments.push(DualFullSegment::new(
+                span(i * 10, i * 10 + 10),
+                RatingInfo {
+                    rating: peak - slope * i,
+                    delta: -slope / 2,
+                },
+                OffsetInfo::constant(TimeDelta::from_i64(i * 100)),
+            ));
+        }
+
+        let maximum: Vec<DualFullSegment> = DualFullSegmentIterator::new(TimeDelta::zero(), segments.into_iter())
+            .left_to_right_maximum()
+            .into_iter()
+            .collect();
+
+        let spans: Vec<PointSpan> = maximum.iter().map(|s| s.span).collect();
+        assert_eq!(spans, vec![span(0, 10), span(10, 60)]);
+        assert_eq!(maximum[1].data.rating_info().rating, peak);
+        assert_eq!(maximum[1].data.rating_info().delta, RatingDelta::zero());
+        assert_eq!(maximum[1].data.offset_info().offset, TimeDelta::from_i64(3));
+    }
+
+    #[test]
+    fn left_to_right_maximum_of_constructed_segments() {
+        let span = |start: i64, end: i64| PointSpan::new(TimeDelta::from_i64(start), TimeDelta::from_i64(end));
+        let offset_info = |offset: i64| OffsetInfo {
+            offset: TimeDelta::from_i64(offset),
+            drag: true,
+        };
+        let slope = RatingDelta::convert_from_f64(0.01);
+
+        // rises to 0.49 (at t=39), drops to 0.2 and rises above the old maximum at t=70
+        let segments = vec![
+            DualFullSegment::new(
+                span(-10, 40),
+                RatingInfo {
+                    rating: Rating::zero(),
+                    delta: slope,
+                },
+                offset_info(0),
+            ),
+            DualFullSegment::new(
+                span(40, 80),
+                RatingInfo {
+                    rating: Rating::add_mul(Rating::zero(), slope, TimeDelta::from_i64(20)),
+                    delta: slope,
+                },
+                offset_info(100),
+            ),
+        ];
+
+        let maximum: Vec<DualFullSegment> =
+            DualFullSegmentIterator::new(TimeDelta::from_i64(-10), segments.into_iter())
+                .left_to_right_maximum()
+                .into_iter()
+                .collect();
+
+        let spans: Vec<PointSpan> = maximum.iter().map(|s| s.span).collect();
+        assert_eq!(spans, vec![span(-10, 40), span(40, 70), span(70, 80)]);
+
+        // the first segment is rising, so it is its own maximum
+        assert_eq!(maximum[0].data.rating_info().rating, Rating::zero());
+        assert_eq!(maximum[0].data.offset_info().offset, TimeDelta::from_i64(0));
+
+        // the maximum of the first segment is held until the second segment exceeds it
+        let first_end_rating = Rating::add_mul(Rating::zero(), slope, TimeDelta::from_i64(49));
+        assert_eq!(maximum[1].data.rating_info().rating, first_end_rating);
+        assert_eq!(maximum[1].data.rating_info().delta, RatingDelta::zero());
+        assert_eq!(maximum[1].data.offset_info().offset, TimeDelta::from_i64(49));
+        assert!(!maximum[1].data.offset_info().drag);
+
+        assert!(maximum[2].start_rating() > first_end_rating);
+        assert_eq!(maximum[2].data.offset_info().offset, TimeDelta::from_i64(130));
+    }
+
+    #[test]
+    fn maximum_of_plateau_in_both_directions() {
+        let span = |start: i64, end: i64| PointSpan::new(TimeDelta::from_i64(start), TimeDelta::from_i64(end));
+        let offset_info = |offset: i64| OffsetInfo {
+            offset: TimeDelta::from_i64(offset),
+            drag: true,
+        };
+        let slope = RatingDelta::convert_from_f64(0.01);
+        let top = Rating::add_mul(Rating::zero(), slope, TimeDelta::from_i64(20));
+
+        // rises to a plateau over [20, 60) and falls again behind it
+        let segments = || {
+            vec![
+                DualFullSegment::new(
+                    span(0, 20),
+                    RatingInfo {
+                        rating: Rating::zero(),
+                        delta: slope,
+                    },
+                    offset_info(0),
+                ),
+                DualFullSegment::new(span(20, 40), RatingInfo::constant(top), offset_info(20)),
+                DualFullSegment::new(span(40, 60), RatingInfo::constant(top), offset_info(40)),
+                DualFullSegment::new(
+                    span(60, 80),
+                    RatingInfo {
+                        rating: top - slope,
+                        delta: -slope,
+                    },
+                    offset_info(60),
+                ),
+            ]
+        };
+        let iter = || DualFullSegmentIterator::new(TimeDelta::zero(), segments().into_iter());
+
+        let left_to_right: Vec<DualFullSegment> = iter().left_to_right_maximum().into_iter().collect();
+        let right_to_left: Vec<DualFullSegment> = iter().right_to_left_maximum().into_iter().collect();
+
+        // the maximum at the end (beginning) covers everything before (after) it
+        let last = left_to_right.last().unwrap();
+        let first = right_to_left.first().unwrap();
+        assert_eq!((last.start_rating(), first.start_rating()), (top, top));
+
+        // left to right keeps the first point of the plateau, right to left the last one
+        assert_eq!(last.data.offset_info().offset, TimeDelta::from_i64(20));
+        assert_eq!(first.data.offset_info().offset, TimeDelta::from_i64(59));
+
+        // both cover the whole input without gaps
+        for maximum in [&left_to_right, &right_to_left] {
+            assert_eq!(maximum.first().unwrap().span.start, TimeDelta::zero());
+            assert_eq!(maximum.last().unwrap().span.end, TimeDelta::from_i64(80));
+            assert!(maximum.windows(2).all(|w| w[0].span.end == w[1].span.start));
+        }
+
+        // the falling segment is its own right to left maximum, the rising one is held constant
+        assert_eq!(
+            right_to_left.last().unwrap().data.offset_info().offset,
+            TimeDelta::from_i64(60)
+        );
+        assert_eq!(right_to_left[0].span, span(0, 20));
+        assert!(!right_to_left[0].data.offset_info().drag);
+    }
+
+    #[test]
+    fn right_to_left_maximum_splits_falling_segment() {
+        let span = |start: i64, end: i64| PointSpan::new(TimeDelta::from_i64(start), TimeDelta::from_i64(end));
+        let slope = RatingDelta::convert_from_f64(0.01);
+
+        // falls from 0.4 over [0, 40) and is followed by a constant 0.2 (reached at t=20)
+        let segments = vec![
+            DualFullSegment::new(
+                span(0, 40),
+                RatingInfo {
+                    rating: Rating::add_mul(Rating::zero(), slope, TimeDelta::from_i64(40)),
+                    delta: -slope,
+                },
+                OffsetInfo {
+                    offset: TimeDelta::zero(),
+                    drag: true,
+                },
+            ),
+            DualFullSegment::new(
+                span(40, 50),
+                RatingInfo::constant(Rating::add_mul(Rating::zero(), slope, TimeDelta::from_i64(20))),
+                OffsetInfo {
+                    offset: TimeDelta::from_i64(40),
+                    drag: true,
+                },
+            ),
+        ];
+
+        let maximum: Vec<DualFullSegment> = DualFullSegmentIterator::new(TimeDelta::zero(), segments.into_iter())
+            .right_to_left_maximum()
+            .into_iter()
+            .collect();
+
+        // at t=20 the falling segment is as good as the later constant, which is preferred
+        let spans: Vec<PointSpan> = maximum.iter().map(|s| s.span).collect();
+        assert_eq!(spans, vec![span(0, 20), span(20, 40), span(40, 50)]);
+        assert!(maximum[0].data.offset_info().drag);
+        assert_eq!(maximum[1].data.offset_info().offset, TimeDelta::from_i64(49));
+        assert_eq!(
+            maximum[1].start_rating(),
+            Rating::add_mul(Rating::zero(), slope, TimeDelta::from_i64(20))
+        );
+    }
+
+    #[test]
+    fn rating_buffer_from_speech_intervals() {
+        let p = TimeDelta::from_i64;
+        let intervals = [
+            (p(50), p(60)),
+            (p(-5), p(10)),
+            (p(20), p(30)),
+            (p(25), p(40)),
+            (p(40), p(45)),
+        ];
+
+        let rating_buffer = RatingBuffer::from_speech_intervals(p(0), p(55), &intervals);
+        assert_eq!(rating_buffer.start, p(0));
+
+        let segments: Vec<(i64, f64)> = rating_buffer
+            .buffer
+            .iter()
+            .map(|s| {
+                assert_eq!(s.data.delta, RatingDelta::zero());
+                (s.end_point.as_i64(), s.data.rating.as_readable_f64())
+            })
+            .collect();
+        assert_eq!(segments, vec![(10, 1.), (20, 0.), (45, 1.), (50, 0.), (55, 1.)]);
+    }
+
+    #[test]
+    fn offset_buffer_discontinuities() {
+        let segment = |end_point: i64, offset: i64, drag: bool| OffsetSegment {
+            end_point: TimeDelta::from_i64(end_point),
+            data: OffsetInfo {
+                offset: TimeDelta::from_i64(offset),
+                drag,
+            },
+        };
+
+        // the dragging segments end exactly where their successors start, so only two jumps remain
+        let offset_buffer = OffsetBuffer {
+            start: TimeDelta::zero(),
+            buffer: vec![
+                segment(10, 5, false),
+                segment(20, 15, true),
+                segment(30, 25, false),
+                segment(40, 40, false),
+                segment(50, 40, true),
+            ],
+        };
+
+        assert_eq!(
+            offset_buffer.discontinuities(),
+            vec![
+                (TimeDelta::from_i64(10), TimeDelta::from_i64(5), TimeDelta::from_i64(15)),
+                (
+                    TimeDelta::from_i64(30),
+                    TimeDelta::from_i64(25),
+                    TimeDelta::from_i64(40)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn offset_buffer_maximum_and_minimum() {
+        let segment = |end_point: i64, offset: i64, drag: bool| OffsetSegment {
+            end_point: TimeDelta::from_i64(end_point),
+            data: OffsetInfo {
+                offset: TimeDelta::from_i64(offset),
+                drag,
+            },
+        };
+
+        // a single segment starts at the buffer start
+        let constant = OffsetBuffer {
+            start: TimeDelta::from_i64(-5),
+            buffer: vec![segment(10, 300, false)],
+        };
+        assert_eq!(constant.maximum(), TimeDelta::from_i64(300));
+        assert_eq!(constant.minimum(), TimeDelta::from_i64(300));
+
+        // the dragging segment covers [5, 15) and rises from -20 to -11
+        let single_drag = OffsetBuffer {
+            start: TimeDelta::from_i64(5),
+            buffer: vec![segment(15, -20, true)],
+        };
+        assert_eq!(single_drag.maximum(), TimeDelta::from_i64(-11));
+        assert_eq!(single_drag.minimum(), TimeDelta::from_i64(-20));
+
+        // the dragging segment covers [10, 30) and rises from 40 to 59
+        let mixed = OffsetBuffer {
+            start: TimeDelta::zero(),
+            buffer: vec![segment(10, 50, false), segment(30, 40, true), segment(35, 45, false)],
+        };
+        assert_eq!(mixed.maximum(), TimeDelta::from_i64(59));
+        assert_eq!(mixed.minimum(), TimeDelta::from_i64(40));
+
+        let offsets: Vec<TimeDelta> = (0..35).map(|t| mixed.get_offset_at(TimeDelta::from_i64(t))).collect();
+        assert_eq!(mixed.maximum(), *offsets.iter().max().unwrap());
+        assert_eq!(mixed.minimum(), *offsets.iter().min().unwrap());
+    }
+
+    #[test]
+    fn get_offset_at_matches_linear_scan() {
+        // alternating dragging and constant segments of different lengths
+        let mut end_point = -50;
+        let buffer: Vec<OffsetSegment> = (0..300)
+            .map(|i: i64| {
+                end_point += 1 + (i * 37) % 23;
+                OffsetSegment {
+                    end_point: TimeDelta::from_i64(end_point),
+                    data: OffsetInfo {
+                        offset: TimeDelta::from_i64((i * 7919) % 1000 - 500),
+                        drag: i % 3 != 0,
+                    },
+                }
+            })
+            .collect();
+        let offset_buffer = OffsetBuffer {
+            start: TimeDelta::from_i64(-50),
+            buffer,
+        };
+
+        let linear_offset_at = |t: Point| {
+            let mut segment_start = offset_buffer.start;
+            for segment in &offset_buffer.buffer {
+                if t < segment.end_point {
+                    return segment.data.advanced_offset(t - segment_start);
+                }
+                segment_start = segment.end_point;
+            }
+            unreachable!()
+        };
+
+        for t in -50..end_point {
+            let t = TimeDelta::from_i64(t);
+            assert_eq!(offset_buffer.get_offset_at(t), linear_offset_at(t));
+        }
+    }
+
+    #[test]
+    fn rebase_offset_buffer() {
+        let segment = |end_point: i64, offset: i64, drag: bool| OffsetSegment {
+            end_point: TimeDelta::from_i64(end_point),
+            data: OffsetInfo {
+                offset: TimeDelta::from_i64(offset),
+                drag,
+            },
+        };
+        let offsets = |offset_buffer: &OffsetBuffer| -> Vec<i64> {
+            (0..40)
+                .map(|t| offset_buffer.get_offset_at(TimeDelta::from_i64(t)).as_i64())
+                .collect()
+        };
+
+        let constant_shift = OffsetBuffer {
+            start: TimeDelta::zero(),
+            buffer: vec![
+                segment(10, 700, false),
+                segment(25, 700, false),
+                segment(40, 700, false),
+            ],
+        };
+        for anchor in [0, 9, 10, 24, 39] {
+            assert_eq!(
+                offsets(&constant_shift.rebase(TimeDelta::from_i64(anchor))),
+                vec![0; 40]
+            );
+        }
+
+        // the dragging segment rises from 20 to 29, so anchoring at 15 subtracts 25
+        let dragging = OffsetBuffer {
+            start: TimeDelta::zero(),
+            buffer: vec![segment(10, 5, false), segment(20, 20, true), segment(40, 40, false)],
+        };
+        let rebased = dragging.rebase(TimeDelta::from_i64(15));
+        assert_eq!(rebased.get_offset_at(TimeDelta::from_i64(15)), TimeDelta::zero());
+        assert_eq!(
+            offsets(&rebased),
+            offsets(&dragging)
+                .into_iter()
+                .map(|offset| offset - 25)
+                .collect::<Vec<i64>>()
+        );
+    }
+
+    #[cfg(not(feature = "saturating-rating"))]
+    #[test]
+    fn differential_builder_reports_rating_overflow() {
+        let p = TimeDelta::from_i64;
+
+        // a slope of `i64::MAX / 4` per point overflows after 5 points
+        let mut builder = DifferentialRatingBufferBuilder::new(p(0), p(100)).unwrap();
+        builder.add_segment(p(10), i64::MAX / 4).unwrap();
+        builder.extend_to_end().unwrap();
+        assert_eq!(
+            builder.build().err(),
+            Some(SegmentError::RatingOverflow { end_point: p(100) })
+        );
+
+        // a slope that only reaches `i64::MAX / 4 * 3` still builds
+        let mut builder = DifferentialRatingBufferBuilder::new(p(0), p(13)).unwrap();
+        builder.add_segment(p(10), i64::MAX / 4).unwrap();
+        builder.extend_to_end().unwrap();
+        let segments = builder.build().unwrap().into_rating_iter().save().buffer;
+        assert_eq!(segments[1].data.rating, Rating::zero());
+        assert_eq!(segments[1].end_rating(p(3)), i64::MAX / 4 * 2);
+
+        assert_eq!(Rating::checked_add_mul(i64::MAX - 10, 5, p(2)), Some(i64::MAX));
+        assert_eq!(Rating::checked_add_mul(i64::MAX - 10, 5, p(3)), None);
+        assert_eq!(Rating::checked_add_mul(0, i64::MIN / 2, p(3)), None);
+    }
+
+    #[cfg(feature = "saturating-rating")]
+    #[test]
+    fn saturating_ratings_clamp_instead_of_overflowing() {
+        let p = TimeDelta::from_i64;
+        let near_max = |end: i64| RatingSegment {
+            end_point: p(end),
+            data: RatingInfo::constant(i64::MAX - 10),
+        };
+
+        let sums: Vec<RatingFullSegment> = RatingAdderIterator {
+            segment_start: p(0),
+            dual_seg1: near_max(10),
+            dual_seg2: near_max(10),
+            input_iter1: core::iter::empty(),
+            input_iter2: core::iter::empty(),
+            finished: false,
+        }
+        .collect();
+        assert_eq!(sums.len(), 1);
+        assert_eq!(sums[0].data.rating, i64::MAX);
+
+        assert_eq!(Rating::add_mul(i64::MAX - 10, 5, p(3)), i64::MAX);
+        assert_eq!(Rating::add_mul(i64::MIN + 10, -5, p(3)), i64::MIN);
+        assert_eq!(Rating::div_by_delta_to_i64(i64::MIN, -1), i64::MAX);
+
+        // the builder clamps the accumulated rating instead of reporting an overflow
+        let mut builder = DifferentialRatingBufferBuilder::new(p(0), p(100)).unwrap();
+        builder.add_segment(p(10), i64::MAX / 4).unwrap();
+        builder.extend_to_end().unwrap();
+        let segments = builder.build().unwrap().into_rating_iter().save().buffer;
+        assert_eq!(segments[1].end_rating(p(90)), i64::MAX);
+    }
+
+    #[cfg(feature = "saturating-rating")]
+    #[test]
+    fn saturating_ratings_combined_maximum() {
+        let p = TimeDelta::from_i64;
+        let segment = |rating: Rating, delta: RatingDelta, offset: i64| DualSegment {
+            end_point: p(100),
+            data: DualInfo::new(RatingInfo { rating, delta }, OffsetInfo::constant(p(offset))),
+        };
+        let maximum = |a: &DualSegment, b: &DualSegment| {
+            let iter = |segment: &DualSegment| DualIterator {
+                start: p(0),
+                iter: once(segment.clone()),
+            };
+            summarize(
+                &combined_maximum_of_dual_iterators(iter(a), iter(b))
+                    .into_iter()
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        // two constant segments near the maximum keep their order
+        let (high, low) = (segment(i64::MAX - 10, 0, 1), segment(i64::MAX - 20, 0, 2));
+        assert_eq!(
+            maximum(&low, &high),
+            vec![(PointSpan::new(p(0), p(100)), i64::MAX - 10, 0, p(1))]
+        );
+
+        // the steep segment starts lower, but saturates at `i64::MAX` after the first point
+        let steep = segment(i64::MAX - 1000, 1 << 50, 3);
+        assert_eq!(
+            maximum(&high, &steep),
+            vec![
+                (PointSpan::new(p(0), p(1)), i64::MAX - 10, 0, p(1)),
+                (PointSpan::new(p(1), p(100)), i64::MAX, 1 << 50, p(3)),
+            ]
+        );
+    }
+
+    #[test]
+    fn add_and_subtract_rating_iterators() {
+        let p = TimeDelta::from_i64;
+        let bumps = two_bumps_rating_buffer();
+
+        // a ramp with boundaries that do not line up with the 10-point segments of the bumps
+        let ramp = RatingBuffer {
+            start: p(0),
+            buffer: vec![
+                RatingSegment {
+                    end_point: p(333),
+                    data: RatingInfo {
+                        rating: Rating::convert_from_f64(0.5),
+                        delta: Rating::convert_from_f64(0.001),
+                    },
+                },
+                RatingSegment {
+                    end_point: p(2000),
+                    data: RatingInfo::constant(Rating::convert_from_f64(-0.25)),
+                },
+            ],
+        };
+
+        let sum = add_rating_iterators(bumps.iter(), ramp.iter())
+            .discard_start_times()
+            .save();
+        let difference = subtract_rating_iterators(bumps.iter(), ramp.iter())
+            .discard_start_times()
+            .save();
+
+        for t in [0, 9, 10, 332, 333, 334, 999, 1000, 1555, 1999] {
+            let (bump, ramp) = (rating_at(&bumps, p(t)), rating_at(&ramp, p(t)));
+            assert_eq!(rating_at(&sum, p(t)), bump + ramp, "sum at {}", t);
+            assert_eq!(rating_at(&difference, p(t)), bump - ramp, "difference at {}", t);
+        }
+
+        let end_points = |buffer: &RatingBuffer| buffer.buffer.iter().map(|s| s.end_point.as_i64()).collect::<Vec<_>>();
+        let mut expected: Vec<i64> = end_points(&bumps);
+        expected.push(333);
+        expected.sort_unstable();
+        assert_eq!(end_points(&sum), expected);
+        assert_eq!(end_points(&difference), expected);
+    }
+
+    #[test]
+    fn adding_zero_rating_iterator_is_identity() {
+        let p = TimeDelta::from_i64;
+        let bumps = two_bumps_rating_buffer();
+
+        let sum = add_rating_iterators(zero_rating_iterator(p(0), p(2000)), bumps.iter())
+            .discard_start_times()
+            .save();
+        assert_eq!(sum.start, bumps.start);
+        assert_eq!(rating_summary(&sum), rating_summary(&bumps));
+
+        let shifted = add_rating_iterators(bumps.iter(), constant_rating_iterator(p(0), p(2000), 7))
+            .discard_start_times()
+            .save();
+        for t in [0, 10, 1234, 1999] {
+            assert_eq!(rating_at(&shifted, p(t)), rating_at(&bumps, p(t)) + 7);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "is empty")]
+    fn constant_rating_iterator_rejects_empty_span() {
+        let _ = constant_rating_iterator(TimeDelta::from_i64(5), TimeDelta::from_i64(5), Rating::zero());
+    }
+
+    #[test]
+    fn try_annotate_reports_non_monotonic_end_points() {
+        let p = TimeDelta::from_i64;
+        let segments = |end_points: &[i64]| SegmentIterator {
+            start: p(0),
+            iter: end_points
+                .iter()
+                .map(|&end_point| RatingSegment {
+                    end_point: p(end_point),
+                    data: RatingInfo::constant(Rating::zero()),
+                })
+                .collect::<Vec<_>>()
+                .into_iter(),
+        };
+
+        let spans: Result<Vec<PointSpan>, SegmentError> = segments(&[10, 20, 30])
+            .try_annotate_with_segment_start_points()
+            .map(|segment| segment.map(|segment| segment.span))
+            .collect();
+        assert_eq!(
+            spans,
+            Ok(vec![
+                PointSpan::new(p(0), p(10)),
+                PointSpan::new(p(10), p(20)),
+                PointSpan::new(p(20), p(30)),
+            ])
+        );
+
+        // the iterator ends after the error
+        let results: Vec<Result<PointSpan, SegmentError>> = segments(&[10, 20, 20, 30])
+            .try_annotate_with_segment_start_points()
+            .map(|segment| segment.map(|segment| segment.span))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                Ok(PointSpan::new(p(0), p(10))),
+                Ok(PointSpan::new(p(10), p(20))),
+                Err(SegmentError::NonMonotonicEndPoint {
+                    previous_end: p(20),
+                    end: p(20),
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "is not after the previous end point")]
+    fn annotate_panics_on_non_monotonic_end_points() {
+        let p = TimeDelta::from_i64;
+        let segments = [15, 5].map(|end_point| RatingSegment {
+            end_point: p(end_point),
+            data: RatingInfo::constant(Rating::zero()),
+        });
+        let _ = SegmentIterator {
+            start: p(0),
+            iter: segments.into_iter(),
+        }
+        .annotate_with_segment_start_points()
+        .into_iter()
+        .count();
+    }
+
+    #[test]
+    fn point_span_predicates() {
+        let span = |start: i64, end: i64| PointSpan::new(TimeDelta::from_i64(start), TimeDelta::from_i64(end));
+        let a = span(0, 10);
+
+        assert!(a.contains(TimeDelta::from_i64(0)));
+        assert!(a.contains(TimeDelta::from_i64(9)));
+        assert!(!a.contains(TimeDelta::from_i64(10)));
+        assert!(!a.contains(TimeDelta::from_i64(-1)));
+
+        // `a.end == b.start`: the spans touch, but have no point in common
+        let b = span(10, 20);
+        assert!(!a.overlaps(b) && !b.overlaps(a));
+        assert_eq!(a.intersect(b), None);
+        assert_eq!(b.intersect(a), None);
+
+        let c = span(9, 20);
+        assert!(a.overlaps(c));
+        assert_eq!(a.intersect(c), Some(span(9, 10)));
+        assert_eq!(c.intersect(a), Some(span(9, 10)));
+
+        // a span inside of another one
+        let d = span(3, 5);
+        assert_eq!(a.intersect(d), Some(d));
+        assert_eq!(span(-20, -10).intersect(a), None);
+
+        assert_eq!(a.len(), TimeDelta::from_i64(10));
+        assert_eq!(a.half(), TimeDelta::from_i64(5));
+        assert_eq!(span(-3, 0).half(), TimeDelta::from_i64(-1));
+    }
+
+    #[test]
+    fn differential_builder_rejects_malformed_segments() {
+        let p = TimeDelta::from_i64;
+        let one = RatingDeltaDelta::convert_from_f64(0.01);
+
+        assert_eq!(
+            DifferentialRatingBufferBuilder::new(p(10), p(10)).err(),
+            Some(SegmentError::EmptySpan {
+                start: p(10),
+                end: p(10)
+            })
+        );
+        assert_eq!(
+            PointSpan::try_new(p(5), p(4)),
+            Err(SegmentError::EmptySpan { start: p(5), end: p(4) })
+        );
+
+        let mut builder = DifferentialRatingBufferBuilder::new(p(0), p(100)).unwrap();
+        assert_eq!(
+            builder.add_segment(p(0), one),
+            Err(SegmentError::EmptySpan { start: p(0), end: p(0) })
+        );
+        assert_eq!(builder.add_segment(p(20), one), Ok(()));
+        assert_eq!(builder.add_segment(p(20), -one), Ok(()));
+        assert_eq!(
+            builder.add_segment(p(10), one),
+            Err(SegmentError::NonMonotonicEndPoint {
+                previous_end: p(20),
+                end: p(10)
+            })
+        );
+        assert_eq!(
+            builder.add_segment(p(101), one),
+            Err(SegmentError::OutOfRange {
+                end_point: p(101),
+                end: p(100)
+            })
+        );
+
+        // the rejected segments were not added
+        let incomplete = DifferentialRatingBufferBuilder {
+            start: builder.start,
+            end: builder.end,
+            buffer: builder.buffer.clone(),
+        };
+        assert_eq!(
+            incomplete.build().err().map(|error| error.to_string()),
+            Some("segments end at 20 before the buffer end 100".to_string())
+        );
+
+        assert_eq!(builder.extend_to_end(), Ok(()));
+        let segments = builder.build().unwrap().into_rating_iter().save().buffer;
+        assert_eq!(
+            segments.iter().map(|s| s.end_point).collect::<Vec<_>>(),
+            vec![p(20), p(100)]
+        );
+    }
+
+    #[test]
+    fn validate_reports_all_defects() {
+        assert_eq!(constant_rating_buffer(0, &[10, 20, 30]).validate(), Ok(()));
+        assert_eq!(constant_rating_buffer(0, &[]).validate(), Ok(()));
+
+        let p = TimeDelta::from_i64;
+        assert_eq!(
+            constant_rating_buffer(0, &[10, 10, 30, 25]).validate(),
+            Err(vec![
+                BufferDefect::ZeroLengthSegment { index: 1, end: p(10) },
+                BufferDefect::NonMonotonicEndPoint {
+                    index: 3,
+                    previous_end: p(30),
+                    end: p(25),
+                },
+            ])
+        );
+        assert_eq!(
+            constant_rating_buffer(10, &[10, 20]).validate(),
+            Err(vec![BufferDefect::StartNotBeforeFirstEnd {
+                start: p(10),
+                first_end: p(10),
+            }])
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let rating_buffer = two_bumps_rating_buffer();
+        let json = serde_json::to_string(&rating_buffer).unwrap();
+        let restored: RatingBuffer = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.start, rating_buffer.start);
+        assert_eq!(restored.buffer.len(), rating_buffer.buffer.len());
+        for tie_policy in [MaxTiePolicy::First, MaxTiePolicy::Last, MaxTiePolicy::Central] {
+            assert_eq!(
+                restored.maximum_with(tie_policy),
+                rating_buffer.maximum_with(tie_policy)
+            );
+        }
+
+        let offset_segment = |end_point: i64, offset: i64, drag: bool| OffsetSegment {
+            end_point: TimeDelta::from_i64(end_point),
+            data: OffsetInfo {
+                offset: TimeDelta::from_i64(offset),
+                drag,
+            },
+        };
+        let offset_buffer = OffsetBuffer {
+            start: TimeDelta::from_i64(-5),
+            buffer: vec![offset_segment(10, 5, false), offset_segment(20, 15, true)],
+        };
+        let json = serde_json::to_string(&offset_buffer).unwrap();
+        let restored: OffsetBuffer = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.start(), offset_buffer.start());
+        for t in -5..20 {
+            let t = TimeDelta::from_i64(t);
+            assert_eq!(restored.get_offset_at(t), offset_buffer.get_offset_at(t));
+        }
+    }
+
+    #[test]
+    fn concat_rating_buffers() {
+        let slope = Rating::convert_from_f64(0.01);
+        let rating_at_10 = Rating::add_mul(Rating::zero(), slope, TimeDelta::from_i64(10));
+        let segment = |end_point: i64, rating: Rating, delta: RatingDelta| RatingSegment {
+            end_point: TimeDelta::from_i64(end_point),
+            data: RatingInfo { rating, delta },
+        };
+        let rising = || RatingBuffer {
+            start: TimeDelta::zero(),
+            buffer: vec![segment(10, Rating::zero(), slope)],
+        };
+
+        // the rising slope continues in the second buffer -> one segment
+        let continued = RatingBuffer {
+            start: TimeDelta::from_i64(10),
+            buffer: vec![segment(20, rating_at_10, slope)],
+        };
+        let joined = rising().concat(continued).unwrap();
+        assert_eq!(joined.start, TimeDelta::zero());
+        assert_eq!(
+            joined.buffer.iter().map(|s| s.end_point.as_i64()).collect::<Vec<_>>(),
+            vec![20]
+        );
+        assert_eq!(
+            rating_at(&joined, TimeDelta::from_i64(15)),
+            Rating::add_mul(Rating::zero(), slope, TimeDelta::from_i64(15))
+        );
+
+        // the second buffer holds the rating constant -> the boundary stays
+        let constant = RatingBuffer {
+            start: TimeDelta::from_i64(10),
+            buffer: vec![segment(20, rating_at_10, Rating::zero())],
+        };
+        let joined = rising().concat(constant).unwrap();
+        assert_eq!(
+            joined.buffer.iter().map(|s| s.end_point.as_i64()).collect::<Vec<_>>(),
+            vec![10, 20]
+        );
+
+        let later = RatingBuffer {
+            start: TimeDelta::from_i64(12),
+            buffer: vec![segment(20, rating_at_10, slope)],
+        };
+        assert_eq!(
+            rising().concat(later).unwrap_err(),
+            ConcatError::Gap {
+                end: TimeDelta::from_i64(10),
+                start: TimeDelta::from_i64(12)
+            }
+        );
+
+        let earlier = RatingBuffer {
+            start: TimeDelta::from_i64(5),
+            buffer: vec![segment(20, rating_at_10, slope)],
+        };
+        assert_eq!(
+            rising().concat(earlier).unwrap_err(),
+            ConcatError::Overlap {
+                end: TimeDelta::from_i64(10),
+                start: TimeDelta::from_i64(5)
+            }
+        );
+    }
+
+    #[test]
+    fn annotate_with_offset_buffer_in_lockstep() {
+        let rating_buffer = two_bumps_rating_buffer();
+
+        // dragging offsets with the same segment borders give the same result as the closure
+        let mut segment_start = rating_buffer.start;
+        let mut drag_segments = Vec::new();
+        for segment in &rating_buffer.buffer {
+            drag_segments.push(OffsetSegment {
+                end_point: segment.end_point,
+                data: OffsetInfo {
+                    offset: segment_start + TimeDelta::from_i64(7),
+                    drag: true,
+                },
+            });
+            segment_start = segment.end_point;
+        }
+        let offset_buffer = OffsetBuffer {
+            start: rating_buffer.start,
+            buffer: drag_segments,
+        };
+
+        let lockstep: Vec<DualFullSegment> = rating_buffer
+            .iter()
+            .annotate_with_segment_start_points()
+            .annotate_with_offset_buffer(&offset_buffer)
+            .into_iter()
+            .collect();
+        let closure: Vec<DualFullSegment> = rating_buffer
+            .iter()
+            .annotate_with_segment_start_points()
+            .annotate_with_offset_info(|segment_start| offset_buffer.get_offset_at(segment_start))
+            .into_iter()
+            .collect();
+        assert_eq!(summarize(&lockstep), summarize(&closure));
+
+        // a single constant offset segment does not split the ratings, a jump in the offsets does
+        let jump_at = |t: i64| OffsetBuffer {
+            start: rating_buffer.start,
+            buffer: vec![
+                OffsetSegment {
+                    end_point: TimeDelta::from_i64(t),
+                    data: OffsetInfo::constant(TimeDelta::from_i64(1)),
+                },
+                OffsetSegment {
+                    end_point: rating_buffer.end().unwrap(),
+                    data: OffsetInfo::constant(TimeDelta::from_i64(2)),
+                },
+            ],
+        };
+        let annotated: Vec<DualFullSegment> = rating_buffer
+            .iter()
+            .annotate_with_segment_start_points()
+            .annotate_with_offset_buffer(&jump_at(1005))
+            .into_iter()
+            .collect();
+        assert_eq!(annotated.len(), rating_buffer.buffer.len() + 1);
+        assert_eq!(
+            annotated[100].span,
+            PointSpan::new(TimeDelta::from_i64(1000), TimeDelta::from_i64(1005))
+        );
+        assert_eq!(annotated[100].data.offset_info.offset, TimeDelta::from_i64(1));
+        assert_eq!(
+            annotated[101].span,
+            PointSpan::new(TimeDelta::from_i64(1005), TimeDelta::from_i64(1010))
+        );
+        assert_eq!(annotated[101].data.offset_info.offset, TimeDelta::from_i64(2));
+        assert_eq!(
+            annotated[101].data.rating_info.rating,
+            rating_at(&rating_buffer, TimeDelta::from_i64(1005))
+        );
+    }
+}
@@ -0,0 +1,16 @@
+//! Inputs shared by the tests of `ilass` and the crates built on it (not part of the stable API).
+
+use crate::time_types::{TimePoint, TimeSpan};
+use alloc::vec::Vec;
+
+/// `count` non-overlapping spans with irregular lengths and gaps, starting at zero.
+pub fn test_spans(count: i64) -> Vec<TimeSpan> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    for i in 0..count {
+        let len = 800 + (i * 137) % 700;
+        spans.push(TimeSpan::new(TimePoint::from(start), TimePoint::from(start + len)));
+        start += 2500 + (i * 379) % 1500;
+    }
+    spans
+}
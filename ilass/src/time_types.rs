@@ -1,5 +1,5 @@
-use std::cmp::{Ordering, max, min};
-use std::ops::*;
+use core::cmp::{Ordering, max, min};
+use core::ops::*;
 
 /// Implements conversion to integer variables for TimeDelta and TimePoint.
 macro_rules! impl_from {
@@ -15,6 +15,7 @@ macro_rules! impl_from {
 /// This struct represents a time difference between two `TimePoints`.
 /// Internally its an integer type.
 #[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TimeDelta(i64);
 
 impl TimeDelta {
@@ -47,6 +48,54 @@ impl TimeDelta {
     pub fn as_i64(&self) -> i64 {
         self.0
     }
+
+    /// Creates the time difference closest to `seconds` for a metric in which `TimeDelta::one()`
+    /// is `1 / ticks_per_second` seconds.
+    ///
+    /// The library itself never assumes a tick rate (see `TimePoint`), so the caller has to use
+    /// the same `ticks_per_second` for all conversions of one alignment.
+    ///
+    /// Needs the `std` feature (for `f64::round`).
+    ///
+    /// ```
+    /// use ilass::TimeDelta;
+    ///
+    /// // milliseconds
+    /// assert_eq!(TimeDelta::from_seconds(1.5, 1000.), TimeDelta::from_i64(1500));
+    /// // samples at 8kHz
+    /// assert_eq!(TimeDelta::from_seconds(1.5, 8000.), TimeDelta::from_i64(12000));
+    /// assert_eq!(TimeDelta::from_seconds(-0.0001, 8000.), TimeDelta::from_i64(-1));
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn from_seconds(seconds: f64, ticks_per_second: f64) -> TimeDelta {
+        assert!(ticks_per_second > 0.);
+        TimeDelta((seconds * ticks_per_second).round() as i64)
+    }
+
+    /// Returns the time difference in seconds for a metric in which `TimeDelta::one()` is
+    /// `1 / ticks_per_second` seconds (the inverse of `TimeDelta::from_seconds`).
+    ///
+    /// ```
+    /// use ilass::TimeDelta;
+    ///
+    /// assert_eq!(TimeDelta::from_i64(2500).to_seconds(1000.), 2.5);
+    /// assert_eq!(TimeDelta::from_i64(2500).to_seconds(8000.), 0.3125);
+    /// ```
+    pub fn to_seconds(self, ticks_per_second: f64) -> f64 {
+        assert!(ticks_per_second > 0.);
+        self.0 as f64 / ticks_per_second
+    }
+
+    /// Adds two time differences, returning `None` on overflow.
+    pub fn checked_add(self, rhs: TimeDelta) -> Option<TimeDelta> {
+        self.0.checked_add(rhs.0).map(TimeDelta)
+    }
+
+    /// Adds two time differences, saturating at the smallest/largest representable time
+    /// difference instead of overflowing.
+    pub fn saturating_add(self, rhs: TimeDelta) -> TimeDelta {
+        TimeDelta(self.0.saturating_add(rhs.0))
+    }
 }
 
 impl_from!(TimeDelta, i32);
@@ -54,19 +103,19 @@ impl_from!(TimeDelta, u32);
 impl_from!(TimeDelta, i64);
 impl_from!(TimeDelta, u64);
 
-impl std::iter::Sum for TimeDelta {
+impl core::iter::Sum for TimeDelta {
     fn sum<I: Iterator<Item = TimeDelta>>(iter: I) -> TimeDelta {
         TimeDelta(iter.map(|d| d.0).sum())
     }
 }
 
-impl std::fmt::Display for TimePoint {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl core::fmt::Display for TimePoint {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         write!(f, "{}", self.0)
     }
 }
-impl std::fmt::Display for TimeDelta {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl core::fmt::Display for TimeDelta {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         write!(f, "{}", self.0)
     }
 }
@@ -153,6 +202,7 @@ impl Neg for TimeDelta {
 /// ```
 ///
 #[derive(Copy, Clone, Debug, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TimePoint(i64);
 
 impl TimePoint {
@@ -170,6 +220,24 @@ impl TimePoint {
     pub fn as_i64(self) -> i64 {
         self.0
     }
+
+    /// Creates the time point closest to `seconds` (see `TimeDelta::from_seconds`).
+    ///
+    /// ```
+    /// use ilass::TimePoint;
+    ///
+    /// assert_eq!(TimePoint::from_seconds(90., 1000.), TimePoint::from(90000));
+    /// assert_eq!(TimePoint::from_seconds(90., 100.), TimePoint::from(9000));
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn from_seconds(seconds: f64, ticks_per_second: f64) -> TimePoint {
+        TimePoint(TimeDelta::from_seconds(seconds, ticks_per_second).0)
+    }
+
+    /// Returns the time point in seconds (see `TimeDelta::to_seconds`).
+    pub fn to_seconds(self, ticks_per_second: f64) -> f64 {
+        TimeDelta(self.0).to_seconds(ticks_per_second)
+    }
 }
 
 impl From<i64> for TimePoint {
@@ -1,5 +1,6 @@
 use crate::{TimeDelta, TimeSpan};
-use std::cmp::max;
+use alloc::vec::Vec;
+use core::cmp::max;
 
 fn prepare_spans_sorted(overlapping: &[TimeSpan]) -> (Vec<TimeSpan>, Vec<usize>) {
     if overlapping.is_empty() {
@@ -13,7 +14,7 @@ fn prepare_spans_sorted(overlapping: &[TimeSpan]) -> (Vec<TimeSpan>, Vec<usize>)
     sorted_overlapping.sort_by(|a, b| TimeSpan::cmp_start(a.1, b.1));
 
     // create a mapping from "original vector index -> sorted vector index"
-    let mut mapping = std::vec::from_elem(0usize, overlapping.len());
+    let mut mapping = alloc::vec::from_elem(0usize, overlapping.len());
     for (i2, &(i, _)) in sorted_overlapping.iter().enumerate() {
         mapping[i] = i2;
     }